@@ -1,18 +1,40 @@
 use crate::api::dto::{
-    AccessTokenDTO, IdTokenGrantParams, PasswordGrantParams, RefreshTokenGrantParams, Response,
-    SignUpDTO, TokenGrantParams, TokenGrantType, TokenInfoDto, TokenInfoQueryDTO, TokenQueryDTO,
+    AccessTokenDTO, AppleNotificationDTO, AuthCodeGrantParams, AuthorizeQueryDTO, CallbackDTO,
+    ClientCredentialsGrantParams, DeviceAuthorizationDTO, DeviceCodeDTO, DeviceCodeGrantParams,
+    DeviceCodeRequestDTO, DeviceDecisionDTO, DeviceQueryDTO, IdTokenGrantParams,
+    IdentityDTO, PasswordGrantParams, RefreshTokenGrantParams, Response, SamlResponseDTO,
+    SignUpDTO, SsoAuthorizeQueryDTO, SsoDTO, SsoRedirectDTO, TokenGrantParams, TokenGrantType, TokenInfoDto, TokenInfoQueryDTO, TokenQueryDTO,
 };
-use crate::api::middleware::JWTClaimsDTO;
-use actix_web::{delete, get, post, web, HttpResponse, Responder, ResponseError};
-use authcare::config::AppConfig;
+use crate::api::middleware::{dpop_proof, JWTClaimsDTO, TokenEndpointServices};
+use crate::api::oauth_controller::{
+    authenticate_client, client_access_token, client_credentials, oauth_error,
+};
+use actix_web::http::{header, StatusCode};
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse, Responder, ResponseError};
+use actix_web_httpauth::extractors::basic::BasicAuth;
+use authcare::config::{AppConfig, OAuthProviderConfiguration};
+use authcare::model::flow_state::FlowState;
 use authcare::model::jwt::{encode_jwt, JWTClaims};
 use authcare::model::refresh_token::RefreshToken;
 use authcare::model::user::User;
-use authcare::oidc::oidc::{OidcClient, OidcError};
-use authcare::service::auth_service::AuthService;
+use authcare::oauth::dpop::DPOP;
+use authcare::oauth::{grant_scope, restrict_scope, OAuthError, GRANT_TYPE_REFRESH_TOKEN};
+use authcare::oidc::oauth2::OAuth2Client;
+use authcare::oidc::oidc::{AuthorizationCodeClient, OidcClient, OidcError, OidcProvider};
+use authcare::oidc::provider::apple::AppleUser;
+use authcare::oidc::provider::UserProvidedData;
+use authcare::saml::email_domain;
+use authcare::saml::metadata::sp_metadata;
+use authcare::service::auth_service::{AuthService, AuthServiceError};
+use authcare::service::client_service::ClientService;
+use authcare::service::device_authorization_service::{
+    DeviceAuthorizationService, DeviceAuthorizationServiceError,
+};
+use authcare::service::flow_state_service::{FlowStateService, FlowStateServiceError};
 use authcare::service::session_service::SessionService;
-use authcare::service::token_service::TokenService;
-use authcare::service::user_serivce::UserService;
+use authcare::service::token_service::{TokenService, TokenServiceError};
+use authcare::service::user_serivce::{UserService, UserServiceError};
+use openidconnect::url::Url;
 use thiserror::Error;
 use validator::Validate;
 
@@ -31,9 +53,25 @@ pub enum ControllerError {
 
     #[error("Internal JWT error")]
     InternalOidcError(#[from] OidcError),
+
+    #[error("Invalid DPoP proof")]
+    InvalidDPoPProof,
+
+    #[error("Internal token service error")]
+    InternalTokenServiceError(#[from] TokenServiceError),
+
+    #[error("Internal error")]
+    InternalError,
 }
 
-impl ResponseError for ControllerError {}
+impl ResponseError for ControllerError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ControllerError::InvalidDPoPProof => StatusCode::UNAUTHORIZED,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
 
 #[post("/auth/signup")]
 pub async fn signup_handler(
@@ -48,18 +86,26 @@ pub async fn signup_handler(
         }
     };
 
-    let Ok(user) = user_service
+    let user = match user_service
         .create_user(dto.email.clone(), dto.password.clone())
         .await
-    else {
-        return HttpResponse::InternalServerError().json(Response::internal_error());
+    {
+        Ok(user) => user,
+        Err(UserServiceError::SsoRequired(_)) => {
+            return HttpResponse::Forbidden().json(Response::fail("SSO required".to_string()));
+        }
+        Err(_) => return HttpResponse::InternalServerError().json(Response::internal_error()),
     };
 
-    let Ok(refresh_token) = token_service.issue_refresh_token(&user).await else {
+    let scope = AppConfig::user_scopes(&user).join(" ");
+    let Ok(refresh_token) = token_service
+        .issue_refresh_token(&user, None, Some(scope.clone()), None)
+        .await
+    else {
         return HttpResponse::InternalServerError().json(Response::internal_error());
     };
 
-    let Ok(access_token) = generate_access_token(&user, &refresh_token) else {
+    let Ok(access_token) = generate_access_token(&user, &refresh_token, scope, None) else {
         return HttpResponse::InternalServerError().json(Response::internal_error());
     };
 
@@ -68,27 +114,256 @@ pub async fn signup_handler(
 
 #[post("/auth/token")]
 pub async fn token_handler(
+    req: HttpRequest,
     query: web::Query<TokenQueryDTO>,
     dto: web::Json<TokenGrantParams>,
-    auth_service: web::Data<AuthService>,
-    token_service: web::Data<TokenService>,
-    user_service: web::Data<UserService>,
+    basic_auth: Option<BasicAuth>,
+    services: TokenEndpointServices,
 ) -> impl Responder {
     //TODO: Add rate limit
 
+    // Tokens are bound to the key of a DPoP proof, see RFC 9449
+    let dpop_jkt = match dpop_proof(&req, None) {
+        Ok(proof) => proof.map(|v| v.jkt),
+        Err(error) => return HttpResponse::BadRequest().json(Response::fail(error.to_string())),
+    };
+
+    let TokenEndpointServices {
+        auth_service,
+        client_service,
+        device_authorization_service,
+        flow_state_service,
+        token_service,
+        user_service,
+        ..
+    } = services;
+
     match query.grant_type {
         TokenGrantType::Password => {
-            return token_password_handler(dto.0.into(), auth_service, token_service).await
+            token_password_handler(dto.0.into(), dpop_jkt, auth_service, token_service).await
         }
         TokenGrantType::RefreshToken => {
-            return token_refresh_handler(dto.0.into(), token_service, user_service).await
+            token_refresh_handler(
+                dto.0.into(),
+                dpop_jkt,
+                basic_auth,
+                client_service,
+                token_service,
+                user_service,
+            )
+            .await
         }
         TokenGrantType::IdToken => {
-            return id_token_handler(dto.0.into(), token_service, user_service).await
+            id_token_handler(dto.0.into(), dpop_jkt, token_service, user_service).await
+        }
+        TokenGrantType::AuthorizationCode => {
+            let dto = match AuthCodeGrantParams::try_from(dto.0) {
+                Ok(dto) => dto,
+                Err(error) => return oauth_error(&error),
+            };
+
+            auth_code_handler(dto, dpop_jkt, token_service, user_service, flow_state_service).await
+        }
+        TokenGrantType::ClientCredentials => {
+            client_credentials_handler(dto.0.into(), dpop_jkt, basic_auth, client_service).await
+        }
+        TokenGrantType::DeviceCode => {
+            let dto = match DeviceCodeGrantParams::try_from(dto.0) {
+                Ok(dto) => dto,
+                Err(error) => return oauth_error(&error),
+            };
+
+            device_code_grant_handler(
+                dto,
+                dpop_jkt,
+                basic_auth,
+                client_service,
+                device_authorization_service,
+                token_service,
+                user_service,
+            )
+            .await
+        }
+    }
+}
+
+#[get("/auth/authorize")]
+pub async fn authorize_handler(
+    query: web::Query<AuthorizeQueryDTO>,
+    flow_state_service: web::Data<FlowStateService>,
+) -> impl Responder {
+    let Ok(client) = extract_authorization_client(&query.provider).await else {
+        return HttpResponse::InternalServerError().json(Response::internal_error());
+    };
+
+    match flow_state_service
+        .start_flow(&query.provider, client.as_ref(), &query.redirect_uri)
+        .await
+    {
+        Ok(url) => HttpResponse::Found()
+            .append_header((header::LOCATION, url.to_string()))
+            .finish(),
+        Err(FlowStateServiceError::RedirectUriNotAllowed) => {
+            HttpResponse::BadRequest().json(Response::fail("Invalid redirect uri".to_string()))
+        }
+        Err(_) => HttpResponse::InternalServerError().json(Response::internal_error()),
+    }
+}
+
+#[get("/auth/callback")]
+pub async fn callback_handler(
+    query: web::Query<CallbackDTO>,
+    user_service: web::Data<UserService>,
+    flow_state_service: web::Data<FlowStateService>,
+) -> impl Responder {
+    complete_flow(query.into_inner(), user_service, flow_state_service).await
+}
+
+/// Apple posts the callback as a form when name or email scopes are requested
+#[post("/auth/callback")]
+pub async fn callback_form_handler(
+    form: web::Form<CallbackDTO>,
+    user_service: web::Data<UserService>,
+    flow_state_service: web::Data<FlowStateService>,
+) -> impl Responder {
+    complete_flow(form.into_inner(), user_service, flow_state_service).await
+}
+
+/// Finds the SSO connection of an email domain so users don't have to pick their IdP
+#[post("/auth/sso")]
+pub async fn sso_handler(
+    dto: web::Json<SsoDTO>,
+    flow_state_service: web::Data<FlowStateService>,
+) -> impl Responder {
+    let domain = match (&dto.email, &dto.domain) {
+        (Some(email), _) => email_domain(email),
+        (None, Some(domain)) => Some(domain.trim().to_lowercase()),
+        (None, None) => None,
+    };
+
+    let Some(domain) = domain else {
+        return HttpResponse::BadRequest().json(Response::fail("Missing email or domain".to_string()));
+    };
+
+    let Some(connection) = AppConfig::sso_connection_for_domain(&domain) else {
+        return HttpResponse::NotFound().json(Response::fail("No SSO connection for domain".to_string()));
+    };
+
+    match flow_state_service
+        .start_sso_flow(connection, &dto.redirect_uri)
+        .await
+    {
+        Ok(url) => HttpResponse::Ok().json(Response::success(SsoRedirectDTO {
+            connection: connection.name.clone(),
+            url: url.to_string(),
+        })),
+        Err(FlowStateServiceError::RedirectUriNotAllowed) => {
+            HttpResponse::BadRequest().json(Response::fail("Invalid redirect uri".to_string()))
         }
+        Err(_) => HttpResponse::InternalServerError().json(Response::internal_error()),
     }
 }
 
+#[get("/auth/sso/metadata")]
+pub async fn sso_metadata_handler() -> impl Responder {
+    let Some(sp) = AppConfig::saml_service_provider() else {
+        return HttpResponse::NotFound().json(Response::fail("SSO is not configured".to_string()));
+    };
+
+    HttpResponse::Ok()
+        .content_type("application/samlmetadata+xml")
+        .body(sp_metadata(&sp.entity_id, &sp.acs_url))
+}
+
+#[get("/auth/sso/{connection}/authorize")]
+pub async fn sso_authorize_handler(
+    path: web::Path<String>,
+    query: web::Query<SsoAuthorizeQueryDTO>,
+    flow_state_service: web::Data<FlowStateService>,
+) -> impl Responder {
+    let Some(connection) = AppConfig::saml_connection(&path.into_inner()) else {
+        return HttpResponse::NotFound().json(Response::fail("Unknown SSO connection".to_string()));
+    };
+
+    match flow_state_service
+        .start_sso_flow(connection, &query.redirect_uri)
+        .await
+    {
+        Ok(url) => HttpResponse::Found()
+            .append_header((header::LOCATION, url.to_string()))
+            .finish(),
+        Err(FlowStateServiceError::RedirectUriNotAllowed) => {
+            HttpResponse::BadRequest().json(Response::fail("Invalid redirect uri".to_string()))
+        }
+        Err(_) => HttpResponse::InternalServerError().json(Response::internal_error()),
+    }
+}
+
+/// Assertion consumer service, the IdP posts the SAML response here
+#[post("/auth/sso/acs")]
+pub async fn sso_acs_handler(
+    form: web::Form<SamlResponseDTO>,
+    user_service: web::Data<UserService>,
+    flow_state_service: web::Data<FlowStateService>,
+) -> impl Responder {
+    let Ok(flow_state) = flow_state_service.take_sso_flow(&form.relay_state).await else {
+        return HttpResponse::BadRequest().json(Response::fail("Invalid state".to_string()));
+    };
+
+    let redirect_uri = flow_state.redirect_uri.clone();
+
+    let Some(connection) = flow_state
+        .provider
+        .strip_prefix("sso:")
+        .and_then(AppConfig::saml_connection)
+    else {
+        return redirect_with(&redirect_uri, "error", "server_error");
+    };
+
+    let Ok(claims) =
+        flow_state_service.validate_sso_response(&flow_state, connection, &form.saml_response)
+    else {
+        return redirect_with(&redirect_uri, "error", "access_denied");
+    };
+
+    let user = user_service
+        .create_user_from_sso_identity(&claims, connection)
+        .await;
+
+    redirect_with_auth_code(&flow_state_service, flow_state, user, None).await
+}
+
+#[post("/auth/apple/notifications")]
+pub async fn apple_notification_handler(
+    dto: web::Json<AppleNotificationDTO>,
+    user_service: web::Data<UserService>,
+    session_service: web::Data<SessionService>,
+) -> impl Responder {
+    let Ok(provider) = extract_provider(&OidcProvider::Apple).await else {
+        return HttpResponse::InternalServerError().json(Response::internal_error());
+    };
+
+    let Ok(notification) = provider.verify_apple_notification(&dto.payload) else {
+        return HttpResponse::BadRequest().json(Response::fail("Invalid notification".to_string()));
+    };
+
+    let user_id = match user_service.handle_apple_notification(&notification).await {
+        Ok(user_id) => user_id,
+        // Nothing to update for users that never signed in here, or for a delivery seen before
+        Err(UserServiceError::IdentityNotFound) => None,
+        Err(UserServiceError::NotificationAlreadyHandled) => None,
+        Err(_) => return HttpResponse::InternalServerError().json(Response::internal_error()),
+    };
+
+    if let Some(user_id) = user_id {
+        let Ok(()) = session_service.revoke_user_sessions(&user_id).await else {
+            return HttpResponse::InternalServerError().json(Response::internal_error());
+        };
+    }
+
+    HttpResponse::Ok().finish()
+}
+
 #[get("/auth/token")]
 pub async fn token_info_handler(
     query: web::Query<TokenInfoQueryDTO>,
@@ -124,6 +399,12 @@ pub async fn delete_user_handler(
     user_service: web::Data<UserService>,
     claims: JWTClaimsDTO,
 ) -> impl Responder {
+    // Super users acting as the user can't change the account, see RFC 8693 section 4.1
+    if claims.0.act.is_some() {
+        return HttpResponse::Forbidden()
+            .json(Response::fail("Not allowed while impersonating".to_string()));
+    }
+
     let Ok(uid) = uuid::Uuid::parse_str(claims.0.sub.as_str()) else {
         return HttpResponse::Unauthorized().json(Response::fail("Invalid JWT claims".to_string()));
     };
@@ -135,10 +416,207 @@ pub async fn delete_user_handler(
     HttpResponse::Ok().json(Response::success("Have a good one!"))
 }
 
+#[get("/auth/user/identities")]
+pub async fn identities_handler(
+    user_service: web::Data<UserService>,
+    claims: JWTClaimsDTO,
+) -> impl Responder {
+    let Ok(uid) = uuid::Uuid::parse_str(claims.0.sub.as_str()) else {
+        return HttpResponse::Unauthorized().json(Response::fail("Invalid JWT claims".to_string()));
+    };
+
+    let Ok(identities) = user_service.get_identities(&uid).await else {
+        return HttpResponse::InternalServerError().json(Response::internal_error());
+    };
+
+    let dto: Vec<IdentityDTO> = identities.into_iter().map(|v| v.into()).collect();
+    HttpResponse::Ok().json(Response::success(dto))
+}
+
+#[post("/auth/user/identities")]
+pub async fn link_identity_handler(
+    dto: web::Json<IdTokenGrantParams>,
+    user_service: web::Data<UserService>,
+    claims: JWTClaimsDTO,
+) -> impl Responder {
+    // Super users acting as the user can't change the account, see RFC 8693 section 4.1
+    if claims.0.act.is_some() {
+        return HttpResponse::Forbidden()
+            .json(Response::fail("Not allowed while impersonating".to_string()));
+    }
+
+    let Ok(uid) = uuid::Uuid::parse_str(claims.0.sub.as_str()) else {
+        return HttpResponse::Unauthorized().json(Response::fail("Invalid JWT claims".to_string()));
+    };
+
+    let Ok(provider) = extract_provider(&dto.provider).await else {
+        return HttpResponse::InternalServerError().json(Response::internal_error());
+    };
+
+    let Ok(mut provider_data) = provider.verify(dto.token.as_str(), None) else {
+        return HttpResponse::Unauthorized()
+            .json(Response::fail("Invalid Credentials".to_string()));
+    };
+    apply_apple_user(&dto.provider, dto.user.as_ref(), &mut provider_data);
+
+    let dto = dto.into_inner();
+    let identity = match user_service
+        .link_identity(&uid, &provider_data, dto.provider)
+        .await
+    {
+        Ok(identity) => identity,
+        Err(UserServiceError::IdentityAlreadyLinked) => {
+            return HttpResponse::Conflict()
+                .json(Response::fail("Identity is already linked".to_string()));
+        }
+        Err(UserServiceError::InvalidExternalIdentity) => {
+            return HttpResponse::BadRequest()
+                .json(Response::fail("Invalid external identity".to_string()));
+        }
+        Err(_) => {
+            return HttpResponse::InternalServerError().json(Response::internal_error());
+        }
+    };
+
+    let dto: IdentityDTO = identity.into();
+    HttpResponse::Ok().json(Response::success(dto))
+}
+
+#[delete("/auth/user/identities/{provider}")]
+pub async fn unlink_identity_handler(
+    path: web::Path<OidcProvider>,
+    user_service: web::Data<UserService>,
+    claims: JWTClaimsDTO,
+) -> impl Responder {
+    // Super users acting as the user can't change the account, see RFC 8693 section 4.1
+    if claims.0.act.is_some() {
+        return HttpResponse::Forbidden()
+            .json(Response::fail("Not allowed while impersonating".to_string()));
+    }
+
+    let Ok(uid) = uuid::Uuid::parse_str(claims.0.sub.as_str()) else {
+        return HttpResponse::Unauthorized().json(Response::fail("Invalid JWT claims".to_string()));
+    };
+
+    match user_service.unlink_identity(&uid, path.into_inner()).await {
+        Ok(()) => HttpResponse::Ok().json(Response::success("Identity removed")),
+        Err(UserServiceError::IdentityNotFound) => {
+            HttpResponse::NotFound().json(Response::fail("Identity not found".to_string()))
+        }
+        Err(UserServiceError::LastIdentity) => HttpResponse::Conflict()
+            .json(Response::fail("Can't remove the last login method".to_string())),
+        Err(_) => HttpResponse::InternalServerError().json(Response::internal_error()),
+    }
+}
+
+/// Device authorization endpoint, the device shows the user code and polls the token endpoint
+#[post("/auth/device/code")]
+pub async fn device_code_handler(
+    form: web::Form<DeviceCodeRequestDTO>,
+    basic_auth: Option<BasicAuth>,
+    client_service: web::Data<ClientService>,
+    device_authorization_service: web::Data<DeviceAuthorizationService>,
+) -> impl Responder {
+    let form = form.into_inner();
+    let client = match authenticate_client(
+        basic_auth.as_ref(),
+        form.client_id,
+        form.client_secret,
+        &client_service,
+    )
+    .await
+    {
+        Ok(client) => client,
+        Err(error) => return oauth_error(&error),
+    };
+
+    let device_authorization = match device_authorization_service
+        .start_device_authorization(&client, form.scope.as_deref())
+        .await
+    {
+        Ok(device_authorization) => device_authorization,
+        Err(error) => return oauth_error(&error),
+    };
+
+    let Some(verification_uri) = AppConfig::device_verification_uri() else {
+        return oauth_error(&OAuthError::ServerError);
+    };
+    let Ok(mut verification_uri_complete) = Url::parse(&verification_uri) else {
+        return oauth_error(&OAuthError::ServerError);
+    };
+    let user_code = device_authorization.display_user_code();
+    verification_uri_complete
+        .query_pairs_mut()
+        .append_pair("user_code", &user_code);
+
+    HttpResponse::Ok()
+        .append_header((header::CACHE_CONTROL, "no-store"))
+        .json(DeviceCodeDTO {
+            device_code: device_authorization.device_code,
+            user_code,
+            verification_uri,
+            verification_uri_complete: verification_uri_complete.to_string(),
+            expires_in: (device_authorization.expires_at - device_authorization.created_at)
+                .num_seconds(),
+            interval: device_authorization.interval,
+        })
+}
+
+/// Pending device request of a user code, so the user can check what they approve
+#[get("/auth/device")]
+pub async fn device_handler(
+    query: web::Query<DeviceQueryDTO>,
+    device_authorization_service: web::Data<DeviceAuthorizationService>,
+    claims: JWTClaimsDTO,
+) -> impl Responder {
+    let Ok(uid) = uuid::Uuid::parse_str(claims.0.sub.as_str()) else {
+        return HttpResponse::Unauthorized().json(Response::fail("Invalid JWT claims".to_string()));
+    };
+
+    match device_authorization_service.get_pending(&query.user_code, &uid).await {
+        Ok(device_authorization) => {
+            HttpResponse::Ok().json(DeviceAuthorizationDTO::from(device_authorization))
+        }
+        Err(DeviceAuthorizationServiceError::DeviceAuthorizationNotFound) => {
+            HttpResponse::NotFound().json(Response::fail("Invalid user code".to_string()))
+        }
+        Err(DeviceAuthorizationServiceError::TooManyAttempts) => HttpResponse::TooManyRequests()
+            .json(Response::fail("Too many attempts".to_string())),
+        Err(_) => HttpResponse::InternalServerError().json(Response::internal_error()),
+    }
+}
+
+/// Approves or denies a device on behalf of the signed in user
+#[post("/auth/device")]
+pub async fn device_decision_handler(
+    dto: web::Json<DeviceDecisionDTO>,
+    device_authorization_service: web::Data<DeviceAuthorizationService>,
+    claims: JWTClaimsDTO,
+) -> impl Responder {
+    let Ok(uid) = uuid::Uuid::parse_str(claims.0.sub.as_str()) else {
+        return HttpResponse::Unauthorized().json(Response::fail("Invalid JWT claims".to_string()));
+    };
+
+    match device_authorization_service
+        .decide(&dto.user_code, &uid, dto.approve)
+        .await
+    {
+        Ok(_) if dto.approve => HttpResponse::Ok().json(Response::success("Device approved")),
+        Ok(_) => HttpResponse::Ok().json(Response::success("Device denied")),
+        Err(DeviceAuthorizationServiceError::DeviceAuthorizationNotFound) => {
+            HttpResponse::NotFound().json(Response::fail("Invalid user code".to_string()))
+        }
+        Err(DeviceAuthorizationServiceError::TooManyAttempts) => HttpResponse::TooManyRequests()
+            .json(Response::fail("Too many attempts".to_string())),
+        Err(_) => HttpResponse::InternalServerError().json(Response::internal_error()),
+    }
+}
+
 // Private
 
 async fn token_password_handler(
     dto: PasswordGrantParams,
+    dpop_jkt: Option<String>,
     auth_service: web::Data<AuthService>,
     token_service: web::Data<TokenService>,
 ) -> HttpResponse {
@@ -152,39 +630,193 @@ async fn token_password_handler(
     let email = dto.email.clone();
     let password = dto.password.clone();
 
-    let Ok(user) = auth_service.authenticate(email, password).await else {
-        return HttpResponse::Unauthorized()
-            .json(Response::fail("Invalid Credentials".to_string()));
+    let user = match auth_service.authenticate(email, password).await {
+        Ok(user) => user,
+        Err(AuthServiceError::SsoRequired(_)) => {
+            return HttpResponse::Forbidden().json(Response::fail("SSO required".to_string()));
+        }
+        Err(AuthServiceError::DirectoryError(_)) => {
+            return HttpResponse::InternalServerError().json(Response::internal_error());
+        }
+        Err(_) => {
+            return HttpResponse::Unauthorized()
+                .json(Response::fail("Invalid Credentials".to_string()));
+        }
     };
 
-    let Ok(refresh_token) = token_service.issue_refresh_token(&user).await else {
+    let Ok(scope) = grant_scope(dto.scope.as_deref(), &AppConfig::user_scopes(&user)) else {
+        return HttpResponse::BadRequest().json(Response::fail("Invalid scope".to_string()));
+    };
+
+    let Ok(refresh_token) = token_service
+        .issue_refresh_token(&user, None, Some(scope.clone()), dpop_jkt.clone())
+        .await
+    else {
         return HttpResponse::InternalServerError().json(Response::internal_error());
     };
 
-    let Ok(access_token) = generate_access_token(&user, &refresh_token) else {
+    let Ok(access_token) = generate_access_token(&user, &refresh_token, scope, dpop_jkt.as_deref())
+    else {
         return HttpResponse::InternalServerError().json(Response::internal_error());
     };
 
     HttpResponse::Ok().json(access_token)
 }
 
-async fn token_refresh_handler(
-    dto: RefreshTokenGrantParams,
+async fn client_credentials_handler(
+    dto: ClientCredentialsGrantParams,
+    dpop_jkt: Option<String>,
+    basic_auth: Option<BasicAuth>,
+    client_service: web::Data<ClientService>,
+) -> HttpResponse {
+    let (client_id, client_secret, auth_method) =
+        client_credentials(basic_auth.as_ref(), dto.client_id, dto.client_secret);
+
+    let Some(client_id) = client_id else {
+        return HttpResponse::Unauthorized().json(Response::fail("Invalid client".to_string()));
+    };
+
+    let Ok(client) = client_service
+        .authenticate_client(&client_id, client_secret.as_deref(), auth_method)
+        .await
+    else {
+        return HttpResponse::Unauthorized().json(Response::fail("Invalid client".to_string()));
+    };
+
+    let mut claims = match client_service.client_token_claims(&client, dto.scope.as_deref()) {
+        Ok(claims) => claims,
+        Err(OAuthError::InvalidScope) => {
+            return HttpResponse::BadRequest().json(Response::fail("Invalid scope".to_string()));
+        }
+        Err(_) => {
+            return HttpResponse::Forbidden()
+                .json(Response::fail("Grant not allowed".to_string()));
+        }
+    };
+    claims.bind(dpop_jkt.as_deref());
+
+    let Ok(access_token) = client_access_token(&claims) else {
+        return HttpResponse::InternalServerError().json(Response::internal_error());
+    };
+
+    HttpResponse::Ok().json(access_token)
+}
+
+/// Device polls follow RFC 8628 section 3.5, so errors carry the OAuth error code
+async fn device_code_grant_handler(
+    dto: DeviceCodeGrantParams,
+    dpop_jkt: Option<String>,
+    basic_auth: Option<BasicAuth>,
+    client_service: web::Data<ClientService>,
+    device_authorization_service: web::Data<DeviceAuthorizationService>,
     token_service: web::Data<TokenService>,
     user_service: web::Data<UserService>,
 ) -> HttpResponse {
+    let client = match authenticate_client(
+        basic_auth.as_ref(),
+        dto.client_id,
+        dto.client_secret,
+        &client_service,
+    )
+    .await
+    {
+        Ok(client) => client,
+        Err(error) => return oauth_error(&error),
+    };
+
+    let device_authorization = match device_authorization_service
+        .poll(&client, &dto.device_code)
+        .await
+    {
+        Ok(device_authorization) => device_authorization,
+        Err(error) => return oauth_error(&error),
+    };
+
+    let Some(user_id) = device_authorization.user_id else {
+        return oauth_error(&OAuthError::ServerError);
+    };
+
+    let Ok(user) = user_service.get_user(&user_id).await else {
+        return oauth_error(&OAuthError::InvalidGrant("user not found".to_string()));
+    };
+
+    let scope = restrict_scope(&device_authorization.scope, &AppConfig::user_scopes(&user));
     let Ok(refresh_token) = token_service
-        .swap_refresh_token(dto.refresh_token.as_str())
+        .issue_refresh_token(&user, Some(client.id.clone()), Some(scope.clone()), dpop_jkt.clone())
         .await
     else {
-        return HttpResponse::InternalServerError().json(Response::internal_error());
+        return oauth_error(&OAuthError::ServerError);
+    };
+
+    let Ok(access_token) = generate_access_token(&user, &refresh_token, scope, dpop_jkt.as_deref())
+    else {
+        return oauth_error(&OAuthError::ServerError);
+    };
+
+    HttpResponse::Ok().json(access_token)
+}
+
+async fn token_refresh_handler(
+    dto: RefreshTokenGrantParams,
+    dpop_jkt: Option<String>,
+    basic_auth: Option<BasicAuth>,
+    client_service: web::Data<ClientService>,
+    token_service: web::Data<TokenService>,
+    user_service: web::Data<UserService>,
+) -> HttpResponse {
+    // Tokens of relying parties, e.g. of the device flow, are only refreshed by their client
+    let swapped = if basic_auth.is_some() || dto.client_id.is_some() {
+        let client = match authenticate_client(
+            basic_auth.as_ref(),
+            dto.client_id,
+            dto.client_secret,
+            &client_service,
+        )
+        .await
+        {
+            Ok(client) => client,
+            Err(error) => return oauth_error(&error),
+        };
+
+        if !client.allows_grant(GRANT_TYPE_REFRESH_TOKEN) {
+            return oauth_error(&OAuthError::UnauthorizedClient);
+        }
+
+        token_service
+            .swap_client_refresh_token(&dto.refresh_token, &client.id, dpop_jkt.as_deref())
+            .await
+    } else {
+        token_service
+            .swap_user_refresh_token(&dto.refresh_token, dpop_jkt.as_deref())
+            .await
+    };
+
+    let (refresh_token, session) = match swapped {
+        Ok(swapped) => swapped,
+        Err(TokenServiceError::InvalidDPoPKey) => {
+            return HttpResponse::Unauthorized()
+                .json(Response::fail("Invalid DPoP proof".to_string()));
+        }
+        Err(TokenServiceError::RefreshTokenNotFound) => {
+            return HttpResponse::Unauthorized()
+                .json(Response::fail("Invalid refresh token".to_string()));
+        }
+        Err(_) => return HttpResponse::InternalServerError().json(Response::internal_error()),
     };
 
     let Ok(user) = user_service.get_user(&refresh_token.user_id).await else {
         return HttpResponse::InternalServerError().json(Response::internal_error());
     };
 
-    let Ok(access_token) = generate_access_token(&user, &refresh_token) else {
+    // Scopes the user lost since signing in are dropped
+    let allowed = AppConfig::user_scopes(&user);
+    let scope = match &session.scope {
+        Some(scope) => restrict_scope(scope, &allowed),
+        None => allowed.join(" "),
+    };
+
+    let Ok(access_token) = generate_access_token(&user, &refresh_token, scope, dpop_jkt.as_deref())
+    else {
         return HttpResponse::InternalServerError().json(Response::internal_error());
     };
 
@@ -193,67 +825,414 @@ async fn token_refresh_handler(
 
 async fn id_token_handler(
     dto: IdTokenGrantParams,
+    dpop_jkt: Option<String>,
     token_service: web::Data<TokenService>,
     user_service: web::Data<UserService>,
 ) -> HttpResponse {
-    let Ok(provider) = extract_provider(&dto).await else {
+    let Ok(provider) = extract_provider(&dto.provider).await else {
         return HttpResponse::InternalServerError().json(Response::internal_error());
     };
 
-    let Ok(claims) = provider.verify(dto.token.as_str(), None) else {
-        return HttpResponse::Unauthorized()
-            .json(Response::fail("Invalid Credentials".to_string()));
+    let mut claims = match provider.verify(dto.token.as_str(), None) {
+        Ok(claims) => claims,
+        Err(OidcError::HostedDomainNotAllowed) => {
+            return HttpResponse::Forbidden()
+                .json(Response::fail("Account domain is not allowed".to_string()));
+        }
+        Err(OidcError::UnverifiedEmail) => {
+            return HttpResponse::Forbidden()
+                .json(Response::fail("Email is not verified".to_string()));
+        }
+        Err(_) => {
+            return HttpResponse::Unauthorized()
+                .json(Response::fail("Invalid Credentials".to_string()));
+        }
     };
+    apply_apple_user(&dto.provider, dto.user.as_ref(), &mut claims);
+
+    // Native apps only get the Apple refresh token by redeeming the code as the app itself
+    if let (OidcProvider::Apple, Some(code)) = (&dto.provider, &dto.code) {
+        let metadata = claims.metadata.as_ref();
+        let (Some(client_id), Some(subject)) = (
+            metadata.and_then(|v| v.aud.as_deref()),
+            metadata.and_then(|v| v.subject.as_deref()),
+        ) else {
+            return HttpResponse::InternalServerError().json(Response::internal_error());
+        };
+
+        let Ok(native_client) = extract_native_provider(&dto.provider, client_id).await else {
+            return HttpResponse::InternalServerError().json(Response::internal_error());
+        };
+
+        let Ok(provider_tokens) = native_client.exchange_native_code(code, subject).await else {
+            return HttpResponse::Unauthorized()
+                .json(Response::fail("Invalid authorization code".to_string()));
+        };
+        claims.provider_tokens = Some(provider_tokens);
+    }
 
-    let Ok(user) = user_service
+    let user = match user_service
         .create_user_from_external_identity(&claims, dto.provider)
         .await
+    {
+        Ok(user) => user,
+        Err(UserServiceError::MultipleAccounts(_)) => {
+            return HttpResponse::Conflict()
+                .json(Response::fail("Multiple accounts use this email".to_string()));
+        }
+        Err(UserServiceError::AccountLinkingNotAllowed)
+        | Err(UserServiceError::AccountLinkingRequiresSignIn) => {
+            return HttpResponse::Conflict()
+                .json(Response::fail("Account with this email already exists".to_string()));
+        }
+        Err(_) => {
+            return HttpResponse::Unauthorized()
+                .json(Response::fail("Invalid Credentials".to_string()));
+        }
+    };
+
+    let Ok(scope) = grant_scope(dto.scope.as_deref(), &AppConfig::user_scopes(&user)) else {
+        return HttpResponse::BadRequest().json(Response::fail("Invalid scope".to_string()));
+    };
+
+    let client_id = claims.metadata.as_ref().and_then(|v| v.aud.clone());
+    let Ok(refresh_token) = token_service
+        .issue_refresh_token(&user, client_id, Some(scope.clone()), dpop_jkt.clone())
+        .await
     else {
-        return HttpResponse::Unauthorized()
-            .json(Response::fail("Invalid Credentials".to_string()));
+        return HttpResponse::InternalServerError().json(Response::internal_error());
     };
 
-    let Ok(refresh_token) = token_service.issue_refresh_token(&user).await else {
+    let Ok(access_token) = generate_access_token(&user, &refresh_token, scope, dpop_jkt.as_deref())
+    else {
         return HttpResponse::InternalServerError().json(Response::internal_error());
     };
 
-    let Ok(access_token) = generate_access_token(&user, &refresh_token) else {
+    HttpResponse::Ok().json(access_token)
+}
+
+async fn auth_code_handler(
+    dto: AuthCodeGrantParams,
+    dpop_jkt: Option<String>,
+    token_service: web::Data<TokenService>,
+    user_service: web::Data<UserService>,
+    flow_state_service: web::Data<FlowStateService>,
+) -> HttpResponse {
+    let Ok(flow_state) = flow_state_service.redeem_auth_code(dto.code.as_str()).await else {
+        return HttpResponse::Unauthorized().json(Response::fail("Invalid code".to_string()));
+    };
+
+    let Some(user_id) = flow_state.user_id else {
+        return HttpResponse::Unauthorized().json(Response::fail("Invalid code".to_string()));
+    };
+
+    let Ok(user) = user_service.get_user(&user_id).await else {
+        return HttpResponse::InternalServerError().json(Response::internal_error());
+    };
+
+    let Ok(scope) = grant_scope(dto.scope.as_deref(), &AppConfig::user_scopes(&user)) else {
+        return HttpResponse::BadRequest().json(Response::fail("Invalid scope".to_string()));
+    };
+
+    let Ok(refresh_token) = token_service
+        .issue_refresh_token(&user, flow_state.client_id, Some(scope.clone()), dpop_jkt.clone())
+        .await
+    else {
+        return HttpResponse::InternalServerError().json(Response::internal_error());
+    };
+
+    let Ok(access_token) = generate_access_token(&user, &refresh_token, scope, dpop_jkt.as_deref())
+    else {
         return HttpResponse::InternalServerError().json(Response::internal_error());
     };
 
     HttpResponse::Ok().json(access_token)
 }
 
-async fn extract_provider(dto: &IdTokenGrantParams) -> Result<OidcClient, ControllerError> {
-    let Some(external_configuration) = AppConfig::provider_configuration(&dto.provider) else {
+async fn complete_flow(
+    dto: CallbackDTO,
+    user_service: web::Data<UserService>,
+    flow_state_service: web::Data<FlowStateService>,
+) -> HttpResponse {
+    let Ok(flow_state) = flow_state_service.get_flow(dto.state.as_str()).await else {
+        return HttpResponse::BadRequest().json(Response::fail("Invalid state".to_string()));
+    };
+
+    let redirect_uri = flow_state.redirect_uri.clone();
+
+    if let Some(error) = dto.error {
+        return redirect_with(&redirect_uri, "error", &error);
+    }
+
+    let Some(code) = dto.code else {
+        return redirect_with(&redirect_uri, "error", "invalid_request");
+    };
+
+    let Some(provider) = OidcProvider::from_name(&flow_state.provider) else {
+        return redirect_with(&redirect_uri, "error", "server_error");
+    };
+
+    let Ok(client) = extract_authorization_client(&provider).await else {
+        return redirect_with(&redirect_uri, "error", "server_error");
+    };
+
+    let Ok(mut claims) = flow_state_service
+        .exchange_code(&flow_state, client.as_ref(), &code)
+        .await
+    else {
+        return redirect_with(&redirect_uri, "error", "access_denied");
+    };
+
+    let apple_user = dto.user.and_then(|v| serde_json::from_str::<AppleUser>(&v).ok());
+    apply_apple_user(&provider, apple_user.as_ref(), &mut claims);
+
+    let user = user_service
+        .create_user_from_external_identity(&claims, provider)
+        .await;
+    let client_id = claims.metadata.as_ref().and_then(|v| v.aud.clone());
+
+    redirect_with_auth_code(&flow_state_service, flow_state, user, client_id).await
+}
+
+/// Ends a flow by sending the user back to the client with an auth code or an error
+async fn redirect_with_auth_code(
+    flow_state_service: &FlowStateService,
+    flow_state: FlowState,
+    user: Result<User, UserServiceError>,
+    client_id: Option<String>,
+) -> HttpResponse {
+    let redirect_uri = flow_state.redirect_uri.clone();
+
+    let user = match user {
+        Ok(user) => user,
+        Err(UserServiceError::MultipleAccounts(_))
+        | Err(UserServiceError::AccountLinkingNotAllowed)
+        | Err(UserServiceError::AccountLinkingRequiresSignIn) => {
+            return redirect_with(&redirect_uri, "error", "account_exists");
+        }
+        Err(_) => {
+            return redirect_with(&redirect_uri, "error", "access_denied");
+        }
+    };
+
+    let Ok(auth_code) = flow_state_service
+        .issue_auth_code(flow_state, &user, client_id)
+        .await
+    else {
+        return redirect_with(&redirect_uri, "error", "server_error");
+    };
+
+    redirect_with(&redirect_uri, "code", &auth_code)
+}
+
+fn redirect_with(redirect_uri: &str, key: &str, value: &str) -> HttpResponse {
+    let Ok(mut url) = Url::parse(redirect_uri) else {
+        return HttpResponse::InternalServerError().json(Response::internal_error());
+    };
+
+    url.query_pairs_mut().append_pair(key, value);
+
+    HttpResponse::Found()
+        .append_header((header::LOCATION, url.to_string()))
+        .finish()
+}
+
+/// Adds the name Apple sends outside of the ID token on the first authorization
+fn apply_apple_user(provider: &OidcProvider, user: Option<&AppleUser>, data: &mut UserProvidedData) {
+    if let (OidcProvider::Apple, Some(user)) = (provider, user) {
+        user.apply(data);
+    }
+}
+
+async fn extract_provider(provider: &OidcProvider) -> Result<OidcClient, ControllerError> {
+    let Some(external_configuration) = AppConfig::provider_configuration(provider) else {
         return Err(ControllerError::InternalOidcError(OidcError::UnknownProvider))
     };
 
-    let provider_metadata = dto.provider.fetch_provider_metadata().await?;
+    let mut oid_client =
+        build_oidc_client(provider, external_configuration, &external_configuration.client_id)
+            .await?;
+
+    if let Some(redirect_uri) = &external_configuration.redirect_uri {
+        oid_client = oid_client.set_redirect_uri(redirect_uri)?;
+    }
+
+    Ok(oid_client)
+}
+
+/// Client acting as the native app the ID token was issued to, e.g. an iOS bundle id
+async fn extract_native_provider(
+    provider: &OidcProvider,
+    client_id: &str,
+) -> Result<OidcClient, ControllerError> {
+    let Some(external_configuration) = AppConfig::provider_configuration(provider) else {
+        return Err(ControllerError::InternalOidcError(OidcError::UnknownProvider))
+    };
+
+    build_oidc_client(provider, external_configuration, client_id).await
+}
+
+async fn build_oidc_client(
+    provider: &OidcProvider,
+    external_configuration: &OAuthProviderConfiguration,
+    client_id: &str,
+) -> Result<OidcClient, ControllerError> {
+    let issuer_url = external_configuration
+        .issuer_url
+        .as_deref()
+        .unwrap_or(provider.issuer_url());
+    let provider_metadata = OidcProvider::discover_provider_metadata(issuer_url).await?;
 
     let oid_client = OidcClient::new(
+        provider.clone(),
         provider_metadata,
+        client_id.to_string(),
+        external_configuration.client_secret_for(client_id)?,
+        external_configuration.audiences.clone(),
+    )
+    .set_hosted_domains(external_configuration.hosted_domains.clone());
+
+    Ok(oid_client)
+}
+
+async fn extract_authorization_client(
+    provider: &OidcProvider,
+) -> Result<Box<dyn AuthorizationCodeClient + Send + Sync>, ControllerError> {
+    if provider.is_oidc() {
+        return Ok(Box::new(extract_provider(provider).await?));
+    }
+
+    let Some(external_configuration) = AppConfig::provider_configuration(provider) else {
+        return Err(ControllerError::InternalOidcError(OidcError::UnknownProvider))
+    };
+
+    let Some(endpoints) = external_configuration.oauth2_endpoints.clone() else {
+        return Err(ControllerError::InternalOidcError(OidcError::UnknownProvider))
+    };
+
+    let mut client = OAuth2Client::new(
+        provider.clone(),
+        endpoints,
         external_configuration.client_id.clone(),
-        external_configuration.secret.clone()
-    );
+        external_configuration.client_secret()?,
+    )?;
+
+    if let Some(redirect_uri) = &external_configuration.redirect_uri {
+        client = client.set_redirect_uri(redirect_uri);
+    }
 
-    return Ok(oid_client);
+    Ok(Box::new(client))
 }
 
+/// Access token of the session, bound to the DPoP key of the thumbprint when there is one
 fn generate_access_token(
     user: &User,
     refresh_token: &RefreshToken,
+    scope: String,
+    dpop_jkt: Option<&str>,
 ) -> Result<AccessTokenDTO, ControllerError> {
     let token = refresh_token.token.clone();
     let session_id = refresh_token.session_id;
-    let jwt = JWTClaims::new(user.id.to_string(), session_id.to_string());
+    let mut jwt = JWTClaims::new(user.id.to_string(), session_id.to_string(), scope);
+    jwt.bind(dpop_jkt);
     let encoded_jwt = encode_jwt(&jwt, AppConfig::jwt_secret())?;
 
-    Ok(AccessTokenDTO::new(
+    let mut access_token = AccessTokenDTO::new(
         encoded_jwt,
         jwt.exp,
         jwt.iat,
         token,
         user.into(),
-    ))
+    );
+    if jwt.cnf.is_some() {
+        access_token.token_type = DPOP.to_string();
+    }
+
+    Ok(access_token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use authcare::model::client::OAuthClient;
+    use authcare::model::client_repository::DbClientRepository;
+    use authcare::model::identity_repository::DbIdentityRepository;
+    use authcare::model::provider_notification_repository::DbProviderNotificationRepository;
+    use authcare::model::provider_token_revocation_repository::DbProviderTokenRevocationRepository;
+    use authcare::model::refresh_token_repository::DbRefreshTokenRepository;
+    use authcare::model::session_repository::DbSessionRepository;
+    use authcare::model::user_repository::{DbUserRepository, UserRepository};
+    use authcare::oauth::GRANT_TYPE_AUTHORIZATION_CODE;
+    use authcare::oidc::revocation::{ProviderTokenRevoker, ReqwestHttpClient};
+    use sqlx::PgPool;
+    use std::sync::Arc;
+
+    #[sqlx::test(migrations = "../lib/migrations")]
+    async fn refuse_client_refresh_token_test(
+        pool: PgPool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let user_repository = Arc::new(DbUserRepository::new(pool.clone()));
+        let token_service = web::Data::new(TokenService::new(
+            Arc::new(DbRefreshTokenRepository::new(pool.clone())),
+            user_repository.clone(),
+            Arc::new(DbSessionRepository::new(pool.clone())),
+        ));
+        let user_service = web::Data::new(UserService::new(
+            user_repository.clone(),
+            Arc::new(DbIdentityRepository::new(pool.clone())),
+            Arc::new(ProviderTokenRevoker::new(Arc::new(ReqwestHttpClient::new()))),
+            Arc::new(DbProviderTokenRevocationRepository::new(pool.clone())),
+            Arc::new(DbProviderNotificationRepository::new(pool.clone())),
+        ));
+        let client_service =
+            web::Data::new(ClientService::new(Arc::new(DbClientRepository::new(pool.clone()))));
+
+        let grant_types = vec![
+            GRANT_TYPE_AUTHORIZATION_CODE.to_string(),
+            GRANT_TYPE_REFRESH_TOKEN.to_string(),
+        ];
+        let client = OAuthClient::new(grant_types.clone(), vec![], vec![]);
+        let (client, _) = client_service.create_client(client, true).await?;
+        let other = OAuthClient::new(grant_types, vec![], vec![]);
+        let (other, other_secret) = client_service.create_client(other, true).await?;
+
+        let user = user_repository
+            .add(User::new("jane@example.com".to_string(), "hash".to_string()))
+            .await?;
+        let sso_session_id = token_service
+            .issue_refresh_token(&user, None, None, None)
+            .await?
+            .session_id;
+        let refresh_token = token_service
+            .issue_sso_refresh_token(&user, client.id.clone(), None, None, sso_session_id)
+            .await?;
+
+        // A relying party token is refused without its client, or with another client
+        let credentials = [(None, None), (Some(other.id), other_secret)];
+        for (client_id, client_secret) in credentials {
+            let dto = RefreshTokenGrantParams {
+                refresh_token: refresh_token.token.clone(),
+                client_id,
+                client_secret,
+            };
+            let response = token_refresh_handler(
+                dto,
+                None,
+                None,
+                client_service.clone(),
+                token_service.clone(),
+                user_service.clone(),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+
+        // The refused requests didn't use up the token of the client
+        let (_, session) = token_service
+            .swap_client_refresh_token(&refresh_token.token, &client.id, None)
+            .await?;
+        assert_eq!(session.client_id, Some(client.id));
+
+        Ok(())
+    }
 }
@@ -1,11 +1,19 @@
+use authcare::config::AppConfig;
 use authcare::constants::TOKEN_TYPE;
+use authcare::model::device_authorization::DeviceAuthorization;
+use authcare::model::client::OAuthClient;
+use authcare::model::identity::Identity;
 use authcare::model::jwt::JWTClaims;
 use authcare::model::refresh_token::RefreshToken;
 use authcare::model::token_info::TokenInfo;
 use authcare::model::user::User;
+use authcare::oauth::registration::ClientRegistration;
+use authcare::oauth::{AuthorizationRequest, OAuthError};
 use serde::{Deserialize, Serialize};
 use validator::Validate;
 use authcare::oidc::oidc::OidcProvider;
+use authcare::oidc::provider::apple::AppleUser;
+use chrono::{DateTime, Utc};
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "lowercase")]
@@ -50,6 +58,7 @@ pub struct UserDTO {
     pub id: uuid::Uuid,
     pub email: String,
     pub is_super_user: bool,
+    pub user_metadata: serde_json::Value,
 }
 
 impl From<User> for UserDTO {
@@ -58,6 +67,7 @@ impl From<User> for UserDTO {
             id: value.id,
             email: value.email.expect("Let's expect for now"),
             is_super_user: value.is_super_user.unwrap_or(false),
+            user_metadata: value.user_metadata,
         }
     }
 }
@@ -68,6 +78,27 @@ impl From<&User> for UserDTO {
             id: value.id,
             email: value.email.clone().expect("Let's expect for now"),
             is_super_user: value.is_super_user.unwrap_or(false),
+            user_metadata: value.user_metadata.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IdentityDTO {
+    pub provider: String,
+    pub email: Option<String>,
+    pub last_sign_in_at: Option<DateTime<Utc>>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+impl From<Identity> for IdentityDTO {
+    fn from(value: Identity) -> Self {
+        Self {
+            provider: value.provider,
+            email: value.email,
+            last_sign_in_at: value.last_sign_in_at,
+            created_at: value.created_at,
         }
     }
 }
@@ -128,6 +159,10 @@ pub enum TokenGrantType {
     Password,
     RefreshToken,
     IdToken,
+    AuthorizationCode,
+    ClientCredentials,
+    #[serde(rename = "urn:ietf:params:oauth:grant-type:device_code")]
+    DeviceCode,
 }
 
 #[derive(Debug, Validate, Deserialize)]
@@ -140,10 +175,25 @@ pub struct TokenGrantParams {
     // refresh token
     pub refresh_token: Option<String>,
 
+    // authorization code
+    pub code: Option<String>,
+
     // id token
     pub token: Option<String>,
     pub provider: Option<OidcProvider>,
     pub issuer: Option<String>,
+    /// Name Apple only returns on the first authorization
+    pub user: Option<AppleUser>,
+
+    // client credentials, basic auth is preferred
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+
+    // device code
+    pub device_code: Option<String>,
+
+    /// Space separated scopes, everything allowed is granted without it
+    pub scope: Option<String>,
 }
 
 #[derive(Debug, Validate)]
@@ -151,6 +201,7 @@ pub struct PasswordGrantParams {
     #[validate(email)]
     pub email: String,
     pub password: String,
+    pub scope: Option<String>,
 }
 
 impl From<TokenGrantParams> for PasswordGrantParams {
@@ -158,6 +209,7 @@ impl From<TokenGrantParams> for PasswordGrantParams {
         Self {
             email: value.email.expect("Expect email"),
             password: value.password.expect("Expect password"),
+            scope: value.scope,
         }
     }
 }
@@ -165,12 +217,17 @@ impl From<TokenGrantParams> for PasswordGrantParams {
 #[derive(Debug)]
 pub struct RefreshTokenGrantParams {
     pub refresh_token: String,
+    /// Set by relying parties, their refresh tokens are bound to the client
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
 }
 
 impl From<TokenGrantParams> for RefreshTokenGrantParams {
     fn from(value: TokenGrantParams) -> Self {
         Self {
             refresh_token: value.refresh_token.expect("Expect refresh token"),
+            client_id: value.client_id,
+            client_secret: value.client_secret,
         }
     }
 }
@@ -180,6 +237,10 @@ impl From<TokenGrantParams> for RefreshTokenGrantParams {
 pub struct IdTokenGrantParams {
     pub token: String,
     pub provider: OidcProvider,
+    pub user: Option<AppleUser>,
+    /// Authorization code Apple returns to native apps together with the ID token
+    pub code: Option<String>,
+    pub scope: Option<String>,
 }
 
 impl From<TokenGrantParams> for IdTokenGrantParams {
@@ -187,10 +248,181 @@ impl From<TokenGrantParams> for IdTokenGrantParams {
         Self {
             token: value.token.expect("Expect token"),
             provider: value.provider.expect("Expect provider"),
+            user: value.user,
+            code: value.code,
+            scope: value.scope,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct AuthCodeGrantParams {
+    pub code: String,
+    pub scope: Option<String>,
+}
+
+impl TryFrom<TokenGrantParams> for AuthCodeGrantParams {
+    type Error = OAuthError;
+
+    fn try_from(value: TokenGrantParams) -> Result<Self, Self::Error> {
+        let Some(code) = value.code else {
+            return Err(OAuthError::InvalidRequest("Missing code".to_string()));
+        };
+
+        Ok(Self {
+            code,
+            scope: value.scope,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct ClientCredentialsGrantParams {
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    pub scope: Option<String>,
+}
+
+impl From<TokenGrantParams> for ClientCredentialsGrantParams {
+    fn from(value: TokenGrantParams) -> Self {
+        Self {
+            client_id: value.client_id,
+            client_secret: value.client_secret,
+            scope: value.scope,
         }
     }
 }
 
+#[derive(Debug)]
+pub struct DeviceCodeGrantParams {
+    pub device_code: String,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
+
+impl TryFrom<TokenGrantParams> for DeviceCodeGrantParams {
+    type Error = OAuthError;
+
+    fn try_from(value: TokenGrantParams) -> Result<Self, Self::Error> {
+        let Some(device_code) = value.device_code else {
+            return Err(OAuthError::InvalidRequest("Missing device_code".to_string()));
+        };
+
+        Ok(Self {
+            device_code,
+            client_id: value.client_id,
+            client_secret: value.client_secret,
+        })
+    }
+}
+
+/// Form body of the device authorization endpoint, see RFC 8628 section 3.1
+#[derive(Debug, Deserialize)]
+pub struct DeviceCodeRequestDTO {
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    pub scope: Option<String>,
+}
+
+/// Response of the device authorization endpoint, see RFC 8628 section 3.2
+#[derive(Debug, Serialize)]
+pub struct DeviceCodeDTO {
+    pub device_code: String,
+    pub user_code: String,
+    pub verification_uri: String,
+    pub verification_uri_complete: String,
+    pub expires_in: i64,
+    pub interval: i32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceQueryDTO {
+    pub user_code: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceDecisionDTO {
+    pub user_code: String,
+    pub approve: bool,
+}
+
+/// Pending device request shown to the user before approving it
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceAuthorizationDTO {
+    pub user_code: String,
+    pub client_id: String,
+    pub scope: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl From<DeviceAuthorization> for DeviceAuthorizationDTO {
+    fn from(value: DeviceAuthorization) -> Self {
+        Self {
+            user_code: value.display_user_code(),
+            client_id: value.client_id,
+            scope: value.scope,
+            expires_at: value.expires_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthorizeQueryDTO {
+    pub provider: OidcProvider,
+    pub redirect_uri: String,
+}
+
+/// Email or domain of the user signing in with SSO
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SsoDTO {
+    pub email: Option<String>,
+    pub domain: Option<String>,
+    pub redirect_uri: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SsoRedirectDTO {
+    pub connection: String,
+    pub url: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SsoAuthorizeQueryDTO {
+    pub redirect_uri: String,
+}
+
+/// Form the IdP posts to the assertion consumer service, named by the SAML spec
+#[derive(Debug, Deserialize)]
+pub struct SamlResponseDTO {
+    #[serde(rename = "SAMLResponse")]
+    pub saml_response: String,
+    #[serde(rename = "RelayState")]
+    pub relay_state: String,
+}
+
+/// Parameters the provider redirects back with, named by the OAuth spec
+#[derive(Debug, Deserialize)]
+pub struct CallbackDTO {
+    pub state: String,
+    pub code: Option<String>,
+    pub error: Option<String>,
+    /// JSON encoded `AppleUser`, posted by Apple on the first authorization
+    pub user: Option<String>,
+}
+
+/// Body of the Sign in with Apple server-to-server notifications
+#[derive(Debug, Deserialize)]
+pub struct AppleNotificationDTO {
+    pub payload: String,
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TokenInfoQueryDTO {
@@ -211,3 +443,169 @@ impl From<TokenInfo> for TokenInfoDto {
         }
     }
 }
+
+#[derive(Debug, Deserialize)]
+pub struct OAuthLoginQueryDTO {
+    pub authorization_id: uuid::Uuid,
+}
+
+/// Form of the hosted login page
+#[derive(Debug, Deserialize)]
+pub struct OAuthLoginDTO {
+    pub authorization_id: uuid::Uuid,
+    pub email: String,
+    pub password: String,
+    /// Must match the `authcare_csrf` cookie set with the form
+    pub csrf_token: Option<String>,
+}
+
+/// Parameters of the end session endpoint, see OpenID Connect RP-Initiated Logout section 2
+#[derive(Debug, Deserialize)]
+pub struct EndSessionRequestDTO {
+    pub id_token_hint: Option<String>,
+    pub client_id: Option<String>,
+    pub post_logout_redirect_uri: Option<String>,
+    pub state: Option<String>,
+    /// Set by the confirmation page, must match the `authcare_csrf` cookie set with it
+    pub csrf_token: Option<String>,
+}
+
+/// Form body of the token endpoint, see RFC 6749 section 4.1.3 and 6
+#[derive(Debug, Deserialize)]
+pub struct OAuthTokenRequestDTO {
+    pub grant_type: String,
+    pub code: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub code_verifier: Option<String>,
+    pub refresh_token: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    pub scope: Option<String>,
+    // token exchange, see RFC 8693 section 2.1
+    pub subject_token: Option<String>,
+    pub subject_token_type: Option<String>,
+    pub requested_token_type: Option<String>,
+    pub requested_subject: Option<String>,
+    pub audience: Option<String>,
+}
+
+/// Form body of the introspection and revocation endpoints, see RFC 7662 section 2.1 and RFC 7009
+/// section 2.1, the `token_type_hint` is not needed to find the token
+#[derive(Debug, Deserialize)]
+pub struct OAuthTokenFormDTO {
+    pub token: String,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
+
+/// Pushed authorization request, the client authenticates like at the token endpoint
+#[derive(Debug, Deserialize)]
+pub struct PushedAuthorizationRequestDTO {
+    #[serde(flatten)]
+    pub request: AuthorizationRequest,
+    pub client_secret: Option<String>,
+}
+
+/// Response of the PAR endpoint, see RFC 9126 section 2.2
+#[derive(Debug, Serialize)]
+pub struct PushedAuthorizationResponseDTO {
+    pub request_uri: String,
+    pub expires_in: i64,
+}
+
+/// Successful response of the token endpoint, see RFC 6749 section 5.1
+#[derive(Debug, Serialize)]
+pub struct OAuthTokenDTO {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub issued_token_type: Option<String>,
+}
+
+/// Registered client, see RFC 7591 section 3.2.1 and RFC 7592 section 3
+#[derive(Debug, Serialize)]
+pub struct ClientRegistrationDTO {
+    pub client_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_secret: Option<String>,
+    /// Secrets don't expire
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_secret_expires_at: Option<i64>,
+    pub client_id_issued_at: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub registration_access_token: Option<String>,
+    pub registration_client_uri: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_name: Option<String>,
+    pub redirect_uris: Vec<String>,
+    pub grant_types: Vec<String>,
+    pub token_endpoint_auth_method: Option<String>,
+    pub scope: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jwks: Option<serde_json::Value>,
+    pub require_pushed_authorization_requests: bool,
+    pub post_logout_redirect_uris: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frontchannel_logout_uri: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backchannel_logout_uri: Option<String>,
+}
+
+impl From<OAuthClient> for ClientRegistrationDTO {
+    fn from(value: OAuthClient) -> Self {
+        Self {
+            registration_client_uri: format!("{}/oauth/register/{}", AppConfig::oidc_issuer(), value.id),
+            client_id: value.id,
+            client_secret: None,
+            client_secret_expires_at: None,
+            client_id_issued_at: value.created_at.timestamp(),
+            registration_access_token: None,
+            client_name: value.client_name,
+            redirect_uris: value.redirect_uris,
+            grant_types: value.grant_types,
+            token_endpoint_auth_method: value.token_endpoint_auth_method,
+            scope: value.scopes.join(" "),
+            jwks: value.jwks,
+            require_pushed_authorization_requests: value.require_pushed_authorization_requests,
+            post_logout_redirect_uris: value.post_logout_redirect_uris,
+            frontchannel_logout_uri: value.frontchannel_logout_uri,
+            backchannel_logout_uri: value.backchannel_logout_uri,
+        }
+    }
+}
+
+impl From<ClientRegistration> for ClientRegistrationDTO {
+    fn from(value: ClientRegistration) -> Self {
+        let client_secret_expires_at = value.client_secret.as_ref().map(|_| 0);
+
+        Self {
+            client_secret: value.client_secret,
+            client_secret_expires_at,
+            registration_access_token: Some(value.registration_access_token),
+            ..value.client.into()
+        }
+    }
+}
+
+/// Error response of the token endpoint, see RFC 6749 section 5.2
+#[derive(Debug, Serialize)]
+pub struct OAuthErrorDTO {
+    pub error: String,
+    pub error_description: String,
+}
+
+impl From<&OAuthError> for OAuthErrorDTO {
+    fn from(value: &OAuthError) -> Self {
+        Self {
+            error: value.code().to_string(),
+            error_description: value.to_string(),
+        }
+    }
+}
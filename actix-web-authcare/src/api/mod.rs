@@ -1,3 +1,4 @@
 pub mod controller;
 pub mod dto;
 pub mod middleware;
+pub mod oauth_controller;
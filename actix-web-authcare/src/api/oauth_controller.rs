@@ -0,0 +1,1302 @@
+use crate::api::controller::ControllerError;
+use crate::api::dto::{
+    ClientRegistrationDTO, EndSessionRequestDTO, OAuthErrorDTO, OAuthLoginDTO, OAuthLoginQueryDTO, OAuthTokenDTO,
+    OAuthTokenFormDTO, OAuthTokenRequestDTO, PushedAuthorizationRequestDTO, PushedAuthorizationResponseDTO, Response,
+};
+use crate::api::middleware::{dpop_proof, JWTClaimsDTO, RequireScope, TokenEndpointServices};
+use actix_web::cookie::{time, Cookie, SameSite};
+use actix_web::http::header;
+use actix_web::{delete, get, post, put, route, web, HttpRequest, HttpResponse, Responder};
+use actix_web_httpauth::extractors::basic::BasicAuth;
+use actix_web_httpauth::extractors::bearer::BearerAuth;
+use authcare::config::AppConfig;
+use authcare::constants::{
+    OAUTH_AUTHORIZATION_EXPIRED_IN, OAUTH_CSRF_COOKIE, OAUTH_REQUEST_URI_EXPIRED_IN,
+    OAUTH_SESSION_COOKIE, OAUTH_SESSION_EXPIRED_IN,
+};
+use authcare::model::client::OAuthClient;
+use authcare::model::jwt::{encode_jwt, JWTClaims};
+use authcare::model::refresh_token::RefreshToken;
+use authcare::model::session::Session;
+use authcare::model::user::User;
+use authcare::oauth::discovery::ProviderMetadata;
+use authcare::oauth::dpop::DPOP;
+use authcare::oauth::id_token::IdTokenClaims;
+use authcare::oauth::logout::logout_sid;
+use authcare::oauth::registration::{
+    ClientMetadata, AUTH_METHOD_CLIENT_SECRET_BASIC, AUTH_METHOD_CLIENT_SECRET_POST,
+    AUTH_METHOD_NONE,
+};
+use authcare::oauth::{
+    parse_scope, restrict_scope, AuthorizationRequest, OAuthError, TokenExchangeRequest,
+    GRANT_TYPE_AUTHORIZATION_CODE, GRANT_TYPE_CLIENT_CREDENTIALS, GRANT_TYPE_REFRESH_TOKEN,
+    GRANT_TYPE_TOKEN_EXCHANGE, OIDC_SCOPES, SCOPE_OPENID, TOKEN_TYPE_ACCESS_TOKEN,
+};
+use authcare::service::auth_service::AuthService;
+use authcare::service::authorization_service::{AuthorizationService, AuthorizationServiceError};
+use authcare::service::client_service::ClientService;
+use authcare::service::session_service::SessionService;
+use authcare::service::token_exchange_service::TokenExchangeService;
+use authcare::service::token_service::{TokenService, TokenServiceError};
+use authcare::service::user_serivce::UserService;
+use authcare::utils::crypto::{constant_time_eq, random_secret_token};
+use authcare::utils::escape::escape_attribute;
+use openidconnect::url::Url;
+
+#[get("/.well-known/openid-configuration")]
+pub async fn openid_configuration_handler() -> impl Responder {
+    HttpResponse::Ok().json(ProviderMetadata::new(&AppConfig::oidc_issuer()))
+}
+
+#[get("/oauth/jwks")]
+pub async fn jwks_handler() -> impl Responder {
+    let Some(signing_key) = AppConfig::oidc_signing_key() else {
+        return HttpResponse::NotFound().json(Response::fail("OpenID provider disabled".to_string()));
+    };
+
+    HttpResponse::Ok().json(signing_key.jwks())
+}
+
+/// Authorization endpoint, users without a browser session are sent to the hosted login
+#[get("/oauth/authorize")]
+pub async fn oauth_authorize_handler(
+    req: HttpRequest,
+    query: web::Query<AuthorizationRequest>,
+    authorization_service: web::Data<AuthorizationService>,
+) -> impl Responder {
+    let authorization = match authorization_service.start_authorization(&query).await {
+        Ok(authorization) => authorization,
+        Err(AuthorizationServiceError::InvalidClient) => {
+            return HttpResponse::BadRequest()
+                .json(Response::fail("Invalid client or redirect uri".to_string()));
+        }
+        Err(AuthorizationServiceError::ErrorRedirect(url)) => return redirect_to(&url),
+        Err(AuthorizationServiceError::OAuthError(error)) => return oauth_error(&error),
+        Err(_) => return HttpResponse::InternalServerError().json(Response::internal_error()),
+    };
+
+    // Taken from the authorization, so it can't be changed next to a request uri
+    let prompt = authorization.prompt.clone().unwrap_or_default();
+    let session = match req.cookie(OAUTH_SESSION_COOKIE) {
+        Some(cookie) if prompt != "login" => authorization_service
+            .authenticate_session(cookie.value())
+            .await
+            .ok(),
+        _ => None,
+    };
+
+    if let Some(session) = session {
+        return match authorization_service
+            .complete_authorization(authorization, &session)
+            .await
+        {
+            Ok(url) => redirect_to(&url),
+            Err(_) => HttpResponse::InternalServerError().json(Response::internal_error()),
+        };
+    }
+
+    if prompt == "none" {
+        let error = OAuthError::LoginRequired;
+        return match authorization_service.deny_authorization(&authorization, &error) {
+            Ok(url) => redirect_to(&url),
+            Err(_) => HttpResponse::BadRequest().json(Response::fail("Invalid redirect uri".to_string())),
+        };
+    }
+
+    let Ok(mut login_url) = Url::parse(&format!("{}/oauth/login", AppConfig::oidc_issuer())) else {
+        return HttpResponse::InternalServerError().json(Response::internal_error());
+    };
+    login_url
+        .query_pairs_mut()
+        .append_pair("authorization_id", &authorization.id.to_string());
+
+    redirect_to(&login_url)
+}
+
+/// Pushed authorization request endpoint, see RFC 9126
+#[post("/oauth/par")]
+pub async fn oauth_par_handler(
+    form: web::Form<PushedAuthorizationRequestDTO>,
+    basic_auth: Option<BasicAuth>,
+    client_service: web::Data<ClientService>,
+    authorization_service: web::Data<AuthorizationService>,
+) -> impl Responder {
+    let client = match authenticate_client(
+        basic_auth.as_ref(),
+        form.request.client_id.clone(),
+        form.client_secret.clone(),
+        &client_service,
+    )
+    .await
+    {
+        Ok(client) => client,
+        Err(error) => return oauth_error(&error),
+    };
+
+    match authorization_service
+        .push_authorization(&client, &form.request)
+        .await
+    {
+        Ok(authorization) => HttpResponse::Created()
+            .append_header((header::CACHE_CONTROL, "no-store"))
+            .json(PushedAuthorizationResponseDTO {
+                request_uri: authorization.request_uri.unwrap_or_default(),
+                expires_in: OAUTH_REQUEST_URI_EXPIRED_IN,
+            }),
+        Err(error) => oauth_error(&error),
+    }
+}
+
+/// Hosted login page of the authorization endpoint
+#[get("/oauth/login")]
+pub async fn oauth_login_page_handler(
+    query: web::Query<OAuthLoginQueryDTO>,
+    authorization_service: web::Data<AuthorizationService>,
+) -> impl Responder {
+    if authorization_service
+        .get_authorization(&query.authorization_id)
+        .await
+        .is_err()
+    {
+        return HttpResponse::BadRequest().json(Response::fail("Invalid authorization".to_string()));
+    }
+
+    login_page(HttpResponse::Ok(), &query.authorization_id, None)
+}
+
+#[post("/oauth/login")]
+pub async fn oauth_login_handler(
+    req: HttpRequest,
+    form: web::Form<OAuthLoginDTO>,
+    auth_service: web::Data<AuthService>,
+    authorization_service: web::Data<AuthorizationService>,
+) -> impl Responder {
+    let Ok(authorization) = authorization_service
+        .get_authorization(&form.authorization_id)
+        .await
+    else {
+        return HttpResponse::BadRequest().json(Response::fail("Invalid authorization".to_string()));
+    };
+
+    if !is_valid_csrf(&req, form.csrf_token.as_deref()) {
+        return login_page(
+            HttpResponse::Forbidden(),
+            &form.authorization_id,
+            Some("Your session expired, please try again"),
+        );
+    }
+
+    let Ok(user) = auth_service
+        .authenticate(form.email.clone(), form.password.clone())
+        .await
+    else {
+        return login_page(
+            HttpResponse::Unauthorized(),
+            &form.authorization_id,
+            Some("Invalid email or password"),
+        );
+    };
+
+    let Ok(cookie_value) = authorization_service.start_session(&user).await else {
+        return HttpResponse::InternalServerError().json(Response::internal_error());
+    };
+
+    let Ok(session) = authorization_service
+        .authenticate_session(&cookie_value)
+        .await
+    else {
+        return HttpResponse::InternalServerError().json(Response::internal_error());
+    };
+
+    let Ok(url) = authorization_service
+        .complete_authorization(authorization, &session)
+        .await
+    else {
+        return HttpResponse::InternalServerError().json(Response::internal_error());
+    };
+
+    let cookie = Cookie::build(OAUTH_SESSION_COOKIE, cookie_value)
+        .path("/")
+        .http_only(true)
+        .secure(true)
+        .same_site(SameSite::Lax)
+        .max_age(time::Duration::minutes(OAUTH_SESSION_EXPIRED_IN))
+        .finish();
+
+    HttpResponse::Found()
+        .cookie(cookie)
+        .append_header((header::LOCATION, url.to_string()))
+        .finish()
+}
+
+/// End session endpoint, see OpenID Connect RP-Initiated Logout
+///
+/// Unless the ID token hint is of the signed in session the request could come from any site, so
+/// the user confirms it.
+#[get("/oauth/logout")]
+pub async fn oauth_logout_page_handler(
+    req: HttpRequest,
+    query: web::Query<EndSessionRequestDTO>,
+    authorization_service: web::Data<AuthorizationService>,
+    client_service: web::Data<ClientService>,
+    session_service: web::Data<SessionService>,
+) -> impl Responder {
+    end_session(
+        &req,
+        query.into_inner(),
+        false,
+        &authorization_service,
+        &client_service,
+        &session_service,
+    )
+    .await
+}
+
+/// Posted by relying parties or by the confirmation page
+///
+/// Other sites can post too, so the post is only confirmed with the token of the confirmation
+/// page, or with the lax session cookie which is only sent by the same site.
+#[post("/oauth/logout")]
+pub async fn oauth_logout_handler(
+    req: HttpRequest,
+    form: web::Form<EndSessionRequestDTO>,
+    authorization_service: web::Data<AuthorizationService>,
+    client_service: web::Data<ClientService>,
+    session_service: web::Data<SessionService>,
+) -> impl Responder {
+    let confirmed = is_valid_csrf(&req, form.csrf_token.as_deref())
+        || req.cookie(OAUTH_SESSION_COOKIE).is_some();
+
+    end_session(
+        &req,
+        form.into_inner(),
+        confirmed,
+        &authorization_service,
+        &client_service,
+        &session_service,
+    )
+    .await
+}
+
+#[post("/oauth/token")]
+pub async fn oauth_token_handler(
+    req: HttpRequest,
+    form: web::Form<OAuthTokenRequestDTO>,
+    basic_auth: Option<BasicAuth>,
+    services: TokenEndpointServices,
+) -> impl Responder {
+    let TokenEndpointServices {
+        authorization_service,
+        client_service,
+        token_service,
+        token_exchange_service,
+        user_service,
+        ..
+    } = services;
+    let form = form.into_inner();
+    let client = match authenticate_client(
+        basic_auth.as_ref(),
+        form.client_id.clone(),
+        form.client_secret.clone(),
+        &client_service,
+    )
+    .await
+    {
+        Ok(client) => client,
+        Err(error) => return oauth_error(&error),
+    };
+
+    let grant_type = form.grant_type.clone();
+    if !client.allows_grant(&grant_type) {
+        return oauth_error(&OAuthError::UnauthorizedClient);
+    }
+
+    // Tokens are bound to the key of a DPoP proof, see RFC 9449 section 5
+    let dpop_jkt = match dpop_proof(&req, None) {
+        Ok(proof) => proof.map(|v| v.jkt),
+        Err(error) => return oauth_error(&error),
+    };
+
+    match grant_type.as_str() {
+        GRANT_TYPE_AUTHORIZATION_CODE => {
+            oauth_code_grant(
+                form,
+                &client,
+                dpop_jkt,
+                authorization_service,
+                token_service,
+                user_service,
+            )
+            .await
+        }
+        GRANT_TYPE_REFRESH_TOKEN => {
+            oauth_refresh_grant(form, &client, dpop_jkt, token_service, user_service).await
+        }
+        GRANT_TYPE_CLIENT_CREDENTIALS => {
+            oauth_client_credentials_grant(form, &client, dpop_jkt, client_service)
+        }
+        GRANT_TYPE_TOKEN_EXCHANGE => {
+            oauth_token_exchange_grant(form, &client, dpop_jkt, token_exchange_service).await
+        }
+        _ => oauth_error(&OAuthError::UnsupportedGrantType),
+    }
+}
+
+/// Introspection endpoint for confidential clients, only their own tokens are active
+#[post("/oauth/introspect")]
+pub async fn oauth_introspect_handler(
+    form: web::Form<OAuthTokenFormDTO>,
+    basic_auth: Option<BasicAuth>,
+    client_service: web::Data<ClientService>,
+    token_service: web::Data<TokenService>,
+) -> impl Responder {
+    let form = form.into_inner();
+    let client = match authenticate_client(
+        basic_auth.as_ref(),
+        form.client_id,
+        form.client_secret,
+        &client_service,
+    )
+    .await
+    {
+        Ok(client) => client,
+        Err(error) => return oauth_error(&error),
+    };
+
+    // Public clients can't prove who is asking, see RFC 7662 section 2.1
+    if client.is_public() {
+        return oauth_error(&OAuthError::InvalidClient);
+    }
+
+    HttpResponse::Ok()
+        .append_header((header::CACHE_CONTROL, "no-store"))
+        .json(token_service.introspect_for_client(&form.token, &client.id).await)
+}
+
+/// Revocation endpoint, revoking either token ends the session of the token
+#[post("/oauth/revoke")]
+pub async fn oauth_revoke_handler(
+    form: web::Form<OAuthTokenFormDTO>,
+    basic_auth: Option<BasicAuth>,
+    client_service: web::Data<ClientService>,
+    token_service: web::Data<TokenService>,
+    session_service: web::Data<SessionService>,
+) -> impl Responder {
+    let form = form.into_inner();
+    let client = match authenticate_client(
+        basic_auth.as_ref(),
+        form.client_id,
+        form.client_secret,
+        &client_service,
+    )
+    .await
+    {
+        Ok(client) => client,
+        Err(error) => return oauth_error(&error),
+    };
+
+    // Invalid tokens are not an error, the client has nothing left to clean up
+    let introspection = token_service.introspect(&form.token).await;
+    if !introspection.active {
+        return HttpResponse::Ok().finish();
+    }
+
+    if introspection.client_id.as_deref() != Some(client.id.as_str()) {
+        return oauth_error(&OAuthError::InvalidRequest(
+            "token was issued to another client".to_string(),
+        ));
+    }
+
+    let Some(session_id) = introspection.sid.and_then(|v| uuid::Uuid::parse_str(&v).ok()) else {
+        return oauth_error(&OAuthError::UnsupportedTokenType);
+    };
+
+    let Ok(()) = session_service.revoke_session(&session_id).await else {
+        return oauth_error(&OAuthError::ServerError);
+    };
+
+    HttpResponse::Ok().finish()
+}
+
+/// Dynamic client registration, partners need the initial access token we handed them
+#[post("/oauth/register")]
+pub async fn oauth_register_handler(
+    dto: web::Json<ClientMetadata>,
+    bearer_auth: Option<BearerAuth>,
+    client_service: web::Data<ClientService>,
+) -> impl Responder {
+    let Some(initial_access_token) = AppConfig::client_registration_token() else {
+        return HttpResponse::Forbidden().json(Response::fail("Registration disabled".to_string()));
+    };
+
+    let authorized = bearer_auth.is_some_and(|v| constant_time_eq(v.token(), &initial_access_token));
+    if !authorized {
+        return invalid_token();
+    }
+
+    match client_service.register_client(&dto).await {
+        Ok(registration) => HttpResponse::Created()
+            .append_header((header::CACHE_CONTROL, "no-store"))
+            .json(ClientRegistrationDTO::from(registration)),
+        Err(error) => oauth_error(&error),
+    }
+}
+
+#[get("/oauth/register/{client_id}")]
+pub async fn oauth_registration_handler(
+    path: web::Path<String>,
+    bearer_auth: Option<BearerAuth>,
+    client_service: web::Data<ClientService>,
+) -> impl Responder {
+    let Some(client) = registered_client(&path, bearer_auth, &client_service).await else {
+        return invalid_token();
+    };
+
+    HttpResponse::Ok()
+        .append_header((header::CACHE_CONTROL, "no-store"))
+        .json(ClientRegistrationDTO::from(client))
+}
+
+#[put("/oauth/register/{client_id}")]
+pub async fn oauth_update_registration_handler(
+    path: web::Path<String>,
+    dto: web::Json<ClientMetadata>,
+    bearer_auth: Option<BearerAuth>,
+    client_service: web::Data<ClientService>,
+) -> impl Responder {
+    let Some(client) = registered_client(&path, bearer_auth, &client_service).await else {
+        return invalid_token();
+    };
+
+    match client_service.update_registration(client, &dto).await {
+        Ok(client) => HttpResponse::Ok()
+            .append_header((header::CACHE_CONTROL, "no-store"))
+            .json(ClientRegistrationDTO::from(client)),
+        Err(error) => oauth_error(&error),
+    }
+}
+
+#[delete("/oauth/register/{client_id}")]
+pub async fn oauth_delete_registration_handler(
+    path: web::Path<String>,
+    bearer_auth: Option<BearerAuth>,
+    client_service: web::Data<ClientService>,
+) -> impl Responder {
+    let Some(client) = registered_client(&path, bearer_auth, &client_service).await else {
+        return invalid_token();
+    };
+
+    let Ok(()) = client_service.delete_client(&client.id).await else {
+        return HttpResponse::InternalServerError().json(Response::internal_error());
+    };
+
+    HttpResponse::NoContent().finish()
+}
+
+#[route(
+    "/oauth/userinfo",
+    method = "GET",
+    method = "POST",
+    wrap = "RequireScope(SCOPE_OPENID)"
+)]
+pub async fn userinfo_handler(
+    jwt_claims: JWTClaimsDTO,
+    authorization_service: web::Data<AuthorizationService>,
+    user_service: web::Data<UserService>,
+) -> impl Responder {
+    let Ok(user_id) = uuid::Uuid::parse_str(&jwt_claims.0.sub) else {
+        return HttpResponse::Unauthorized().finish();
+    };
+
+    let Ok(user) = user_service.get_user(&user_id).await else {
+        return HttpResponse::Unauthorized().finish();
+    };
+
+    let Ok(user_info) = authorization_service
+        .user_info(&user, &parse_scope(&jwt_claims.0.scope))
+        .await
+    else {
+        return HttpResponse::InternalServerError().json(Response::internal_error());
+    };
+
+    HttpResponse::Ok().json(user_info)
+}
+
+async fn oauth_code_grant(
+    dto: OAuthTokenRequestDTO,
+    client: &OAuthClient,
+    dpop_jkt: Option<String>,
+    authorization_service: web::Data<AuthorizationService>,
+    token_service: web::Data<TokenService>,
+    user_service: web::Data<UserService>,
+) -> HttpResponse {
+    let Some(code) = &dto.code else {
+        return oauth_error(&OAuthError::InvalidRequest("code is required".to_string()));
+    };
+
+    let authorization = match authorization_service
+        .redeem_code(
+            client,
+            code,
+            dto.redirect_uri.as_deref(),
+            dto.code_verifier.as_deref(),
+        )
+        .await
+    {
+        Ok(authorization) => authorization,
+        Err(error) => return oauth_error(&error),
+    };
+
+    let Some(user_id) = authorization.user_id else {
+        return oauth_error(&OAuthError::ServerError);
+    };
+
+    let Ok(user) = user_service.get_user(&user_id).await else {
+        return oauth_error(&OAuthError::InvalidGrant("user not found".to_string()));
+    };
+
+    let Some(session_id) = authorization.session_id else {
+        return oauth_error(&OAuthError::ServerError);
+    };
+
+    // Linked to the browser session, so the client session ends when the user signs out
+    let scope = oauth_user_scope(&user, &authorization.scope);
+    let Ok(refresh_token) = token_service
+        .issue_sso_refresh_token(
+            &user,
+            client.id.clone(),
+            Some(scope.clone()),
+            dpop_jkt.clone(),
+            session_id,
+        )
+        .await
+    else {
+        return oauth_error(&OAuthError::ServerError);
+    };
+
+    let Ok(mut token) = oauth_access_token(&user, &refresh_token, scope, dpop_jkt.as_deref()) else {
+        return oauth_error(&OAuthError::ServerError);
+    };
+    if !client.allows_grant(GRANT_TYPE_REFRESH_TOKEN) {
+        token.refresh_token = None;
+    }
+
+    if authorization.scopes().contains(&SCOPE_OPENID) {
+        let Ok(id_token) = authorization_service
+            .issue_id_token(&authorization, &user, &token.access_token)
+            .await
+        else {
+            return oauth_error(&OAuthError::ServerError);
+        };
+        token.id_token = Some(id_token);
+    }
+
+    token_response(token)
+}
+
+async fn oauth_refresh_grant(
+    dto: OAuthTokenRequestDTO,
+    client: &OAuthClient,
+    dpop_jkt: Option<String>,
+    token_service: web::Data<TokenService>,
+    user_service: web::Data<UserService>,
+) -> HttpResponse {
+    let Some(refresh_token) = &dto.refresh_token else {
+        return oauth_error(&OAuthError::InvalidRequest(
+            "refresh_token is required".to_string(),
+        ));
+    };
+
+    let (refresh_token, session) = match token_service
+        .swap_client_refresh_token(refresh_token, &client.id, dpop_jkt.as_deref())
+        .await
+    {
+        Ok(swapped) => swapped,
+        Err(TokenServiceError::InvalidDPoPKey) => {
+            return oauth_error(&OAuthError::InvalidDPoPProof(
+                "refresh token is bound to another key".to_string(),
+            ));
+        }
+        Err(_) => {
+            return oauth_error(&OAuthError::InvalidGrant("invalid refresh token".to_string()));
+        }
+    };
+
+    let Ok(user) = user_service.get_user(&refresh_token.user_id).await else {
+        return oauth_error(&OAuthError::InvalidGrant("user not found".to_string()));
+    };
+
+    let scope = oauth_user_scope(&user, session.scope.as_deref().unwrap_or_default());
+    let Ok(token) = oauth_access_token(&user, &refresh_token, scope, dpop_jkt.as_deref()) else {
+        return oauth_error(&OAuthError::ServerError);
+    };
+
+    token_response(token)
+}
+
+async fn oauth_token_exchange_grant(
+    dto: OAuthTokenRequestDTO,
+    client: &OAuthClient,
+    dpop_jkt: Option<String>,
+    token_exchange_service: web::Data<TokenExchangeService>,
+) -> HttpResponse {
+    let (Some(subject_token), Some(subject_token_type)) = (dto.subject_token, dto.subject_token_type)
+    else {
+        return oauth_error(&OAuthError::InvalidRequest(
+            "subject_token and subject_token_type are required".to_string(),
+        ));
+    };
+
+    let request = TokenExchangeRequest {
+        subject_token,
+        subject_token_type,
+        requested_token_type: dto.requested_token_type,
+        requested_subject: dto.requested_subject,
+        audience: dto.audience,
+        scope: dto.scope,
+    };
+
+    let claims = match token_exchange_service
+        .exchange(client, &request, dpop_jkt.as_deref())
+        .await
+    {
+        Ok(claims) => claims,
+        Err(error) => return oauth_error(&error),
+    };
+
+    let Ok(access_token) = encode_jwt(&claims, AppConfig::jwt_secret()) else {
+        return oauth_error(&OAuthError::ServerError);
+    };
+
+    token_response(OAuthTokenDTO {
+        access_token,
+        token_type: token_type(&claims),
+        expires_in: claims.exp - claims.iat,
+        refresh_token: None,
+        id_token: None,
+        scope: Some(claims.scope),
+        issued_token_type: Some(TOKEN_TYPE_ACCESS_TOKEN.to_string()),
+    })
+}
+
+fn oauth_client_credentials_grant(
+    dto: OAuthTokenRequestDTO,
+    client: &OAuthClient,
+    dpop_jkt: Option<String>,
+    client_service: web::Data<ClientService>,
+) -> HttpResponse {
+    let mut claims = match client_service.client_token_claims(client, dto.scope.as_deref()) {
+        Ok(claims) => claims,
+        Err(error) => return oauth_error(&error),
+    };
+    claims.bind(dpop_jkt.as_deref());
+
+    let Ok(token) = client_access_token(&claims) else {
+        return oauth_error(&OAuthError::ServerError);
+    };
+
+    token_response(token)
+}
+
+/// Scopes granted to a client on behalf of the user, dropping the ones the user no longer holds
+fn oauth_user_scope(user: &User, scope: &str) -> String {
+    let mut allowed = AppConfig::user_scopes(user);
+    allowed.extend(OIDC_SCOPES.iter().map(|v| v.to_string()));
+
+    restrict_scope(scope, &allowed)
+}
+
+fn oauth_access_token(
+    user: &User,
+    refresh_token: &RefreshToken,
+    scope: String,
+    dpop_jkt: Option<&str>,
+) -> Result<OAuthTokenDTO, ControllerError> {
+    let mut jwt = JWTClaims::new(user.id.to_string(), refresh_token.session_id.to_string(), scope);
+    jwt.bind(dpop_jkt);
+    let access_token = encode_jwt(&jwt, AppConfig::jwt_secret())?;
+
+    Ok(OAuthTokenDTO {
+        access_token,
+        token_type: token_type(&jwt),
+        expires_in: jwt.exp - jwt.iat,
+        refresh_token: Some(refresh_token.token.clone()),
+        id_token: None,
+        scope: Some(jwt.scope),
+        issued_token_type: None,
+    })
+}
+
+/// Access token of a client credentials grant, it has no refresh token
+pub(crate) fn client_access_token(claims: &JWTClaims) -> Result<OAuthTokenDTO, ControllerError> {
+    let access_token = encode_jwt(claims, AppConfig::jwt_secret())?;
+
+    Ok(OAuthTokenDTO {
+        access_token,
+        token_type: token_type(claims),
+        expires_in: claims.exp - claims.iat,
+        refresh_token: None,
+        id_token: None,
+        scope: Some(claims.scope.clone()),
+        issued_token_type: None,
+    })
+}
+
+/// Bound tokens are DPoP tokens, see RFC 9449 section 5
+fn token_type(claims: &JWTClaims) -> String {
+    match claims.cnf {
+        Some(_) => DPOP.to_string(),
+        None => "Bearer".to_string(),
+    }
+}
+
+/// Authenticates the client with basic auth or with the credentials of the request body
+pub(crate) async fn authenticate_client(
+    basic_auth: Option<&BasicAuth>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+    client_service: &ClientService,
+) -> Result<OAuthClient, OAuthError> {
+    let (client_id, client_secret, auth_method) =
+        client_credentials(basic_auth, client_id, client_secret);
+
+    let Some(client_id) = client_id else {
+        return Err(OAuthError::InvalidClient);
+    };
+
+    client_service
+        .authenticate_client(&client_id, client_secret.as_deref(), auth_method)
+        .await
+}
+
+/// Client of the registration client uri, when the registration access token matches
+async fn registered_client(
+    client_id: &str,
+    bearer_auth: Option<BearerAuth>,
+    client_service: &ClientService,
+) -> Option<OAuthClient> {
+    client_service
+        .authenticate_registration(client_id, bearer_auth?.token())
+        .await
+        .ok()
+}
+
+/// Unknown clients are not revealed, they get the same answer as a wrong token
+fn invalid_token() -> HttpResponse {
+    HttpResponse::Unauthorized()
+        .append_header((header::WWW_AUTHENTICATE, r#"Bearer error="invalid_token""#))
+        .json(OAuthErrorDTO::from(&OAuthError::InvalidToken))
+}
+
+/// Ends the browser session with the client sessions started from it
+async fn end_session(
+    req: &HttpRequest,
+    request: EndSessionRequestDTO,
+    confirmed: bool,
+    authorization_service: &AuthorizationService,
+    client_service: &ClientService,
+    session_service: &SessionService,
+) -> HttpResponse {
+    let id_token = match &request.id_token_hint {
+        Some(id_token_hint) => match verify_id_token_hint(id_token_hint) {
+            Some(id_token) => Some(id_token),
+            None => {
+                return oauth_error(&OAuthError::InvalidRequest(
+                    "invalid id_token_hint".to_string(),
+                ));
+            }
+        },
+        None => None,
+    };
+
+    let client_id = request
+        .client_id
+        .clone()
+        .or_else(|| id_token.as_ref().map(|v| v.aud.clone()));
+    if id_token
+        .as_ref()
+        .is_some_and(|v| Some(&v.aud) != client_id.as_ref())
+    {
+        return oauth_error(&OAuthError::InvalidRequest(
+            "id_token_hint was issued to another client".to_string(),
+        ));
+    }
+
+    // Only registered uris are followed, anything else would be an open redirect
+    let redirect_url = match &request.post_logout_redirect_uri {
+        Some(post_logout_redirect_uri) => {
+            let client = match &client_id {
+                Some(client_id) => client_service.get_client(client_id).await.ok(),
+                None => None,
+            };
+            let Some(mut url) = client
+                .filter(|v| {
+                    v.post_logout_redirect_uris
+                        .contains(post_logout_redirect_uri)
+                })
+                .and_then(|_| Url::parse(post_logout_redirect_uri).ok())
+            else {
+                return HttpResponse::BadRequest().json(Response::fail(
+                    "Invalid client or post logout redirect uri".to_string(),
+                ));
+            };
+            if let Some(state) = &request.state {
+                url.query_pairs_mut().append_pair("state", state);
+            }
+            Some(url)
+        }
+        None => None,
+    };
+
+    let session = match req.cookie(OAUTH_SESSION_COOKIE) {
+        Some(cookie) => authorization_service
+            .authenticate_session(cookie.value())
+            .await
+            .ok(),
+        None => None,
+    };
+
+    if !confirmed && !is_hinted_session(id_token.as_ref(), session.as_ref()) {
+        return logout_confirmation_page(&request);
+    }
+
+    let sid = session
+        .map(|v| v.sid)
+        .or_else(|| id_token.and_then(|v| v.sid))
+        .and_then(|v| uuid::Uuid::parse_str(&v).ok());
+
+    let sessions = match sid {
+        Some(sid) => match session_service.end_session(&sid).await {
+            Ok(sessions) => sessions,
+            Err(_) => return HttpResponse::InternalServerError().json(Response::internal_error()),
+        },
+        None => vec![],
+    };
+
+    let mut frontchannel_urls: Vec<Url> = vec![];
+    for session in &sessions {
+        let Some(client_id) = &session.client_id else {
+            continue;
+        };
+        let Ok(client) = client_service.get_client(client_id).await else {
+            continue;
+        };
+        let Some(url) = frontchannel_logout_url(&client, session, &AppConfig::oidc_issuer()) else {
+            continue;
+        };
+
+        if !frontchannel_urls.contains(&url) {
+            frontchannel_urls.push(url);
+        }
+    }
+
+    let mut cookie = Cookie::build(OAUTH_SESSION_COOKIE, "")
+        .path("/")
+        .http_only(true)
+        .secure(true)
+        .same_site(SameSite::Lax)
+        .finish();
+    cookie.make_removal();
+
+    match redirect_url {
+        Some(url) if frontchannel_urls.is_empty() => HttpResponse::Found()
+            .cookie(cookie)
+            .append_header((header::LOCATION, url.to_string()))
+            .finish(),
+        url => {
+            let mut response = HttpResponse::Ok();
+            response.cookie(cookie);
+            logged_out_page(response, &frontchannel_urls, url.as_ref())
+        }
+    }
+}
+
+/// A GET is only trusted when the hint is of the signed in session, anyone can link to the end
+/// session endpoint with an old ID token
+fn is_hinted_session(id_token: Option<&IdTokenClaims>, session: Option<&JWTClaims>) -> bool {
+    match (id_token, session) {
+        (Some(id_token), Some(session)) => {
+            id_token.user_info.sub == session.sub
+                && id_token.sid.as_deref() == Some(session.sid.as_str())
+        }
+        _ => false,
+    }
+}
+
+/// Front-channel logout uri of the client with the session, see OpenID Connect Front-Channel
+/// Logout section 2
+fn frontchannel_logout_url(client: &OAuthClient, session: &Session, issuer: &str) -> Option<Url> {
+    let mut url = Url::parse(client.frontchannel_logout_uri.as_deref()?).ok()?;
+    url.query_pairs_mut()
+        .append_pair("iss", issuer)
+        .append_pair("sid", &logout_sid(session));
+
+    Some(url)
+}
+
+/// Claims of an ID token we issued, it may have expired since
+fn verify_id_token_hint(id_token_hint: &str) -> Option<IdTokenClaims> {
+    let signing_key = AppConfig::oidc_signing_key()?;
+
+    let mut validation = signing_key.validation();
+    validation.validate_exp = false;
+    validation.validate_aud = false;
+    validation.set_issuer(&[AppConfig::oidc_issuer()]);
+
+    signing_key.verify(id_token_hint, &validation).ok()
+}
+
+/// Client id and secret from basic auth, or from the request body, with the
+/// `token_endpoint_auth_method` they were sent with
+pub(crate) fn client_credentials(
+    basic_auth: Option<&BasicAuth>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+) -> (Option<String>, Option<String>, &'static str) {
+    match (basic_auth, client_secret) {
+        (Some(auth), _) => (
+            Some(auth.user_id().to_string()),
+            auth.password().map(|v| v.to_string()),
+            AUTH_METHOD_CLIENT_SECRET_BASIC,
+        ),
+        (None, Some(client_secret)) => {
+            (client_id, Some(client_secret), AUTH_METHOD_CLIENT_SECRET_POST)
+        }
+        (None, None) => (client_id, None, AUTH_METHOD_NONE),
+    }
+}
+
+fn token_response(token: OAuthTokenDTO) -> HttpResponse {
+    HttpResponse::Ok()
+        .append_header((header::CACHE_CONTROL, "no-store"))
+        .json(token)
+}
+
+pub(crate) fn oauth_error(error: &OAuthError) -> HttpResponse {
+    let mut response = match error {
+        OAuthError::InvalidClient => HttpResponse::Unauthorized(),
+        OAuthError::ServerError => HttpResponse::InternalServerError(),
+        _ => HttpResponse::BadRequest(),
+    };
+
+    response
+        .append_header((header::CACHE_CONTROL, "no-store"))
+        .json(OAuthErrorDTO::from(error))
+}
+
+fn redirect_to(url: &Url) -> HttpResponse {
+    HttpResponse::Found()
+        .append_header((header::LOCATION, url.to_string()))
+        .finish()
+}
+
+fn login_page(
+    mut response: actix_web::HttpResponseBuilder,
+    authorization_id: &uuid::Uuid,
+    error: Option<&str>,
+) -> HttpResponse {
+    let error = error
+        .map(|v| format!(r#"<p class="error">{}</p>"#, v))
+        .unwrap_or_default();
+
+    let (csrf_token, csrf_cookie) = csrf_cookie();
+
+    let body = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><title>Sign in</title></head>
+<body>
+<form method="post" action="login">
+{}
+<input type="hidden" name="authorization_id" value="{}">
+<input type="hidden" name="csrf_token" value="{}">
+<label>Email <input type="email" name="email" autocomplete="username" required></label>
+<label>Password <input type="password" name="password" autocomplete="current-password" required></label>
+<button type="submit">Sign in</button>
+</form>
+</body>
+</html>"#,
+        error, authorization_id, csrf_token
+    );
+
+    response
+        .cookie(csrf_cookie)
+        .content_type("text/html; charset=utf-8")
+        // The login form must not be framed by other sites
+        .append_header((header::X_FRAME_OPTIONS, "DENY"))
+        .body(body)
+}
+
+/// Every rendered form gets a new token, submitted back together with the cookie
+fn csrf_cookie() -> (String, Cookie<'static>) {
+    let csrf_token = random_secret_token(32);
+    let csrf_cookie = Cookie::build(OAUTH_CSRF_COOKIE, csrf_token.clone())
+        .path("/")
+        .http_only(true)
+        .secure(true)
+        .same_site(SameSite::Strict)
+        .max_age(time::Duration::minutes(OAUTH_AUTHORIZATION_EXPIRED_IN))
+        .finish();
+
+    (csrf_token, csrf_cookie)
+}
+
+/// Other sites can't read the cookie, so they can't post a matching token
+fn is_valid_csrf(req: &HttpRequest, csrf_token: Option<&str>) -> bool {
+    match (req.cookie(OAUTH_CSRF_COOKIE), csrf_token) {
+        (Some(cookie), Some(token)) => constant_time_eq(cookie.value(), token),
+        _ => false,
+    }
+}
+
+fn logout_confirmation_page(request: &EndSessionRequestDTO) -> HttpResponse {
+    let (csrf_token, csrf_cookie) = csrf_cookie();
+    let hidden = [
+        ("id_token_hint", &request.id_token_hint),
+        ("client_id", &request.client_id),
+        (
+            "post_logout_redirect_uri",
+            &request.post_logout_redirect_uri,
+        ),
+        ("state", &request.state),
+    ]
+    .iter()
+    .filter_map(|(name, value)| {
+        value.as_ref().map(|v| {
+            format!(
+                r#"<input type="hidden" name="{}" value="{}">"#,
+                name,
+                escape_attribute(v)
+            )
+        })
+    })
+    .collect::<Vec<_>>()
+    .join("\n");
+
+    let body = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><title>Sign out</title></head>
+<body>
+<form method="post" action="logout">
+{}
+<input type="hidden" name="csrf_token" value="{}">
+<p>Do you want to sign out?</p>
+<button type="submit">Sign out</button>
+</form>
+</body>
+</html>"#,
+        hidden, csrf_token
+    );
+
+    HttpResponse::Ok()
+        .cookie(csrf_cookie)
+        .content_type("text/html; charset=utf-8")
+        .append_header((header::X_FRAME_OPTIONS, "DENY"))
+        .body(body)
+}
+
+/// Loads the front-channel logout uris of the clients, then continues to the relying party
+fn logged_out_page(
+    mut response: actix_web::HttpResponseBuilder,
+    frontchannel_urls: &[Url],
+    redirect_url: Option<&Url>,
+) -> HttpResponse {
+    let iframes = frontchannel_urls
+        .iter()
+        .map(|v| {
+            format!(
+                r#"<iframe src="{}" style="display:none"></iframe>"#,
+                escape_attribute(v.as_str())
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    // The load event of the window waits for the iframes
+    let next = match redirect_url {
+        Some(url) => format!(
+            r#"<a id="continue" href="{}">Continue</a>
+<script>window.addEventListener("load", () => location.replace(document.getElementById("continue").href));</script>"#,
+            escape_attribute(url.as_str())
+        ),
+        None => String::new(),
+    };
+
+    let body = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><title>Signed out</title></head>
+<body>
+<p>You are signed out.</p>
+{}
+{}
+</body>
+</html>"#,
+        iframes, next
+    );
+
+    response
+        .content_type("text/html; charset=utf-8")
+        .append_header((header::X_FRAME_OPTIONS, "DENY"))
+        .body(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body::to_bytes;
+    use actix_web::test::{call_and_read_body, init_service, TestRequest};
+    use actix_web::App;
+    use authcare::model::authorization_repository::DbAuthorizationRepository;
+    use authcare::model::client_repository::DbClientRepository;
+    use authcare::model::identity_repository::DbIdentityRepository;
+    use authcare::model::session_repository::DbSessionRepository;
+    use authcare::oauth::id_token::UserInfo;
+    use std::sync::Arc;
+
+    const ISSUER: &str = "https://auth.example.com/api/v1";
+
+    #[test]
+    fn test_is_hinted_session() {
+        let user_id = uuid::Uuid::new_v4().to_string();
+        let sid = uuid::Uuid::new_v4().to_string();
+        let session = JWTClaims::new(user_id.clone(), sid.clone(), String::new());
+
+        let mut id_token = IdTokenClaims {
+            iss: ISSUER.to_string(),
+            aud: "web-app".to_string(),
+            exp: 0,
+            iat: 0,
+            auth_time: None,
+            nonce: None,
+            sid: Some(sid),
+            at_hash: None,
+            user_info: UserInfo {
+                sub: user_id,
+                ..Default::default()
+            },
+        };
+        assert!(is_hinted_session(Some(&id_token), Some(&session)));
+
+        // Without a signed in session, or for another one, the user has to confirm
+        assert!(!is_hinted_session(Some(&id_token), None));
+        assert!(!is_hinted_session(None, Some(&session)));
+        id_token.sid = Some(uuid::Uuid::new_v4().to_string());
+        assert!(!is_hinted_session(Some(&id_token), Some(&session)));
+        id_token.sid = None;
+        assert!(!is_hinted_session(Some(&id_token), Some(&session)));
+    }
+
+    #[actix_web::test]
+    async fn test_frontchannel_logout() -> Result<(), Box<dyn std::error::Error>> {
+        let mut client = OAuthClient::new(vec![], vec![], vec![]);
+        let mut session = Session::new(uuid::Uuid::new_v4(), Some(client.id.clone()), None);
+        session.sso_session_id = Some(uuid::Uuid::new_v4());
+        assert!(frontchannel_logout_url(&client, &session, ISSUER).is_none());
+
+        client.frontchannel_logout_uri = Some("https://app.example.com/logout?v=1".to_string());
+        let url = frontchannel_logout_url(&client, &session, ISSUER).expect("Expect url");
+        let query: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+        assert_eq!(
+            query,
+            vec![
+                ("v".to_string(), "1".to_string()),
+                ("iss".to_string(), ISSUER.to_string()),
+                ("sid".to_string(), session.sso_session_id.unwrap().to_string()),
+            ]
+        );
+
+        // Each client is loaded in a frame before continuing to the relying party
+        let redirect_url = Url::parse("https://app.example.com/?a=1&b=2")?;
+        let iframe = format!(r#"<iframe src="{}""#, escape_attribute(url.as_str()));
+        let response = logged_out_page(HttpResponse::Ok(), &[url], Some(&redirect_url));
+        let body = to_bytes(response.into_body()).await.map_err(|_| "Expect body")?;
+        let body = String::from_utf8(body.to_vec())?;
+        assert!(body.contains(&iframe));
+        assert!(body.contains(r#"href="https://app.example.com/?a=1&amp;b=2""#));
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_logout_confirmation_page() -> Result<(), Box<dyn std::error::Error>> {
+        let request = EndSessionRequestDTO {
+            id_token_hint: Some("id-token".to_string()),
+            client_id: Some("web-app".to_string()),
+            post_logout_redirect_uri: Some("https://app.example.com/".to_string()),
+            state: Some(r#""><script>"#.to_string()),
+            csrf_token: None,
+        };
+
+        // The confirmation posts the parameters of the relying party back
+        let response = logout_confirmation_page(&request);
+        assert_eq!(
+            response.headers().get(header::X_FRAME_OPTIONS).map(|v| v.as_bytes()),
+            Some("DENY".as_bytes())
+        );
+        let csrf_token = response
+            .cookies()
+            .find(|v| v.name() == OAUTH_CSRF_COOKIE)
+            .map(|v| v.value().to_string())
+            .ok_or("Expect csrf cookie")?;
+        let body = to_bytes(response.into_body()).await.map_err(|_| "Expect body")?;
+        let body = String::from_utf8(body.to_vec())?;
+        assert!(body.contains(r#"<input type="hidden" name="id_token_hint" value="id-token">"#));
+        assert!(body.contains(r#"<input type="hidden" name="client_id" value="web-app">"#));
+        assert!(body.contains(r#"name="state" value="&quot;>&lt;script>""#));
+        let csrf_input = format!(r#"name="csrf_token" value="{}">"#, csrf_token);
+        assert!(body.contains(&csrf_input));
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_logout_requires_same_site_post() -> Result<(), Box<dyn std::error::Error>> {
+        // Without a hint or a session nothing is loaded, the repositories are never connected to
+        let pool = sqlx::PgPool::connect_lazy("postgres://localhost/authcare")?;
+        let client_service = ClientService::new(Arc::new(DbClientRepository::new(pool.clone())));
+        let session_repository = Arc::new(DbSessionRepository::new(pool.clone()));
+        let authorization_service = AuthorizationService::new(
+            Arc::new(DbAuthorizationRepository::new(pool.clone())),
+            session_repository.clone(),
+            Arc::new(DbIdentityRepository::new(pool)),
+            client_service.clone(),
+        );
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(authorization_service))
+                .app_data(web::Data::new(client_service))
+                .app_data(web::Data::new(SessionService::new(session_repository)))
+                .service(oauth_logout_handler),
+        )
+        .await;
+
+        // A post from another site is confirmed by the user first
+        let req = TestRequest::post()
+            .uri("/oauth/logout")
+            .set_form([("client_id", "web-app")])
+            .to_request();
+        let body = String::from_utf8(call_and_read_body(&app, req).await.to_vec())?;
+        assert!(body.contains("Do you want to sign out?"));
+
+        let req = TestRequest::post()
+            .uri("/oauth/logout")
+            .cookie(Cookie::new(OAUTH_CSRF_COOKIE, "csrf-token"))
+            .set_form([("client_id", "web-app"), ("csrf_token", "another-token")])
+            .to_request();
+        let body = String::from_utf8(call_and_read_body(&app, req).await.to_vec())?;
+        assert!(body.contains("Do you want to sign out?"));
+
+        // The confirmation page posts the token of its cookie
+        let req = TestRequest::post()
+            .uri("/oauth/logout")
+            .cookie(Cookie::new(OAUTH_CSRF_COOKIE, "csrf-token"))
+            .set_form([("client_id", "web-app"), ("csrf_token", "csrf-token")])
+            .to_request();
+        let body = String::from_utf8(call_and_read_body(&app, req).await.to_vec())?;
+        assert!(body.contains("<title>Signed out</title>"));
+
+        Ok(())
+    }
+}
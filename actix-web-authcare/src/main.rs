@@ -3,12 +3,28 @@
 use actix_web::middleware::Logger;
 use actix_web::{web, App, HttpServer};
 use authcare::config::AppConfig;
-use authcare::model::identity_repository::DbIdentityRepository;
+use authcare::ldap::LdapDirectory;
+use authcare::model::audit_log_repository::DbAuditLogRepository;
+use authcare::model::authorization_repository::DbAuthorizationRepository;
+use authcare::model::client_repository::DbClientRepository;
+use authcare::model::device_authorization_repository::DbDeviceAuthorizationRepository;
+use authcare::model::flow_state_repository::DbFlowStateRepository;
+use authcare::model::identity_repository::{DbIdentityRepository, IdentityRepository};
+use authcare::model::provider_notification_repository::DbProviderNotificationRepository;
+use authcare::model::provider_token_revocation_repository::DbProviderTokenRevocationRepository;
 use authcare::model::refresh_token_repository::DbRefreshTokenRepository;
 use authcare::model::session_repository::DbSessionRepository;
 use authcare::model::user_repository::DbUserRepository;
+use authcare::oauth::logout::LogoutNotifier;
+use authcare::oidc::revocation::{sweep_revocations, ProviderTokenRevoker, ReqwestHttpClient};
 use authcare::service::auth_service::AuthService;
+use authcare::service::authorization_service::AuthorizationService;
+use authcare::service::client_service::ClientService;
+use authcare::service::device_authorization_service::DeviceAuthorizationService;
+use authcare::service::dpop_service::DPoPService;
+use authcare::service::flow_state_service::FlowStateService;
 use authcare::service::session_service::SessionService;
+use authcare::service::token_exchange_service::TokenExchangeService;
 use authcare::service::token_service::TokenService;
 use authcare::service::user_serivce::UserService;
 use dotenv::dotenv;
@@ -24,6 +40,11 @@ async fn main() -> std::io::Result<()> {
 
     dotenv().ok();
 
+    if let Err(err) = AppConfig::validate() {
+        println!("🔥 Invalid configuration: {}", err);
+        std::process::exit(1);
+    }
+
     let pool = match PgPoolOptions::new()
         .max_connections(10)
         .connect(AppConfig::database_url().as_str())
@@ -45,6 +66,25 @@ async fn main() -> std::io::Result<()> {
     let account_repo = Arc::new(DbUserRepository::new(pool.clone()));
     let session_repo = Arc::new(DbSessionRepository::new(pool.clone()));
     let identity_repo = Arc::new(DbIdentityRepository::new(pool.clone()));
+    let flow_state_repo = Arc::new(DbFlowStateRepository::new(pool.clone()));
+    let authorization_repo = Arc::new(DbAuthorizationRepository::new(pool.clone()));
+    let client_repo = Arc::new(DbClientRepository::new(pool.clone()));
+    let device_authorization_repo = Arc::new(DbDeviceAuthorizationRepository::new(pool.clone()));
+    let audit_log_repo = Arc::new(DbAuditLogRepository::new(pool.clone()));
+    let revocation_repo = Arc::new(DbProviderTokenRevocationRepository::new(pool.clone()));
+    let notification_repo = Arc::new(DbProviderNotificationRepository::new(pool.clone()));
+    let token_revoker = Arc::new(ProviderTokenRevoker::new(Arc::new(ReqwestHttpClient::new())));
+
+    if let Some(secret) = AppConfig::provider_token_secret() {
+        if let Err(err) = identity_repo.encrypt_pending_refresh_tokens(&secret).await {
+            println!("🔥 Failed to encrypt provider refresh tokens: {:?}", err);
+        }
+    }
+
+    // Provider tokens of users deleted before a restart are still revoked
+    if let Err(err) = sweep_revocations(token_revoker.clone(), revocation_repo.clone()).await {
+        println!("🔥 Failed to load pending revocations: {:?}", err);
+    }
 
     let token_service = TokenService::new(
         refresh_token_repo.clone(),
@@ -52,14 +92,61 @@ async fn main() -> std::io::Result<()> {
         session_repo.clone(),
     );
 
-    let auth_service = AuthService::new(account_repo.clone());
-    let user_service = UserService::new(account_repo.clone(), identity_repo.clone());
-    let session_service = SessionService::new(session_repo.clone());
+    let user_service = UserService::new(
+        account_repo.clone(),
+        identity_repo.clone(),
+        token_revoker.clone(),
+        revocation_repo.clone(),
+        notification_repo.clone(),
+    );
+    let mut auth_service = AuthService::new(account_repo.clone());
+    if let Some(configuration) = AppConfig::ldap_configuration() {
+        auth_service = auth_service.set_directory(
+            Arc::new(LdapDirectory::new(configuration.clone())),
+            configuration.mode,
+            user_service.clone(),
+        );
+    }
+    let logout_notifier = Arc::new(LogoutNotifier::new(
+        client_repo.clone(),
+        Arc::new(ReqwestHttpClient::new()),
+    ));
+    let session_service =
+        SessionService::new(session_repo.clone()).set_logout_notifier(logout_notifier);
+    let flow_state_service = FlowStateService::new(flow_state_repo.clone());
+    let client_service = ClientService::new(client_repo.clone());
+    if let Err(err) = client_service
+        .save_configured_clients(AppConfig::oauth_clients())
+        .await
+    {
+        println!("🔥 Failed to store the configured clients: {}", err);
+        std::process::exit(1);
+    }
+    let authorization_service = AuthorizationService::new(
+        authorization_repo.clone(),
+        session_repo.clone(),
+        identity_repo.clone(),
+        client_service.clone(),
+    );
+    let device_authorization_service =
+        DeviceAuthorizationService::new(device_authorization_repo.clone());
+    let token_exchange_service = TokenExchangeService::new(
+        account_repo.clone(),
+        session_repo.clone(),
+        audit_log_repo.clone(),
+    );
+    let dpop_service = DPoPService::new().set_public_url(AppConfig::oidc_issuer());
 
     let token_service_data = web::Data::new(token_service);
     let auth_service_data = web::Data::new(auth_service);
     let user_service_data = web::Data::new(user_service);
     let session_service_data = web::Data::new(session_service);
+    let flow_state_service_data = web::Data::new(flow_state_service);
+    let authorization_service_data = web::Data::new(authorization_service);
+    let client_service_data = web::Data::new(client_service);
+    let device_authorization_service_data = web::Data::new(device_authorization_service);
+    let token_exchange_service_data = web::Data::new(token_exchange_service);
+    let dpop_service_data = web::Data::new(dpop_service);
 
     HttpServer::new(move || {
         App::new()
@@ -67,6 +154,12 @@ async fn main() -> std::io::Result<()> {
             .app_data(token_service_data.clone())
             .app_data(user_service_data.clone())
             .app_data(session_service_data.clone())
+            .app_data(flow_state_service_data.clone())
+            .app_data(authorization_service_data.clone())
+            .app_data(client_service_data.clone())
+            .app_data(device_authorization_service_data.clone())
+            .app_data(token_exchange_service_data.clone())
+            .app_data(dpop_service_data.clone())
             .configure(configure_routes)
             .wrap(Logger::default())
     })
@@ -81,7 +174,37 @@ pub fn configure_routes(config: &mut web::ServiceConfig) {
         .service(api::controller::token_handler)
         .service(api::controller::token_info_handler)
         .service(api::controller::signout_handler)
-        .service(api::controller::delete_user_handler);
+        .service(api::controller::delete_user_handler)
+        .service(api::controller::identities_handler)
+        .service(api::controller::link_identity_handler)
+        .service(api::controller::unlink_identity_handler)
+        .service(api::controller::authorize_handler)
+        .service(api::controller::callback_handler)
+        .service(api::controller::callback_form_handler)
+        .service(api::controller::apple_notification_handler)
+        .service(api::controller::sso_handler)
+        .service(api::controller::sso_metadata_handler)
+        .service(api::controller::sso_authorize_handler)
+        .service(api::controller::sso_acs_handler)
+        .service(api::controller::device_code_handler)
+        .service(api::controller::device_handler)
+        .service(api::controller::device_decision_handler)
+        .service(api::oauth_controller::openid_configuration_handler)
+        .service(api::oauth_controller::jwks_handler)
+        .service(api::oauth_controller::oauth_authorize_handler)
+        .service(api::oauth_controller::oauth_par_handler)
+        .service(api::oauth_controller::oauth_login_page_handler)
+        .service(api::oauth_controller::oauth_login_handler)
+        .service(api::oauth_controller::oauth_logout_page_handler)
+        .service(api::oauth_controller::oauth_logout_handler)
+        .service(api::oauth_controller::oauth_token_handler)
+        .service(api::oauth_controller::oauth_introspect_handler)
+        .service(api::oauth_controller::oauth_revoke_handler)
+        .service(api::oauth_controller::oauth_register_handler)
+        .service(api::oauth_controller::oauth_registration_handler)
+        .service(api::oauth_controller::oauth_update_registration_handler)
+        .service(api::oauth_controller::oauth_delete_registration_handler)
+        .service(api::oauth_controller::userinfo_handler);
 
     config.service(scope);
 }
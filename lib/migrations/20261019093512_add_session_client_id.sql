@@ -0,0 +1,3 @@
+-- Record which client (audience) a session was issued for
+
+ALTER TABLE auth_session ADD COLUMN IF NOT EXISTS client_id text NULL;
@@ -0,0 +1,3 @@
+-- Profile data (name, picture) copied from external identities
+
+ALTER TABLE auth_user ADD COLUMN IF NOT EXISTS user_metadata JSONB NOT NULL DEFAULT '{}'::jsonb;
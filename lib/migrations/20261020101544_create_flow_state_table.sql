@@ -0,0 +1,19 @@
+-- Add flow state table
+
+CREATE TABLE IF NOT EXISTS auth_flow_state (
+    id uuid NOT NULL,
+    state text NOT NULL UNIQUE,
+    provider text NOT NULL,
+    code_verifier text NOT NULL,
+    nonce text NOT NULL,
+    redirect_uri text NOT NULL,
+    auth_code text NULL UNIQUE,
+    user_id uuid NULL,
+    client_id text NULL,
+    created_at timestamptz NOT NULL DEFAULT NOW(),
+    updated_at timestamptz NOT NULL DEFAULT NOW(),
+    CONSTRAINT flow_state_pkey PRIMARY KEY (id),
+    CONSTRAINT flow_state_user_id_fkey FOREIGN KEY (user_id) REFERENCES auth_user(id) ON DELETE CASCADE
+);
+
+COMMENT ON TABLE auth_flow_state is 'Auth: Stores pending authorization code flows.';
@@ -0,0 +1,3 @@
+-- Refresh token issued by the provider, e.g. needed to revoke Sign in with Apple
+
+ALTER TABLE identity ADD COLUMN IF NOT EXISTS provider_refresh_token text NULL;
@@ -0,0 +1,18 @@
+-- Provider tokens are stored encrypted, see PROVIDER_TOKEN_SECRET
+-- Refresh tokens stored before are moved aside and encrypted in place on the next startup
+
+CREATE TABLE IF NOT EXISTS pending_provider_token_encryption (
+    identity_id text NOT NULL,
+    provider text NOT NULL,
+    refresh_token text NOT NULL,
+    CONSTRAINT pending_provider_token_encryption_pkey PRIMARY KEY (provider, identity_id)
+);
+
+COMMENT ON TABLE pending_provider_token_encryption is 'Auth: Holds plaintext provider refresh tokens until they are encrypted with PROVIDER_TOKEN_SECRET.';
+
+INSERT INTO pending_provider_token_encryption (identity_id, provider, refresh_token)
+SELECT id, provider, provider_refresh_token FROM identity WHERE provider_refresh_token IS NOT NULL;
+
+UPDATE identity SET provider_refresh_token = NULL;
+ALTER TABLE identity ADD COLUMN IF NOT EXISTS provider_access_token text NULL;
+ALTER TABLE identity ADD COLUMN IF NOT EXISTS provider_token_expires_at timestamptz NULL;
@@ -0,0 +1,23 @@
+-- Add oauth authorization table
+
+CREATE TABLE IF NOT EXISTS oauth_authorization (
+    id uuid NOT NULL,
+    client_id text NOT NULL,
+    redirect_uri text NOT NULL,
+    scope text NOT NULL,
+    state text NULL,
+    nonce text NULL,
+    code_challenge text NULL,
+    code_challenge_method text NULL,
+    code text NULL UNIQUE,
+    user_id uuid NULL,
+    session_id uuid NULL,
+    auth_time timestamptz NULL,
+    expires_at timestamptz NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT NOW(),
+    updated_at timestamptz NOT NULL DEFAULT NOW(),
+    CONSTRAINT oauth_authorization_pkey PRIMARY KEY (id),
+    CONSTRAINT oauth_authorization_user_id_fkey FOREIGN KEY (user_id) REFERENCES auth_user(id) ON DELETE CASCADE
+);
+
+COMMENT ON TABLE oauth_authorization is 'Auth: Stores authorization requests of relying parties and their codes.';
@@ -0,0 +1,16 @@
+-- Add oauth client table
+
+CREATE TABLE IF NOT EXISTS oauth_client (
+    id text NOT NULL,
+    client_secret_hash text NULL,
+    grant_types text[] NOT NULL DEFAULT '{}',
+    scopes text[] NOT NULL DEFAULT '{}',
+    redirect_uris text[] NOT NULL DEFAULT '{}',
+    token_ttl integer NULL,
+    created_at timestamptz NOT NULL DEFAULT NOW(),
+    updated_at timestamptz NOT NULL DEFAULT NOW(),
+    CONSTRAINT oauth_client_pkey PRIMARY KEY (id)
+);
+
+COMMENT ON TABLE oauth_client is 'Auth: Stores OAuth clients, their hashed secrets and allowed grants.';
+COMMENT ON COLUMN oauth_client.token_ttl is 'Access token lifetime in seconds, the default lifetime when NULL.';
@@ -0,0 +1,3 @@
+-- Space separated scopes granted at sign in, kept when the session is refreshed
+
+ALTER TABLE auth_session ADD COLUMN IF NOT EXISTS scope text NULL;
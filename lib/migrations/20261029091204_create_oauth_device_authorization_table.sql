@@ -0,0 +1,20 @@
+-- Add oauth device authorization table
+
+CREATE TABLE IF NOT EXISTS oauth_device_authorization (
+    id uuid NOT NULL,
+    device_code text NOT NULL UNIQUE,
+    user_code text NOT NULL UNIQUE,
+    client_id text NOT NULL,
+    scope text NOT NULL,
+    user_id uuid NULL,
+    approved boolean NULL,
+    "interval" integer NOT NULL,
+    last_polled_at timestamptz NULL,
+    expires_at timestamptz NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT NOW(),
+    updated_at timestamptz NOT NULL DEFAULT NOW(),
+    CONSTRAINT oauth_device_authorization_pkey PRIMARY KEY (id),
+    CONSTRAINT oauth_device_authorization_user_id_fkey FOREIGN KEY (user_id) REFERENCES auth_user(id) ON DELETE CASCADE
+);
+
+COMMENT ON TABLE oauth_device_authorization is 'Auth: Stores device authorization requests of input constrained clients.';
@@ -0,0 +1,15 @@
+-- Add audit log table
+
+CREATE TABLE IF NOT EXISTS auth_audit_log (
+    id uuid NOT NULL,
+    action text NOT NULL,
+    actor text NOT NULL,
+    subject text NULL,
+    client_id text NULL,
+    details jsonb NOT NULL DEFAULT '{}'::jsonb,
+    created_at timestamptz NOT NULL DEFAULT NOW(),
+    CONSTRAINT audit_log_pkey PRIMARY KEY (id)
+);
+
+CREATE INDEX IF NOT EXISTS audit_log_subject_idx on auth_audit_log (subject);
+COMMENT ON TABLE auth_audit_log is 'Auth: Stores security relevant actions, entries outlive their users.';
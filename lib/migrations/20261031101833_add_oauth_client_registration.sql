@@ -0,0 +1,7 @@
+-- Add dynamic client registration metadata
+
+ALTER TABLE oauth_client ADD COLUMN IF NOT EXISTS client_name text NULL;
+ALTER TABLE oauth_client ADD COLUMN IF NOT EXISTS token_endpoint_auth_method text NULL;
+ALTER TABLE oauth_client ADD COLUMN IF NOT EXISTS registration_access_token_hash text NULL;
+
+COMMENT ON COLUMN oauth_client.registration_access_token_hash is 'Hash of the token managing a dynamically registered client, NULL for other clients.';
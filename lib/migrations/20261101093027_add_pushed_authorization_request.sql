@@ -0,0 +1,10 @@
+-- Add pushed authorization requests and request objects
+
+ALTER TABLE oauth_authorization ADD COLUMN IF NOT EXISTS request_uri text NULL UNIQUE;
+ALTER TABLE oauth_authorization ADD COLUMN IF NOT EXISTS prompt text NULL;
+
+ALTER TABLE oauth_client ADD COLUMN IF NOT EXISTS jwks jsonb NULL;
+ALTER TABLE oauth_client ADD COLUMN IF NOT EXISTS require_pushed_authorization_requests boolean NOT NULL DEFAULT false;
+
+COMMENT ON COLUMN oauth_authorization.request_uri is 'Reference of a pushed request, NULL once the user agent used it.';
+COMMENT ON COLUMN oauth_client.jwks is 'Public keys verifying the request objects of the client.';
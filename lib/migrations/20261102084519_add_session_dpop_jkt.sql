@@ -0,0 +1,3 @@
+-- Thumbprint of the DPoP key the refresh tokens of the session are bound to
+
+ALTER TABLE auth_session ADD COLUMN IF NOT EXISTS dpop_jkt text NULL;
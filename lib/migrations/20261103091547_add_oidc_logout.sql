@@ -0,0 +1,11 @@
+-- Add relying party logout
+
+ALTER TABLE oauth_client ADD COLUMN IF NOT EXISTS post_logout_redirect_uris text[] NOT NULL DEFAULT '{}';
+ALTER TABLE oauth_client ADD COLUMN IF NOT EXISTS frontchannel_logout_uri text NULL;
+ALTER TABLE oauth_client ADD COLUMN IF NOT EXISTS backchannel_logout_uri text NULL;
+
+ALTER TABLE auth_session ADD COLUMN IF NOT EXISTS sso_session_id uuid NULL;
+ALTER TABLE auth_session ADD CONSTRAINT auth_session_sso_session_id_fkey FOREIGN KEY (sso_session_id) REFERENCES auth_session(id) ON DELETE CASCADE;
+CREATE INDEX IF NOT EXISTS auth_session_sso_session_id_idx ON auth_session (sso_session_id);
+
+COMMENT ON COLUMN auth_session.sso_session_id is 'Browser session a relying party session was started from, it ends with it.';
@@ -0,0 +1,14 @@
+-- Add provider token revocation table
+
+CREATE TABLE IF NOT EXISTS provider_token_revocation (
+    id uuid NOT NULL,
+    identity_id text NOT NULL,
+    provider text NOT NULL,
+    client_id text NULL,
+    refresh_token text NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT NOW(),
+    CONSTRAINT provider_token_revocation_pkey PRIMARY KEY (id)
+);
+
+COMMENT ON TABLE provider_token_revocation is 'Auth: Stores provider refresh tokens of deleted identities until the provider revoked them.';
+COMMENT ON COLUMN provider_token_revocation.refresh_token is 'Encrypted with PROVIDER_TOKEN_SECRET like on the identity.';
@@ -0,0 +1,11 @@
+-- Add provider notification table
+
+CREATE TABLE IF NOT EXISTS provider_notification (
+    provider text NOT NULL,
+    jti text NOT NULL,
+    expires_at timestamptz NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT NOW(),
+    CONSTRAINT provider_notification_pkey PRIMARY KEY (provider, jti)
+);
+
+COMMENT ON TABLE provider_notification is 'Auth: Stores the ids of handled provider notifications so replays are rejected.';
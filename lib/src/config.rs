@@ -1,17 +1,89 @@
 use crate::constants::JWT_EXPIRED_IN;
 use lazy_static::lazy_static;
 use std::collections::HashMap;
-use crate::oidc::oidc::{OidcProvider};
+use crate::oidc::oauth2::OAuth2Endpoints;
+use crate::oidc::oidc::{OidcError, OidcProvider};
+use crate::oidc::provider::apple::{generate_apple_client_secret, AppleSigningKey};
+use crate::ldap::DirectoryMode;
+use crate::model::user::User;
+use crate::oauth::keys::SigningKey;
+use crate::oauth::{
+    GRANT_TYPE_AUTHORIZATION_CODE, GRANT_TYPE_CLIENT_CREDENTIALS, GRANT_TYPE_DEVICE_CODE,
+    GRANT_TYPE_REFRESH_TOKEN,
+};
+use crate::saml::metadata::IdpMetadata;
+use crate::saml::response::AttributeMapping;
+use thiserror::Error;
 
 lazy_static! {
-    static ref OAUTH_PROVIDERS: HashMap<String, OAuthProviderConfiguration> =
+    static ref OAUTH_PROVIDERS: Result<HashMap<String, OAuthProviderConfiguration>, ConfigError> =
         build_ouath_providers();
+    static ref SAML_CONNECTIONS: Result<HashMap<String, SamlConnection>, ConfigError> =
+        build_saml_connections();
+    static ref SAML_SERVICE_PROVIDER: Result<Option<SamlServiceProvider>, ConfigError> =
+        build_saml_service_provider();
+    static ref SSO_DOMAINS: Result<SsoDomains, ConfigError> =
+        SsoDomains::new(SAML_CONNECTIONS.iter().flat_map(|v| v.values()));
+    static ref LDAP_CONFIGURATION: Result<Option<LdapConfiguration>, ConfigError> =
+        LdapConfiguration::from_env();
+    static ref OIDC_SIGNING_KEY: Result<Option<SigningKey>, ConfigError> = oidc_signing_key();
+    static ref OIDC_ISSUER: Result<Option<String>, ConfigError> = oidc_issuer();
+    static ref DEVICE_VERIFICATION_URI: Result<Option<String>, ConfigError> =
+        device_verification_uri();
+    static ref USER_SCOPES: UserScopes = UserScopes::from_env();
+    static ref OIDC_CLIENTS: Result<HashMap<String, OAuthClientConfiguration>, ConfigError> =
+        build_oauth_clients();
+}
+
+/// Invalid settings, reported at startup instead of on the first request using them
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    #[error("Missing {0} env")]
+    Missing(String),
+
+    #[error("Invalid {0} env: {1}")]
+    Invalid(String, String),
 }
 
 #[derive(Debug, Clone)]
 pub struct AppConfig;
 
 impl AppConfig {
+    /// Loads the lazily read settings, so a misconfiguration stops the server at startup
+    pub fn validate() -> Result<(), ConfigError> {
+        // Sign ins with ID tokens only return no provider tokens to store
+        let providers = OAUTH_PROVIDERS.as_ref().map_err(|e| e.clone())?;
+        let stores_tokens = providers.values().any(|v| v.returns_provider_tokens());
+        if stores_tokens && Self::provider_token_secret().is_none() {
+            return Err(ConfigError::Missing("PROVIDER_TOKEN_SECRET".to_string()));
+        }
+        SAML_CONNECTIONS.as_ref().map_err(|e| e.clone())?;
+        SAML_SERVICE_PROVIDER.as_ref().map_err(|e| e.clone())?;
+        SSO_DOMAINS.as_ref().map_err(|e| e.clone())?;
+        let clients = OIDC_CLIENTS.as_ref().map_err(|e| e.clone())?;
+        LDAP_CONFIGURATION.as_ref().map_err(|e| e.clone())?;
+
+        // Tokens and urls of the OpenID provider carry the issuer
+        let signing_key = OIDC_SIGNING_KEY.as_ref().map_err(|e| e.clone())?;
+        let issuer = OIDC_ISSUER.as_ref().map_err(|e| e.clone())?;
+        let is_provider = signing_key.is_some()
+            || !clients.is_empty()
+            || Self::client_registration_token().is_some();
+        if is_provider && issuer.is_none() {
+            return Err(ConfigError::Missing("OIDC_ISSUER".to_string()));
+        }
+
+        // Device authorization responses point the user to the verification page
+        let verification_uri = DEVICE_VERIFICATION_URI.as_ref().map_err(|e| e.clone())?;
+        let has_device_clients = clients
+            .values()
+            .any(|v| v.grant_types.iter().any(|grant| grant == GRANT_TYPE_DEVICE_CODE));
+        if has_device_clients && verification_uri.is_none() {
+            return Err(ConfigError::Missing("DEVICE_VERIFICATION_URI".to_string()));
+        }
+        Ok(())
+    }
+
     pub fn database_url() -> String {
         std::env::var("DATABASE_URL").expect("DATABASE_URL must be set")
     }
@@ -28,47 +100,739 @@ impl AppConfig {
 
     pub fn provider_configuration(provider: &OidcProvider) -> Option<&'static OAuthProviderConfiguration> {
         OAUTH_PROVIDERS
-            .get(provider.name())
-            // .map(|v| v.clone())
+            .as_ref()
+            .ok()
+            .and_then(|v| v.get(provider.name()))
+    }
+
+    pub fn account_linking_policy(provider: &OidcProvider) -> AccountLinkingPolicy {
+        Self::provider_configuration(provider)
+            .map(|v| v.linking_policy)
+            .unwrap_or_default()
+    }
+
+    /// Redirect uris the authorization code flow may send users back to
+    pub fn redirect_allowlist() -> Vec<String> {
+        std::env::var("AUTH_REDIRECT_ALLOWLIST")
+            .map(|val| parse_list(&val))
+            .unwrap_or_default()
+    }
+
+    /// Secret encrypting provider tokens at rest, provider tokens are not stored without it
+    pub fn provider_token_secret() -> Option<String> {
+        std::env::var("PROVIDER_TOKEN_SECRET").ok()
+    }
+
+    pub fn sync_user_profile(provider: &OidcProvider) -> bool {
+        Self::provider_configuration(provider).is_some_and(|v| v.sync_profile)
+    }
+
+    /// Settings of authcare as a SAML service provider, set whenever connections are configured
+    pub fn saml_service_provider() -> Option<&'static SamlServiceProvider> {
+        SAML_SERVICE_PROVIDER.as_ref().ok().and_then(|v| v.as_ref())
+    }
+
+    pub fn saml_connection(name: &str) -> Option<&'static SamlConnection> {
+        SAML_CONNECTIONS.as_ref().ok().and_then(|v| v.get(name))
+    }
+
+    /// Directory verifying passwords, enabled by `LDAP_URL`
+    pub fn ldap_configuration() -> Option<&'static LdapConfiguration> {
+        LDAP_CONFIGURATION.as_ref().ok().and_then(|v| v.as_ref())
+    }
+
+    /// SSO connection that owns the email domain, e.g. `acme.com`
+    pub fn sso_connection_for_domain(domain: &str) -> Option<&'static SamlConnection> {
+        Self::sso_domains().and_then(|v| v.connection(domain))
+    }
+
+    pub fn sso_domains() -> Option<&'static SsoDomains> {
+        SSO_DOMAINS.as_ref().ok()
+    }
+
+    /// Issuer of the ID tokens, the public url of the api, e.g. `https://auth.example.com/api/v1`
+    ///
+    /// Checked on startup when the OpenID provider is used, empty otherwise. DPoP proofs are
+    /// verified against urls under it, so they are rejected when it isn't set.
+    pub fn oidc_issuer() -> String {
+        OIDC_ISSUER
+            .as_ref()
+            .ok()
+            .and_then(|v| v.clone())
+            .unwrap_or_default()
+    }
+
+    /// Scopes users may be granted, `SUPER_USER_SCOPES` are added for super users
+    pub fn user_scopes(user: &User) -> Vec<String> {
+        USER_SCOPES.for_user(user)
+    }
+
+    /// Scopes read once at startup, services keep a copy of them
+    pub fn user_scope_configuration() -> &'static UserScopes {
+        &USER_SCOPES
+    }
+
+    /// Key signing the ID tokens, the OpenID provider is disabled without it
+    pub fn oidc_signing_key() -> Option<&'static SigningKey> {
+        OIDC_SIGNING_KEY.as_ref().ok().and_then(|v| v.as_ref())
+    }
+
+    /// Relying parties registered with `OIDC_CLIENTS`, stored in the client registry on startup
+    pub fn oauth_clients() -> impl Iterator<Item = &'static OAuthClientConfiguration> {
+        OIDC_CLIENTS.iter().flat_map(|v| v.values())
+    }
+
+    /// Initial access token partners register clients with, registration is disabled without it
+    pub fn client_registration_token() -> Option<String> {
+        std::env::var("CLIENT_REGISTRATION_TOKEN").ok()
+    }
+
+    /// Scopes dynamically registered clients may be granted
+    pub fn client_registration_scopes() -> Vec<String> {
+        std::env::var("CLIENT_REGISTRATION_SCOPES")
+            .map(|val| parse_list(&val))
+            .unwrap_or_default()
+    }
+
+    /// Page where signed in users enter the user code of a device, e.g. `https://example.com/device`
+    ///
+    /// Checked on startup when a configured client uses the device flow, `None` when unset.
+    pub fn device_verification_uri() -> Option<String> {
+        DEVICE_VERIFICATION_URI.as_ref().ok().and_then(|v| v.clone())
+    }
+}
+
+/// Decides what happens when an external identity matches the email of an existing account
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountLinkingPolicy {
+    /// Link the identity when both the provider and the existing account verified the email
+    #[default]
+    AutoLinkVerified,
+    /// Never link, the sign in is rejected
+    Never,
+    /// Link only through an explicit request of the signed in user
+    RequireSignedIn,
+}
+
+impl AccountLinkingPolicy {
+    fn from_env(key: &str) -> Result<Self, ConfigError> {
+        Self::from_env_or(key, AccountLinkingPolicy::default())
+    }
+
+    fn from_env_or(key: &str, default: AccountLinkingPolicy) -> Result<Self, ConfigError> {
+        let Ok(value) = std::env::var(key) else {
+            return Ok(default);
+        };
+
+        value
+            .parse()
+            .map_err(|_| ConfigError::Invalid(key.to_string(), value))
+    }
+}
+
+impl std::str::FromStr for AccountLinkingPolicy {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "auto_link_verified" => Ok(AccountLinkingPolicy::AutoLinkVerified),
+            "never" => Ok(AccountLinkingPolicy::Never),
+            "require_signed_in" => Ok(AccountLinkingPolicy::RequireSignedIn),
+            _ => Err(()),
+        }
     }
 }
 
 #[derive(Clone, serde::Deserialize)]
 pub struct OAuthProviderConfiguration {
+    /// Primary client id, used when acting as a client of the provider
     pub client_id: String,
+    /// Every client id (audience) accepted in ID tokens issued by the provider
+    pub audiences: Vec<String>,
     pub secret: Option<String>,
+    /// Our callback url registered with the provider, used by the authorization code flow
     pub redirect_uri: Option<String>,
+    /// Overrides the discovery issuer, e.g. to run against a local mock IdP
+    pub issuer_url: Option<String>,
+    /// Endpoints of providers without ID tokens, e.g. a self-hosted GitLab
+    pub oauth2_endpoints: Option<OAuth2Endpoints>,
+    /// Key used to generate the Apple client secret, replaces `secret`
+    pub apple_signing_key: Option<AppleSigningKey>,
+    pub linking_policy: AccountLinkingPolicy,
+    /// Copy profile changes (name, picture) to the user on every sign in
+    pub sync_profile: bool,
+    /// Google Workspace domains allowed to sign in, empty allows every account
+    pub hosted_domains: Vec<String>,
 }
 
 impl OAuthProviderConfiguration {
-    fn new(client_id: &str, secret: Option<String>) -> Self {
-        Self {
-            client_id: client_id.to_string(),
+    pub(crate) fn new(client_ids: &str, secret: Option<String>) -> Result<Self, ConfigError> {
+        let audiences = parse_list(client_ids);
+        let Some(client_id) = audiences.first().cloned() else {
+            return Err(ConfigError::Invalid("client id".to_string(), client_ids.to_string()));
+        };
+
+        Ok(Self {
+            client_id,
+            audiences,
             secret: secret,
             redirect_uri: None,
+            issuer_url: None,
+            oauth2_endpoints: None,
+            apple_signing_key: None,
+            linking_policy: AccountLinkingPolicy::default(),
+            sync_profile: false,
+            hosted_domains: Vec::new(),
+        })
+    }
+
+    /// Reads the optional provider settings, e.g. `OAUTH_GOOGLE_LINKING_POLICY`
+    fn with_env_options(mut self, provider: &OidcProvider) -> Result<Self, ConfigError> {
+        let prefix = env_prefix(provider);
+        self.redirect_uri = std::env::var(format!("{}_REDIRECT_URI", prefix)).ok();
+        self.issuer_url = std::env::var(format!("{}_ISSUER_URL", prefix)).ok();
+        self.oauth2_endpoints = OAuth2Endpoints::for_provider(provider).map(|mut endpoints| {
+            let env = |name: &str| std::env::var(format!("{}_{}", prefix, name)).ok();
+            endpoints.auth_url = env("AUTH_URL").unwrap_or(endpoints.auth_url);
+            endpoints.token_url = env("TOKEN_URL").unwrap_or(endpoints.token_url);
+            endpoints.userinfo_url = env("USERINFO_URL").unwrap_or(endpoints.userinfo_url);
+            endpoints.emails_url = env("EMAILS_URL").or(endpoints.emails_url);
+            endpoints
+        });
+        self.linking_policy = AccountLinkingPolicy::from_env(&format!("{}_LINKING_POLICY", prefix))?;
+        self.sync_profile = std::env::var(format!("{}_SYNC_PROFILE", prefix))
+            .map(|v| v == "true")
+            .unwrap_or(false);
+        self.hosted_domains = std::env::var(format!("{}_HOSTED_DOMAINS", prefix))
+            .map(|v| parse_list(&v.to_lowercase()))
+            .unwrap_or_default();
+        Ok(self)
+    }
+
+    /// Whether sign ins redeem authorization codes, the code flow and native Apple apps get
+    /// provider tokens that are stored
+    pub fn returns_provider_tokens(&self) -> bool {
+        self.redirect_uri.is_some()
+            || self.oauth2_endpoints.is_some()
+            || self.apple_signing_key.is_some()
+    }
+
+    /// Client secret sent to the provider token endpoint, generated when signing with a key
+    pub fn client_secret(&self) -> Result<Option<String>, OidcError> {
+        self.client_secret_for(&self.client_id)
+    }
+
+    /// Client secret of one of the audiences, e.g. the bundle id of a native Apple app
+    pub fn client_secret_for(&self, client_id: &str) -> Result<Option<String>, OidcError> {
+        match &self.apple_signing_key {
+            Some(key) => Ok(Some(generate_apple_client_secret(key, client_id)?)),
+            None => Ok(self.secret.clone()),
+        }
+    }
+}
+
+/// Enterprise SAML IdP, identities are stored with the `sso:<name>` provider
+#[derive(Debug, Clone)]
+pub struct SamlConnection {
+    pub name: String,
+    pub idp: IdpMetadata,
+    pub attribute_mapping: AttributeMapping,
+    pub linking_policy: AccountLinkingPolicy,
+    pub sync_profile: bool,
+    /// Email domains routed to this connection
+    pub domains: Vec<String>,
+    /// Block password sign in for the domains, users must go through the IdP
+    pub require_sso: bool,
+}
+
+impl SamlConnection {
+    /// Reads the connection settings, e.g. `SAML_ACME_METADATA_PATH`
+    fn from_env(name: &str) -> Result<Self, ConfigError> {
+        let prefix = format!("SAML_{}", name.to_uppercase().replace('-', "_"));
+        let env = |key: &str| std::env::var(format!("{}_{}", prefix, key)).ok();
+
+        let metadata = match (env("METADATA"), env("METADATA_PATH")) {
+            (Some(metadata), _) => metadata,
+            (None, Some(path)) => std::fs::read_to_string(&path).map_err(|e| {
+                ConfigError::Invalid(format!("{}_METADATA_PATH", prefix), e.to_string())
+            })?,
+            (None, None) => {
+                return Err(ConfigError::Missing(format!(
+                    "{}_METADATA or {}_METADATA_PATH",
+                    prefix, prefix
+                )))
+            }
+        };
+
+        let idp = IdpMetadata::parse(&metadata)
+            .map_err(|e| ConfigError::Invalid(format!("{}_METADATA", prefix), e.to_string()))?;
+
+        let default_mapping = AttributeMapping::default();
+        let attribute_mapping = AttributeMapping {
+            email: env("EMAIL_ATTRIBUTE").unwrap_or(default_mapping.email),
+            name: env("NAME_ATTRIBUTE").unwrap_or(default_mapping.name),
+            given_name: env("GIVEN_NAME_ATTRIBUTE").unwrap_or(default_mapping.given_name),
+            family_name: env("FAMILY_NAME_ATTRIBUTE").unwrap_or(default_mapping.family_name),
+        };
+
+        Ok(Self {
+            name: name.to_string(),
+            idp,
+            attribute_mapping,
+            // The IdP asserts emails of any domain, linking by email is opt-in
+            linking_policy: AccountLinkingPolicy::from_env_or(
+                &format!("{}_LINKING_POLICY", prefix),
+                AccountLinkingPolicy::RequireSignedIn,
+            )?,
+            sync_profile: env("SYNC_PROFILE").is_some_and(|v| v == "true"),
+            domains: env("DOMAINS")
+                .map(|v| parse_list(&v.to_lowercase()))
+                .unwrap_or_default(),
+            require_sso: env("REQUIRE_SSO").is_some_and(|v| v == "true"),
+        })
+    }
+
+    /// Provider name of the identities created by this connection
+    pub fn identity_provider(&self) -> String {
+        crate::saml::identity_provider(&self.name)
+    }
+}
+
+/// Routes email domains to the SSO connection that owns them
+#[derive(Debug, Clone, Default)]
+pub struct SsoDomains {
+    connections: HashMap<String, SamlConnection>,
+}
+
+impl SsoDomains {
+    /// Fails when two connections claim the same domain
+    pub fn new<'a>(
+        connections: impl IntoIterator<Item = &'a SamlConnection>,
+    ) -> Result<Self, ConfigError> {
+        let mut hash_map: HashMap<String, SamlConnection> = HashMap::new();
+
+        for connection in connections {
+            for domain in &connection.domains {
+                if let Some(other) = hash_map.insert(domain.clone(), connection.clone()) {
+                    return Err(ConfigError::Invalid(
+                        format!("SAML_{}_DOMAINS", connection.name.to_uppercase()),
+                        format!("{} is claimed by the {} connection too", domain, other.name),
+                    ));
+                }
+            }
+        }
+
+        Ok(Self {
+            connections: hash_map,
+        })
+    }
+
+    pub fn connection(&self, domain: &str) -> Option<&SamlConnection> {
+        self.connections.get(&domain.to_lowercase())
+    }
+
+    /// Connection that users of the email domain must sign in with, password sign in is blocked
+    pub fn required_connection(&self, email: &str) -> Option<&SamlConnection> {
+        crate::saml::email_domain(email)
+            .and_then(|v| self.connection(&v))
+            .filter(|v| v.require_sso)
+    }
+}
+
+/// Identifies authcare to the SAML IdPs
+#[derive(Debug, Clone)]
+pub struct SamlServiceProvider {
+    pub entity_id: String,
+    /// Assertion consumer service url the IdPs post responses to
+    pub acs_url: String,
+}
+
+/// LDAP or Active Directory server verifying passwords of the password grant
+#[derive(Debug, Clone)]
+pub struct LdapConfiguration {
+    pub url: String,
+    pub starttls: bool,
+    /// Service account searching the user entry, anonymous search without it
+    pub bind_dn: Option<String>,
+    pub bind_password: Option<String>,
+    pub base_dn: String,
+    /// Search filter of the user entry, `{username}` is replaced by the escaped username
+    pub user_filter: String,
+    /// Binds directly as e.g. `uid={username},ou=people,dc=example,dc=com`, skipping the search
+    pub user_dn_template: Option<String>,
+    /// Stable attribute identifying the user, e.g. `objectGUID` on Active Directory
+    pub id_attribute: String,
+    pub attribute_mapping: AttributeMapping,
+    pub mode: DirectoryMode,
+    pub linking_policy: AccountLinkingPolicy,
+    pub sync_profile: bool,
+}
+
+impl LdapConfiguration {
+    fn from_env() -> Result<Option<Self>, ConfigError> {
+        let Ok(url) = std::env::var("LDAP_URL") else {
+            return Ok(None);
+        };
+        let env = |key: &str| std::env::var(format!("LDAP_{}", key)).ok();
+
+        let user_dn_template = env("USER_DN_TEMPLATE");
+        let base_dn = match (env("BASE_DN"), &user_dn_template) {
+            (Some(base_dn), _) => base_dn,
+            (None, Some(_)) => String::new(),
+            (None, None) => {
+                return Err(ConfigError::Missing(
+                    "LDAP_BASE_DN or LDAP_USER_DN_TEMPLATE".to_string(),
+                ))
+            }
+        };
+
+        let mode = match env("MODE").as_deref() {
+            None | Some("fallback") => DirectoryMode::Fallback,
+            Some("exclusive") => DirectoryMode::Exclusive,
+            Some(value) => {
+                return Err(ConfigError::Invalid("LDAP_MODE".to_string(), value.to_string()))
+            }
+        };
+
+        Ok(Some(Self {
+            url,
+            starttls: env("STARTTLS").is_some_and(|v| v == "true"),
+            bind_dn: env("BIND_DN"),
+            bind_password: env("BIND_PASSWORD"),
+            base_dn,
+            user_filter: env("USER_FILTER").unwrap_or("(mail={username})".to_string()),
+            user_dn_template,
+            id_attribute: env("ID_ATTRIBUTE").unwrap_or("entryUUID".to_string()),
+            attribute_mapping: AttributeMapping {
+                email: env("EMAIL_ATTRIBUTE").unwrap_or("mail".to_string()),
+                name: env("NAME_ATTRIBUTE").unwrap_or("cn".to_string()),
+                given_name: env("GIVEN_NAME_ATTRIBUTE").unwrap_or("givenName".to_string()),
+                family_name: env("FAMILY_NAME_ATTRIBUTE").unwrap_or("sn".to_string()),
+            },
+            mode,
+            linking_policy: AccountLinkingPolicy::from_env("LDAP_LINKING_POLICY")?,
+            sync_profile: env("SYNC_PROFILE").is_some_and(|v| v == "true"),
+        }))
+    }
+}
+
+/// Scopes of `USER_SCOPES` and `SUPER_USER_SCOPES`
+#[derive(Debug, Clone, Default)]
+pub struct UserScopes {
+    pub user: Vec<String>,
+    pub super_user: Vec<String>,
+}
+
+impl UserScopes {
+    fn from_env() -> Self {
+        let env = |key: &str| std::env::var(key).map(|v| parse_list(&v)).unwrap_or_default();
+        Self {
+            user: env("USER_SCOPES"),
+            super_user: env("SUPER_USER_SCOPES"),
+        }
+    }
+
+    pub fn for_user(&self, user: &User) -> Vec<String> {
+        let mut scopes = self.user.clone();
+        if user.is_super_user == Some(true) {
+            scopes.extend(self.super_user.iter().cloned());
+        }
+
+        scopes
+    }
+}
+
+/// Relying party using authcare as its OpenID provider
+#[derive(Debug, Clone)]
+pub struct OAuthClientConfiguration {
+    pub client_id: String,
+    /// Public clients have no secret and must use PKCE
+    pub secret: Option<String>,
+    /// Exact redirect uris the authorization response may be sent to
+    pub redirect_uris: Vec<String>,
+    pub post_logout_redirect_uris: Vec<String>,
+    pub grant_types: Vec<String>,
+    pub scopes: Vec<String>,
+}
+
+impl OAuthClientConfiguration {
+    /// Reads the client settings, e.g. `OIDC_CLIENT_DASHBOARD_REDIRECT_URIS`
+    fn from_env(client_id: &str) -> Result<Self, ConfigError> {
+        let prefix = format!("OIDC_CLIENT_{}", client_id.to_uppercase().replace(['-', '.'], "_"));
+        let env = |key: &str| std::env::var(format!("{}_{}", prefix, key)).ok();
+
+        let Some(redirect_uris) = env("REDIRECT_URIS").map(|v| parse_list(&v)) else {
+            return Err(ConfigError::Missing(format!("{}_REDIRECT_URIS", prefix)));
+        };
+
+        let grant_types = env("GRANT_TYPES")
+            .map(|v| parse_list(&v))
+            .unwrap_or(vec![
+                GRANT_TYPE_AUTHORIZATION_CODE.to_string(),
+                GRANT_TYPE_REFRESH_TOKEN.to_string(),
+            ]);
+        let secret = env("SECRET");
+
+        // A public client can't authenticate as itself
+        if secret.is_none() && grant_types.iter().any(|v| v == GRANT_TYPE_CLIENT_CREDENTIALS) {
+            return Err(ConfigError::Missing(format!("{}_SECRET", prefix)));
         }
+
+        Ok(Self {
+            client_id: client_id.to_string(),
+            secret,
+            redirect_uris,
+            post_logout_redirect_uris: env("POST_LOGOUT_REDIRECT_URIS")
+                .map(|v| parse_list(&v))
+                .unwrap_or_default(),
+            grant_types,
+            scopes: env("SCOPES").map(|v| parse_list(&v)).unwrap_or_default(),
+        })
     }
 }
-fn build_ouath_providers() -> HashMap<String, OAuthProviderConfiguration> {
+
+/// Reads the ID token signing key, given inline or as a PEM file path
+fn oidc_signing_key() -> Result<Option<SigningKey>, ConfigError> {
+    let (name, pem) = match std::env::var("OIDC_SIGNING_KEY") {
+        Ok(pem) => ("OIDC_SIGNING_KEY", pem.replace("\\n", "\n")),
+        Err(_) => {
+            let Ok(path) = std::env::var("OIDC_SIGNING_KEY_PATH") else {
+                return Ok(None);
+            };
+            let pem = std::fs::read_to_string(&path).map_err(|e| {
+                ConfigError::Invalid("OIDC_SIGNING_KEY_PATH".to_string(), e.to_string())
+            })?;
+            ("OIDC_SIGNING_KEY_PATH", pem)
+        }
+    };
+
+    SigningKey::from_pem(&pem)
+        .map(Some)
+        .map_err(|e| ConfigError::Invalid(name.to_string(), e.to_string()))
+}
+
+/// Reads the public url of the api, it must be absolute
+fn oidc_issuer() -> Result<Option<String>, ConfigError> {
+    let Ok(issuer) = std::env::var("OIDC_ISSUER") else {
+        return Ok(None);
+    };
+    openidconnect::url::Url::parse(&issuer)
+        .map_err(|e| ConfigError::Invalid("OIDC_ISSUER".to_string(), e.to_string()))?;
+
+    Ok(Some(issuer))
+}
+
+/// Reads the page users enter the device user codes on, it must be absolute
+fn device_verification_uri() -> Result<Option<String>, ConfigError> {
+    let Ok(uri) = std::env::var("DEVICE_VERIFICATION_URI") else {
+        return Ok(None);
+    };
+    openidconnect::url::Url::parse(&uri)
+        .map_err(|e| ConfigError::Invalid("DEVICE_VERIFICATION_URI".to_string(), e.to_string()))?;
+
+    Ok(Some(uri))
+}
+
+/// Reads the Apple key settings, the private key is given inline or as a .p8 file path
+fn apple_signing_key() -> Result<Option<AppleSigningKey>, ConfigError> {
+    let Ok(team_id) = std::env::var("OAUTH_APPLE_TEAM_ID") else {
+        return Ok(None);
+    };
+
+    let Ok(key_id) = std::env::var("OAUTH_APPLE_KEY_ID") else {
+        return Err(ConfigError::Missing("OAUTH_APPLE_KEY_ID".to_string()));
+    };
+
+    let private_key = match std::env::var("OAUTH_APPLE_PRIVATE_KEY") {
+        Ok(private_key) => private_key.replace("\\n", "\n"),
+        Err(_) => {
+            let Ok(path) = std::env::var("OAUTH_APPLE_PRIVATE_KEY_PATH") else {
+                return Err(ConfigError::Missing(
+                    "OAUTH_APPLE_PRIVATE_KEY or OAUTH_APPLE_PRIVATE_KEY_PATH".to_string(),
+                ));
+            };
+
+            std::fs::read_to_string(&path).map_err(|e| {
+                ConfigError::Invalid("OAUTH_APPLE_PRIVATE_KEY_PATH".to_string(), e.to_string())
+            })?
+        }
+    };
+
+    Ok(Some(AppleSigningKey {
+        team_id,
+        key_id,
+        private_key,
+    }))
+}
+
+fn env_prefix(provider: &OidcProvider) -> String {
+    format!("OAUTH_{}", provider.name().to_uppercase())
+}
+
+fn build_oauth_clients() -> Result<HashMap<String, OAuthClientConfiguration>, ConfigError> {
+    std::env::var("OIDC_CLIENTS")
+        .map(|v| parse_list(&v))
+        .unwrap_or_default()
+        .into_iter()
+        .map(|client_id| Ok((client_id.clone(), OAuthClientConfiguration::from_env(&client_id)?)))
+        .collect()
+}
+
+/// Parses a comma separated env value, e.g. `ios.client.id,web.client.id`
+fn parse_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .map(|v| v.to_string())
+        .collect()
+}
+
+fn build_ouath_providers() -> Result<HashMap<String, OAuthProviderConfiguration>, ConfigError> {
     let mut hash_map = HashMap::new();
 
     if let Ok(client_id) = std::env::var("OAUTH_APPLE_CLIENT_ID") {
-        hash_map.insert(
-            OidcProvider::Apple.name().to_string(),
-            OAuthProviderConfiguration::new(&client_id, None),
-        );
+        let mut configuration = OAuthProviderConfiguration::new(&client_id, None)?
+            .with_env_options(&OidcProvider::Apple)?;
+        configuration.apple_signing_key = apple_signing_key()?;
+
+        hash_map.insert(OidcProvider::Apple.name().to_string(), configuration);
     };
 
     if let Ok(client_id) = std::env::var("OAUTH_GOOGLE_CLIENT_ID") {
         let Ok(secret) = std::env::var("OAUTH_GOOGLE_SECRET") else {
-            panic!("Missing OAUTH_GOOGLE_SECRET env")
+            return Err(ConfigError::Missing("OAUTH_GOOGLE_SECRET".to_string()));
         };
 
         hash_map.insert(
             OidcProvider::Google.name().to_string(),
-            OAuthProviderConfiguration::new(&client_id, Some(secret)),
+            OAuthProviderConfiguration::new(&client_id, Some(secret))?
+                .with_env_options(&OidcProvider::Google)?,
         );
     };
 
-    hash_map
+    for provider in [OidcProvider::GitHub, OidcProvider::GitLab, OidcProvider::Discord] {
+        let prefix = env_prefix(&provider);
+        let Ok(client_id) = std::env::var(format!("{}_CLIENT_ID", prefix)) else {
+            continue;
+        };
+
+        let Ok(secret) = std::env::var(format!("{}_SECRET", prefix)) else {
+            return Err(ConfigError::Missing(format!("{}_SECRET", prefix)));
+        };
+
+        hash_map.insert(
+            provider.name().to_string(),
+            OAuthProviderConfiguration::new(&client_id, Some(secret))?.with_env_options(&provider)?,
+        );
+    }
+
+    Ok(hash_map)
+}
+
+fn build_saml_connections() -> Result<HashMap<String, SamlConnection>, ConfigError> {
+    std::env::var("SAML_CONNECTIONS")
+        .map(|v| parse_list(&v))
+        .unwrap_or_default()
+        .into_iter()
+        .map(|name| Ok((name.clone(), SamlConnection::from_env(&name)?)))
+        .collect()
+}
+
+fn build_saml_service_provider() -> Result<Option<SamlServiceProvider>, ConfigError> {
+    if SAML_CONNECTIONS.as_ref().map_or(true, |v| v.is_empty()) {
+        return Ok(None);
+    }
+
+    let Ok(entity_id) = std::env::var("SAML_SP_ENTITY_ID") else {
+        return Err(ConfigError::Missing("SAML_SP_ENTITY_ID".to_string()));
+    };
+    let Ok(acs_url) = std::env::var("SAML_ACS_URL") else {
+        return Err(ConfigError::Missing("SAML_ACS_URL".to_string()));
+    };
+    openidconnect::url::Url::parse(&acs_url)
+        .map_err(|e| ConfigError::Invalid("SAML_ACS_URL".to_string(), e.to_string()))?;
+
+    Ok(Some(SamlServiceProvider { entity_id, acs_url }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_provider_audiences() {
+        let configuration = OAuthProviderConfiguration::new("ios.client.id, web.client.id", None)
+            .expect("Expect configuration");
+        assert_eq!(configuration.client_id, "ios.client.id");
+        assert_eq!(configuration.audiences, vec!["ios.client.id", "web.client.id"]);
+
+        assert!(matches!(
+            OAuthProviderConfiguration::new(" , ", None),
+            Err(ConfigError::Invalid(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_returns_provider_tokens() {
+        // ID tokens only, no PROVIDER_TOKEN_SECRET needed
+        let mut configuration = OAuthProviderConfiguration::new("web.client.id", None)
+            .expect("Expect configuration");
+        assert!(!configuration.returns_provider_tokens());
+
+        configuration.redirect_uri = Some("https://auth.example.com/callback".to_string());
+        assert!(configuration.returns_provider_tokens());
+
+        let mut configuration = OAuthProviderConfiguration::new("github.client.id", None)
+            .expect("Expect configuration");
+        configuration.oauth2_endpoints = OAuth2Endpoints::for_provider(&OidcProvider::GitHub);
+        assert!(configuration.returns_provider_tokens());
+    }
+
+    #[test]
+    fn test_account_linking_policy() {
+        assert_eq!("never".parse(), Ok(AccountLinkingPolicy::Never));
+        assert_eq!("require_signed_in".parse(), Ok(AccountLinkingPolicy::RequireSignedIn));
+        assert_eq!("always".parse::<AccountLinkingPolicy>(), Err(()));
+        assert_eq!(
+            AccountLinkingPolicy::from_env_or("UNSET_LINKING_POLICY", AccountLinkingPolicy::Never),
+            Ok(AccountLinkingPolicy::Never)
+        );
+    }
+
+    fn saml_connection(name: &str, domains: &[&str], require_sso: bool) -> SamlConnection {
+        SamlConnection {
+            name: name.to_string(),
+            idp: IdpMetadata::parse(include_str!("saml/fixtures/idp_metadata.xml"))
+                .expect("Expect metadata"),
+            attribute_mapping: AttributeMapping::default(),
+            linking_policy: AccountLinkingPolicy::RequireSignedIn,
+            sync_profile: false,
+            domains: domains.iter().map(|v| v.to_string()).collect(),
+            require_sso,
+        }
+    }
+
+    #[test]
+    fn test_sso_domains() {
+        let acme = saml_connection("acme", &["acme.com", "acme.org"], true);
+        let other = saml_connection("other", &["other.com"], false);
+        let sso_domains = SsoDomains::new([&acme, &other]).expect("Expect domains");
+
+        assert_eq!(sso_domains.connection("ACME.org").map(|v| v.name.as_str()), Some("acme"));
+        assert_eq!(sso_domains.connection("other.com").map(|v| v.name.as_str()), Some("other"));
+        assert!(sso_domains.connection("gmail.com").is_none());
+
+        let required = sso_domains.required_connection("Jane@Acme.com");
+        assert_eq!(required.map(|v| v.name.as_str()), Some("acme"));
+        assert!(sso_domains.required_connection("jane@other.com").is_none());
+
+        let duplicate = saml_connection("duplicate", &["acme.com"], false);
+        assert!(matches!(
+            SsoDomains::new([&acme, &duplicate]),
+            Err(ConfigError::Invalid(_, _))
+        ));
+    }
 }
@@ -1,5 +1,28 @@
 pub const JWT_AUD_CLAIM: &str = "user";
+pub const JWT_CLIENT_AUD_CLAIM: &str = "client";
 pub const JWT_ISS_CLAIM: &str = "authcare-v1";
 pub const JWT_EXPIRED_IN: i64 = 60; //Minutes
+pub const FLOW_STATE_EXPIRED_IN: i64 = 10; //Minutes
+pub const APPLE_CLIENT_SECRET_EXPIRED_IN: i64 = 5; //Minutes
+pub const APPLE_NOTIFICATION_EXPIRED_IN: i64 = 60; //Minutes
+pub const REVOCATION_MAX_ATTEMPTS: u32 = 5;
+pub const SAML_CLOCK_SKEW: i64 = 3; //Minutes
+
+pub const OAUTH_AUTHORIZATION_EXPIRED_IN: i64 = 10; //Minutes
+pub const OAUTH_CODE_EXPIRED_IN: i64 = 1; //Minutes
+pub const OAUTH_REQUEST_URI_EXPIRED_IN: i64 = 60; //Seconds
+pub const OAUTH_REQUEST_OBJECT_EXPIRED_IN: i64 = 60; //Minutes
+pub const OAUTH_SESSION_EXPIRED_IN: i64 = 720; //Minutes
+pub const OAUTH_SESSION_AUD_CLAIM: &str = "session";
+pub const OAUTH_SESSION_COOKIE: &str = "authcare_session";
+pub const OAUTH_CSRF_COOKIE: &str = "authcare_csrf";
+pub const DEVICE_CODE_EXPIRED_IN: i64 = 10; //Minutes
+pub const DEVICE_CODE_INTERVAL: i32 = 5; //Seconds
+pub const DEVICE_USER_CODE_MAX_ATTEMPTS: u32 = 5;
+pub const DEVICE_USER_CODE_ATTEMPTS_RESET_IN: i64 = 10; //Minutes
+pub const TOKEN_EXCHANGE_EXPIRED_IN: i64 = 15; //Minutes
+pub const DPOP_PROOF_EXPIRED_IN: i64 = 60; //Seconds
+pub const LOGOUT_TOKEN_EXPIRED_IN: i64 = 2; //Minutes
+pub const LOGOUT_MAX_ATTEMPTS: u32 = 5;
 
 pub const TOKEN_TYPE: &str = "bearer";
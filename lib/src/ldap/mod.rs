@@ -0,0 +1,438 @@
+use crate::config::LdapConfiguration;
+use crate::oidc::provider::{Claims, Email, UserProvidedData};
+use async_trait::async_trait;
+use ldap3::{dn_escape, ldap_escape, Ldap, LdapConnAsync, LdapConnSettings, Scope, SearchEntry};
+use std::collections::HashMap;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Provider name of directory identities in the `identity` table
+pub const LDAP_PROVIDER: &str = "ldap";
+
+/// LDAP result code of a bind with a wrong password or an unknown DN
+const INVALID_CREDENTIALS: u32 = 49;
+
+#[derive(Error, Debug)]
+pub enum LdapError {
+    #[error("LDAP error")]
+    InternalLdapError(#[from] ldap3::LdapError),
+
+    #[error("Required attribute {0} is missing")]
+    MissingAttribute(String),
+}
+
+/// How the directory takes part in the password grant
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DirectoryMode {
+    /// Local passwords are checked first, the directory is tried when they don't match
+    #[default]
+    Fallback,
+    /// Only the directory verifies passwords
+    Exclusive,
+}
+
+/// Verifies passwords against an external directory, replaced by a stub in tests
+#[async_trait]
+pub trait Directory {
+    /// Returns the user data when the directory accepts the credentials
+    async fn authenticate(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> Result<Option<UserProvidedData>, LdapError>;
+}
+
+pub struct LdapDirectory {
+    configuration: LdapConfiguration,
+}
+
+impl LdapDirectory {
+    pub fn new(configuration: LdapConfiguration) -> Self {
+        Self { configuration }
+    }
+
+    /// DN of the user, either built from the template or searched with the service account
+    async fn user_dn(&self, ldap: &mut Ldap, username: &str) -> Result<Option<String>, LdapError> {
+        if let Some(template) = &self.configuration.user_dn_template {
+            return Ok(Some(template.replace("{username}", &dn_escape(username))));
+        }
+
+        if let Some(bind_dn) = &self.configuration.bind_dn {
+            let bind_password = self.configuration.bind_password.as_deref().unwrap_or_default();
+            ldap.simple_bind(bind_dn, bind_password).await?.success()?;
+        }
+
+        let filter = self
+            .configuration
+            .user_filter
+            .replace("{username}", &ldap_escape(username));
+        let (entries, _) = ldap
+            .search(&self.configuration.base_dn, Scope::Subtree, &filter, vec!["1.1"])
+            .await?
+            .success()?;
+
+        // An ambiguous filter must not pick one of the accounts
+        match <[_; 1]>::try_from(entries) {
+            Ok([entry]) => Ok(Some(SearchEntry::construct(entry).dn)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    async fn bind_as_user(
+        &self,
+        ldap: &mut Ldap,
+        username: &str,
+        password: &str,
+    ) -> Result<Option<UserProvidedData>, LdapError> {
+        let Some(user_dn) = self.user_dn(ldap, username).await? else {
+            return Ok(None);
+        };
+
+        let result = ldap.simple_bind(&user_dn, password).await?;
+        if result.rc == INVALID_CREDENTIALS {
+            return Ok(None);
+        }
+        result.success()?;
+
+        let mapping = &self.configuration.attribute_mapping;
+        let attributes = vec![
+            self.configuration.id_attribute.as_str(),
+            mapping.email.as_str(),
+            mapping.name.as_str(),
+            mapping.given_name.as_str(),
+            mapping.family_name.as_str(),
+        ];
+        let (entries, _) = ldap
+            .search(&user_dn, Scope::Base, "(objectClass=*)", attributes)
+            .await?
+            .success()?;
+
+        let Some(entry) = entries.into_iter().next() else {
+            return Ok(None);
+        };
+
+        Ok(Some(map_entry(&self.configuration, SearchEntry::construct(entry))?))
+    }
+}
+
+#[async_trait]
+impl Directory for LdapDirectory {
+    async fn authenticate(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> Result<Option<UserProvidedData>, LdapError> {
+        // Directories treat a bind without password as an anonymous bind that always succeeds
+        if username.is_empty() || password.is_empty() {
+            return Ok(None);
+        }
+
+        let settings = LdapConnSettings::new()
+            .set_conn_timeout(Duration::from_secs(10))
+            .set_starttls(self.configuration.starttls);
+        let (conn, mut ldap) = LdapConnAsync::with_settings(settings, &self.configuration.url).await?;
+        ldap3::drive!(conn);
+
+        let result = self.bind_as_user(&mut ldap, username, password).await;
+        let _ = ldap.unbind().await;
+
+        result
+    }
+}
+
+/// Maps a directory entry to the user data, the directory is trusted to own the email
+pub fn map_entry(
+    configuration: &LdapConfiguration,
+    entry: SearchEntry,
+) -> Result<UserProvidedData, LdapError> {
+    let attribute = |name: &str| -> Option<String> {
+        entry
+            .attrs
+            .get(name)
+            .and_then(|v| v.first())
+            .filter(|v| !v.is_empty())
+            .cloned()
+    };
+
+    let mapping = &configuration.attribute_mapping;
+    let Some(email) = attribute(&mapping.email) else {
+        return Err(LdapError::MissingAttribute(mapping.email.clone()));
+    };
+
+    // The DN changes when the user is moved, a stable id attribute is preferred
+    let subject = attribute(&configuration.id_attribute).unwrap_or_else(|| entry.dn.clone());
+
+    let mut custom_claims = HashMap::new();
+    custom_claims.insert("dn".to_string(), entry.dn.clone().into());
+
+    let claims = Claims {
+        issuer: Some(configuration.url.clone()),
+        subject: Some(subject),
+        name: attribute(&mapping.name),
+        given_name: attribute(&mapping.given_name),
+        family_name: attribute(&mapping.family_name),
+        email: Some(email.clone()),
+        email_verified: Some(true),
+        custom_claims: Some(custom_claims),
+        ..Default::default()
+    };
+
+    Ok(UserProvidedData {
+        emails: vec![Email {
+            email,
+            verified: true,
+            primary: true,
+        }],
+        metadata: Some(claims),
+        provider_tokens: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::saml::response::AttributeMapping;
+    use bytes::BytesMut;
+    use ldap3::asn1::{parse_tag, parse_uint, write, ASNTag, Enumerated};
+    use ldap3::asn1::{StructureTag, TagClass, Types, PL};
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    const BASE_DN: &str = "ou=people,dc=example,dc=com";
+    const SERVICE_DN: &str = "cn=service,dc=example,dc=com";
+    const SERVICE_PASSWORD: &str = "service-password";
+
+    struct Entry {
+        dn: &'static str,
+        password: &'static str,
+        attributes: Vec<(&'static str, &'static str)>,
+    }
+
+    /// Search filters the mock received, to check what the username turned into
+    type Filters = Arc<Mutex<Vec<StructureTag>>>;
+
+    fn entries() -> Vec<Entry> {
+        let entry = |uid: &'static str, dn: &'static str, mail: &'static str| Entry {
+            dn,
+            password: "password",
+            attributes: vec![("entryUUID", uid), ("mail", mail), ("cn", "Jane Doe")],
+        };
+
+        vec![
+            entry("jane-uuid", "uid=jane,ou=people,dc=example,dc=com", "jane@example.com"),
+            entry("john-uuid", "uid=john,ou=people,dc=example,dc=com", "shared@example.com"),
+            entry("joan-uuid", "uid=joan,ou=people,dc=example,dc=com", "shared@example.com"),
+        ]
+    }
+
+    fn configuration(url: &str) -> LdapConfiguration {
+        LdapConfiguration {
+            url: url.to_string(),
+            starttls: false,
+            bind_dn: Some(SERVICE_DN.to_string()),
+            bind_password: Some(SERVICE_PASSWORD.to_string()),
+            base_dn: BASE_DN.to_string(),
+            user_filter: "(mail={username})".to_string(),
+            user_dn_template: None,
+            id_attribute: "entryUUID".to_string(),
+            attribute_mapping: AttributeMapping {
+                email: "mail".to_string(),
+                name: "cn".to_string(),
+                given_name: "givenName".to_string(),
+                family_name: "sn".to_string(),
+            },
+            mode: DirectoryMode::Fallback,
+            linking_policy: Default::default(),
+            sync_profile: false,
+        }
+    }
+
+    fn primitive(class: TagClass, id: u64, value: &str) -> StructureTag {
+        StructureTag {
+            class,
+            id,
+            payload: PL::P(value.as_bytes().to_vec()),
+        }
+    }
+
+    fn constructed(class: TagClass, id: u64, tags: Vec<StructureTag>) -> StructureTag {
+        StructureTag {
+            class,
+            id,
+            payload: PL::C(tags),
+        }
+    }
+
+    fn octet_string(value: &str) -> StructureTag {
+        primitive(TagClass::Universal, Types::OctetString as u64, value)
+    }
+
+    fn text(tag: StructureTag) -> String {
+        String::from_utf8(tag.expect_primitive().unwrap_or_default()).unwrap_or_default()
+    }
+
+    fn ldap_result(id: u64, result_code: i64) -> StructureTag {
+        let result_code = Enumerated {
+            inner: result_code,
+            ..Default::default()
+        };
+        let tags = vec![result_code.into_structure(), octet_string(""), octet_string("")];
+        constructed(TagClass::Application, id, tags)
+    }
+
+    /// Directory on a local port answering simple binds and searches over the entries
+    async fn mock_directory(entries: Vec<Entry>) -> (String, Filters) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ldap://{}", listener.local_addr().unwrap());
+        let filters = Filters::default();
+        let entries = Arc::new(entries);
+
+        let received = filters.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, entries.clone(), received.clone()));
+            }
+        });
+
+        (url, filters)
+    }
+
+    async fn serve(mut stream: TcpStream, entries: Arc<Vec<Entry>>, filters: Filters) {
+        let mut buffer = Vec::new();
+        loop {
+            let Ok((rest, message)) = parse_tag(&buffer) else {
+                let mut chunk = [0u8; 4096];
+                match stream.read(&mut chunk).await {
+                    Ok(0) | Err(_) => return,
+                    Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+                }
+                continue;
+            };
+            buffer = rest.to_vec();
+
+            let mut parts = message.expect_constructed().unwrap_or_default().into_iter();
+            let (Some(message_id), Some(operation)) = (parts.next(), parts.next()) else {
+                return;
+            };
+            let operations = match operation.id {
+                0 => vec![bind(&entries, operation)],
+                3 => search(&entries, &filters, operation),
+                // Unbind
+                _ => return,
+            };
+
+            for operation in operations {
+                let mut output = BytesMut::new();
+                let message = constructed(
+                    TagClass::Universal,
+                    Types::Sequence as u64,
+                    vec![message_id.clone(), operation],
+                );
+                write::encode_into(&mut output, message).unwrap();
+                if stream.write_all(&output).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    fn bind(entries: &[Entry], operation: StructureTag) -> StructureTag {
+        let mut parts = operation.expect_constructed().unwrap_or_default().into_iter().skip(1);
+        let dn = parts.next().map(text).unwrap_or_default();
+        let password = parts.next().map(text).unwrap_or_default();
+
+        let accepted = (dn == SERVICE_DN && password == SERVICE_PASSWORD)
+            || entries.iter().any(|v| v.dn == dn && v.password == password);
+        ldap_result(1, if accepted { 0 } else { INVALID_CREDENTIALS as i64 })
+    }
+
+    fn search(entries: &[Entry], filters: &Filters, operation: StructureTag) -> Vec<StructureTag> {
+        let parts = operation.expect_constructed().unwrap_or_default();
+        let base = text(parts[0].clone());
+        let scope = parts[1].clone().expect_primitive().unwrap_or_default();
+        let filter = parts[6].clone();
+        filters.lock().unwrap().push(filter.clone());
+
+        let matches = |entry: &&Entry| {
+            let in_scope = match parse_uint(&scope).map(|(_, v)| v) {
+                Ok(0) => entry.dn == base,
+                _ => entry.dn.ends_with(&base),
+            };
+            let matches_filter = match (filter.id, filter.payload.clone()) {
+                // Equality match, e.g. (mail=jane@example.com)
+                (3, PL::C(tags)) => entry.attributes.iter().any(|(name, value)| {
+                    name.eq_ignore_ascii_case(&text(tags[0].clone()))
+                        && value.eq_ignore_ascii_case(&text(tags[1].clone()))
+                }),
+                // Presence, e.g. (objectClass=*)
+                (7, PL::P(_)) => true,
+                _ => false,
+            };
+            in_scope && matches_filter
+        };
+
+        let mut operations: Vec<StructureTag> = entries
+            .iter()
+            .filter(matches)
+            .map(|entry| {
+                let attributes = entry
+                    .attributes
+                    .iter()
+                    .map(|(name, value)| {
+                        let values = vec![octet_string(value)];
+                        let values = constructed(TagClass::Universal, Types::Set as u64, values);
+                        let attribute = vec![octet_string(name), values];
+                        constructed(TagClass::Universal, Types::Sequence as u64, attribute)
+                    })
+                    .collect();
+                let sequence = Types::Sequence as u64;
+                let attributes = constructed(TagClass::Universal, sequence, attributes);
+                constructed(TagClass::Application, 4, vec![octet_string(entry.dn), attributes])
+            })
+            .collect();
+        operations.push(ldap_result(5, 0));
+        operations
+    }
+
+    #[tokio::test]
+    async fn test_authenticate() -> Result<(), Box<dyn std::error::Error>> {
+        let (url, _) = mock_directory(entries()).await;
+        let directory = LdapDirectory::new(configuration(&url));
+
+        let data = directory
+            .authenticate("jane@example.com", "password")
+            .await?
+            .expect("Expect directory user");
+        let claims = data.metadata.expect("Expect claims");
+        assert_eq!(claims.subject.as_deref(), Some("jane-uuid"));
+        assert_eq!(claims.name.as_deref(), Some("Jane Doe"));
+        assert_eq!(data.emails[0].email, "jane@example.com");
+        assert!(data.emails[0].verified);
+
+        assert!(directory.authenticate("jane@example.com", "wrong").await?.is_none());
+        assert!(directory.authenticate("unknown@example.com", "password").await?.is_none());
+        assert!(directory.authenticate("jane@example.com", "").await?.is_none());
+
+        // Two entries match, neither is picked even with the right password
+        assert!(directory.authenticate("shared@example.com", "password").await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_escape_username() -> Result<(), Box<dyn std::error::Error>> {
+        let (url, filters) = mock_directory(entries()).await;
+        let directory = LdapDirectory::new(configuration(&url));
+
+        // Unescaped, these would be a presence filter and an OR matching every entry
+        for username in ["*", "*)(mail=jane@example.com"] {
+            assert!(directory.authenticate(username, "password").await?.is_none());
+
+            let filter = filters.lock().unwrap().pop().expect("Expect search");
+            assert_eq!(filter.id, 3);
+            let tags = filter.expect_constructed().unwrap_or_default();
+            assert_eq!(text(tags[1].clone()), username);
+        }
+        Ok(())
+    }
+}
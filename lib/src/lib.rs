@@ -1,6 +1,9 @@
 pub mod config;
 pub mod constants;
+pub mod ldap;
 pub mod model;
+pub mod oauth;
 pub mod oidc;
+pub mod saml;
 pub mod service;
 pub mod utils;
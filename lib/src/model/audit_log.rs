@@ -0,0 +1,32 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+pub const AUDIT_ACTION_TOKEN_EXCHANGE: &str = "token_exchange";
+pub const AUDIT_ACTION_IMPERSONATION_DENIED: &str = "impersonation_denied";
+
+/// Security relevant action, the actor is a user id or the id of a client acting on its own
+#[derive(Debug, Deserialize, sqlx::FromRow, Serialize, Clone)]
+pub struct AuditLogEntry {
+    pub id: uuid::Uuid,
+    pub action: String,
+    pub actor: String,
+    /// User the action was performed on
+    pub subject: Option<String>,
+    pub client_id: Option<String>,
+    pub details: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+}
+
+impl AuditLogEntry {
+    pub fn new(action: &str, actor: &str, subject: Option<String>, client_id: Option<String>) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4(),
+            action: action.to_string(),
+            actor: actor.to_string(),
+            subject,
+            client_id,
+            details: serde_json::Value::Object(Default::default()),
+            created_at: Utc::now(),
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use async_trait::async_trait;
+use sqlx::PgPool;
+use thiserror::Error;
+
+use crate::model::audit_log::AuditLogEntry;
+
+#[derive(Error, Debug)]
+pub enum AuditLogRepositoryError {
+    #[error("Internal data store error")]
+    InternalDbError(#[from] sqlx::Error),
+}
+
+/// Entries are only ever appended
+#[async_trait]
+pub trait AuditLogRepository {
+    async fn add(&self, entry: AuditLogEntry) -> Result<AuditLogEntry, AuditLogRepositoryError>;
+    async fn list_by_subject(
+        &self,
+        subject: &str,
+    ) -> Result<Vec<AuditLogEntry>, AuditLogRepositoryError>;
+}
+
+pub struct DbAuditLogRepository {
+    db: PgPool,
+}
+
+impl DbAuditLogRepository {
+    pub fn new(pool: PgPool) -> DbAuditLogRepository {
+        Self { db: pool }
+    }
+}
+
+#[async_trait]
+impl AuditLogRepository for DbAuditLogRepository {
+    async fn add(&self, entry: AuditLogEntry) -> Result<AuditLogEntry, AuditLogRepositoryError> {
+        let query_result = sqlx::query_as!(
+            AuditLogEntry,
+            r#"INSERT INTO auth_audit_log (id, action, actor, subject, client_id, details) VALUES ($1, $2, $3, $4, $5, $6) RETURNING *"#,
+            entry.id,
+            entry.action,
+            entry.actor,
+            entry.subject,
+            entry.client_id,
+            entry.details
+        )
+            .fetch_one(&self.db)
+            .await?;
+
+        Ok(query_result)
+    }
+
+    async fn list_by_subject(
+        &self,
+        subject: &str,
+    ) -> Result<Vec<AuditLogEntry>, AuditLogRepositoryError> {
+        sqlx::query_as!(
+            AuditLogEntry,
+            r#"SELECT * FROM auth_audit_log WHERE subject = $1 ORDER BY created_at"#,
+            subject
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(AuditLogRepositoryError::InternalDbError)
+    }
+}
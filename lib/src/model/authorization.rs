@@ -0,0 +1,78 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::constants::{OAUTH_AUTHORIZATION_EXPIRED_IN, OAUTH_CODE_EXPIRED_IN};
+use crate::oauth::parse_scope;
+
+/// Authorization request of a relying party, it carries the code once the user signed in
+#[derive(Debug, Deserialize, sqlx::FromRow, Serialize, Clone)]
+pub struct Authorization {
+    pub id: uuid::Uuid,
+    pub client_id: String,
+    pub redirect_uri: String,
+    pub scope: String,
+    pub state: Option<String>,
+    pub nonce: Option<String>,
+    pub code_challenge: Option<String>,
+    pub code_challenge_method: Option<String>,
+    pub code: Option<String>,
+    pub user_id: Option<uuid::Uuid>,
+    /// Browser session of the user at authcare
+    pub session_id: Option<uuid::Uuid>,
+    pub auth_time: Option<DateTime<Utc>>,
+    /// Reference of a pushed request, it can be used once by the user agent
+    pub request_uri: Option<String>,
+    pub prompt: Option<String>,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Authorization {
+    pub fn new(client_id: &str, redirect_uri: &str, scope: &str) -> Self {
+        let now = Utc::now();
+
+        Self {
+            id: uuid::Uuid::new_v4(),
+            client_id: client_id.to_string(),
+            redirect_uri: redirect_uri.to_string(),
+            scope: scope.to_string(),
+            state: None,
+            nonce: None,
+            code_challenge: None,
+            code_challenge_method: None,
+            code: None,
+            user_id: None,
+            session_id: None,
+            auth_time: None,
+            request_uri: None,
+            prompt: None,
+            expires_at: now + Duration::minutes(OAUTH_AUTHORIZATION_EXPIRED_IN),
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    /// Grants the authorization to the user, the code is short lived
+    pub fn grant(
+        &mut self,
+        code: String,
+        user_id: uuid::Uuid,
+        session_id: uuid::Uuid,
+        auth_time: DateTime<Utc>,
+    ) {
+        self.code = Some(code);
+        self.user_id = Some(user_id);
+        self.session_id = Some(session_id);
+        self.auth_time = Some(auth_time);
+        self.expires_at = Utc::now() + Duration::minutes(OAUTH_CODE_EXPIRED_IN);
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at < Utc::now()
+    }
+
+    pub fn scopes(&self) -> Vec<&str> {
+        parse_scope(&self.scope)
+    }
+}
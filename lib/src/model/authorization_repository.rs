@@ -0,0 +1,133 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use thiserror::Error;
+
+use crate::model::authorization::Authorization;
+
+#[derive(Error, Debug)]
+pub enum AuthorizationRepositoryError {
+    #[error("Internal data store error")]
+    InternalDbError(#[from] sqlx::Error),
+}
+
+#[async_trait]
+pub trait AuthorizationRepository {
+    async fn get(&self, id: &uuid::Uuid) -> Result<Authorization, AuthorizationRepositoryError>;
+    async fn add(
+        &self,
+        authorization: Authorization,
+    ) -> Result<Authorization, AuthorizationRepositoryError>;
+    /// Unused pushed request of the client, it is consumed and then expires at `expires_at`
+    async fn take_by_request_uri(
+        &self,
+        client_id: &str,
+        request_uri: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<Authorization, AuthorizationRepositoryError>;
+    /// Stores the code of an authorization, fails when the authorization already has one
+    async fn update(
+        &self,
+        authorization: Authorization,
+    ) -> Result<Authorization, AuthorizationRepositoryError>;
+    /// Removes and returns the authorization of a code, so the code can be used only once
+    async fn delete_by_code(&self, code: &str) -> Result<Authorization, AuthorizationRepositoryError>;
+}
+
+pub struct DbAuthorizationRepository {
+    db: PgPool,
+}
+
+impl DbAuthorizationRepository {
+    pub fn new(pool: PgPool) -> DbAuthorizationRepository {
+        Self { db: pool }
+    }
+}
+
+#[async_trait]
+impl AuthorizationRepository for DbAuthorizationRepository {
+    async fn get(&self, id: &uuid::Uuid) -> Result<Authorization, AuthorizationRepositoryError> {
+        sqlx::query_as!(
+            Authorization,
+            r#"SELECT * FROM oauth_authorization WHERE id = $1"#,
+            id
+        )
+        .fetch_one(&self.db)
+        .await
+        .map_err(AuthorizationRepositoryError::InternalDbError)
+    }
+
+    async fn add(
+        &self,
+        authorization: Authorization,
+    ) -> Result<Authorization, AuthorizationRepositoryError> {
+        let query_result = sqlx::query_as!(
+            Authorization,
+            r#"INSERT INTO oauth_authorization (id, client_id, redirect_uri, scope, state, nonce, code_challenge, code_challenge_method, request_uri, prompt, expires_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11) RETURNING *"#,
+            authorization.id,
+            authorization.client_id,
+            authorization.redirect_uri,
+            authorization.scope,
+            authorization.state,
+            authorization.nonce,
+            authorization.code_challenge,
+            authorization.code_challenge_method,
+            authorization.request_uri,
+            authorization.prompt,
+            authorization.expires_at
+        )
+            .fetch_one(&self.db)
+            .await?;
+
+        Ok(query_result)
+    }
+
+    async fn take_by_request_uri(
+        &self,
+        client_id: &str,
+        request_uri: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<Authorization, AuthorizationRepositoryError> {
+        sqlx::query_as!(
+            Authorization,
+            r#"UPDATE oauth_authorization SET request_uri = NULL, expires_at = $3, updated_at = NOW() WHERE client_id = $1 AND request_uri = $2 AND expires_at > NOW() RETURNING *"#,
+            client_id,
+            request_uri,
+            expires_at
+        )
+        .fetch_one(&self.db)
+        .await
+        .map_err(AuthorizationRepositoryError::InternalDbError)
+    }
+
+    async fn update(
+        &self,
+        authorization: Authorization,
+    ) -> Result<Authorization, AuthorizationRepositoryError> {
+        let query_result = sqlx::query_as!(
+            Authorization,
+            r#"UPDATE oauth_authorization SET code = $2, user_id = $3, session_id = $4, auth_time = $5, expires_at = $6, updated_at = NOW() WHERE id = $1 AND code IS NULL RETURNING *"#,
+            authorization.id,
+            authorization.code,
+            authorization.user_id,
+            authorization.session_id,
+            authorization.auth_time,
+            authorization.expires_at
+        )
+            .fetch_one(&self.db)
+            .await?;
+
+        Ok(query_result)
+    }
+
+    async fn delete_by_code(&self, code: &str) -> Result<Authorization, AuthorizationRepositoryError> {
+        sqlx::query_as!(
+            Authorization,
+            r#"DELETE FROM oauth_authorization WHERE code = $1 RETURNING *"#,
+            code
+        )
+        .fetch_one(&self.db)
+        .await
+        .map_err(AuthorizationRepositoryError::InternalDbError)
+    }
+}
@@ -0,0 +1,69 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::crypto::random_secret_token;
+
+/// OAuth client of the registry, either a relying party or a backend service
+#[derive(Debug, Deserialize, sqlx::FromRow, Serialize, Clone)]
+pub struct OAuthClient {
+    pub id: String,
+    /// Public clients have no secret and must use PKCE
+    #[serde(skip_serializing)]
+    pub client_secret_hash: Option<String>,
+    pub grant_types: Vec<String>,
+    pub scopes: Vec<String>,
+    /// Exact redirect uris the authorization response may be sent to
+    pub redirect_uris: Vec<String>,
+    /// Access token lifetime in seconds
+    pub token_ttl: Option<i32>,
+    pub client_name: Option<String>,
+    pub token_endpoint_auth_method: Option<String>,
+    /// Only dynamically registered clients can be managed with a registration access token
+    #[serde(skip_serializing)]
+    pub registration_access_token_hash: Option<String>,
+    /// JWK set of the public keys signing the request objects of the client
+    pub jwks: Option<serde_json::Value>,
+    /// Authorization requests must be pushed to the PAR endpoint, see RFC 9126 section 6
+    pub require_pushed_authorization_requests: bool,
+    /// Exact uris the user may be sent to after signing out
+    pub post_logout_redirect_uris: Vec<String>,
+    /// Loaded in an iframe when the user signs out, see OpenID Connect Front-Channel Logout
+    pub frontchannel_logout_uri: Option<String>,
+    /// Receives logout tokens when a session ends, see OpenID Connect Back-Channel Logout
+    pub backchannel_logout_uri: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl OAuthClient {
+    pub fn new(grant_types: Vec<String>, scopes: Vec<String>, redirect_uris: Vec<String>) -> Self {
+        let now = Utc::now();
+
+        Self {
+            id: random_secret_token(32),
+            client_secret_hash: None,
+            grant_types,
+            scopes,
+            redirect_uris,
+            token_ttl: None,
+            client_name: None,
+            token_endpoint_auth_method: None,
+            registration_access_token_hash: None,
+            jwks: None,
+            require_pushed_authorization_requests: false,
+            post_logout_redirect_uris: vec![],
+            frontchannel_logout_uri: None,
+            backchannel_logout_uri: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    pub fn is_public(&self) -> bool {
+        self.client_secret_hash.is_none()
+    }
+
+    pub fn allows_grant(&self, grant_type: &str) -> bool {
+        self.grant_types.iter().any(|v| v == grant_type)
+    }
+}
@@ -0,0 +1,121 @@
+use async_trait::async_trait;
+use sqlx::PgPool;
+use thiserror::Error;
+
+use crate::model::client::OAuthClient;
+
+#[derive(Error, Debug)]
+pub enum ClientRepositoryError {
+    #[error("Internal data store error")]
+    InternalDbError(#[from] sqlx::Error),
+}
+
+#[async_trait]
+pub trait ClientRepository {
+    async fn get(&self, id: &str) -> Result<OAuthClient, ClientRepositoryError>;
+    async fn add(&self, client: OAuthClient) -> Result<OAuthClient, ClientRepositoryError>;
+    /// Updates the metadata of a client, secrets are kept
+    async fn update(&self, client: OAuthClient) -> Result<OAuthClient, ClientRepositoryError>;
+    /// Removes the client with its sessions, refresh tokens are removed with the sessions
+    async fn delete(&self, id: &str) -> Result<(), ClientRepositoryError>;
+    /// Inserts or replaces a configured client, including its secret
+    async fn upsert(&self, client: OAuthClient) -> Result<OAuthClient, ClientRepositoryError>;
+}
+
+pub struct DbClientRepository {
+    db: PgPool,
+}
+
+impl DbClientRepository {
+    pub fn new(pool: PgPool) -> DbClientRepository {
+        Self { db: pool }
+    }
+}
+
+#[async_trait]
+impl ClientRepository for DbClientRepository {
+    async fn get(&self, id: &str) -> Result<OAuthClient, ClientRepositoryError> {
+        sqlx::query_as!(OAuthClient, r#"SELECT * FROM oauth_client WHERE id = $1"#, id)
+            .fetch_one(&self.db)
+            .await
+            .map_err(ClientRepositoryError::InternalDbError)
+    }
+
+    async fn add(&self, client: OAuthClient) -> Result<OAuthClient, ClientRepositoryError> {
+        let query_result = sqlx::query_as!(
+            OAuthClient,
+            r#"INSERT INTO oauth_client (id, client_secret_hash, grant_types, scopes, redirect_uris, token_ttl, client_name, token_endpoint_auth_method, registration_access_token_hash, jwks, require_pushed_authorization_requests, post_logout_redirect_uris, frontchannel_logout_uri, backchannel_logout_uri) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14) RETURNING *"#,
+            client.id,
+            client.client_secret_hash,
+            &client.grant_types,
+            &client.scopes,
+            &client.redirect_uris,
+            client.token_ttl,
+            client.client_name,
+            client.token_endpoint_auth_method,
+            client.registration_access_token_hash,
+            client.jwks,
+            client.require_pushed_authorization_requests,
+            &client.post_logout_redirect_uris,
+            client.frontchannel_logout_uri,
+            client.backchannel_logout_uri
+        )
+            .fetch_one(&self.db)
+            .await?;
+
+        Ok(query_result)
+    }
+
+    async fn update(&self, client: OAuthClient) -> Result<OAuthClient, ClientRepositoryError> {
+        let query_result = sqlx::query_as!(
+            OAuthClient,
+            r#"UPDATE oauth_client SET grant_types = $2, scopes = $3, redirect_uris = $4, client_name = $5, token_endpoint_auth_method = $6, jwks = $7, require_pushed_authorization_requests = $8, post_logout_redirect_uris = $9, frontchannel_logout_uri = $10, backchannel_logout_uri = $11, updated_at = NOW() WHERE id = $1 RETURNING *"#,
+            client.id,
+            &client.grant_types,
+            &client.scopes,
+            &client.redirect_uris,
+            client.client_name,
+            client.token_endpoint_auth_method,
+            client.jwks,
+            client.require_pushed_authorization_requests,
+            &client.post_logout_redirect_uris,
+            client.frontchannel_logout_uri,
+            client.backchannel_logout_uri
+        )
+            .fetch_one(&self.db)
+            .await?;
+
+        Ok(query_result)
+    }
+
+    async fn delete(&self, id: &str) -> Result<(), ClientRepositoryError> {
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query!("DELETE FROM auth_session WHERE client_id = $1", id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query!("DELETE FROM oauth_client WHERE id = $1", id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn upsert(&self, client: OAuthClient) -> Result<OAuthClient, ClientRepositoryError> {
+        let query_result = sqlx::query_as!(
+            OAuthClient,
+            r#"INSERT INTO oauth_client (id, client_secret_hash, grant_types, scopes, redirect_uris, post_logout_redirect_uris) VALUES ($1, $2, $3, $4, $5, $6) ON CONFLICT (id) DO UPDATE SET client_secret_hash = EXCLUDED.client_secret_hash, grant_types = EXCLUDED.grant_types, scopes = EXCLUDED.scopes, redirect_uris = EXCLUDED.redirect_uris, post_logout_redirect_uris = EXCLUDED.post_logout_redirect_uris, updated_at = NOW() RETURNING *"#,
+            client.id,
+            client.client_secret_hash,
+            &client.grant_types,
+            &client.scopes,
+            &client.redirect_uris,
+            &client.post_logout_redirect_uris
+        )
+            .fetch_one(&self.db)
+            .await?;
+
+        Ok(query_result)
+    }
+}
@@ -0,0 +1,78 @@
+use chrono::{DateTime, Duration, Utc};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::constants::{DEVICE_CODE_EXPIRED_IN, DEVICE_CODE_INTERVAL};
+use crate::utils::crypto::random_secret_token;
+
+/// Letters of the user code, without vowels so codes don't spell words, see RFC 8628 section 6.1
+const USER_CODE_CHARSET: &[u8] = b"BCDFGHJKLMNPQRSTVWXZ";
+const USER_CODE_LENGTH: usize = 8;
+
+/// Authorization request of an input constrained device, the user approves it on another device
+#[derive(Debug, Deserialize, sqlx::FromRow, Serialize, Clone)]
+pub struct DeviceAuthorization {
+    pub id: uuid::Uuid,
+    pub device_code: String,
+    /// Normalized user code, without the separator
+    pub user_code: String,
+    pub client_id: String,
+    pub scope: String,
+    pub user_id: Option<uuid::Uuid>,
+    /// None while pending, false when the user denied the device
+    pub approved: Option<bool>,
+    /// Minimum seconds between two polls of the device
+    pub interval: i32,
+    pub last_polled_at: Option<DateTime<Utc>>,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl DeviceAuthorization {
+    pub fn new(client_id: &str, scope: &str) -> Self {
+        let now = Utc::now();
+
+        Self {
+            id: uuid::Uuid::new_v4(),
+            device_code: random_secret_token(43),
+            user_code: generate_user_code(),
+            client_id: client_id.to_string(),
+            scope: scope.to_string(),
+            user_id: None,
+            approved: None,
+            interval: DEVICE_CODE_INTERVAL,
+            last_polled_at: None,
+            expires_at: now + Duration::minutes(DEVICE_CODE_EXPIRED_IN),
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at < Utc::now()
+    }
+
+    /// User code as displayed to the user, e.g. `WDJB-MJHT`
+    pub fn display_user_code(&self) -> String {
+        let (left, right) = self.user_code.split_at(self.user_code.len() / 2);
+        format!("{}-{}", left, right)
+    }
+}
+
+fn generate_user_code() -> String {
+    let mut rng = rand::thread_rng();
+
+    (0..USER_CODE_LENGTH)
+        .map(|_| char::from(USER_CODE_CHARSET[rng.gen_range(0..USER_CODE_CHARSET.len())]))
+        .collect()
+}
+
+/// Users may type the code in lower case and with or without the separator
+pub fn normalize_user_code(user_code: &str) -> String {
+    user_code
+        .chars()
+        .filter(|v| v.is_ascii_alphabetic())
+        .map(|v| v.to_ascii_uppercase())
+        .collect()
+}
@@ -0,0 +1,172 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use thiserror::Error;
+
+use crate::model::device_authorization::DeviceAuthorization;
+
+#[derive(Error, Debug)]
+pub enum DeviceAuthorizationRepositoryError {
+    #[error("Internal data store error")]
+    InternalDbError(#[from] sqlx::Error),
+}
+
+#[async_trait]
+pub trait DeviceAuthorizationRepository {
+    async fn get_by_device_code(
+        &self,
+        device_code: &str,
+    ) -> Result<DeviceAuthorization, DeviceAuthorizationRepositoryError>;
+    async fn get_by_user_code(
+        &self,
+        user_code: &str,
+    ) -> Result<DeviceAuthorization, DeviceAuthorizationRepositoryError>;
+    async fn add(
+        &self,
+        device_authorization: DeviceAuthorization,
+    ) -> Result<DeviceAuthorization, DeviceAuthorizationRepositoryError>;
+    /// Records a poll of the device and its possibly increased interval
+    async fn update_poll(
+        &self,
+        id: &uuid::Uuid,
+        last_polled_at: DateTime<Utc>,
+        interval: i32,
+    ) -> Result<DeviceAuthorization, DeviceAuthorizationRepositoryError>;
+    /// Stores the decision of the user, fails when the request was already decided
+    async fn update_approval(
+        &self,
+        id: &uuid::Uuid,
+        user_id: &uuid::Uuid,
+        approved: bool,
+    ) -> Result<DeviceAuthorization, DeviceAuthorizationRepositoryError>;
+    /// Removes and returns the request, so the device code can be redeemed only once
+    async fn delete(
+        &self,
+        id: &uuid::Uuid,
+    ) -> Result<DeviceAuthorization, DeviceAuthorizationRepositoryError>;
+    /// Removes the requests that can no longer be approved or redeemed
+    async fn delete_expired(&self) -> Result<u64, DeviceAuthorizationRepositoryError>;
+}
+
+pub struct DbDeviceAuthorizationRepository {
+    db: PgPool,
+}
+
+impl DbDeviceAuthorizationRepository {
+    pub fn new(pool: PgPool) -> DbDeviceAuthorizationRepository {
+        Self { db: pool }
+    }
+}
+
+#[async_trait]
+impl DeviceAuthorizationRepository for DbDeviceAuthorizationRepository {
+    async fn get_by_device_code(
+        &self,
+        device_code: &str,
+    ) -> Result<DeviceAuthorization, DeviceAuthorizationRepositoryError> {
+        sqlx::query_as!(
+            DeviceAuthorization,
+            r#"SELECT * FROM oauth_device_authorization WHERE device_code = $1"#,
+            device_code
+        )
+        .fetch_one(&self.db)
+        .await
+        .map_err(DeviceAuthorizationRepositoryError::InternalDbError)
+    }
+
+    async fn get_by_user_code(
+        &self,
+        user_code: &str,
+    ) -> Result<DeviceAuthorization, DeviceAuthorizationRepositoryError> {
+        sqlx::query_as!(
+            DeviceAuthorization,
+            r#"SELECT * FROM oauth_device_authorization WHERE user_code = $1"#,
+            user_code
+        )
+        .fetch_one(&self.db)
+        .await
+        .map_err(DeviceAuthorizationRepositoryError::InternalDbError)
+    }
+
+    async fn add(
+        &self,
+        device_authorization: DeviceAuthorization,
+    ) -> Result<DeviceAuthorization, DeviceAuthorizationRepositoryError> {
+        let query_result = sqlx::query_as!(
+            DeviceAuthorization,
+            r#"INSERT INTO oauth_device_authorization (id, device_code, user_code, client_id, scope, "interval", expires_at) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING *"#,
+            device_authorization.id,
+            device_authorization.device_code,
+            device_authorization.user_code,
+            device_authorization.client_id,
+            device_authorization.scope,
+            device_authorization.interval,
+            device_authorization.expires_at
+        )
+            .fetch_one(&self.db)
+            .await?;
+
+        Ok(query_result)
+    }
+
+    async fn update_poll(
+        &self,
+        id: &uuid::Uuid,
+        last_polled_at: DateTime<Utc>,
+        interval: i32,
+    ) -> Result<DeviceAuthorization, DeviceAuthorizationRepositoryError> {
+        let query_result = sqlx::query_as!(
+            DeviceAuthorization,
+            r#"UPDATE oauth_device_authorization SET last_polled_at = $2, "interval" = $3, updated_at = NOW() WHERE id = $1 RETURNING *"#,
+            id,
+            last_polled_at,
+            interval
+        )
+            .fetch_one(&self.db)
+            .await?;
+
+        Ok(query_result)
+    }
+
+    async fn update_approval(
+        &self,
+        id: &uuid::Uuid,
+        user_id: &uuid::Uuid,
+        approved: bool,
+    ) -> Result<DeviceAuthorization, DeviceAuthorizationRepositoryError> {
+        let query_result = sqlx::query_as!(
+            DeviceAuthorization,
+            r#"UPDATE oauth_device_authorization SET user_id = $2, approved = $3, updated_at = NOW() WHERE id = $1 AND approved IS NULL RETURNING *"#,
+            id,
+            user_id,
+            approved
+        )
+            .fetch_one(&self.db)
+            .await?;
+
+        Ok(query_result)
+    }
+
+    async fn delete(
+        &self,
+        id: &uuid::Uuid,
+    ) -> Result<DeviceAuthorization, DeviceAuthorizationRepositoryError> {
+        sqlx::query_as!(
+            DeviceAuthorization,
+            r#"DELETE FROM oauth_device_authorization WHERE id = $1 RETURNING *"#,
+            id
+        )
+        .fetch_one(&self.db)
+        .await
+        .map_err(DeviceAuthorizationRepositoryError::InternalDbError)
+    }
+
+    async fn delete_expired(&self) -> Result<u64, DeviceAuthorizationRepositoryError> {
+        let query_result =
+            sqlx::query!(r#"DELETE FROM oauth_device_authorization WHERE expires_at < NOW()"#)
+                .execute(&self.db)
+                .await?;
+
+        Ok(query_result.rows_affected())
+    }
+}
@@ -0,0 +1,68 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::constants::FLOW_STATE_EXPIRED_IN;
+use crate::oidc::oidc::{AuthorizationRedirect, OidcProvider};
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize, sqlx::FromRow, Serialize, Clone)]
+pub struct FlowState {
+    pub id: uuid::Uuid,
+    pub state: String,
+    pub provider: String,
+    pub code_verifier: String,
+    pub nonce: String,
+    pub redirect_uri: String,
+    pub auth_code: Option<String>,
+    pub user_id: Option<uuid::Uuid>,
+    pub client_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl FlowState {
+    pub fn new(
+        provider: &OidcProvider,
+        authorization: &AuthorizationRedirect,
+        redirect_uri: &str,
+    ) -> Self {
+        let now = Utc::now();
+
+        Self {
+            id: uuid::Uuid::new_v4(),
+            state: authorization.state.clone(),
+            provider: provider.name().to_string(),
+            code_verifier: authorization.pkce_verifier.clone(),
+            nonce: authorization.nonce.clone(),
+            redirect_uri: redirect_uri.to_string(),
+            auth_code: None,
+            user_id: None,
+            client_id: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    /// Flow of a SAML login, the AuthnRequest id takes the place of the nonce
+    pub fn new_sso(identity_provider: String, request_id: &str, relay_state: &str, redirect_uri: &str) -> Self {
+        let now = Utc::now();
+
+        Self {
+            id: uuid::Uuid::new_v4(),
+            state: relay_state.to_string(),
+            provider: identity_provider,
+            code_verifier: String::new(),
+            nonce: request_id.to_string(),
+            redirect_uri: redirect_uri.to_string(),
+            auth_code: None,
+            user_id: None,
+            client_id: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.created_at + Duration::minutes(FLOW_STATE_EXPIRED_IN) < Utc::now()
+    }
+}
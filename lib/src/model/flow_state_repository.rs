@@ -0,0 +1,107 @@
+use async_trait::async_trait;
+use sqlx::PgPool;
+use thiserror::Error;
+
+use crate::model::flow_state::FlowState;
+
+#[derive(Error, Debug)]
+pub enum FlowStateRepositoryError {
+    #[error("Internal data store error")]
+    InternalDbError(#[from] sqlx::Error),
+}
+
+#[async_trait]
+pub trait FlowStateRepository {
+    async fn find_by_state(&self, state: &str) -> Result<FlowState, FlowStateRepositoryError>;
+    /// Detaches the pending flow from its state, so it is found only once
+    async fn consume_by_state(&self, state: &str) -> Result<FlowState, FlowStateRepositoryError>;
+    async fn add(&self, flow_state: FlowState) -> Result<FlowState, FlowStateRepositoryError>;
+    /// Stores the auth code of a flow, fails when the flow already has one
+    async fn update(&self, flow_state: FlowState) -> Result<FlowState, FlowStateRepositoryError>;
+    /// Removes and returns the flow of an auth code, so the code can be used only once
+    async fn delete_by_auth_code(
+        &self,
+        auth_code: &str,
+    ) -> Result<FlowState, FlowStateRepositoryError>;
+}
+
+pub struct DbFlowStateRepository {
+    db: PgPool,
+}
+
+impl DbFlowStateRepository {
+    pub fn new(pool: PgPool) -> DbFlowStateRepository {
+        Self { db: pool }
+    }
+}
+
+#[async_trait]
+impl FlowStateRepository for DbFlowStateRepository {
+    async fn find_by_state(&self, state: &str) -> Result<FlowState, FlowStateRepositoryError> {
+        sqlx::query_as!(
+            FlowState,
+            r#"SELECT * FROM auth_flow_state WHERE state = $1"#,
+            state
+        )
+        .fetch_one(&self.db)
+        .await
+        .map_err(FlowStateRepositoryError::InternalDbError)
+    }
+
+    async fn consume_by_state(&self, state: &str) -> Result<FlowState, FlowStateRepositoryError> {
+        sqlx::query_as!(
+            FlowState,
+            r#"UPDATE auth_flow_state SET state = 'consumed:' || id, updated_at = NOW() WHERE state = $1 AND auth_code IS NULL RETURNING *"#,
+            state
+        )
+        .fetch_one(&self.db)
+        .await
+        .map_err(FlowStateRepositoryError::InternalDbError)
+    }
+
+    async fn add(&self, flow_state: FlowState) -> Result<FlowState, FlowStateRepositoryError> {
+        let query_result = sqlx::query_as!(
+            FlowState,
+            r#"INSERT INTO auth_flow_state (id, state, provider, code_verifier, nonce, redirect_uri) VALUES ($1, $2, $3, $4, $5, $6) RETURNING *"#,
+            flow_state.id,
+            flow_state.state,
+            flow_state.provider,
+            flow_state.code_verifier,
+            flow_state.nonce,
+            flow_state.redirect_uri
+        )
+            .fetch_one(&self.db)
+            .await?;
+
+        Ok(query_result)
+    }
+
+    async fn update(&self, flow_state: FlowState) -> Result<FlowState, FlowStateRepositoryError> {
+        let query_result = sqlx::query_as!(
+            FlowState,
+            r#"UPDATE auth_flow_state SET auth_code = $2, user_id = $3, client_id = $4, updated_at = NOW() WHERE id = $1 AND auth_code IS NULL RETURNING *"#,
+            flow_state.id,
+            flow_state.auth_code,
+            flow_state.user_id,
+            flow_state.client_id
+        )
+            .fetch_one(&self.db)
+            .await?;
+
+        Ok(query_result)
+    }
+
+    async fn delete_by_auth_code(
+        &self,
+        auth_code: &str,
+    ) -> Result<FlowState, FlowStateRepositoryError> {
+        sqlx::query_as!(
+            FlowState,
+            r#"DELETE FROM auth_flow_state WHERE auth_code = $1 RETURNING *"#,
+            auth_code
+        )
+        .fetch_one(&self.db)
+        .await
+        .map_err(FlowStateRepositoryError::InternalDbError)
+    }
+}
@@ -1,10 +1,19 @@
+use crate::config::AppConfig;
 use crate::model::user::User;
-use crate::oidc::provider::UserProvidedData;
+use crate::oidc::provider::{ProviderTokens, UserProvidedData};
+use crate::utils::crypto::{decrypt_secret, encrypt_secret};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
 use sqlx::types::JsonValue;
 use std::collections::HashMap;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum IdentityError {
+    #[error("Provider tokens can't be stored without PROVIDER_TOKEN_SECRET")]
+    MissingProviderTokenSecret,
+}
 
 #[allow(non_snake_case)]
 #[derive(Debug, Deserialize, sqlx::FromRow, Serialize, Clone)]
@@ -15,6 +24,12 @@ pub struct Identity {
     pub identity_data: JsonValue,
     pub provider: String,
     pub last_sign_in_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing)]
+    pub provider_refresh_token: Option<String>,
+    #[serde(skip_serializing)]
+    pub provider_access_token: Option<String>,
+    #[serde(skip_serializing)]
+    pub provider_token_expires_at: Option<DateTime<Utc>>,
     pub created_at: Option<DateTime<Utc>>,
     pub updated_at: Option<DateTime<Utc>>,
 }
@@ -40,6 +55,9 @@ impl Identity {
             identity_data: JsonValue::Object(map),
             provider: provider.to_string(),
             last_sign_in_at: None,
+            provider_refresh_token: None,
+            provider_access_token: None,
+            provider_token_expires_at: None,
             created_at: None,
             updated_at: None,
         }
@@ -49,21 +67,102 @@ impl Identity {
         user: &User,
         provider: &str,
         provider_data: &UserProvidedData,
-    ) -> Self {
+    ) -> Result<Self, IdentityError> {
         let meta = provider_data.metadata.as_ref().expect("Expect meta");
         let provider_id = meta.subject.as_ref().expect("Expect sub");
 
-        let email = &provider_data.emails[0].email;
+        let email = provider_data.account_email().map(|v| v.email.clone());
 
-        Identity {
+        let mut identity = Identity {
             id: provider_id.clone(),
             user_id: user.id,
-            email: Some(email.clone()),
+            email,
             identity_data: serde_json::to_value(meta).expect("Expect map"),
             provider: provider.to_string(),
-            last_sign_in_at: None,
+            last_sign_in_at: Some(Utc::now()),
+            provider_refresh_token: None,
+            provider_access_token: None,
+            provider_token_expires_at: None,
             created_at: None,
             updated_at: None,
+        };
+
+        if let Some(tokens) = &provider_data.provider_tokens {
+            identity.set_provider_tokens(tokens)?;
+        }
+
+        Ok(identity)
+    }
+
+    /// Merges the latest provider claims into `identity_data` and records the sign in
+    pub fn update_from_provider(
+        &mut self,
+        provider_data: &UserProvidedData,
+    ) -> Result<(), IdentityError> {
+        let latest = provider_data
+            .metadata
+            .as_ref()
+            .and_then(|meta| serde_json::to_value(meta).ok());
+
+        if let (Some(Value::Object(latest)), Value::Object(data)) =
+            (latest, &mut self.identity_data)
+        {
+            for (key, value) in latest {
+                if !value.is_null() {
+                    data.insert(key, value);
+                }
+            }
         }
+
+        if let Some(tokens) = &provider_data.provider_tokens {
+            self.set_provider_tokens(tokens)?;
+        }
+
+        self.last_sign_in_at = Some(Utc::now());
+        Ok(())
+    }
+
+    /// Whether the provider verified the email, password identities never carry the claim
+    pub fn is_email_verified(&self) -> bool {
+        self.identity_data.get("email_verified").and_then(Value::as_bool) == Some(true)
+    }
+
+    /// Stores the tokens encrypted, `PROVIDER_TOKEN_SECRET` is checked on startup for providers
+    /// returning tokens
+    pub fn set_provider_tokens(&mut self, tokens: &ProviderTokens) -> Result<(), IdentityError> {
+        let Some(secret) = AppConfig::provider_token_secret() else {
+            return Err(IdentityError::MissingProviderTokenSecret);
+        };
+
+        self.provider_access_token = Some(encrypt_secret(&tokens.access_token, &secret));
+        self.provider_token_expires_at = tokens.expires_at;
+
+        // Providers only return a refresh token on some sign ins, keep the last one
+        if let Some(refresh_token) = &tokens.refresh_token {
+            self.provider_refresh_token = Some(encrypt_secret(refresh_token, &secret));
+        }
+
+        Ok(())
+    }
+
+    pub fn provider_tokens(&self) -> Option<ProviderTokens> {
+        let secret = AppConfig::provider_token_secret()?;
+        let access_token = decrypt_secret(self.provider_access_token.as_ref()?, &secret)?;
+        let refresh_token = self
+            .provider_refresh_token
+            .as_ref()
+            .and_then(|v| decrypt_secret(v, &secret));
+
+        Some(ProviderTokens {
+            access_token,
+            refresh_token,
+            expires_at: self.provider_token_expires_at,
+        })
+    }
+
+    pub fn clear_provider_tokens(&mut self) {
+        self.provider_access_token = None;
+        self.provider_refresh_token = None;
+        self.provider_token_expires_at = None;
     }
 }
@@ -3,6 +3,7 @@ use sqlx::PgPool;
 use thiserror::Error;
 
 use crate::model::identity::Identity;
+use crate::utils::crypto::encrypt_secret;
 
 #[derive(Error, Debug)]
 pub enum IdentityRepositoryError {
@@ -12,15 +13,32 @@ pub enum IdentityRepositoryError {
 
 #[async_trait]
 pub trait IdentityRepository {
-    async fn get(&self, id: u64) -> Result<Identity, IdentityRepositoryError>;
+    async fn get(
+        &self,
+        user_id: &uuid::Uuid,
+        provider: &str,
+    ) -> Result<Identity, IdentityRepositoryError>;
     async fn find(&self, id: &str, provider: &str) -> Result<Identity, IdentityRepositoryError>;
     async fn find_all_by_email(
         &self,
         emails: &[String],
     ) -> Result<Vec<Identity>, IdentityRepositoryError>;
+    async fn find_all_by_user(
+        &self,
+        user_id: &uuid::Uuid,
+    ) -> Result<Vec<Identity>, IdentityRepositoryError>;
     async fn add(&self, identity: &Identity) -> Result<Identity, IdentityRepositoryError>;
     async fn update(&self, identity: Identity) -> Result<Identity, IdentityRepositoryError>;
-    async fn delete(&self, id: u64) -> Result<(), IdentityRepositoryError>;
+    async fn delete(&self, id: &str, provider: &str) -> Result<(), IdentityRepositoryError>;
+    async fn delete_unless_last(
+        &self,
+        user_id: &uuid::Uuid,
+        provider: &str,
+    ) -> Result<bool, IdentityRepositoryError>;
+    async fn encrypt_pending_refresh_tokens(
+        &self,
+        secret: &str,
+    ) -> Result<u64, IdentityRepositoryError>;
 }
 
 pub struct DbIdentityRepository {
@@ -35,8 +53,20 @@ impl DbIdentityRepository {
 
 #[async_trait]
 impl IdentityRepository for DbIdentityRepository {
-    async fn get(&self, _id: u64) -> Result<Identity, IdentityRepositoryError> {
-        todo!()
+    async fn get(
+        &self,
+        user_id: &uuid::Uuid,
+        provider: &str,
+    ) -> Result<Identity, IdentityRepositoryError> {
+        sqlx::query_as!(
+            Identity,
+            r#"SELECT * FROM identity WHERE user_id = $1 AND provider = $2"#,
+            user_id,
+            provider
+        )
+        .fetch_one(&self.db)
+        .await
+        .map_err(IdentityRepositoryError::InternalDbError)
     }
 
     async fn find(&self, id: &str, provider: &str) -> Result<Identity, IdentityRepositoryError> {
@@ -64,15 +94,51 @@ impl IdentityRepository for DbIdentityRepository {
         .await
         .map_err(IdentityRepositoryError::InternalDbError)
     }
+
+    async fn find_all_by_user(
+        &self,
+        user_id: &uuid::Uuid,
+    ) -> Result<Vec<Identity>, IdentityRepositoryError> {
+        sqlx::query_as!(
+            Identity,
+            r#"SELECT * FROM identity WHERE user_id = $1 ORDER BY created_at"#,
+            user_id
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(IdentityRepositoryError::InternalDbError)
+    }
+
     async fn add(&self, identity: &Identity) -> Result<Identity, IdentityRepositoryError> {
         let query_result = sqlx::query_as!(
             Identity,
-            r#"INSERT INTO identity (id, user_id, identity_data, provider, last_sign_in_at) VALUES ($1, $2, $3, $4, $5) RETURNING *"#,
+            r#"INSERT INTO identity (id, user_id, identity_data, provider, last_sign_in_at, provider_refresh_token, provider_access_token, provider_token_expires_at, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, NOW(), NOW()) RETURNING *"#,
             identity.id,
             identity.user_id,
             identity.identity_data,
             identity.provider,
-            identity.last_sign_in_at
+            identity.last_sign_in_at,
+            identity.provider_refresh_token,
+            identity.provider_access_token,
+            identity.provider_token_expires_at
+        )
+            .fetch_one(&self.db)
+            .await?;
+
+        Ok(query_result)
+    }
+
+    async fn update(&self, identity: Identity) -> Result<Identity, IdentityRepositoryError> {
+        let query_result = sqlx::query_as!(
+            Identity,
+            r#"UPDATE identity SET identity_data = $3, last_sign_in_at = $4, provider_refresh_token = $5, provider_access_token = $6, provider_token_expires_at = $7, updated_at = NOW() WHERE id = $1 AND provider = $2 RETURNING *"#,
+            identity.id,
+            identity.provider,
+            identity.identity_data,
+            identity.last_sign_in_at,
+            identity.provider_refresh_token,
+            identity.provider_access_token,
+            identity.provider_token_expires_at
         )
             .fetch_one(&self.db)
             .await?;
@@ -80,11 +146,80 @@ impl IdentityRepository for DbIdentityRepository {
         Ok(query_result)
     }
 
-    async fn update(&self, _identity: Identity) -> Result<Identity, IdentityRepositoryError> {
-        todo!()
+    async fn delete(&self, id: &str, provider: &str) -> Result<(), IdentityRepositoryError> {
+        sqlx::query!(
+            "DELETE FROM identity WHERE id = $1 AND provider = $2",
+            id,
+            provider
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
     }
 
-    async fn delete(&self, _id: u64) -> Result<(), IdentityRepositoryError> {
-        todo!()
+    /// Deletes the identity unless it is the last one of the user, returns whether it was deleted
+    async fn delete_unless_last(
+        &self,
+        user_id: &uuid::Uuid,
+        provider: &str,
+    ) -> Result<bool, IdentityRepositoryError> {
+        let mut tx = self.db.begin().await?;
+
+        // Concurrent unlinks wait for the lock, then count the identities the others left
+        let identities = sqlx::query!(
+            "SELECT provider FROM identity WHERE user_id = $1 FOR UPDATE",
+            user_id
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        if identities.len() < 2 {
+            return Ok(false);
+        }
+
+        sqlx::query!(
+            "DELETE FROM identity WHERE user_id = $1 AND provider = $2",
+            user_id,
+            provider
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Encrypts the refresh tokens stored in plaintext before encryption was added
+    async fn encrypt_pending_refresh_tokens(
+        &self,
+        secret: &str,
+    ) -> Result<u64, IdentityRepositoryError> {
+        let mut tx = self.db.begin().await?;
+
+        let pending = sqlx::query!(
+            "SELECT identity_id, provider, refresh_token FROM pending_provider_token_encryption FOR UPDATE"
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        for row in &pending {
+            // A token stored since the migration is newer, keep it
+            sqlx::query!(
+                "UPDATE identity SET provider_refresh_token = $3 WHERE id = $1 AND provider = $2 AND provider_refresh_token IS NULL",
+                row.identity_id,
+                row.provider,
+                encrypt_secret(&row.refresh_token, secret)
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        sqlx::query!("DELETE FROM pending_provider_token_encryption")
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(pending.len() as u64)
     }
 }
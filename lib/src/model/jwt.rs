@@ -2,7 +2,8 @@ use chrono::{Duration, Utc};
 use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation};
 use serde::{Deserialize, Serialize};
 
-use crate::constants::{JWT_AUD_CLAIM, JWT_EXPIRED_IN, JWT_ISS_CLAIM};
+use crate::oauth::parse_scope;
+use crate::constants::{JWT_AUD_CLAIM, JWT_CLIENT_AUD_CLAIM, JWT_EXPIRED_IN, JWT_ISS_CLAIM};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct JWTClaims {
@@ -10,12 +11,33 @@ pub struct JWTClaims {
     pub exp: i64, // Expires at
     pub iat: i64, // Issued at
     pub iss: String,
-    pub sub: String, //User id
-    pub sid: String, //Session id
+    pub sub: String, //User id, or client id of client credentials tokens
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub sid: String, //Session id, empty for client credentials tokens
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub scope: String, //Space separated scopes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<Actor>, //Super user acting as the subject, see RFC 8693 section 4.1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cnf: Option<Confirmation>, //Key the token is bound to, see RFC 9449 section 6.1
+}
+
+/// Confirmation of a sender constrained token, the thumbprint of the DPoP key
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Confirmation {
+    pub jkt: String,
+}
+
+/// Party acting on behalf of the subject of a token, earlier actors are nested
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Actor {
+    pub sub: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<Box<Actor>>,
 }
 
 impl JWTClaims {
-    pub fn new(sub: String, sid: String) -> Self {
+    pub fn new(sub: String, sid: String, scope: String) -> Self {
         let now = Utc::now();
         Self {
             aud: JWT_AUD_CLAIM.to_string(),
@@ -24,8 +46,36 @@ impl JWTClaims {
             iss: JWT_ISS_CLAIM.to_string(),
             sub,
             sid,
+            scope,
+            act: None,
+            cnf: None,
         }
     }
+
+    /// Token of a backend service, it has no user and no session
+    pub fn new_client(client_id: String, scope: String, expires_in: i64) -> Self {
+        let now = Utc::now();
+        Self {
+            aud: JWT_CLIENT_AUD_CLAIM.to_string(),
+            exp: (now + Duration::seconds(expires_in)).timestamp(),
+            iat: now.timestamp(),
+            iss: JWT_ISS_CLAIM.to_string(),
+            sub: client_id,
+            sid: String::new(),
+            scope,
+            act: None,
+            cnf: None,
+        }
+    }
+
+    /// Binds the token to the DPoP key of the thumbprint
+    pub fn bind(&mut self, jkt: Option<&str>) {
+        self.cnf = jkt.map(|v| Confirmation { jkt: v.to_string() });
+    }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        parse_scope(&self.scope).contains(&scope)
+    }
 }
 
 /// Create a json web token (JWT)
@@ -37,7 +87,7 @@ pub fn encode_jwt(
     jsonwebtoken::encode(&Header::default(), jwt_claims, &encoding_key)
 }
 
-/// Decode a json web token (JWT)
+/// Decode a json web token (JWT) of any audience, user routes use `decode_user_jwt`
 pub fn decode_jwt(token: &str, secret: String) -> Result<JWTClaims, jsonwebtoken::errors::Error> {
     let mut validator = Validation::default();
     validator.validate_aud = false;
@@ -46,6 +96,18 @@ pub fn decode_jwt(token: &str, secret: String) -> Result<JWTClaims, jsonwebtoken
     decode_jwt_with_validator(token, secret, &validator)
 }
 
+/// Decode an access token of a user, client credentials tokens are refused
+pub fn decode_user_jwt(
+    token: &str,
+    secret: String,
+) -> Result<JWTClaims, jsonwebtoken::errors::Error> {
+    let mut validator = Validation::default();
+    validator.set_audience(&[JWT_AUD_CLAIM]);
+    validator.validate_nbf = false;
+
+    decode_jwt_with_validator(token, secret, &validator)
+}
+
 pub(crate) fn decode_jwt_with_validator(
     token: &str,
     secret: String,
@@ -55,3 +117,22 @@ pub(crate) fn decode_jwt_with_validator(
     jsonwebtoken::decode::<JWTClaims>(token, &decoding_key, validator)
         .map(|data| data.claims)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_user_jwt() {
+        let secret = "test-jwt-secret".to_string();
+
+        let user = JWTClaims::new("user-id".to_string(), "sid".to_string(), String::new());
+        let token = encode_jwt(&user, secret.clone()).unwrap();
+        assert_eq!(decode_user_jwt(&token, secret.clone()).unwrap().sub, "user-id");
+
+        let client = JWTClaims::new_client("client-id".to_string(), String::new(), 60);
+        let token = encode_jwt(&client, secret.clone()).unwrap();
+        assert!(decode_jwt(&token, secret.clone()).is_ok());
+        assert!(decode_user_jwt(&token, secret).is_err());
+    }
+}
@@ -1,7 +1,20 @@
 pub mod access_token;
+pub mod audit_log;
+pub mod audit_log_repository;
+pub mod authorization;
+pub mod authorization_repository;
+pub mod client;
+pub mod client_repository;
+pub mod device_authorization;
+pub mod device_authorization_repository;
+pub mod flow_state;
+pub mod flow_state_repository;
 pub mod identity;
 pub mod identity_repository;
 pub mod jwt;
+pub mod provider_notification_repository;
+pub mod provider_token_revocation;
+pub mod provider_token_revocation_repository;
 pub mod refresh_token;
 pub mod refresh_token_repository;
 pub mod session;
@@ -0,0 +1,78 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ProviderNotificationRepositoryError {
+    #[error("Internal data store error")]
+    InternalDbError(#[from] sqlx::Error),
+}
+
+#[async_trait]
+pub trait ProviderNotificationRepository {
+    /// Records the notification, false when it was already recorded
+    async fn add(
+        &self,
+        provider: &str,
+        jti: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool, ProviderNotificationRepositoryError>;
+    async fn delete(
+        &self,
+        provider: &str,
+        jti: &str,
+    ) -> Result<(), ProviderNotificationRepositoryError>;
+}
+
+pub struct DbProviderNotificationRepository {
+    db: PgPool,
+}
+
+impl DbProviderNotificationRepository {
+    pub fn new(pool: PgPool) -> DbProviderNotificationRepository {
+        Self { db: pool }
+    }
+}
+
+#[async_trait]
+impl ProviderNotificationRepository for DbProviderNotificationRepository {
+    async fn add(
+        &self,
+        provider: &str,
+        jti: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool, ProviderNotificationRepositoryError> {
+        // Expired notifications are rejected by their iat, they don't need to be kept
+        sqlx::query!("DELETE FROM provider_notification WHERE expires_at < now()")
+            .execute(&self.db)
+            .await?;
+
+        let result = sqlx::query!(
+            r#"INSERT INTO provider_notification (provider, jti, expires_at) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING"#,
+            provider,
+            jti,
+            expires_at
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn delete(
+        &self,
+        provider: &str,
+        jti: &str,
+    ) -> Result<(), ProviderNotificationRepositoryError> {
+        sqlx::query!(
+            "DELETE FROM provider_notification WHERE provider = $1 AND jti = $2",
+            provider,
+            jti
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,44 @@
+use crate::config::AppConfig;
+use crate::model::identity::Identity;
+use crate::utils::crypto::decrypt_secret;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Provider refresh token of a deleted identity, kept until the provider has revoked it
+#[derive(Debug, Deserialize, sqlx::FromRow, Serialize, Clone)]
+pub struct ProviderTokenRevocation {
+    pub id: uuid::Uuid,
+    pub identity_id: String,
+    pub provider: String,
+    /// Client the token was issued to, the audience of the ID token of native apps
+    pub client_id: Option<String>,
+    /// Encrypted like `provider_refresh_token` of the identity
+    #[serde(skip_serializing)]
+    pub refresh_token: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl ProviderTokenRevocation {
+    /// Pending revocation of the refresh token of the identity, none without a refresh token
+    pub fn new(identity: &Identity) -> Option<Self> {
+        let refresh_token = identity.provider_refresh_token.clone()?;
+
+        Some(Self {
+            id: uuid::Uuid::new_v4(),
+            identity_id: identity.id.clone(),
+            provider: identity.provider.clone(),
+            client_id: identity
+                .identity_data
+                .get("aud")
+                .and_then(|v| v.as_str())
+                .map(|v| v.to_string()),
+            refresh_token,
+            created_at: Utc::now(),
+        })
+    }
+
+    pub fn decrypted_refresh_token(&self) -> Option<String> {
+        let secret = AppConfig::provider_token_secret()?;
+        decrypt_secret(&self.refresh_token, &secret)
+    }
+}
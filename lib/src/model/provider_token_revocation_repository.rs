@@ -0,0 +1,80 @@
+use async_trait::async_trait;
+use sqlx::PgPool;
+use thiserror::Error;
+
+use crate::model::provider_token_revocation::ProviderTokenRevocation;
+
+#[derive(Error, Debug)]
+pub enum ProviderTokenRevocationRepositoryError {
+    #[error("Internal data store error")]
+    InternalDbError(#[from] sqlx::Error),
+}
+
+#[async_trait]
+pub trait ProviderTokenRevocationRepository {
+    async fn add_all(
+        &self,
+        revocations: &[ProviderTokenRevocation],
+    ) -> Result<(), ProviderTokenRevocationRepositoryError>;
+    async fn find_all(
+        &self,
+    ) -> Result<Vec<ProviderTokenRevocation>, ProviderTokenRevocationRepositoryError>;
+    async fn delete(&self, id: &uuid::Uuid) -> Result<(), ProviderTokenRevocationRepositoryError>;
+}
+
+pub struct DbProviderTokenRevocationRepository {
+    db: PgPool,
+}
+
+impl DbProviderTokenRevocationRepository {
+    pub fn new(pool: PgPool) -> DbProviderTokenRevocationRepository {
+        Self { db: pool }
+    }
+}
+
+#[async_trait]
+impl ProviderTokenRevocationRepository for DbProviderTokenRevocationRepository {
+    async fn add_all(
+        &self,
+        revocations: &[ProviderTokenRevocation],
+    ) -> Result<(), ProviderTokenRevocationRepositoryError> {
+        let mut tx = self.db.begin().await?;
+
+        for revocation in revocations {
+            sqlx::query!(
+                r#"INSERT INTO provider_token_revocation (id, identity_id, provider, client_id, refresh_token, created_at) VALUES ($1, $2, $3, $4, $5, $6)"#,
+                revocation.id,
+                revocation.identity_id,
+                revocation.provider,
+                revocation.client_id,
+                revocation.refresh_token,
+                revocation.created_at
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn find_all(
+        &self,
+    ) -> Result<Vec<ProviderTokenRevocation>, ProviderTokenRevocationRepositoryError> {
+        sqlx::query_as!(
+            ProviderTokenRevocation,
+            r#"SELECT * FROM provider_token_revocation ORDER BY created_at"#
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(ProviderTokenRevocationRepositoryError::InternalDbError)
+    }
+
+    async fn delete(&self, id: &uuid::Uuid) -> Result<(), ProviderTokenRevocationRepositoryError> {
+        sqlx::query!("DELETE FROM provider_token_revocation WHERE id = $1", id)
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -1,21 +1,32 @@
 use chrono::{DateTime, Utc};
 use uuid::Uuid;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Session {
     pub id: Uuid,
     pub user_id: Uuid,
+    pub client_id: Option<String>,
+    /// Scopes granted at sign in, `None` for sessions older than scopes
+    pub scope: Option<String>,
+    /// DPoP key the refresh tokens are bound to
+    pub dpop_jkt: Option<String>,
+    /// Browser session a relying party session was started from, the `sid` known to the client
+    pub sso_session_id: Option<Uuid>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
 impl Session {
-    pub fn new(user_id: Uuid) -> Session {
+    pub fn new(user_id: Uuid, client_id: Option<String>, scope: Option<String>) -> Session {
         let now = Utc::now();
 
         Self {
             id: uuid::Uuid::new_v4(),
             user_id,
+            client_id,
+            scope,
+            dpop_jkt: None,
+            sso_session_id: None,
             created_at: now,
             updated_at: now,
         }
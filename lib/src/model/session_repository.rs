@@ -14,8 +14,18 @@ pub enum SessionRepositoryError {
 pub trait SessionRepository {
     async fn get(&self, id: uuid::Uuid) -> Result<Session, SessionRepositoryError>;
     async fn add(&self, session: Session) -> Result<Session, SessionRepositoryError>;
+    /// Relying party sessions started from the browser session
+    async fn find_all_by_sso_session(
+        &self,
+        sso_session_id: &uuid::Uuid,
+    ) -> Result<Vec<Session>, SessionRepositoryError>;
+    async fn find_all_by_user(
+        &self,
+        user_id: &uuid::Uuid,
+    ) -> Result<Vec<Session>, SessionRepositoryError>;
     async fn update(&self, session: Session) -> Result<Session, SessionRepositoryError>;
     async fn delete(&self, id: &uuid::Uuid) -> Result<(), SessionRepositoryError>;
+    async fn delete_all_by_user(&self, user_id: &uuid::Uuid) -> Result<(), SessionRepositoryError>;
 }
 
 pub struct DbSessionRepository {
@@ -42,9 +52,13 @@ impl SessionRepository for DbSessionRepository {
     async fn add(&self, session: Session) -> Result<Session, SessionRepositoryError> {
         let query_result = sqlx::query_as!(
             Session,
-            r#"INSERT INTO auth_session (id, user_id) VALUES ($1, $2) RETURNING *"#,
+            r#"INSERT INTO auth_session (id, user_id, client_id, scope, dpop_jkt, sso_session_id) VALUES ($1, $2, $3, $4, $5, $6) RETURNING *"#,
             session.id,
-            session.user_id
+            session.user_id,
+            session.client_id,
+            session.scope,
+            session.dpop_jkt,
+            session.sso_session_id
         )
         .fetch_one(&self.db)
         .await?;
@@ -52,6 +66,36 @@ impl SessionRepository for DbSessionRepository {
         Ok(query_result)
     }
 
+    async fn find_all_by_sso_session(
+        &self,
+        sso_session_id: &uuid::Uuid,
+    ) -> Result<Vec<Session>, SessionRepositoryError> {
+        let query_result = sqlx::query_as!(
+            Session,
+            r#"SELECT * FROM auth_session WHERE sso_session_id = $1"#,
+            sso_session_id
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(query_result)
+    }
+
+    async fn find_all_by_user(
+        &self,
+        user_id: &uuid::Uuid,
+    ) -> Result<Vec<Session>, SessionRepositoryError> {
+        let query_result = sqlx::query_as!(
+            Session,
+            r#"SELECT * FROM auth_session WHERE user_id = $1"#,
+            user_id
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(query_result)
+    }
+
     async fn update(&self, _session: Session) -> Result<Session, SessionRepositoryError> {
         todo!()
     }
@@ -63,4 +107,12 @@ impl SessionRepository for DbSessionRepository {
 
         Ok(())
     }
+
+    async fn delete_all_by_user(&self, user_id: &uuid::Uuid) -> Result<(), SessionRepositoryError> {
+        let _ = sqlx::query!("DELETE FROM auth_session WHERE user_id = $1", user_id)
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
 }
@@ -1,5 +1,7 @@
 use chrono::prelude::*;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sqlx::types::JsonValue;
 use thiserror::Error;
 
 use crate::utils::crypto::compare_hash_and_password;
@@ -30,6 +32,7 @@ pub struct User {
     pub confirmed_at: Option<DateTime<Utc>>,
     pub created_at: Option<DateTime<Utc>>,
     pub updated_at: Option<DateTime<Utc>>,
+    pub user_metadata: JsonValue,
 }
 
 impl User {
@@ -43,10 +46,11 @@ impl User {
             confirmed_at: None,
             created_at: None,
             updated_at: None,
+            user_metadata: JsonValue::Object(Map::new()),
         }
     }
 
-    pub fn new_from_provider(email: &str) -> User {
+    pub fn new_from_provider(email: &str, profile: Map<String, Value>) -> User {
         Self {
             id: uuid::Uuid::new_v4(),
             email: Some(email.to_string()),
@@ -56,6 +60,7 @@ impl User {
             confirmed_at: None,
             created_at: None,
             updated_at: None,
+            user_metadata: JsonValue::Object(profile),
         }
     }
     pub fn mock() -> User {
@@ -68,6 +73,7 @@ impl User {
             confirmed_at: None,
             created_at: None,
             updated_at: None,
+            user_metadata: JsonValue::Object(Map::new()),
         }
     }
 }
@@ -82,4 +88,22 @@ impl User {
 
         Ok(compare_hash_and_password(pass.as_str(), password))
     }
+
+    /// Merges profile claims into `user_metadata`, returns whether anything changed
+    pub fn merge_metadata(&mut self, profile: Map<String, Value>) -> bool {
+        let JsonValue::Object(metadata) = &mut self.user_metadata else {
+            self.user_metadata = JsonValue::Object(profile);
+            return true;
+        };
+
+        let mut changed = false;
+        for (key, value) in profile {
+            if metadata.get(&key) != Some(&value) {
+                metadata.insert(key, value);
+                changed = true;
+            }
+        }
+
+        changed
+    }
 }
@@ -1,4 +1,5 @@
 use async_trait::async_trait;
+use sqlx::types::JsonValue;
 use sqlx::{query, PgPool, Row};
 use thiserror::Error;
 
@@ -19,6 +20,11 @@ pub trait UserRepository {
     async fn find_by_email(&self, email: &str) -> Result<User, UserRepositoryError>;
     async fn contains_with_email(&self, email: &str) -> Result<bool, UserRepositoryError>;
     async fn add(&self, account: User) -> Result<User, UserRepositoryError>;
+    async fn update_metadata(
+        &self,
+        user_id: &uuid::Uuid,
+        user_metadata: &JsonValue,
+    ) -> Result<User, UserRepositoryError>;
     async fn delete(&self, user_id: &uuid::Uuid) -> Result<(), UserRepositoryError>;
 }
 
@@ -61,10 +67,28 @@ impl UserRepository for DbUserRepository {
     async fn add(&self, user: User) -> Result<User, UserRepositoryError> {
         let query_result = sqlx::query_as!(
             User,
-            r#"INSERT INTO auth_user (id,email,encrypted_password) VALUES ($1, $2, $3) RETURNING *"#,
+            r#"INSERT INTO auth_user (id,email,encrypted_password,user_metadata) VALUES ($1, $2, $3, $4) RETURNING *"#,
             user.id,
             user.email,
-            user.encrypted_password
+            user.encrypted_password,
+            user.user_metadata
+        )
+            .fetch_one(&self.db)
+            .await?;
+
+        Ok(query_result)
+    }
+
+    async fn update_metadata(
+        &self,
+        user_id: &uuid::Uuid,
+        user_metadata: &JsonValue,
+    ) -> Result<User, UserRepositoryError> {
+        let query_result = sqlx::query_as!(
+            User,
+            r#"UPDATE auth_user SET user_metadata = $2, updated_at = NOW() WHERE id = $1 RETURNING *"#,
+            user_id,
+            user_metadata
         )
             .fetch_one(&self.db)
             .await?;
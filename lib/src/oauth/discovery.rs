@@ -0,0 +1,104 @@
+use serde::Serialize;
+
+use crate::oauth::{
+    CODE_CHALLENGE_METHOD_S256, GRANT_TYPE_AUTHORIZATION_CODE, GRANT_TYPE_CLIENT_CREDENTIALS,
+    GRANT_TYPE_REFRESH_TOKEN, GRANT_TYPE_TOKEN_EXCHANGE, SCOPE_EMAIL, SCOPE_OPENID, SCOPE_PROFILE,
+};
+
+/// OpenID provider metadata served on `/.well-known/openid-configuration`
+#[derive(Debug, Clone, Serialize)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub userinfo_endpoint: String,
+    pub jwks_uri: String,
+    pub introspection_endpoint: String,
+    pub revocation_endpoint: String,
+    pub registration_endpoint: String,
+    pub pushed_authorization_request_endpoint: String,
+    pub end_session_endpoint: String,
+    pub scopes_supported: Vec<&'static str>,
+    pub response_types_supported: Vec<&'static str>,
+    pub grant_types_supported: Vec<&'static str>,
+    pub subject_types_supported: Vec<&'static str>,
+    pub id_token_signing_alg_values_supported: Vec<&'static str>,
+    pub token_endpoint_auth_methods_supported: Vec<&'static str>,
+    pub code_challenge_methods_supported: Vec<&'static str>,
+    pub claims_supported: Vec<&'static str>,
+    pub request_parameter_supported: bool,
+    /// Only request uris of the PAR endpoint are accepted, they are never fetched
+    pub request_uri_parameter_supported: bool,
+    pub request_object_signing_alg_values_supported: Vec<&'static str>,
+    pub dpop_signing_alg_values_supported: Vec<&'static str>,
+    pub frontchannel_logout_supported: bool,
+    pub frontchannel_logout_session_supported: bool,
+    pub backchannel_logout_supported: bool,
+    pub backchannel_logout_session_supported: bool,
+}
+
+impl ProviderMetadata {
+    /// Endpoints are served below the issuer, e.g. `https://auth.example.com/api/v1/oauth/token`
+    pub fn new(issuer: &str) -> Self {
+        let endpoint = |path: &str| format!("{}/oauth/{}", issuer.trim_end_matches('/'), path);
+
+        Self {
+            issuer: issuer.to_string(),
+            authorization_endpoint: endpoint("authorize"),
+            token_endpoint: endpoint("token"),
+            userinfo_endpoint: endpoint("userinfo"),
+            jwks_uri: endpoint("jwks"),
+            introspection_endpoint: endpoint("introspect"),
+            revocation_endpoint: endpoint("revoke"),
+            registration_endpoint: endpoint("register"),
+            pushed_authorization_request_endpoint: endpoint("par"),
+            end_session_endpoint: endpoint("logout"),
+            scopes_supported: vec![SCOPE_OPENID, SCOPE_EMAIL, SCOPE_PROFILE],
+            response_types_supported: vec!["code"],
+            grant_types_supported: vec![
+                GRANT_TYPE_AUTHORIZATION_CODE,
+                GRANT_TYPE_REFRESH_TOKEN,
+                GRANT_TYPE_CLIENT_CREDENTIALS,
+                GRANT_TYPE_TOKEN_EXCHANGE,
+            ],
+            subject_types_supported: vec!["public"],
+            id_token_signing_alg_values_supported: vec!["RS256"],
+            token_endpoint_auth_methods_supported: vec![
+                "client_secret_basic",
+                "client_secret_post",
+                "none",
+            ],
+            code_challenge_methods_supported: vec![CODE_CHALLENGE_METHOD_S256],
+            claims_supported: vec![
+                "iss",
+                "sub",
+                "aud",
+                "exp",
+                "iat",
+                "auth_time",
+                "nonce",
+                "sid",
+                "at_hash",
+                "email",
+                "email_verified",
+                "name",
+                "given_name",
+                "family_name",
+                "picture",
+                "locale",
+            ],
+            request_parameter_supported: true,
+            request_uri_parameter_supported: false,
+            request_object_signing_alg_values_supported: vec![
+                "RS256", "RS384", "RS512", "PS256", "ES256", "ES384", "EdDSA",
+            ],
+            dpop_signing_alg_values_supported: vec![
+                "RS256", "RS384", "RS512", "PS256", "ES256", "ES384", "EdDSA",
+            ],
+            frontchannel_logout_supported: true,
+            frontchannel_logout_session_supported: true,
+            backchannel_logout_supported: true,
+            backchannel_logout_session_supported: true,
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::Utc;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use openidconnect::url::Url;
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::constants::DPOP_PROOF_EXPIRED_IN;
+use crate::oauth::OAuthError;
+
+/// Header carrying the proof, and the scheme and token type of bound access tokens
+pub const DPOP: &str = "DPoP";
+const DPOP_PROOF_TYPE: &str = "dpop+jwt";
+
+/// Proofs must be signed with an asymmetric key, see RFC 9449 section 4.3
+pub const DPOP_SIGNING_ALGORITHMS: [Algorithm; 7] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+/// Claims of a DPoP proof, see RFC 9449 section 4.2
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DPoPClaims {
+    pub jti: String,
+    pub htm: String,
+    pub htu: String,
+    pub iat: i64,
+    /// Hash of the access token sent with the proof to a protected resource
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ath: Option<String>,
+}
+
+/// Proof with a valid signature, `jkt` is the thumbprint of its public key
+#[derive(Debug, Clone)]
+pub struct DPoPProof {
+    pub jkt: String,
+    pub jti: String,
+    pub iat: i64,
+}
+
+/// Checks the proof of a request, replays are detected by the caller
+pub fn decode_proof(
+    proof: &str,
+    htm: &str,
+    htu: &str,
+    access_token: Option<&str>,
+) -> Result<DPoPProof, OAuthError> {
+    let invalid = |reason: &str| OAuthError::InvalidDPoPProof(reason.to_string());
+
+    let Ok(header) = jsonwebtoken::decode_header(proof) else {
+        return Err(invalid("malformed proof"));
+    };
+
+    if header.typ.as_deref() != Some(DPOP_PROOF_TYPE) {
+        return Err(invalid("typ must be dpop+jwt"));
+    }
+
+    if !DPOP_SIGNING_ALGORITHMS.contains(&header.alg) {
+        return Err(invalid("unsupported signing algorithm"));
+    }
+
+    let Some(jwk) = header.jwk else {
+        return Err(invalid("jwk is required"));
+    };
+    let (Ok(key), Some(jkt)) = (
+        DecodingKey::from_jwk(&jwk),
+        serde_json::to_value(&jwk).ok().and_then(|v| jwk_thumbprint(&v)),
+    ) else {
+        return Err(invalid("unsupported jwk"));
+    };
+
+    let mut validation = Validation::new(header.alg);
+    validation.validate_exp = false;
+    validation.required_spec_claims.clear();
+
+    let Ok(data) = jsonwebtoken::decode::<DPoPClaims>(proof, &key, &validation) else {
+        return Err(invalid("invalid signature or claims"));
+    };
+    let claims = data.claims;
+
+    if claims.htm != htm || !same_target_uri(&claims.htu, htu) {
+        return Err(invalid("htm or htu does not match the request"));
+    }
+
+    if (Utc::now().timestamp() - claims.iat).abs() > DPOP_PROOF_EXPIRED_IN {
+        return Err(invalid("iat is outside of the acceptable window"));
+    }
+
+    if let Some(access_token) = access_token {
+        if claims.ath.as_deref() != Some(access_token_hash(access_token).as_str()) {
+            return Err(invalid("ath does not match the access token"));
+        }
+    }
+
+    Ok(DPoPProof {
+        jkt,
+        jti: claims.jti,
+        iat: claims.iat,
+    })
+}
+
+/// RFC 7638 thumbprint of a public JWK
+pub fn jwk_thumbprint(jwk: &Value) -> Option<String> {
+    let member = |name: &str| jwk.get(name).and_then(Value::as_str);
+
+    // Required members in lexicographic order without whitespace, see RFC 7638 section 3
+    let input = match member("kty")? {
+        "RSA" => format!(
+            r#"{{"e":"{}","kty":"RSA","n":"{}"}}"#,
+            member("e")?,
+            member("n")?
+        ),
+        "EC" => format!(
+            r#"{{"crv":"{}","kty":"EC","x":"{}","y":"{}"}}"#,
+            member("crv")?,
+            member("x")?,
+            member("y")?
+        ),
+        "OKP" => format!(
+            r#"{{"crv":"{}","kty":"OKP","x":"{}"}}"#,
+            member("crv")?,
+            member("x")?
+        ),
+        _ => return None,
+    };
+
+    Some(URL_SAFE_NO_PAD.encode(digest(&SHA256, input.as_bytes())))
+}
+
+/// `ath` claim of a proof sent with the access token
+pub fn access_token_hash(access_token: &str) -> String {
+    URL_SAFE_NO_PAD.encode(digest(&SHA256, access_token.as_bytes()))
+}
+
+/// Target uris are compared without query and fragment, see RFC 9449 section 4.3
+fn same_target_uri(htu: &str, uri: &str) -> bool {
+    let (Ok(mut htu), Ok(mut uri)) = (Url::parse(htu), Url::parse(uri)) else {
+        return false;
+    };
+
+    for url in [&mut htu, &mut uri] {
+        url.set_query(None);
+        url.set_fragment(None);
+    }
+
+    htu == uri
+}
@@ -0,0 +1,116 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
+
+use crate::config::AppConfig;
+use crate::model::user::User;
+use crate::oauth::{SCOPE_EMAIL, SCOPE_PROFILE};
+
+/// Standard claims of the user, returned by the userinfo endpoint and embedded in ID tokens
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UserInfo {
+    pub sub: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email_verified: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub given_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub family_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub picture: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+}
+
+impl UserInfo {
+    /// Claims of the user released for the granted scopes
+    pub fn new(user: &User, email_verified: bool, scopes: &[&str]) -> Self {
+        let mut user_info = Self {
+            sub: user.id.to_string(),
+            ..Default::default()
+        };
+
+        if scopes.contains(&SCOPE_EMAIL) {
+            user_info.email = user.email.clone();
+            user_info.email_verified = Some(email_verified);
+        }
+
+        if scopes.contains(&SCOPE_PROFILE) {
+            let metadata = |key: &str| {
+                user.user_metadata
+                    .get(key)
+                    .and_then(|v| v.as_str())
+                    .map(|v| v.to_string())
+            };
+
+            user_info.name = metadata("name");
+            user_info.given_name = metadata("given_name");
+            user_info.family_name = metadata("family_name");
+            user_info.picture = metadata("picture");
+            user_info.locale = metadata("locale");
+        }
+
+        user_info
+    }
+}
+
+/// OpenID Connect ID token, see OpenID Connect Core section 2
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub aud: String,
+    pub exp: i64,
+    pub iat: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth_time: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub at_hash: Option<String>,
+    #[serde(flatten)]
+    pub user_info: UserInfo,
+}
+
+impl IdTokenClaims {
+    pub fn new(client_id: &str, user_info: UserInfo, auth_time: Option<DateTime<Utc>>) -> Self {
+        let now = Utc::now();
+
+        Self {
+            iss: AppConfig::oidc_issuer(),
+            aud: client_id.to_string(),
+            exp: (now + Duration::minutes(AppConfig::jwt_expires_in())).timestamp(),
+            iat: now.timestamp(),
+            auth_time: auth_time.map(|v| v.timestamp()),
+            nonce: None,
+            sid: None,
+            at_hash: None,
+            user_info,
+        }
+    }
+}
+
+/// Left half of the SHA-256 of the access token, base64url encoded
+pub fn at_hash(access_token: &str) -> String {
+    let hash = digest(&SHA256, access_token.as_bytes());
+    URL_SAFE_NO_PAD.encode(&hash.as_ref()[..16])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_at_hash() {
+        // Example of OpenID Connect Core appendix A.3
+        let access_token = "jHkWEdUXMU1BwAsC4vtUsZwnNvTIxEl0z9K3vx5KF0Y";
+        assert_eq!(at_hash(access_token), "77QmUPtjPfzWtF2AnpK9RQ");
+    }
+}
@@ -0,0 +1,87 @@
+use serde::Serialize;
+
+use crate::constants::TOKEN_TYPE;
+use crate::model::jwt::{Confirmation, JWTClaims};
+use crate::model::refresh_token::RefreshToken;
+use crate::model::session::Session;
+use crate::oauth::dpop::DPOP;
+
+pub const TOKEN_TYPE_REFRESH_TOKEN: &str = "refresh_token";
+
+/// Introspection response, see RFC 7662 section 2.2
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TokenIntrospection {
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iat: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    /// Session of the token, client credentials tokens have none
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
+    /// Key of DPoP bound tokens
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cnf: Option<Confirmation>,
+}
+
+impl TokenIntrospection {
+    /// Nothing else is disclosed about invalid, expired or revoked tokens
+    pub fn inactive() -> Self {
+        Self::default()
+    }
+
+    pub fn access_token(claims: JWTClaims, session: Option<&Session>) -> Self {
+        let client_id = match session {
+            Some(session) => session.client_id.clone(),
+            // Client credentials tokens are issued to the client itself
+            None => Some(claims.sub.clone()),
+        };
+
+        let token_type = match claims.cnf {
+            Some(_) => DPOP,
+            None => TOKEN_TYPE,
+        };
+
+        Self {
+            active: true,
+            scope: Some(claims.scope),
+            client_id,
+            token_type: Some(token_type.to_string()),
+            exp: Some(claims.exp),
+            iat: Some(claims.iat),
+            sub: Some(claims.sub),
+            aud: Some(claims.aud),
+            iss: Some(claims.iss),
+            sid: session.map(|v| v.id.to_string()),
+            cnf: claims.cnf,
+        }
+    }
+
+    pub fn refresh_token(refresh_token: &RefreshToken, session: &Session) -> Self {
+        Self {
+            active: true,
+            scope: session.scope.clone(),
+            client_id: session.client_id.clone(),
+            token_type: Some(TOKEN_TYPE_REFRESH_TOKEN.to_string()),
+            exp: None,
+            iat: Some(refresh_token.created_at.timestamp()),
+            sub: Some(refresh_token.user_id.to_string()),
+            aud: None,
+            iss: None,
+            sid: Some(session.id.to_string()),
+            cnf: session.dpop_jkt.clone().map(|jkt| Confirmation { jkt }),
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use crate::config::AppConfig;
+use crate::constants::{LOGOUT_MAX_ATTEMPTS, LOGOUT_TOKEN_EXPIRED_IN};
+use crate::model::client_repository::ClientRepository;
+use crate::model::session::Session;
+use crate::oidc::oidc::OidcError;
+use crate::oidc::revocation::HttpClient;
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+
+/// Event of a logout token, see OpenID Connect Back-Channel Logout section 2.4
+pub const BACKCHANNEL_LOGOUT_EVENT: &str = "http://schemas.openid.net/event/backchannel-logout";
+/// Header type of logout tokens, so they are never accepted as ID tokens
+pub const LOGOUT_TOKEN_TYPE: &str = "logout+jwt";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogoutTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    pub aud: String,
+    pub iat: i64,
+    pub exp: i64,
+    pub jti: String,
+    pub sid: String,
+    pub events: Value,
+}
+
+impl LogoutTokenClaims {
+    pub fn new(client_id: &str, session: &Session) -> Self {
+        let now = Utc::now();
+
+        Self {
+            iss: AppConfig::oidc_issuer(),
+            sub: session.user_id.to_string(),
+            aud: client_id.to_string(),
+            iat: now.timestamp(),
+            exp: (now + Duration::minutes(LOGOUT_TOKEN_EXPIRED_IN)).timestamp(),
+            jti: uuid::Uuid::new_v4().to_string(),
+            sid: logout_sid(session),
+            events: json!({ BACKCHANNEL_LOGOUT_EVENT: {} }),
+        }
+    }
+}
+
+/// `sid` the relying party knows the session by, the browser session it was started from
+pub fn logout_sid(session: &Session) -> String {
+    session.sso_session_id.unwrap_or(session.id).to_string()
+}
+
+/// Notifies relying parties of ended sessions on their back-channel logout uri
+pub struct LogoutNotifier {
+    client_repository: Arc<dyn ClientRepository + Send + Sync + 'static>,
+    http_client: Arc<dyn HttpClient + Send + Sync>,
+}
+
+impl LogoutNotifier {
+    pub fn new(
+        client_repository: Arc<dyn ClientRepository + Send + Sync + 'static>,
+        http_client: Arc<dyn HttpClient + Send + Sync>,
+    ) -> Self {
+        Self {
+            client_repository,
+            http_client,
+        }
+    }
+
+    /// Posts the logout tokens in the background, each client is retried on its own
+    pub fn notify(&self, sessions: Vec<Session>) -> JoinHandle<()> {
+        let client_repository = self.client_repository.clone();
+        let http_client = self.http_client.clone();
+
+        tokio::spawn(async move {
+            let Some(signing_key) = AppConfig::oidc_signing_key() else {
+                return;
+            };
+
+            let mut notified = HashSet::new();
+            let mut deliveries = vec![];
+            for session in sessions {
+                let Some(client_id) = &session.client_id else {
+                    continue;
+                };
+
+                // Sessions of a client started from the same browser session share the sid
+                if !notified.insert((client_id.clone(), logout_sid(&session))) {
+                    continue;
+                }
+
+                let Ok(client) = client_repository.get(client_id).await else {
+                    continue;
+                };
+
+                let Some(uri) = client.backchannel_logout_uri else {
+                    continue;
+                };
+
+                let claims = LogoutTokenClaims::new(&client.id, &session);
+                let logout_token = match signing_key.sign_typed(&claims, LOGOUT_TOKEN_TYPE) {
+                    Ok(logout_token) => logout_token,
+                    Err(e) => {
+                        log::warn!("Failed to sign logout token of client {}: {}", client.id, e);
+                        continue;
+                    }
+                };
+
+                deliveries.push(tokio::spawn(deliver_logout_token(
+                    http_client.clone(),
+                    client.id,
+                    uri,
+                    logout_token,
+                )));
+            }
+
+            for delivery in deliveries {
+                let _ = delivery.await;
+            }
+        })
+    }
+}
+
+async fn deliver_logout_token(
+    http_client: Arc<dyn HttpClient + Send + Sync>,
+    client_id: String,
+    uri: String,
+    logout_token: String,
+) {
+    for attempt in 1..=LOGOUT_MAX_ATTEMPTS {
+        let result = match http_client
+            .post_form(&uri, &[("logout_token", logout_token.as_str())])
+            .await
+        {
+            Ok(response) if (200..=299).contains(&response.status) => Ok(()),
+            Ok(response) => Err(OidcError::LogoutRejected(response.status)),
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(()) => break,
+            Err(e) if attempt < LOGOUT_MAX_ATTEMPTS && is_retryable(&e) => {
+                tokio::time::sleep(std::time::Duration::from_secs(2u64.pow(attempt))).await;
+            }
+            Err(e) => {
+                log::warn!(
+                    "Failed to deliver logout token to client {}: {}",
+                    client_id,
+                    e
+                );
+                break;
+            }
+        }
+    }
+}
+
+/// Network errors, rate limits and outages of the relying party are worth another attempt
+fn is_retryable(error: &OidcError) -> bool {
+    match error {
+        OidcError::HttpError(_) => true,
+        OidcError::LogoutRejected(status) => *status == 429 || *status >= 500,
+        _ => false,
+    }
+}
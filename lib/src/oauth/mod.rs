@@ -0,0 +1,199 @@
+pub mod discovery;
+pub mod dpop;
+pub mod id_token;
+pub mod introspection;
+pub mod keys;
+pub mod logout;
+pub mod registration;
+pub mod request_object;
+
+use serde::Deserialize;
+use thiserror::Error;
+
+pub const SCOPE_OPENID: &str = "openid";
+pub const SCOPE_EMAIL: &str = "email";
+pub const SCOPE_PROFILE: &str = "profile";
+/// Scopes every relying party may request, they only release user claims
+pub const OIDC_SCOPES: [&str; 3] = [SCOPE_OPENID, SCOPE_EMAIL, SCOPE_PROFILE];
+
+pub const GRANT_TYPE_AUTHORIZATION_CODE: &str = "authorization_code";
+pub const GRANT_TYPE_REFRESH_TOKEN: &str = "refresh_token";
+pub const GRANT_TYPE_CLIENT_CREDENTIALS: &str = "client_credentials";
+pub const GRANT_TYPE_DEVICE_CODE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+pub const GRANT_TYPE_TOKEN_EXCHANGE: &str = "urn:ietf:params:oauth:grant-type:token-exchange";
+
+pub const TOKEN_TYPE_ACCESS_TOKEN: &str = "urn:ietf:params:oauth:token-type:access_token";
+
+pub const CODE_CHALLENGE_METHOD_S256: &str = "S256";
+
+/// Errors of the authorization and token endpoints, see RFC 6749 section 4.1.2.1 and 5.2
+/// RFC 8628 section 3.5 for the device grant, RFC 7009 section 2.2.1 for revocation, RFC 8693
+/// section 2.2.2 for token exchange, RFC 7591 section 3.2.2 for client registration, RFC 9101
+/// section 6.3 for request objects and RFC 9449 section 5 for DPoP
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum OAuthError {
+    #[error("{0}")]
+    InvalidRequest(String),
+
+    #[error("Client authentication failed")]
+    InvalidClient,
+
+    #[error("Invalid authorization grant: {0}")]
+    InvalidGrant(String),
+
+    #[error("Client is not allowed to use this grant")]
+    UnauthorizedClient,
+
+    #[error("Unsupported grant type")]
+    UnsupportedGrantType,
+
+    #[error("Unsupported response type")]
+    UnsupportedResponseType,
+
+    #[error("Invalid scope")]
+    InvalidScope,
+
+    #[error("User is not signed in")]
+    LoginRequired,
+
+    #[error("User denied the request")]
+    AccessDenied,
+
+    #[error("Server error")]
+    ServerError,
+
+    #[error("User has not approved the device yet")]
+    AuthorizationPending,
+
+    #[error("Polling too fast, increase the interval by 5 seconds")]
+    SlowDown,
+
+    #[error("Device code expired")]
+    ExpiredToken,
+
+    #[error("Token type can't be revoked")]
+    UnsupportedTokenType,
+
+    #[error("Audience can't be requested")]
+    InvalidTarget,
+
+    #[error("Invalid redirect uri")]
+    InvalidRedirectUri,
+
+    #[error("Invalid client metadata: {0}")]
+    InvalidClientMetadata(String),
+
+    #[error("Invalid access token")]
+    InvalidToken,
+
+    #[error("Invalid request object: {0}")]
+    InvalidRequestObject(String),
+
+    #[error("Unknown, expired or already used request_uri")]
+    InvalidRequestUri,
+
+    #[error("Invalid DPoP proof: {0}")]
+    InvalidDPoPProof(String),
+}
+
+impl OAuthError {
+    /// Error code sent to the client in the `error` parameter
+    pub fn code(&self) -> &'static str {
+        match self {
+            OAuthError::InvalidRequest(_) => "invalid_request",
+            OAuthError::InvalidClient => "invalid_client",
+            OAuthError::InvalidGrant(_) => "invalid_grant",
+            OAuthError::UnauthorizedClient => "unauthorized_client",
+            OAuthError::UnsupportedGrantType => "unsupported_grant_type",
+            OAuthError::UnsupportedResponseType => "unsupported_response_type",
+            OAuthError::InvalidScope => "invalid_scope",
+            OAuthError::LoginRequired => "login_required",
+            OAuthError::AccessDenied => "access_denied",
+            OAuthError::ServerError => "server_error",
+            OAuthError::AuthorizationPending => "authorization_pending",
+            OAuthError::SlowDown => "slow_down",
+            OAuthError::ExpiredToken => "expired_token",
+            OAuthError::UnsupportedTokenType => "unsupported_token_type",
+            OAuthError::InvalidTarget => "invalid_target",
+            OAuthError::InvalidRedirectUri => "invalid_redirect_uri",
+            OAuthError::InvalidClientMetadata(_) => "invalid_client_metadata",
+            OAuthError::InvalidToken => "invalid_token",
+            OAuthError::InvalidRequestObject(_) => "invalid_request_object",
+            OAuthError::InvalidRequestUri => "invalid_request_uri",
+            OAuthError::InvalidDPoPProof(_) => "invalid_dpop_proof",
+        }
+    }
+}
+
+/// Parameters of an authorization request sent by a relying party
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AuthorizationRequest {
+    pub response_type: Option<String>,
+    pub client_id: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub scope: Option<String>,
+    pub state: Option<String>,
+    pub nonce: Option<String>,
+    pub code_challenge: Option<String>,
+    pub code_challenge_method: Option<String>,
+    pub prompt: Option<String>,
+    /// Signed request object carrying the other parameters, see RFC 9101
+    pub request: Option<String>,
+    /// Reference of a request pushed to the PAR endpoint, see RFC 9126
+    pub request_uri: Option<String>,
+}
+
+/// Parameters of a token exchange, see RFC 8693 section 2.1
+#[derive(Debug, Clone, Default)]
+pub struct TokenExchangeRequest {
+    pub subject_token: String,
+    pub subject_token_type: String,
+    pub requested_token_type: Option<String>,
+    /// User to impersonate, only super users may request it
+    pub requested_subject: Option<String>,
+    pub audience: Option<String>,
+    pub scope: Option<String>,
+}
+
+/// Splits a space separated scope parameter
+pub fn parse_scope(scope: &str) -> Vec<&str> {
+    scope.split(' ').filter(|v| !v.is_empty()).collect()
+}
+
+/// Scope granted for a request, everything allowed is granted when no scope is requested
+pub fn grant_scope(requested: Option<&str>, allowed: &[String]) -> Result<String, OAuthError> {
+    let Some(requested) = requested else {
+        return Ok(allowed.join(" "));
+    };
+
+    let scopes = parse_scope(requested);
+    if scopes.iter().any(|v| !allowed.iter().any(|a| a == v)) {
+        return Err(OAuthError::InvalidScope);
+    }
+
+    Ok(scopes.join(" "))
+}
+
+/// Drops the scopes that are no longer allowed, e.g. when a session is refreshed
+pub fn restrict_scope(scope: &str, allowed: &[String]) -> String {
+    parse_scope(scope)
+        .into_iter()
+        .filter(|v| allowed.iter().any(|a| a == v))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grant_scope() {
+        let allowed = vec!["users:read".to_string(), "users:write".to_string()];
+
+        assert_eq!(grant_scope(None, &allowed), Ok("users:read users:write".to_string()));
+        assert_eq!(grant_scope(Some("users:read"), &allowed), Ok("users:read".to_string()));
+        assert_eq!(grant_scope(Some("users:read admin"), &allowed), Err(OAuthError::InvalidScope));
+        assert_eq!(restrict_scope("users:write admin", &allowed), "users:write");
+    }
+}
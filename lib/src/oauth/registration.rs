@@ -0,0 +1,260 @@
+use jsonwebtoken::jwk::JwkSet;
+use openidconnect::url::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::model::client::OAuthClient;
+use crate::oauth::{
+    grant_scope, parse_scope, OAuthError, GRANT_TYPE_AUTHORIZATION_CODE, GRANT_TYPE_CLIENT_CREDENTIALS,
+    GRANT_TYPE_DEVICE_CODE, GRANT_TYPE_REFRESH_TOKEN,
+};
+
+pub const AUTH_METHOD_CLIENT_SECRET_BASIC: &str = "client_secret_basic";
+pub const AUTH_METHOD_CLIENT_SECRET_POST: &str = "client_secret_post";
+pub const AUTH_METHOD_NONE: &str = "none";
+
+/// Grants partners may register, token exchange stays with clients created by us
+const REGISTRABLE_GRANT_TYPES: [&str; 4] = [
+    GRANT_TYPE_AUTHORIZATION_CODE,
+    GRANT_TYPE_REFRESH_TOKEN,
+    GRANT_TYPE_CLIENT_CREDENTIALS,
+    GRANT_TYPE_DEVICE_CODE,
+];
+
+/// Client metadata of a registration request, see RFC 7591 section 2
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ClientMetadata {
+    /// Only sent when updating a client, it must match the registered client
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(default)]
+    pub redirect_uris: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grant_types: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_endpoint_auth_method: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    /// Public keys of the request objects, see RFC 9101 section 9.1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jwks: Option<serde_json::Value>,
+    #[serde(default)]
+    pub require_pushed_authorization_requests: bool,
+    /// See OpenID Connect RP-Initiated Logout section 3.1
+    #[serde(default)]
+    pub post_logout_redirect_uris: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frontchannel_logout_uri: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backchannel_logout_uri: Option<String>,
+}
+
+/// Metadata with the defaults of RFC 7591 applied
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidatedClientMetadata {
+    pub redirect_uris: Vec<String>,
+    pub grant_types: Vec<String>,
+    pub token_endpoint_auth_method: String,
+    pub client_name: Option<String>,
+    pub scopes: Vec<String>,
+    pub jwks: Option<serde_json::Value>,
+    pub require_pushed_authorization_requests: bool,
+    pub post_logout_redirect_uris: Vec<String>,
+    pub frontchannel_logout_uri: Option<String>,
+    pub backchannel_logout_uri: Option<String>,
+}
+
+impl ValidatedClientMetadata {
+    pub fn is_public(&self) -> bool {
+        self.token_endpoint_auth_method == AUTH_METHOD_NONE
+    }
+}
+
+/// Client of a successful registration, the secrets are never returned again
+#[derive(Debug, Clone)]
+pub struct ClientRegistration {
+    pub client: OAuthClient,
+    pub client_secret: Option<String>,
+    pub registration_access_token: String,
+}
+
+impl ClientMetadata {
+    /// Validates the metadata, scopes must be within the scopes open to registered clients
+    pub fn validate(&self, allowed_scopes: &[String]) -> Result<ValidatedClientMetadata, OAuthError> {
+        let grant_types = self
+            .grant_types
+            .clone()
+            .unwrap_or_else(|| vec![GRANT_TYPE_AUTHORIZATION_CODE.to_string()]);
+        if grant_types.is_empty()
+            || grant_types
+                .iter()
+                .any(|v| !REGISTRABLE_GRANT_TYPES.contains(&v.as_str()))
+        {
+            return Err(OAuthError::InvalidClientMetadata("unsupported grant_types".to_string()));
+        }
+
+        let token_endpoint_auth_method = self
+            .token_endpoint_auth_method
+            .clone()
+            .unwrap_or_else(|| AUTH_METHOD_CLIENT_SECRET_BASIC.to_string());
+        if ![
+            AUTH_METHOD_CLIENT_SECRET_BASIC,
+            AUTH_METHOD_CLIENT_SECRET_POST,
+            AUTH_METHOD_NONE,
+        ]
+        .contains(&token_endpoint_auth_method.as_str())
+        {
+            return Err(OAuthError::InvalidClientMetadata(
+                "unsupported token_endpoint_auth_method".to_string(),
+            ));
+        }
+
+        // Public clients can't keep a secret, so they can't act on their own behalf
+        if token_endpoint_auth_method == AUTH_METHOD_NONE
+            && grant_types.iter().any(|v| v == GRANT_TYPE_CLIENT_CREDENTIALS)
+        {
+            return Err(OAuthError::InvalidClientMetadata(
+                "client_credentials requires a client secret".to_string(),
+            ));
+        }
+
+        let redirects = grant_types.iter().any(|v| v == GRANT_TYPE_AUTHORIZATION_CODE);
+        if redirects && self.redirect_uris.is_empty() {
+            return Err(OAuthError::InvalidRedirectUri);
+        }
+        if !self.redirect_uris.iter().all(|v| is_valid_redirect_uri(v)) {
+            return Err(OAuthError::InvalidRedirectUri);
+        }
+
+        if !self
+            .post_logout_redirect_uris
+            .iter()
+            .all(|v| is_valid_redirect_uri(v))
+        {
+            return Err(OAuthError::InvalidClientMetadata(
+                "invalid post_logout_redirect_uris".to_string(),
+            ));
+        }
+
+        let logout_uris = [&self.frontchannel_logout_uri, &self.backchannel_logout_uri];
+        if !logout_uris.iter().all(|v| v.as_deref().is_none_or(is_valid_logout_uri)) {
+            return Err(OAuthError::InvalidClientMetadata("invalid logout uri".to_string()));
+        }
+
+        if self
+            .jwks
+            .clone()
+            .is_some_and(|v| serde_json::from_value::<JwkSet>(v).is_err())
+        {
+            return Err(OAuthError::InvalidClientMetadata("invalid jwks".to_string()));
+        }
+
+        let scope = grant_scope(self.scope.as_deref(), allowed_scopes)
+            .map_err(|_| OAuthError::InvalidClientMetadata("scope not allowed".to_string()))?;
+
+        Ok(ValidatedClientMetadata {
+            redirect_uris: self.redirect_uris.clone(),
+            grant_types,
+            token_endpoint_auth_method,
+            client_name: self.client_name.clone(),
+            scopes: parse_scope(&scope).into_iter().map(|v| v.to_string()).collect(),
+            jwks: self.jwks.clone(),
+            require_pushed_authorization_requests: self.require_pushed_authorization_requests,
+            post_logout_redirect_uris: self.post_logout_redirect_uris.clone(),
+            frontchannel_logout_uri: self.frontchannel_logout_uri.clone(),
+            backchannel_logout_uri: self.backchannel_logout_uri.clone(),
+        })
+    }
+}
+
+/// Https, loopback http for native apps or a private use scheme like `com.example.app:/callback`,
+/// see RFC 8252 section 7
+fn is_valid_redirect_uri(redirect_uri: &str) -> bool {
+    let Ok(url) = Url::parse(redirect_uri) else {
+        return false;
+    };
+
+    if url.fragment().is_some() {
+        return false;
+    }
+
+    match url.scheme() {
+        "https" => url.host_str().is_some(),
+        "http" => matches!(url.host_str(), Some("localhost" | "127.0.0.1" | "[::1]")),
+        // Private use schemes are reverse domain names
+        scheme => scheme.contains('.'),
+    }
+}
+
+/// Logout uris are called by the browser or by us, so they must be web uris
+fn is_valid_logout_uri(logout_uri: &str) -> bool {
+    let Ok(url) = Url::parse(logout_uri) else {
+        return false;
+    };
+
+    matches!(url.scheme(), "https" | "http") && is_valid_redirect_uri(logout_uri)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_metadata() {
+        let allowed = vec!["users:read".to_string()];
+        let metadata = ClientMetadata {
+            redirect_uris: vec![
+                "https://partner.example.com/callback".to_string(),
+                "http://127.0.0.1:8080/callback".to_string(),
+                "com.example.app:/callback".to_string(),
+            ],
+            ..Default::default()
+        };
+
+        let validated = metadata.validate(&allowed).expect("Expect valid metadata");
+        assert_eq!(validated.grant_types, vec![GRANT_TYPE_AUTHORIZATION_CODE]);
+        assert_eq!(validated.token_endpoint_auth_method, AUTH_METHOD_CLIENT_SECRET_BASIC);
+        assert_eq!(validated.scopes, allowed);
+
+        for redirect_uri in [
+            "http://partner.example.com/callback",
+            "https://partner.example.com/callback#fragment",
+            "javascript:alert(1)",
+            "not a url",
+        ] {
+            let metadata = ClientMetadata {
+                redirect_uris: vec![redirect_uri.to_string()],
+                ..Default::default()
+            };
+            assert_eq!(metadata.validate(&allowed).err(), Some(OAuthError::InvalidRedirectUri));
+        }
+
+        let metadata = ClientMetadata {
+            grant_types: Some(vec![GRANT_TYPE_CLIENT_CREDENTIALS.to_string()]),
+            token_endpoint_auth_method: Some(AUTH_METHOD_NONE.to_string()),
+            ..Default::default()
+        };
+        assert!(metadata.validate(&allowed).is_err());
+
+        let metadata = ClientMetadata {
+            grant_types: Some(vec!["password".to_string()]),
+            ..Default::default()
+        };
+        assert!(metadata.validate(&allowed).is_err());
+
+        let metadata = ClientMetadata {
+            grant_types: Some(vec![GRANT_TYPE_CLIENT_CREDENTIALS.to_string()]),
+            scope: Some("users:write".to_string()),
+            ..Default::default()
+        };
+        assert!(metadata.validate(&allowed).is_err());
+
+        let metadata = ClientMetadata {
+            grant_types: Some(vec![GRANT_TYPE_CLIENT_CREDENTIALS.to_string()]),
+            backchannel_logout_uri: Some("com.example.app:/logout".to_string()),
+            ..Default::default()
+        };
+        assert!(metadata.validate(&allowed).is_err());
+    }
+}
@@ -0,0 +1,162 @@
+use chrono::Utc;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+
+use crate::config::AppConfig;
+use crate::constants::OAUTH_REQUEST_OBJECT_EXPIRED_IN;
+use crate::model::client::OAuthClient;
+use crate::oauth::{AuthorizationRequest, OAuthError};
+
+/// Prefix of the request uris issued by the PAR endpoint, see RFC 9126 section 2.2
+pub const REQUEST_URI_PREFIX: &str = "urn:ietf:params:oauth:request_uri:";
+
+/// Request objects must be signed with a key of the client, secrets and `none` are rejected
+const REQUEST_OBJECT_ALGORITHMS: [Algorithm; 7] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+/// Lifetime claims of a request object, a captured object can't be replayed for long
+#[derive(Deserialize)]
+struct RequestObjectClaims {
+    #[serde(flatten)]
+    request: AuthorizationRequest,
+    exp: i64,
+    nbf: i64,
+    iat: i64,
+}
+
+/// Parameters of a signed request object, see RFC 9101 section 6.3
+///
+/// Only the parameters of the request object are used, so none of them can be tampered with in
+/// the front channel.
+pub fn decode_request_object(
+    client: &OAuthClient,
+    request_object: &str,
+) -> Result<AuthorizationRequest, OAuthError> {
+    decode(client, request_object, &AppConfig::oidc_issuer())
+}
+
+fn decode(
+    client: &OAuthClient,
+    request_object: &str,
+    audience: &str,
+) -> Result<AuthorizationRequest, OAuthError> {
+    let invalid = |reason: &str| OAuthError::InvalidRequestObject(reason.to_string());
+
+    let Ok(header) = jsonwebtoken::decode_header(request_object) else {
+        return Err(invalid("malformed request object"));
+    };
+
+    if !REQUEST_OBJECT_ALGORITHMS.contains(&header.alg) {
+        return Err(invalid("unsupported signing algorithm"));
+    }
+
+    let Some(jwks) = client
+        .jwks
+        .clone()
+        .and_then(|v| serde_json::from_value::<JwkSet>(v).ok())
+    else {
+        return Err(invalid("client has no keys"));
+    };
+
+    // Clients with a single key may omit the key id
+    let jwk = match (&header.kid, jwks.keys.as_slice()) {
+        (Some(kid), _) => jwks.find(kid),
+        (None, [jwk]) => Some(jwk),
+        (None, _) => None,
+    };
+    let Some(Ok(key)) = jwk.map(DecodingKey::from_jwk) else {
+        return Err(invalid("unknown signing key"));
+    };
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_required_spec_claims(&["exp", "nbf", "iss", "aud"]);
+    validation.validate_nbf = true;
+    validation.set_issuer(&[&client.id]);
+    validation.set_audience(&[audience]);
+
+    let Ok(data) = jsonwebtoken::decode::<RequestObjectClaims>(request_object, &key, &validation)
+    else {
+        return Err(invalid("invalid signature or claims"));
+    };
+
+    // The lifetime is capped to an hour, like FAPI 1.0 Advanced section 5.2.2 does
+    let claims = data.claims;
+    let max_lifetime = OAUTH_REQUEST_OBJECT_EXPIRED_IN * 60;
+    let now = Utc::now().timestamp();
+    if claims.exp - claims.nbf > max_lifetime
+        || now - claims.iat > max_lifetime
+        || claims.iat > now + (validation.leeway as i64)
+    {
+        return Err(invalid("request object lifetime is too long"));
+    }
+
+    let mut request = claims.request;
+    if request.client_id.as_ref().is_some_and(|v| v != &client.id) {
+        return Err(invalid("client_id does not match"));
+    }
+    if request.request.is_some() || request.request_uri.is_some() {
+        return Err(invalid("request objects can't be nested"));
+    }
+    request.client_id = Some(client.id.clone());
+
+    Ok(request)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oauth::keys::tests::RSA_PRIVATE_KEY;
+    use crate::oauth::keys::SigningKey;
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde_json::json;
+
+    const ISSUER: &str = "https://auth.example.com/api/v1";
+
+    #[test]
+    fn test_decode_request_object() -> Result<(), Box<dyn std::error::Error>> {
+        let signing_key = SigningKey::from_pem(RSA_PRIVATE_KEY)?;
+        let encoding_key = EncodingKey::from_rsa_pem(RSA_PRIVATE_KEY.as_bytes())?;
+        let mut client = OAuthClient::new(vec![], vec![], vec![]);
+        client.jwks = Some(signing_key.jwks());
+
+        let now = Utc::now().timestamp();
+        let request_object = |nbf: Option<i64>, iat: i64, exp: i64| {
+            let mut claims = json!({
+                "iss": client.id,
+                "aud": ISSUER,
+                "iat": iat,
+                "exp": exp,
+                "redirect_uri": "https://app.example.com/callback",
+            });
+            if let Some(nbf) = nbf {
+                claims["nbf"] = json!(nbf);
+            }
+            jsonwebtoken::encode(&Header::new(Algorithm::RS256), &claims, &encoding_key)
+        };
+
+        let request = decode(&client, &request_object(Some(now), now, now + 300)?, ISSUER)?;
+        assert_eq!(request.client_id.as_deref(), Some(client.id.as_str()));
+        assert_eq!(request.redirect_uri.as_deref(), Some("https://app.example.com/callback"));
+
+        let max_lifetime = OAUTH_REQUEST_OBJECT_EXPIRED_IN * 60;
+        for (nbf, iat, exp) in [
+            (None, now, now + 300),
+            (Some(now), now, now + max_lifetime + 300),
+            (Some(now - max_lifetime - 300), now - max_lifetime - 300, now + 300),
+            (Some(now + 300), now, now + 600),
+        ] {
+            let result = decode(&client, &request_object(nbf, iat, exp)?, ISSUER);
+            assert!(matches!(result, Err(OAuthError::InvalidRequestObject(_))));
+        }
+
+        Ok(())
+    }
+}
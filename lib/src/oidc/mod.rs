@@ -1,5 +1,7 @@
+pub mod oauth2;
 pub mod oidc;
 pub mod provider;
+pub mod revocation;
 
 pub mod serde_string_bool {
     use serde::{de, Deserializer};
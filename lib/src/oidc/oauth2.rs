@@ -0,0 +1,351 @@
+use crate::config::{AppConfig, OAuthProviderConfiguration};
+use crate::oidc::oidc::{AuthorizationCodeClient, AuthorizationRedirect, OidcError, OidcProvider};
+use crate::oidc::provider::apple::TOKEN_URL_APPLE;
+use crate::oidc::provider::discord::{
+    parse_discord_user_info, AUTH_URL_DISCORD, TOKEN_URL_DISCORD, USERINFO_URL_DISCORD,
+};
+use crate::oidc::provider::github::{
+    parse_github_user_info, AUTH_URL_GITHUB, EMAILS_URL_GITHUB, TOKEN_URL_GITHUB,
+    USERINFO_URL_GITHUB,
+};
+use crate::oidc::provider::gitlab::{
+    parse_gitlab_user_info, AUTH_URL_GITLAB, TOKEN_URL_GITLAB, USERINFO_URL_GITLAB,
+};
+use crate::oidc::provider::google::TOKEN_URL_GOOGLE;
+use crate::oidc::provider::{ProviderTokens, UserProvidedData};
+use crate::oidc::revocation::HttpClient;
+use async_trait::async_trait;
+use chrono::Duration;
+use openidconnect::url::Url;
+use openidconnect::{CsrfToken, PkceCodeChallenge};
+use reqwest::header::ACCEPT;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OAuth2Endpoints {
+    pub auth_url: String,
+    pub token_url: String,
+    pub userinfo_url: String,
+    /// Extra endpoint listing every email of the user, with `verified`/`primary` flags
+    pub emails_url: Option<String>,
+}
+
+impl OAuth2Endpoints {
+    pub fn for_provider(provider: &OidcProvider) -> Option<OAuth2Endpoints> {
+        let endpoints = match provider {
+            OidcProvider::GitHub => OAuth2Endpoints {
+                auth_url: AUTH_URL_GITHUB.to_string(),
+                token_url: TOKEN_URL_GITHUB.to_string(),
+                userinfo_url: USERINFO_URL_GITHUB.to_string(),
+                emails_url: Some(EMAILS_URL_GITHUB.to_string()),
+            },
+            OidcProvider::GitLab => OAuth2Endpoints {
+                auth_url: AUTH_URL_GITLAB.to_string(),
+                token_url: TOKEN_URL_GITLAB.to_string(),
+                userinfo_url: USERINFO_URL_GITLAB.to_string(),
+                emails_url: None,
+            },
+            OidcProvider::Discord => OAuth2Endpoints {
+                auth_url: AUTH_URL_DISCORD.to_string(),
+                token_url: TOKEN_URL_DISCORD.to_string(),
+                userinfo_url: USERINFO_URL_DISCORD.to_string(),
+                emails_url: None,
+            },
+            _ => return None,
+        };
+
+        Some(endpoints)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct OAuth2TokenResponse {
+    pub access_token: Option<String>,
+    pub refresh_token: Option<String>,
+    pub expires_in: Option<i64>,
+    pub error: Option<String>,
+}
+
+impl OAuth2TokenResponse {
+    pub(crate) fn into_provider_tokens(self) -> Result<ProviderTokens, OidcError> {
+        match self.access_token {
+            Some(access_token) => Ok(ProviderTokens::new(
+                access_token,
+                self.refresh_token,
+                self.expires_in.map(Duration::seconds),
+            )),
+            None => Err(OidcError::CodeExchangeError(self.error.unwrap_or_default())),
+        }
+    }
+}
+
+/// Client for providers without ID tokens, the user is read from the user info endpoint
+pub struct OAuth2Client {
+    provider: OidcProvider,
+    auth_url: Url,
+    endpoints: OAuth2Endpoints,
+    client_id: String,
+    secret: Option<String>,
+    redirect_uri: Option<String>,
+    http_client: reqwest::Client,
+}
+
+impl OAuth2Client {
+    pub fn new(
+        provider: OidcProvider,
+        endpoints: OAuth2Endpoints,
+        client_id: String,
+        secret: Option<String>,
+    ) -> Result<OAuth2Client, OidcError> {
+        let Ok(auth_url) = Url::parse(&endpoints.auth_url) else {
+            return Err(OidcError::DiscoveryError);
+        };
+
+        // GitHub rejects API requests without a user agent
+        let http_client = reqwest::Client::builder()
+            .user_agent("authcare")
+            .build()?;
+
+        Ok(OAuth2Client {
+            provider,
+            auth_url,
+            endpoints,
+            client_id,
+            secret,
+            redirect_uri: None,
+            http_client,
+        })
+    }
+
+    pub fn set_redirect_uri(mut self, redirect_uri: &str) -> Self {
+        self.redirect_uri = Some(redirect_uri.to_string());
+        self
+    }
+
+    async fn request_access_token(
+        &self,
+        code: &str,
+        pkce_verifier: &str,
+    ) -> Result<ProviderTokens, OidcError> {
+        let mut params = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("client_id", self.client_id.as_str()),
+            ("code_verifier", pkce_verifier),
+        ];
+
+        if let Some(secret) = &self.secret {
+            params.push(("client_secret", secret.as_str()));
+        }
+
+        if let Some(redirect_uri) = &self.redirect_uri {
+            params.push(("redirect_uri", redirect_uri.as_str()));
+        }
+
+        let body = self
+            .http_client
+            .post(&self.endpoints.token_url)
+            .header(ACCEPT, "application/json")
+            .form(&params)
+            .send()
+            .await?
+            .text()
+            .await?;
+
+        // GitHub reports errors with a 200 status, so look at the body instead
+        let token_response: OAuth2TokenResponse = serde_json::from_str(&body)?;
+        token_response.into_provider_tokens()
+    }
+
+    async fn get_json(&self, url: &str, access_token: &str) -> Result<serde_json::Value, OidcError> {
+        let body = self
+            .http_client
+            .get(url)
+            .header(ACCEPT, "application/json")
+            .bearer_auth(access_token)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+
+        Ok(serde_json::from_str(&body)?)
+    }
+}
+
+#[async_trait]
+impl AuthorizationCodeClient for OAuth2Client {
+    fn authorize_url(&self) -> AuthorizationRedirect {
+        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+        let state = CsrfToken::new_random();
+
+        let mut url = self.auth_url.clone();
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.client_id)
+            .append_pair("scope", &self.provider.scopes().join(" "))
+            .append_pair("state", state.secret())
+            .append_pair("code_challenge", pkce_challenge.as_str())
+            .append_pair("code_challenge_method", pkce_challenge.method().as_str());
+
+        if let Some(redirect_uri) = &self.redirect_uri {
+            url.query_pairs_mut().append_pair("redirect_uri", redirect_uri);
+        }
+
+        AuthorizationRedirect {
+            url,
+            state: state.secret().clone(),
+            // Without ID tokens there is nothing to bind a nonce to
+            nonce: String::new(),
+            pkce_verifier: pkce_verifier.secret().clone(),
+        }
+    }
+
+    async fn exchange_code(
+        &self,
+        code: &str,
+        pkce_verifier: &str,
+        _nonce: &str,
+    ) -> Result<UserProvidedData, OidcError> {
+        let tokens = self.request_access_token(code, pkce_verifier).await?;
+        let access_token = tokens.access_token.as_str();
+        let user = self.get_json(&self.endpoints.userinfo_url, access_token).await?;
+
+        let emails = match &self.endpoints.emails_url {
+            Some(url) => Some(self.get_json(url, access_token).await?),
+            None => None,
+        };
+
+        let mut user_provided_data = match self.provider {
+            OidcProvider::GitHub => parse_github_user_info(user, emails)?,
+            OidcProvider::GitLab => parse_gitlab_user_info(user)?,
+            OidcProvider::Discord => parse_discord_user_info(user)?,
+            _ => return Err(OidcError::UnknownProvider),
+        };
+
+        if !user_provided_data.emails.iter().any(|v| v.verified) {
+            return Err(OidcError::UnverifiedEmail);
+        }
+
+        user_provided_data.provider_tokens = Some(tokens);
+        Ok(user_provided_data)
+    }
+}
+
+/// Exchanges a provider refresh token for new provider tokens
+pub async fn refresh_provider_tokens(
+    http_client: &(dyn HttpClient + Send + Sync),
+    provider: &OidcProvider,
+    refresh_token: &str,
+) -> Result<ProviderTokens, OidcError> {
+    let Some(configuration) = AppConfig::provider_configuration(provider) else {
+        return Err(OidcError::UnknownProvider);
+    };
+
+    let token_url = match provider {
+        OidcProvider::Apple => TOKEN_URL_APPLE.to_string(),
+        OidcProvider::Google => TOKEN_URL_GOOGLE.to_string(),
+        _ => match &configuration.oauth2_endpoints {
+            Some(endpoints) => endpoints.token_url.clone(),
+            None => return Err(OidcError::UnknownProvider),
+        },
+    };
+
+    request_refreshed_tokens(http_client, &token_url, configuration, refresh_token).await
+}
+
+async fn request_refreshed_tokens(
+    http_client: &(dyn HttpClient + Send + Sync),
+    token_url: &str,
+    configuration: &OAuthProviderConfiguration,
+    refresh_token: &str,
+) -> Result<ProviderTokens, OidcError> {
+    let client_secret = configuration.client_secret()?;
+    let mut form = vec![
+        ("grant_type", "refresh_token"),
+        ("refresh_token", refresh_token),
+        ("client_id", configuration.client_id.as_str()),
+    ];
+
+    if let Some(client_secret) = &client_secret {
+        form.push(("client_secret", client_secret.as_str()));
+    }
+
+    let response = http_client.post_form(token_url, &form).await?;
+    let token_response: OAuth2TokenResponse = serde_json::from_str(&response.body)?;
+    let mut tokens = token_response.into_provider_tokens()?;
+
+    // Refresh tokens are only rotated by some providers
+    if tokens.refresh_token.is_none() {
+        tokens.refresh_token = Some(refresh_token.to_string());
+    }
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oidc::revocation::HttpClientResponse;
+    use std::sync::Mutex;
+
+    /// Answers every request with the body and keeps the last form
+    struct StubHttpClient {
+        body: String,
+        form: Mutex<Vec<(String, String)>>,
+    }
+
+    #[async_trait]
+    impl HttpClient for StubHttpClient {
+        async fn post_form(
+            &self,
+            _url: &str,
+            form: &[(&str, &str)],
+        ) -> Result<HttpClientResponse, OidcError> {
+            *self.form.lock().unwrap() =
+                form.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+            Ok(HttpClientResponse {
+                status: 200,
+                body: self.body.clone(),
+            })
+        }
+    }
+
+    async fn refresh(body: &str) -> (Result<ProviderTokens, OidcError>, Vec<(String, String)>) {
+        let http_client = StubHttpClient {
+            body: body.to_string(),
+            form: Mutex::new(vec![]),
+        };
+        let configuration = OAuthProviderConfiguration::new("client-id", Some("secret".into()))
+            .expect("Expect configuration");
+        let result = request_refreshed_tokens(
+            &http_client,
+            "https://github.com/login/oauth/access_token",
+            &configuration,
+            "refresh-token",
+        )
+        .await;
+        (result, http_client.form.into_inner().unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_refresh_provider_tokens() {
+        let (result, form) = refresh(r#"{"access_token":"access-token","expires_in":3600}"#).await;
+        let tokens = result.expect("Expect tokens");
+        assert_eq!(tokens.access_token, "access-token");
+        assert!(!tokens.is_expired());
+        // Providers that don't rotate keep the refresh token
+        assert_eq!(tokens.refresh_token.as_deref(), Some("refresh-token"));
+        assert!(form.contains(&("grant_type".to_string(), "refresh_token".to_string())));
+        assert!(form.contains(&("refresh_token".to_string(), "refresh-token".to_string())));
+        assert!(form.contains(&("client_id".to_string(), "client-id".to_string())));
+        assert!(form.contains(&("client_secret".to_string(), "secret".to_string())));
+
+        let (result, _) =
+            refresh(r#"{"access_token":"access-token","refresh_token":"rotated"}"#).await;
+        assert_eq!(result.expect("Expect tokens").refresh_token.as_deref(), Some("rotated"));
+
+        let (result, _) = refresh(r#"{"error":"invalid_grant"}"#).await;
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,102 @@
+use crate::oidc::oidc::OidcError;
+use crate::oidc::provider::{Claims, Email, UserProvidedData};
+use serde::Deserialize;
+
+pub const ISSUER_DISCORD: &str = "https://discord.com";
+pub const AUTH_URL_DISCORD: &str = "https://discord.com/oauth2/authorize";
+pub const TOKEN_URL_DISCORD: &str = "https://discord.com/api/oauth2/token";
+pub const USERINFO_URL_DISCORD: &str = "https://discord.com/api/users/@me";
+
+#[derive(Debug, Deserialize)]
+struct DiscordUser {
+    id: String,
+    username: String,
+    global_name: Option<String>,
+    email: Option<String>,
+    verified: Option<bool>,
+    avatar: Option<String>,
+    locale: Option<String>,
+}
+
+/// Maps the `/users/@me` response
+pub fn parse_discord_user_info(user: serde_json::Value) -> Result<UserProvidedData, OidcError> {
+    let user: DiscordUser = serde_json::from_value(user)?;
+
+    let Some(email) = user.email else {
+        return Err(OidcError::InvalidUserInfo("missing email".to_string()));
+    };
+
+    let verified = user.verified.unwrap_or(false);
+    let picture = user
+        .avatar
+        .map(|v| format!("https://cdn.discordapp.com/avatars/{}/{}.png", user.id, v));
+
+    let metadata = Claims {
+        issuer: Some(ISSUER_DISCORD.to_string()),
+        subject: Some(user.id),
+        name: user.global_name,
+        preferred_username: Some(user.username),
+        picture,
+        locale: user.locale,
+        email: Some(email.clone()),
+        email_verified: Some(verified),
+        ..Default::default()
+    };
+
+    let data = UserProvidedData {
+        emails: vec![Email {
+            email,
+            verified,
+            primary: true,
+        }],
+        metadata: Some(metadata),
+        provider_tokens: None,
+    };
+
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_user_info() {
+        let user = json!({
+            "id": "80351110224678912",
+            "username": "nelly",
+            "global_name": "Nelly",
+            "email": "nelly@discord.com",
+            "verified": true,
+            "avatar": "8342729096ea3675442027381ff50dfe",
+            "locale": "en-US"
+        });
+
+        let data = parse_discord_user_info(user).expect("Expect user info");
+        assert_eq!(data.emails.len(), 1);
+        assert_eq!(data.emails[0].email, "nelly@discord.com");
+        assert!(data.emails[0].verified);
+
+        let metadata = data.metadata.expect("Expect metadata");
+        assert_eq!(metadata.subject.as_deref(), Some("80351110224678912"));
+        assert_eq!(metadata.name.as_deref(), Some("Nelly"));
+        assert_eq!(
+            metadata.picture.as_deref(),
+            Some("https://cdn.discordapp.com/avatars/80351110224678912/8342729096ea3675442027381ff50dfe.png")
+        );
+    }
+
+    #[test]
+    fn test_parse_unverified_user_info() {
+        let user = json!({
+            "id": "80351110224678913",
+            "username": "unverified",
+            "email": "unverified@discord.com"
+        });
+
+        let data = parse_discord_user_info(user).expect("Expect user info");
+        assert!(!data.emails[0].verified);
+        assert_eq!(data.metadata.and_then(|v| v.picture), None);
+    }
+}
@@ -0,0 +1,115 @@
+use crate::oidc::oidc::OidcError;
+use crate::oidc::provider::{sort_primary_first, Claims, Email, UserProvidedData};
+use serde::Deserialize;
+
+pub const ISSUER_GITHUB: &str = "https://github.com";
+pub const AUTH_URL_GITHUB: &str = "https://github.com/login/oauth/authorize";
+pub const TOKEN_URL_GITHUB: &str = "https://github.com/login/oauth/access_token";
+pub const USERINFO_URL_GITHUB: &str = "https://api.github.com/user";
+pub const EMAILS_URL_GITHUB: &str = "https://api.github.com/user/emails";
+
+#[derive(Debug, Deserialize)]
+struct GitHubUser {
+    id: u64,
+    login: String,
+    name: Option<String>,
+    email: Option<String>,
+    avatar_url: Option<String>,
+    html_url: Option<String>,
+    blog: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubEmail {
+    email: String,
+    primary: bool,
+    verified: bool,
+}
+
+/// Maps the `/user` and `/user/emails` responses, the public profile email is not verified
+pub fn parse_github_user_info(
+    user: serde_json::Value,
+    emails: Option<serde_json::Value>,
+) -> Result<UserProvidedData, OidcError> {
+    let user: GitHubUser = serde_json::from_value(user)?;
+
+    let mut emails: Vec<Email> = match emails {
+        Some(emails) => serde_json::from_value::<Vec<GitHubEmail>>(emails)?
+            .into_iter()
+            .map(|e| Email {
+                email: e.email,
+                verified: e.verified,
+                primary: e.primary,
+            })
+            .collect(),
+        None => user
+            .email
+            .iter()
+            .map(|email| Email {
+                email: email.clone(),
+                verified: false,
+                primary: true,
+            })
+            .collect(),
+    };
+    sort_primary_first(&mut emails);
+
+    let Some(primary) = emails.first() else {
+        return Err(OidcError::InvalidUserInfo("missing email".to_string()));
+    };
+
+    let metadata = Claims {
+        issuer: Some(ISSUER_GITHUB.to_string()),
+        subject: Some(user.id.to_string()),
+        name: user.name,
+        preferred_username: Some(user.login),
+        profile: user.html_url,
+        picture: user.avatar_url,
+        website: user.blog.filter(|v| !v.is_empty()),
+        email: Some(primary.email.clone()),
+        email_verified: Some(primary.verified),
+        ..Default::default()
+    };
+
+    let data = UserProvidedData {
+        emails,
+        metadata: Some(metadata),
+        provider_tokens: None,
+    };
+
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_user_info() {
+        let user = json!({
+            "id": 583231,
+            "login": "octocat",
+            "name": "The Octocat",
+            "email": null,
+            "avatar_url": "https://avatars.githubusercontent.com/u/583231?v=4",
+            "html_url": "https://github.com/octocat",
+            "blog": ""
+        });
+
+        let emails = json!([
+            { "email": "octocat@users.noreply.github.com", "primary": false, "verified": true, "visibility": null },
+            { "email": "octocat@github.com", "primary": true, "verified": true, "visibility": "public" }
+        ]);
+
+        let data = parse_github_user_info(user, Some(emails)).expect("Expect user info");
+        assert_eq!(data.emails.len(), 2);
+        assert_eq!(data.emails[0].email, "octocat@github.com");
+        assert!(data.emails[0].primary);
+
+        let metadata = data.metadata.expect("Expect metadata");
+        assert_eq!(metadata.subject.as_deref(), Some("583231"));
+        assert_eq!(metadata.preferred_username.as_deref(), Some("octocat"));
+        assert_eq!(metadata.website, None);
+    }
+}
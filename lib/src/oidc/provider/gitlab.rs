@@ -0,0 +1,102 @@
+use crate::oidc::oidc::OidcError;
+use crate::oidc::provider::{Claims, Email, UserProvidedData};
+use serde::Deserialize;
+
+pub const ISSUER_GITLAB: &str = "https://gitlab.com";
+pub const AUTH_URL_GITLAB: &str = "https://gitlab.com/oauth/authorize";
+pub const TOKEN_URL_GITLAB: &str = "https://gitlab.com/oauth/token";
+pub const USERINFO_URL_GITLAB: &str = "https://gitlab.com/api/v4/user";
+
+#[derive(Debug, Deserialize)]
+struct GitLabUser {
+    id: u64,
+    username: String,
+    name: Option<String>,
+    email: Option<String>,
+    avatar_url: Option<String>,
+    web_url: Option<String>,
+    confirmed_at: Option<String>,
+}
+
+/// Maps the `/api/v4/user` response, the email is verified once the account is confirmed
+pub fn parse_gitlab_user_info(user: serde_json::Value) -> Result<UserProvidedData, OidcError> {
+    let user: GitLabUser = serde_json::from_value(user)?;
+
+    let Some(email) = user.email else {
+        return Err(OidcError::InvalidUserInfo("missing email".to_string()));
+    };
+
+    let verified = user.confirmed_at.is_some();
+
+    let metadata = Claims {
+        issuer: Some(ISSUER_GITLAB.to_string()),
+        subject: Some(user.id.to_string()),
+        name: user.name,
+        preferred_username: Some(user.username),
+        profile: user.web_url,
+        picture: user.avatar_url,
+        email: Some(email.clone()),
+        email_verified: Some(verified),
+        ..Default::default()
+    };
+
+    let data = UserProvidedData {
+        emails: vec![Email {
+            email,
+            verified,
+            primary: true,
+        }],
+        metadata: Some(metadata),
+        provider_tokens: None,
+    };
+
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_user_info() {
+        let user = json!({
+            "id": 1,
+            "username": "john_smith",
+            "name": "John Smith",
+            "email": "john@example.com",
+            "avatar_url": "https://gitlab.com/uploads/user/avatar/1/index.jpg",
+            "web_url": "https://gitlab.com/john_smith",
+            "confirmed_at": "2012-05-23T09:05:22Z"
+        });
+
+        let data = parse_gitlab_user_info(user).expect("Expect user info");
+        assert_eq!(data.emails.len(), 1);
+        assert_eq!(data.emails[0].email, "john@example.com");
+        assert!(data.emails[0].verified);
+
+        let metadata = data.metadata.expect("Expect metadata");
+        assert_eq!(metadata.subject.as_deref(), Some("1"));
+        assert_eq!(metadata.preferred_username.as_deref(), Some("john_smith"));
+        assert_eq!(metadata.profile.as_deref(), Some("https://gitlab.com/john_smith"));
+    }
+
+    #[test]
+    fn test_parse_unconfirmed_user_info() {
+        let user = json!({
+            "id": 2,
+            "username": "jane",
+            "email": "jane@example.com",
+            "confirmed_at": null
+        });
+
+        let data = parse_gitlab_user_info(user).expect("Expect user info");
+        assert!(!data.emails[0].verified);
+
+        let user = json!({ "id": 3, "username": "no_email" });
+        assert!(matches!(
+            parse_gitlab_user_info(user),
+            Err(OidcError::InvalidUserInfo(_))
+        ));
+    }
+}
@@ -1,29 +1,50 @@
-use crate::oidc::oidc::{OidcError};
+use crate::oidc::oidc::{match_audience, OidcError};
 use crate::oidc::provider::{Claims, Email, UserProvidedData};
 use openidconnect::core::{CoreGenderClaim, CoreIdTokenVerifier, CoreJsonWebKeyType, CoreJweContentEncryptionAlgorithm, CoreJwsSigningAlgorithm};
-use openidconnect::{EmptyAdditionalClaims, NonceVerifier};
+use openidconnect::NonceVerifier;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::str::FromStr;
 
 pub const ISSUER_GOOGLE: &'static str = "https://accounts.google.com";
+pub const TOKEN_URL_GOOGLE: &str = "https://oauth2.googleapis.com/token";
+pub const REVOKE_URL_GOOGLE: &str = "https://oauth2.googleapis.com/revoke";
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct AdditionalClaims {
+    /// Google Workspace domain of the account, missing for consumer accounts
+    pub hd: Option<String>,
+}
+
+impl openidconnect::AdditionalClaims for AdditionalClaims {}
 
 pub type IdToken = openidconnect::IdToken<
-    EmptyAdditionalClaims,
+    AdditionalClaims,
     CoreGenderClaim,
     CoreJweContentEncryptionAlgorithm,
     CoreJwsSigningAlgorithm,
     CoreJsonWebKeyType,
 >;
 
-pub fn parse_google_id_token_claims<N: NonceVerifier>(verifier: &CoreIdTokenVerifier, nonce_verifier: N, id_token: &str) -> Result<UserProvidedData, OidcError> {
+pub fn parse_google_id_token_claims<N: NonceVerifier>(verifier: &CoreIdTokenVerifier, nonce_verifier: N, audiences: &[String], hosted_domains: &[String], id_token: &str) -> Result<UserProvidedData, OidcError> {
     let token = IdToken::from_str(id_token)?;
     let claims = token.claims(verifier, nonce_verifier)?;
+    let aud = match_audience(claims.audiences(), audiences)?;
+
+    let hd = claims.additional_claims().hd.as_ref();
+    match_hosted_domain(hd.map(|v| v.as_str()), hosted_domains)?;
 
     let email = Email {
         email: claims.email().expect("Expect email").to_string(),
-        verified: true,
+        verified: claims.email_verified().unwrap_or(false),
         primary: true,
     };
 
+    let mut custom_claims: HashMap<String, serde_json::Value> = HashMap::new();
+    if let Some(hd) = hd {
+        custom_claims.insert("hd".to_string(), serde_json::to_value(hd)?);
+    }
+
     let name = match claims.name() {
         Some(name) => name.get(None).map(|v| v.to_string()),
         None => None
@@ -37,7 +58,7 @@ pub fn parse_google_id_token_claims<N: NonceVerifier>(verifier: &CoreIdTokenVeri
     let metadata = Claims {
         issuer: Some(claims.issuer().to_string()),
         subject: claims.subject().to_string().into(),
-        aud: None,
+        aud: Some(aud),
         iat: None,
         exp: None,
         name: name,
@@ -57,14 +78,165 @@ pub fn parse_google_id_token_claims<N: NonceVerifier>(verifier: &CoreIdTokenVeri
         email: claims.email().map(|v| v.to_string()),
         email_verified: claims.email_verified(),
         phone: None,
-        custom_claims: None,
+        custom_claims: if custom_claims.is_empty() {
+            None
+        } else {
+            Some(custom_claims)
+        },
         phone_verified: None,
     };
 
     let data = UserProvidedData {
         emails: vec![email],
         metadata: Some(metadata),
+        provider_tokens: None,
     };
 
     Ok(data)
 }
+
+/// Checks the hosted domain against the allowlist, an empty allowlist accepts every account
+fn match_hosted_domain(hd: Option<&str>, hosted_domains: &[String]) -> Result<(), OidcError> {
+    if hosted_domains.is_empty() {
+        return Ok(());
+    }
+
+    match hd {
+        Some(hd) if hosted_domains.iter().any(|v| v.eq_ignore_ascii_case(hd)) => Ok(()),
+        _ => Err(OidcError::HostedDomainNotAllowed),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::oauth::keys::tests::RSA_PRIVATE_KEY;
+    use crate::oauth::keys::SigningKey;
+    use crate::oidc::oidc::{OidcClient, OidcProvider};
+    use openidconnect::core::CoreProviderMetadata;
+    use openidconnect::url::form_urlencoded;
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Metadata of a mock IdP publishing the test signing key
+    pub(crate) fn provider_metadata(issuer: &str, token_endpoint: &str) -> CoreProviderMetadata {
+        let signing_key = SigningKey::from_pem(RSA_PRIVATE_KEY).expect("Expect signing key");
+        let metadata: CoreProviderMetadata = serde_json::from_value(json!({
+            "issuer": issuer,
+            "authorization_endpoint": format!("{}/authorize", issuer),
+            "token_endpoint": token_endpoint,
+            "jwks_uri": format!("{}/jwks", issuer),
+            "response_types_supported": ["code"],
+            "subject_types_supported": ["public"],
+            "id_token_signing_alg_values_supported": ["RS256"],
+        }))
+        .expect("Expect provider metadata");
+
+        metadata.set_jwks(serde_json::from_value(signing_key.jwks()).expect("Expect jwks"))
+    }
+
+    /// ID token of the mock IdP with the given claims
+    pub(crate) fn sign_id_token(claims: Value) -> String {
+        let signing_key = SigningKey::from_pem(RSA_PRIVATE_KEY).expect("Expect signing key");
+        signing_key.sign(&claims).expect("Expect ID token")
+    }
+
+    /// Token endpoint of a mock IdP answering one request with the response built from its form
+    pub(crate) async fn mock_token_endpoint<F>(listener: TcpListener, respond: F)
+    where
+        F: FnOnce(HashMap<String, String>) -> (u16, Value),
+    {
+        let Ok((mut stream, _)) = listener.accept().await else {
+            return;
+        };
+
+        let mut request = vec![];
+        let mut buf = [0u8; 4096];
+        let body = loop {
+            let Ok(n) = stream.read(&mut buf).await else {
+                return;
+            };
+            request.extend_from_slice(&buf[..n]);
+
+            let text = String::from_utf8_lossy(&request).to_string();
+            let Some((head, body)) = text.split_once("\r\n\r\n") else {
+                continue;
+            };
+            let length = head
+                .lines()
+                .find_map(|v| {
+                    v.to_lowercase()
+                        .strip_prefix("content-length:")
+                        .map(|v| v.trim().to_string())
+                })
+                .and_then(|v| v.parse::<usize>().ok())
+                .unwrap_or_default();
+            if body.len() >= length || n == 0 {
+                break body.to_string();
+            }
+        };
+
+        let form = form_urlencoded::parse(body.as_bytes()).into_owned().collect();
+        let (status, body) = respond(form);
+        let body = body.to_string();
+        let response = format!(
+            "HTTP/1.1 {} Status\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+        let _ = stream.write_all(response.as_bytes()).await;
+    }
+
+    #[test]
+    fn test_accept_every_audience() {
+        let audiences = vec!["ios.client.id".to_string(), "web.client.id".to_string()];
+        let client = OidcClient::new(
+            OidcProvider::Google,
+            provider_metadata(ISSUER_GOOGLE, TOKEN_URL_GOOGLE),
+            audiences[0].clone(),
+            None,
+            audiences.clone(),
+        );
+
+        let now = chrono::Utc::now().timestamp();
+        let id_token = |aud: &str| {
+            sign_id_token(json!({
+                "iss": ISSUER_GOOGLE,
+                "sub": "1234567890",
+                "aud": aud,
+                "iat": now,
+                "exp": now + 600,
+                "email": "test@email.com",
+                "email_verified": true,
+            }))
+        };
+
+        for aud in &audiences {
+            let data = client.verify(&id_token(aud), None).expect("Expect accepted audience");
+            assert_eq!(data.metadata.and_then(|v| v.aud).as_ref(), Some(aud));
+        }
+
+        assert!(matches!(
+            client.verify(&id_token("other.client.id"), None),
+            Err(OidcError::InvalidAudience)
+        ));
+    }
+
+    #[test]
+    fn test_match_hosted_domain() {
+        let allowed = vec!["example.com".to_string()];
+        assert!(match_hosted_domain(None, &[]).is_ok());
+        assert!(match_hosted_domain(Some("Example.com"), &allowed).is_ok());
+        assert!(matches!(
+            match_hosted_domain(Some("other.com"), &allowed),
+            Err(OidcError::HostedDomainNotAllowed)
+        ));
+        assert!(matches!(
+            match_hosted_domain(None, &allowed),
+            Err(OidcError::HostedDomainNotAllowed)
+        ));
+    }
+}
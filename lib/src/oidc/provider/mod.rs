@@ -1,10 +1,14 @@
+use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 pub mod apple;
+pub mod discord;
+pub mod github;
+pub mod gitlab;
 pub mod google;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Claims {
     // Reserved claims
 
@@ -84,11 +88,74 @@ pub struct Claims {
     pub custom_claims: Option<HashMap<String, serde_json::Value>>,
 }
 
+impl Claims {
+    /// Profile claims that are worth keeping on the user record
+    pub fn profile(&self) -> serde_json::Map<String, serde_json::Value> {
+        let mut profile = serde_json::Map::new();
+        let claims = [
+            ("name", &self.name),
+            ("given_name", &self.given_name),
+            ("family_name", &self.family_name),
+            ("middle_name", &self.middle_name),
+            ("nickname", &self.nickname),
+            ("picture", &self.picture),
+            ("locale", &self.locale),
+        ];
+
+        for (key, value) in claims {
+            if let Some(value) = value {
+                profile.insert(key.to_string(), value.clone().into());
+            }
+        }
+
+        profile
+    }
+}
+
 /// Struct that contains the user's data returned from the oauth provider
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UserProvidedData {
     pub emails: Vec<Email>,
     pub metadata: Option<Claims>,
+    /// Tokens issued by the provider during a code exchange
+    #[serde(skip)]
+    pub provider_tokens: Option<ProviderTokens>,
+}
+
+impl UserProvidedData {
+    /// Email the account is created with, a verified one is preferred over the primary one
+    pub fn account_email(&self) -> Option<&Email> {
+        self.emails
+            .iter()
+            .find(|e| e.verified && e.primary)
+            .or_else(|| self.emails.iter().find(|e| e.verified))
+            .or_else(|| self.emails.iter().find(|e| e.primary))
+            .or_else(|| self.emails.first())
+    }
+}
+
+/// Provider tokens used to call the provider APIs on behalf of the user
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProviderTokens {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl ProviderTokens {
+    pub fn new(access_token: String, refresh_token: Option<String>, expires_in: Option<Duration>) -> Self {
+        Self {
+            access_token,
+            refresh_token,
+            expires_at: expires_in.map(|v| Utc::now() + v),
+        }
+    }
+
+    /// Expired or expiring within a minute
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|v| v <= Utc::now() + Duration::minutes(1))
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -97,3 +164,8 @@ pub struct Email {
     pub verified: bool,
     pub primary: bool,
 }
+
+/// Orders emails so the primary one comes first, it becomes the user email
+pub(crate) fn sort_primary_first(emails: &mut [Email]) {
+    emails.sort_by_key(|e| !e.primary);
+}
@@ -0,0 +1,193 @@
+use crate::config::AppConfig;
+use crate::constants::REVOCATION_MAX_ATTEMPTS;
+use crate::model::provider_token_revocation::ProviderTokenRevocation;
+use crate::model::provider_token_revocation_repository::{
+    ProviderTokenRevocationRepository, ProviderTokenRevocationRepositoryError,
+};
+use crate::oidc::oidc::{OidcError, OidcProvider};
+use crate::oidc::provider::apple::REVOKE_URL_APPLE;
+use crate::oidc::provider::google::REVOKE_URL_GOOGLE;
+use async_trait::async_trait;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+pub struct HttpClientResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+/// Http client used to call provider endpoints, replaced by a stub in tests
+#[async_trait]
+pub trait HttpClient {
+    /// Posts an url encoded form
+    async fn post_form(
+        &self,
+        url: &str,
+        form: &[(&str, &str)],
+    ) -> Result<HttpClientResponse, OidcError>;
+}
+
+#[derive(Clone, Default)]
+pub struct ReqwestHttpClient {
+    client: reqwest::Client,
+}
+
+impl ReqwestHttpClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl HttpClient for ReqwestHttpClient {
+    async fn post_form(
+        &self,
+        url: &str,
+        form: &[(&str, &str)],
+    ) -> Result<HttpClientResponse, OidcError> {
+        let response = self
+            .client
+            .post(url)
+            .header(reqwest::header::ACCEPT, "application/json")
+            .form(form)
+            .send()
+            .await?;
+        let status = response.status().as_u16();
+        let body = response.text().await?;
+
+        Ok(HttpClientResponse { status, body })
+    }
+}
+
+/// Revokes the refresh token a provider issued for an identity
+#[async_trait]
+pub trait TokenRevoker {
+    async fn revoke(&self, revocation: &ProviderTokenRevocation) -> Result<(), OidcError>;
+}
+
+pub struct ProviderTokenRevoker {
+    http_client: Arc<dyn HttpClient + Send + Sync>,
+}
+
+impl ProviderTokenRevoker {
+    pub fn new(http_client: Arc<dyn HttpClient + Send + Sync>) -> Self {
+        Self { http_client }
+    }
+}
+
+#[async_trait]
+impl TokenRevoker for ProviderTokenRevoker {
+    async fn revoke(&self, revocation: &ProviderTokenRevocation) -> Result<(), OidcError> {
+        let Some(token) = revocation.decrypted_refresh_token() else {
+            return Ok(());
+        };
+        let token = token.as_str();
+
+        let Some(provider) = OidcProvider::from_name(&revocation.provider) else {
+            return Err(OidcError::UnknownProvider);
+        };
+
+        let response = match provider {
+            OidcProvider::Apple => {
+                let Some(configuration) = AppConfig::provider_configuration(&provider) else {
+                    return Err(OidcError::UnknownProvider);
+                };
+
+                // Tokens of native apps were issued to the app, the audience of the ID token
+                let client_id = revocation
+                    .client_id
+                    .as_deref()
+                    .filter(|v| configuration.audiences.iter().any(|aud| aud == v))
+                    .unwrap_or(configuration.client_id.as_str());
+
+                let Some(client_secret) = configuration.client_secret_for(client_id)? else {
+                    return Err(OidcError::UnknownProvider);
+                };
+
+                let form = [
+                    ("client_id", client_id),
+                    ("client_secret", client_secret.as_str()),
+                    ("token", token),
+                    ("token_type_hint", "refresh_token"),
+                ];
+                self.http_client.post_form(REVOKE_URL_APPLE, &form).await?
+            }
+            OidcProvider::Google => {
+                self.http_client
+                    .post_form(REVOKE_URL_GOOGLE, &[("token", token)])
+                    .await?
+            }
+            // Revocation is only required by Apple, the other tokens are left to expire
+            _ => return Ok(()),
+        };
+
+        match response.status {
+            200..=299 => Ok(()),
+            status => Err(OidcError::RevocationRejected(status)),
+        }
+    }
+}
+
+/// Revokes the pending tokens in the background, retrying temporary failures. Tokens the
+/// provider could not be reached for stay pending until the next sweep
+pub fn spawn_revocation(
+    revoker: Arc<dyn TokenRevoker + Send + Sync>,
+    repository: Arc<dyn ProviderTokenRevocationRepository + Send + Sync>,
+    revocations: Vec<ProviderTokenRevocation>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        for revocation in revocations {
+            for attempt in 1..=REVOCATION_MAX_ATTEMPTS {
+                match revoker.revoke(&revocation).await {
+                    Ok(()) => {
+                        if let Err(e) = repository.delete(&revocation.id).await {
+                            log::warn!("Failed to remove revocation {}: {}", revocation.id, e);
+                        }
+                        break;
+                    }
+                    Err(e) if is_retryable(&e) => {
+                        if attempt == REVOCATION_MAX_ATTEMPTS {
+                            log::warn!(
+                                "Revocation of {} token of identity {} is left pending: {}",
+                                revocation.provider,
+                                revocation.identity_id,
+                                e
+                            );
+                            break;
+                        }
+                        tokio::time::sleep(Duration::from_secs(2u64.pow(attempt))).await;
+                    }
+                    Err(e) => {
+                        log::warn!(
+                            "Failed to revoke {} token of identity {}: {}",
+                            revocation.provider,
+                            revocation.identity_id,
+                            e
+                        );
+                        let _ = repository.delete(&revocation.id).await;
+                        break;
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// Retries the revocations left pending, e.g. by a restart or a provider outage
+pub async fn sweep_revocations(
+    revoker: Arc<dyn TokenRevoker + Send + Sync>,
+    repository: Arc<dyn ProviderTokenRevocationRepository + Send + Sync>,
+) -> Result<JoinHandle<()>, ProviderTokenRevocationRepositoryError> {
+    let revocations = repository.find_all().await?;
+    Ok(spawn_revocation(revoker, repository, revocations))
+}
+
+/// Network errors, rate limits and provider outages are worth another attempt
+fn is_retryable(error: &OidcError) -> bool {
+    match error {
+        OidcError::HttpError(_) => true,
+        OidcError::RevocationRejected(status) => *status == 429 || *status >= 500,
+        _ => false,
+    }
+}
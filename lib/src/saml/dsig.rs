@@ -0,0 +1,191 @@
+use crate::saml::xml::Element;
+use crate::saml::{SamlError, NS_DSIG};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use ring::{digest, signature};
+
+const EXC_C14N: &str = "http://www.w3.org/2001/10/xml-exc-c14n#";
+const ENVELOPED_SIGNATURE: &str = "http://www.w3.org/2000/09/xmldsig#enveloped-signature";
+const RSA_SHA256: &str = "http://www.w3.org/2001/04/xmldsig-more#rsa-sha256";
+const SHA256: &str = "http://www.w3.org/2001/04/xmlenc#sha256";
+
+fn invalid(message: &str) -> SamlError {
+    SamlError::InvalidSignature(message.to_string())
+}
+
+/// Decodes base64 content of XML elements, which is usually wrapped
+pub fn decode_base64(value: &str) -> Result<Vec<u8>, SamlError> {
+    let value: String = value.chars().filter(|c| !c.is_whitespace()).collect();
+    STANDARD
+        .decode(value)
+        .map_err(|_| SamlError::InvalidEncoding)
+}
+
+/// Verifies the enveloped signature of `element` with one of the trusted certificates.
+/// Key info embedded in the signature is ignored, only the IdP metadata is trusted.
+pub fn verify_enveloped_signature(
+    element: &Element,
+    certificates: &[Vec<u8>],
+) -> Result<(), SamlError> {
+    let mut signatures = element.children_named(NS_DSIG, "Signature");
+    let Some(signature) = signatures.next() else {
+        return Err(invalid("element is not signed"));
+    };
+    if signatures.next().is_some() {
+        return Err(invalid("multiple signatures"));
+    }
+
+    let Some(id) = element.attr("ID") else {
+        return Err(invalid("signed element has no ID"));
+    };
+
+    let Some(signed_info) = signature.child(NS_DSIG, "SignedInfo") else {
+        return Err(invalid("missing SignedInfo"));
+    };
+
+    let Some(canonicalization) = signed_info.child(NS_DSIG, "CanonicalizationMethod") else {
+        return Err(invalid("missing CanonicalizationMethod"));
+    };
+    if canonicalization.attr("Algorithm") != Some(EXC_C14N) {
+        return Err(invalid("unsupported canonicalization"));
+    }
+
+    let signature_method = signed_info
+        .child(NS_DSIG, "SignatureMethod")
+        .and_then(|v| v.attr("Algorithm"));
+    if signature_method != Some(RSA_SHA256) {
+        return Err(invalid("unsupported signature method"));
+    }
+
+    let references: Vec<&Element> = signed_info.children_named(NS_DSIG, "Reference").collect();
+    let [reference] = references.as_slice() else {
+        return Err(invalid("expected a single reference"));
+    };
+
+    // The reference must cover the element holding the signature, see signature wrapping
+    if reference.attr("URI") != Some(format!("#{}", id).as_str()) {
+        return Err(invalid("reference does not match the signed element"));
+    }
+
+    let mut inclusive_prefixes = Vec::new();
+    let mut canonicalized = false;
+    if let Some(transforms) = reference.child(NS_DSIG, "Transforms") {
+        for transform in transforms.children_named(NS_DSIG, "Transform") {
+            match transform.attr("Algorithm") {
+                Some(ENVELOPED_SIGNATURE) => {}
+                Some(EXC_C14N) => {
+                    canonicalized = true;
+                    inclusive_prefixes = prefix_list(transform);
+                }
+                _ => return Err(invalid("unsupported transform")),
+            }
+        }
+    }
+    if !canonicalized {
+        return Err(invalid("reference is not canonicalized"));
+    }
+
+    let digest_method = reference
+        .child(NS_DSIG, "DigestMethod")
+        .and_then(|v| v.attr("Algorithm"));
+    if digest_method != Some(SHA256) {
+        return Err(invalid("unsupported digest method"));
+    }
+
+    let Some(digest_value) = reference.child(NS_DSIG, "DigestValue") else {
+        return Err(invalid("missing DigestValue"));
+    };
+
+    let canonical = element.canonicalize(Some(signature), &inclusive_prefixes);
+    let digest = digest::digest(&digest::SHA256, canonical.as_bytes());
+    if digest.as_ref() != decode_base64(&digest_value.text())?.as_slice() {
+        return Err(invalid("digest does not match"));
+    }
+
+    let Some(signature_value) = signature.child(NS_DSIG, "SignatureValue") else {
+        return Err(invalid("missing SignatureValue"));
+    };
+    let signature_value = decode_base64(&signature_value.text())?;
+    let signed_info = signed_info.canonicalize(None, &prefix_list(canonicalization));
+
+    let verified = certificates
+        .iter()
+        .filter_map(|v| rsa_public_key(v))
+        .any(|key| {
+            signature::UnparsedPublicKey::new(&signature::RSA_PKCS1_2048_8192_SHA256, key)
+                .verify(signed_info.as_bytes(), &signature_value)
+                .is_ok()
+        });
+
+    if !verified {
+        return Err(invalid("signature does not match a trusted certificate"));
+    }
+
+    Ok(())
+}
+
+fn prefix_list(transform: &Element) -> Vec<String> {
+    transform
+        .child(EXC_C14N, "InclusiveNamespaces")
+        .and_then(|v| v.attr("PrefixList"))
+        .map(|v| v.split_whitespace().map(String::from).collect())
+        .unwrap_or_default()
+}
+
+/// Extracts the PKCS#1 RSA public key from a DER encoded X.509 certificate
+fn rsa_public_key(certificate: &[u8]) -> Option<&[u8]> {
+    let (certificate, _) = der_read(certificate, 0x30)?;
+    let (tbs_certificate, _) = der_read(certificate, 0x30)?;
+
+    let mut rest = tbs_certificate;
+    // Optional explicit version
+    if rest.first() == Some(&0xa0) {
+        rest = der_skip(rest)?;
+    }
+    // Serial number, signature algorithm, issuer, validity and subject
+    for _ in 0..5 {
+        rest = der_skip(rest)?;
+    }
+
+    let (public_key_info, _) = der_read(rest, 0x30)?;
+    let (_algorithm, rest) = der_read(public_key_info, 0x30)?;
+    let (bits, _) = der_read(rest, 0x03)?;
+
+    // The first byte counts the unused bits of the bit string
+    match bits.split_first() {
+        Some((0, key)) => Some(key),
+        _ => None,
+    }
+}
+
+/// Reads a DER value with the expected tag, returns its content and the remaining input
+fn der_read(input: &[u8], tag: u8) -> Option<(&[u8], &[u8])> {
+    if *input.first()? != tag {
+        return None;
+    }
+    der_content(input)
+}
+
+fn der_skip(input: &[u8]) -> Option<&[u8]> {
+    der_content(input).map(|(_, rest)| rest)
+}
+
+fn der_content(input: &[u8]) -> Option<(&[u8], &[u8])> {
+    let first = *input.get(1)?;
+    let (length, offset) = if first < 0x80 {
+        (first as usize, 2)
+    } else {
+        let size = (first & 0x7f) as usize;
+        if size == 0 || size > 4 {
+            return None;
+        }
+        let length = input
+            .get(2..2 + size)?
+            .iter()
+            .fold(0usize, |acc, v| (acc << 8) | *v as usize);
+        (length, 2 + size)
+    };
+
+    let content = input.get(offset..offset.checked_add(length)?)?;
+    Some((content, &input[offset + length..]))
+}
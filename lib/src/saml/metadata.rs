@@ -0,0 +1,98 @@
+use crate::saml::dsig::decode_base64;
+use crate::saml::xml::{self, Element};
+use crate::saml::{SamlError, BINDING_HTTP_POST, BINDING_HTTP_REDIRECT, NS_DSIG, NS_METADATA};
+use crate::utils::escape::escape_attribute;
+
+/// IdP settings imported from its metadata document
+#[derive(Debug, Clone)]
+pub struct IdpMetadata {
+    pub entity_id: String,
+    /// Single sign-on endpoint for the HTTP-Redirect binding
+    pub sso_url: String,
+    /// DER encoded signing certificates
+    pub certificates: Vec<Vec<u8>>,
+}
+
+fn invalid(message: &str) -> SamlError {
+    SamlError::InvalidMetadata(message.to_string())
+}
+
+impl IdpMetadata {
+    pub fn parse(metadata: &str) -> Result<IdpMetadata, SamlError> {
+        let root = xml::parse(metadata)?;
+
+        let entity = if root.is(NS_METADATA, "EntitiesDescriptor") {
+            root.children_named(NS_METADATA, "EntityDescriptor")
+                .find(|v| v.child(NS_METADATA, "IDPSSODescriptor").is_some())
+        } else if root.is(NS_METADATA, "EntityDescriptor") {
+            Some(&root)
+        } else {
+            None
+        };
+
+        let Some(entity) = entity else {
+            return Err(invalid("missing IdP EntityDescriptor"));
+        };
+
+        let Some(entity_id) = entity.attr("entityID") else {
+            return Err(invalid("missing entityID"));
+        };
+
+        let Some(descriptor) = entity.child(NS_METADATA, "IDPSSODescriptor") else {
+            return Err(invalid("missing IDPSSODescriptor"));
+        };
+
+        let Some(sso_url) = descriptor
+            .children_named(NS_METADATA, "SingleSignOnService")
+            .find(|v| v.attr("Binding") == Some(BINDING_HTTP_REDIRECT))
+            .and_then(|v| v.attr("Location"))
+        else {
+            return Err(invalid("missing HTTP-Redirect SingleSignOnService"));
+        };
+
+        let certificates = descriptor
+            .children_named(NS_METADATA, "KeyDescriptor")
+            .filter(|v| v.attr("use").is_none_or(|v| v == "signing"))
+            .flat_map(certificates_of)
+            .map(|v| decode_base64(&v.text()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if certificates.is_empty() {
+            return Err(invalid("missing signing certificate"));
+        }
+
+        Ok(IdpMetadata {
+            entity_id: entity_id.to_string(),
+            sso_url: sso_url.to_string(),
+            certificates,
+        })
+    }
+}
+
+fn certificates_of(key_descriptor: &Element) -> impl Iterator<Item = &Element> {
+    key_descriptor
+        .children_named(NS_DSIG, "KeyInfo")
+        .flat_map(|v| v.children_named(NS_DSIG, "X509Data"))
+        .flat_map(|v| v.children_named(NS_DSIG, "X509Certificate"))
+}
+
+/// Metadata document describing authcare as a service provider
+pub fn sp_metadata(entity_id: &str, acs_url: &str) -> String {
+    format!(
+        concat!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+            r#"<md:EntityDescriptor xmlns:md="{}" entityID="{}">"#,
+            r#"<md:SPSSODescriptor AuthnRequestsSigned="false" WantAssertionsSigned="true" "#,
+            r#"protocolSupportEnumeration="urn:oasis:names:tc:SAML:2.0:protocol">"#,
+            r#"<md:NameIDFormat>urn:oasis:names:tc:SAML:1.1:nameid-format:emailAddress</md:NameIDFormat>"#,
+            r#"<md:NameIDFormat>urn:oasis:names:tc:SAML:2.0:nameid-format:persistent</md:NameIDFormat>"#,
+            r#"<md:AssertionConsumerService Binding="{}" Location="{}" index="0" isDefault="true"/>"#,
+            r#"</md:SPSSODescriptor>"#,
+            r#"</md:EntityDescriptor>"#
+        ),
+        NS_METADATA,
+        escape_attribute(entity_id),
+        BINDING_HTTP_POST,
+        escape_attribute(acs_url),
+    )
+}
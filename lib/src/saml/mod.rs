@@ -0,0 +1,58 @@
+pub mod dsig;
+pub mod metadata;
+pub mod request;
+pub mod response;
+pub mod xml;
+
+use thiserror::Error;
+
+pub const NS_ASSERTION: &str = "urn:oasis:names:tc:SAML:2.0:assertion";
+pub const NS_PROTOCOL: &str = "urn:oasis:names:tc:SAML:2.0:protocol";
+pub const NS_METADATA: &str = "urn:oasis:names:tc:SAML:2.0:metadata";
+pub const NS_DSIG: &str = "http://www.w3.org/2000/09/xmldsig#";
+
+pub const BINDING_HTTP_REDIRECT: &str = "urn:oasis:names:tc:SAML:2.0:bindings:HTTP-Redirect";
+pub const BINDING_HTTP_POST: &str = "urn:oasis:names:tc:SAML:2.0:bindings:HTTP-POST";
+
+/// Identity provider name of a SAML connection in the `identity` table
+pub fn identity_provider(connection: &str) -> String {
+    format!("sso:{}", connection)
+}
+
+/// Domain part of an email, lowercased
+pub fn email_domain(email: &str) -> Option<String> {
+    email
+        .rsplit_once('@')
+        .map(|(_, domain)| domain.trim().to_lowercase())
+        .filter(|v| !v.is_empty())
+}
+
+#[derive(Error, Debug)]
+pub enum SamlError {
+    #[error("Invalid XML: {0}")]
+    InvalidXml(String),
+
+    #[error("Invalid base64 encoding")]
+    InvalidEncoding,
+
+    #[error("Invalid IdP metadata: {0}")]
+    InvalidMetadata(String),
+
+    #[error("Invalid signature: {0}")]
+    InvalidSignature(String),
+
+    #[error("Invalid SAML response: {0}")]
+    InvalidResponse(String),
+
+    #[error("IdP returned status {0}")]
+    StatusError(String),
+
+    #[error("Assertion conditions are not met: {0}")]
+    ConditionsNotMet(String),
+
+    #[error("Required attribute {0} is missing")]
+    MissingAttribute(String),
+
+    #[error("Unknown SSO connection")]
+    UnknownConnection,
+}
@@ -0,0 +1,71 @@
+use crate::saml::metadata::IdpMetadata;
+use crate::saml::{SamlError, BINDING_HTTP_POST, NS_ASSERTION, NS_PROTOCOL};
+use crate::utils::crypto::random_secret_token;
+use crate::utils::escape::escape_attribute;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{SecondsFormat, Utc};
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use openidconnect::url::Url;
+use std::io::Write;
+
+/// AuthnRequest sent to the IdP with the HTTP-Redirect binding
+pub struct AuthnRequest {
+    /// Request id, the IdP echoes it in `InResponseTo`
+    pub id: String,
+    destination: String,
+    xml: String,
+}
+
+impl AuthnRequest {
+    pub fn new(idp: &IdpMetadata, sp_entity_id: &str, acs_url: &str) -> AuthnRequest {
+        // Ids must not start with a digit
+        let id = format!("_{}", random_secret_token(32));
+        let issue_instant = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+
+        let xml = format!(
+            concat!(
+                r#"<samlp:AuthnRequest xmlns:samlp="{}" xmlns:saml="{}" ID="{}" Version="2.0" "#,
+                r#"IssueInstant="{}" Destination="{}" AssertionConsumerServiceURL="{}" "#,
+                r#"ProtocolBinding="{}">"#,
+                r#"<saml:Issuer>{}</saml:Issuer>"#,
+                r#"<samlp:NameIDPolicy AllowCreate="true"/>"#,
+                r#"</samlp:AuthnRequest>"#
+            ),
+            NS_PROTOCOL,
+            NS_ASSERTION,
+            id,
+            issue_instant,
+            escape_attribute(&idp.sso_url),
+            escape_attribute(acs_url),
+            BINDING_HTTP_POST,
+            escape_attribute(sp_entity_id),
+        );
+
+        AuthnRequest {
+            id,
+            destination: idp.sso_url.clone(),
+            xml,
+        }
+    }
+
+    /// IdP url carrying the deflated request and the relay state
+    pub fn redirect_url(&self, relay_state: &str) -> Result<Url, SamlError> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        let deflated = encoder
+            .write_all(self.xml.as_bytes())
+            .and_then(|_| encoder.finish())
+            .map_err(|e| SamlError::InvalidXml(e.to_string()))?;
+
+        let Ok(mut url) = Url::parse(&self.destination) else {
+            return Err(SamlError::InvalidMetadata("invalid SSO url".to_string()));
+        };
+
+        url.query_pairs_mut()
+            .append_pair("SAMLRequest", &STANDARD.encode(deflated))
+            .append_pair("RelayState", relay_state);
+
+        Ok(url)
+    }
+}
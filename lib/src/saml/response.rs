@@ -0,0 +1,361 @@
+use crate::constants::SAML_CLOCK_SKEW;
+use crate::oidc::provider::{Claims, Email, UserProvidedData};
+use crate::saml::dsig::{decode_base64, verify_enveloped_signature};
+use crate::saml::metadata::IdpMetadata;
+use crate::saml::xml::{self, Element};
+use crate::saml::{email_domain, SamlError, NS_ASSERTION, NS_DSIG, NS_PROTOCOL};
+use chrono::{DateTime, Duration, Utc};
+use std::collections::{HashMap, HashSet};
+
+const STATUS_SUCCESS: &str = "urn:oasis:names:tc:SAML:2.0:status:Success";
+const CONFIRMATION_BEARER: &str = "urn:oasis:names:tc:SAML:2.0:cm:bearer";
+const NAMEID_TRANSIENT: &str = "urn:oasis:names:tc:SAML:2.0:nameid-format:transient";
+
+/// Names of the assertion attributes holding the user profile
+#[derive(Debug, Clone)]
+pub struct AttributeMapping {
+    pub email: String,
+    pub name: String,
+    pub given_name: String,
+    pub family_name: String,
+}
+
+impl Default for AttributeMapping {
+    fn default() -> Self {
+        Self {
+            email: "email".to_string(),
+            name: "name".to_string(),
+            given_name: "given_name".to_string(),
+            family_name: "family_name".to_string(),
+        }
+    }
+}
+
+/// What a response is checked against
+pub struct ResponseValidation<'a> {
+    pub idp: &'a IdpMetadata,
+    pub sp_entity_id: &'a str,
+    pub acs_url: &'a str,
+    /// Id of the AuthnRequest stored in the flow state
+    pub request_id: &'a str,
+    pub now: DateTime<Utc>,
+}
+
+/// Verified content of an assertion
+#[derive(Debug, Clone)]
+pub struct SamlAssertion {
+    pub issuer: String,
+    pub name_id: String,
+    pub session_index: Option<String>,
+    pub attributes: HashMap<String, Vec<String>>,
+}
+
+fn invalid(message: &str) -> SamlError {
+    SamlError::InvalidResponse(message.to_string())
+}
+
+fn not_met(message: &str) -> SamlError {
+    SamlError::ConditionsNotMet(message.to_string())
+}
+
+fn parse_instant(value: &str) -> Result<DateTime<Utc>, SamlError> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|v| v.with_timezone(&Utc))
+        .map_err(|_| invalid("invalid instant"))
+}
+
+/// Decodes and validates the `SAMLResponse` posted to the ACS endpoint
+pub fn parse_response(
+    saml_response: &str,
+    validation: &ResponseValidation,
+) -> Result<SamlAssertion, SamlError> {
+    let Ok(document) = String::from_utf8(decode_base64(saml_response)?) else {
+        return Err(SamlError::InvalidEncoding);
+    };
+
+    let response = xml::parse(&document)?;
+    if !response.is(NS_PROTOCOL, "Response") {
+        return Err(invalid("expected a Response"));
+    }
+
+    // Duplicated ids allow a signed element to be swapped for a forged one
+    let ids = response.ids();
+    if ids.iter().collect::<HashSet<_>>().len() != ids.len() {
+        return Err(invalid("duplicated ID"));
+    }
+
+    if let Some(destination) = response.attr("Destination") {
+        if destination != validation.acs_url {
+            return Err(invalid("unexpected Destination"));
+        }
+    }
+
+    // Unsolicited responses are not supported, every login starts with a flow state
+    if response.attr("InResponseTo") != Some(validation.request_id) {
+        return Err(invalid("unexpected InResponseTo"));
+    }
+
+    let status = response
+        .child(NS_PROTOCOL, "Status")
+        .and_then(|v| v.child(NS_PROTOCOL, "StatusCode"))
+        .and_then(|v| v.attr("Value"));
+    if status != Some(STATUS_SUCCESS) {
+        return Err(SamlError::StatusError(status.unwrap_or_default().to_string()));
+    }
+
+    if let Some(issuer) = response.child(NS_ASSERTION, "Issuer") {
+        if issuer.text() != validation.idp.entity_id {
+            return Err(invalid("unexpected Issuer"));
+        }
+    }
+
+    if response.child(NS_ASSERTION, "EncryptedAssertion").is_some() {
+        return Err(invalid("encrypted assertions are not supported"));
+    }
+
+    let assertions: Vec<&Element> = response.children_named(NS_ASSERTION, "Assertion").collect();
+    let [assertion] = assertions.as_slice() else {
+        return Err(invalid("expected a single Assertion"));
+    };
+
+    let response_signed = response.child(NS_DSIG, "Signature").is_some();
+    if response_signed {
+        verify_enveloped_signature(&response, &validation.idp.certificates)?;
+    }
+    if assertion.child(NS_DSIG, "Signature").is_some() {
+        verify_enveloped_signature(assertion, &validation.idp.certificates)?;
+    } else if !response_signed {
+        return Err(SamlError::InvalidSignature("response is not signed".to_string()));
+    }
+
+    parse_assertion(assertion, validation)
+}
+
+fn parse_assertion(
+    assertion: &Element,
+    validation: &ResponseValidation,
+) -> Result<SamlAssertion, SamlError> {
+    let issuer = assertion
+        .child(NS_ASSERTION, "Issuer")
+        .map(|v| v.text())
+        .unwrap_or_default();
+    if issuer != validation.idp.entity_id {
+        return Err(invalid("unexpected assertion Issuer"));
+    }
+
+    let skew = Duration::minutes(SAML_CLOCK_SKEW);
+
+    let Some(subject) = assertion.child(NS_ASSERTION, "Subject") else {
+        return Err(invalid("missing Subject"));
+    };
+
+    let Some(name_id) = subject.child(NS_ASSERTION, "NameID") else {
+        return Err(invalid("missing NameID"));
+    };
+    // A transient NameID changes on every login and can't identify the user
+    if name_id.attr("Format") == Some(NAMEID_TRANSIENT) {
+        return Err(invalid("transient NameID"));
+    }
+
+    let confirmed = subject
+        .children_named(NS_ASSERTION, "SubjectConfirmation")
+        .filter(|v| v.attr("Method") == Some(CONFIRMATION_BEARER))
+        .filter_map(|v| v.child(NS_ASSERTION, "SubjectConfirmationData"))
+        .map(|data| -> Result<bool, SamlError> {
+            let not_on_or_after = match data.attr("NotOnOrAfter") {
+                Some(value) => parse_instant(value)?,
+                None => return Ok(false),
+            };
+
+            Ok(data.attr("Recipient") == Some(validation.acs_url)
+                && data
+                    .attr("InResponseTo")
+                    .is_none_or(|v| v == validation.request_id)
+                && validation.now < not_on_or_after + skew)
+        })
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .any(|v| v);
+    if !confirmed {
+        return Err(not_met("subject confirmation"));
+    }
+
+    let Some(conditions) = assertion.child(NS_ASSERTION, "Conditions") else {
+        return Err(invalid("missing Conditions"));
+    };
+
+    if let Some(not_before) = conditions.attr("NotBefore") {
+        if validation.now + skew < parse_instant(not_before)? {
+            return Err(not_met("NotBefore"));
+        }
+    }
+
+    if let Some(not_on_or_after) = conditions.attr("NotOnOrAfter") {
+        if validation.now >= parse_instant(not_on_or_after)? + skew {
+            return Err(not_met("NotOnOrAfter"));
+        }
+    }
+
+    let restrictions: Vec<&Element> = conditions
+        .children_named(NS_ASSERTION, "AudienceRestriction")
+        .collect();
+    let audience_allowed = !restrictions.is_empty()
+        && restrictions.iter().all(|restriction| {
+            restriction
+                .children_named(NS_ASSERTION, "Audience")
+                .any(|v| v.text() == validation.sp_entity_id)
+        });
+    if !audience_allowed {
+        return Err(not_met("Audience"));
+    }
+
+    let session_index = assertion
+        .child(NS_ASSERTION, "AuthnStatement")
+        .and_then(|v| v.attr("SessionIndex"))
+        .map(String::from);
+
+    let mut attributes: HashMap<String, Vec<String>> = HashMap::new();
+    for attribute in assertion
+        .children_named(NS_ASSERTION, "AttributeStatement")
+        .flat_map(|v| v.children_named(NS_ASSERTION, "Attribute"))
+    {
+        let Some(name) = attribute.attr("Name") else {
+            continue;
+        };
+
+        attributes.entry(name.to_string()).or_default().extend(
+            attribute
+                .children_named(NS_ASSERTION, "AttributeValue")
+                .map(|v| v.text()),
+        );
+    }
+
+    Ok(SamlAssertion {
+        issuer,
+        name_id: name_id.text(),
+        session_index,
+        attributes,
+    })
+}
+
+impl SamlAssertion {
+    fn attribute(&self, name: &str) -> Option<String> {
+        self.attributes
+            .get(name)
+            .and_then(|v| v.first())
+            .filter(|v| !v.is_empty())
+            .cloned()
+    }
+
+    /// Maps the assertion to the user data, the email is only verified within the `domains`
+    /// of the connection, the IdP can't vouch for addresses of other organizations
+    pub fn into_user_provided_data(
+        self,
+        mapping: &AttributeMapping,
+        domains: &[String],
+    ) -> Result<UserProvidedData, SamlError> {
+        let email = match self.attribute(&mapping.email) {
+            Some(email) => email,
+            None if self.name_id.contains('@') => self.name_id.clone(),
+            None => return Err(SamlError::MissingAttribute(mapping.email.clone())),
+        };
+
+        let verified = email_domain(&email).is_some_and(|v| domains.contains(&v));
+
+        let mut custom_claims = HashMap::new();
+        if let Some(session_index) = &self.session_index {
+            custom_claims.insert("session_index".to_string(), session_index.clone().into());
+        }
+        custom_claims.insert(
+            "attributes".to_string(),
+            serde_json::to_value(&self.attributes).unwrap_or_default(),
+        );
+
+        let claims = Claims {
+            issuer: Some(self.issuer.clone()),
+            subject: Some(self.name_id.clone()),
+            name: self.attribute(&mapping.name),
+            given_name: self.attribute(&mapping.given_name),
+            family_name: self.attribute(&mapping.family_name),
+            email: Some(email.clone()),
+            email_verified: Some(verified),
+            custom_claims: Some(custom_claims),
+            ..Default::default()
+        };
+
+        Ok(UserProvidedData {
+            emails: vec![Email {
+                email,
+                verified,
+                primary: true,
+            }],
+            metadata: Some(claims),
+            provider_tokens: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+
+    // The response assertion was signed with openssl over `xmllint --exc-c14n` output
+    const IDP_METADATA: &str = include_str!("fixtures/idp_metadata.xml");
+    const RESPONSE: &str = include_str!("fixtures/response.xml");
+
+    fn validate(response: &str, sp_entity_id: &str, now: &str) -> Result<SamlAssertion, SamlError> {
+        let idp = IdpMetadata::parse(IDP_METADATA)?;
+        let validation = ResponseValidation {
+            idp: &idp,
+            sp_entity_id,
+            acs_url: "https://auth.example.com/auth/sso/acs",
+            request_id: "_request-id",
+            now: parse_instant(now)?,
+        };
+
+        parse_response(&STANDARD.encode(response), &validation)
+    }
+
+    #[test]
+    fn test_parse_response() -> Result<(), Box<dyn std::error::Error>> {
+        let sp = "https://auth.example.com/saml";
+        let assertion = validate(RESPONSE, sp, "2026-10-19T10:01:00Z")?;
+        assert_eq!(assertion.name_id, "00u1a2b3c4");
+        assert_eq!(assertion.session_index.as_deref(), Some("_session-1"));
+        assert_eq!(assertion.attributes["groups"], vec!["admins", "developers"]);
+
+        let domains = vec!["acme.com".to_string()];
+        let mapping = AttributeMapping::default();
+        let data = assertion.clone().into_user_provided_data(&mapping, &domains)?;
+        let claims = data.metadata.unwrap();
+        assert_eq!(data.emails[0].email, "jane.doe@acme.com");
+        assert!(data.emails[0].verified);
+        assert_eq!(claims.email_verified, Some(true));
+        assert_eq!(claims.subject.as_deref(), Some("00u1a2b3c4"));
+        assert_eq!(claims.family_name.as_deref(), Some("Doe & Co"));
+
+        // The IdP of another organization can't vouch for acme.com
+        let domains = vec!["other.com".to_string()];
+        let data = assertion.into_user_provided_data(&mapping, &domains)?;
+        assert!(!data.emails[0].verified);
+
+        let tampered = RESPONSE.replace("jane.doe@acme.com", "admin@acme.com");
+        let result = validate(&tampered, sp, "2026-10-19T10:01:00Z");
+        assert!(matches!(result, Err(SamlError::InvalidSignature(_))));
+
+        let start = RESPONSE.find("<ds:Signature").unwrap();
+        let end = RESPONSE.find("</ds:Signature>").unwrap() + "</ds:Signature>".len();
+        let unsigned = format!("{}{}", &RESPONSE[..start], &RESPONSE[end..]);
+        let result = validate(&unsigned, sp, "2026-10-19T10:01:00Z");
+        assert!(matches!(result, Err(SamlError::InvalidSignature(_))));
+
+        let result = validate(RESPONSE, "https://other.example.com", "2026-10-19T10:01:00Z");
+        assert!(matches!(result, Err(SamlError::ConditionsNotMet(_))));
+
+        let result = validate(RESPONSE, sp, "2026-10-19T10:30:00Z");
+        assert!(matches!(result, Err(SamlError::ConditionsNotMet(_))));
+        Ok(())
+    }
+}
@@ -0,0 +1,499 @@
+use crate::saml::SamlError;
+use crate::utils::escape::escape_attribute;
+use std::collections::BTreeMap;
+
+const NS_XML: &str = "http://www.w3.org/XML/1998/namespace";
+const MAX_DEPTH: usize = 64;
+
+/// Element of a parsed document, namespaces are resolved while parsing
+#[derive(Debug, Clone)]
+pub struct Element {
+    pub prefix: Option<String>,
+    pub name: String,
+    pub namespace: Option<String>,
+    pub attributes: Vec<Attribute>,
+    /// Namespaces in scope, the default namespace is keyed by an empty prefix
+    pub in_scope: BTreeMap<String, String>,
+    pub children: Vec<Node>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Attribute {
+    pub prefix: Option<String>,
+    pub name: String,
+    pub namespace: Option<String>,
+    pub value: String,
+}
+
+#[derive(Debug, Clone)]
+pub enum Node {
+    Element(Element),
+    Text(String),
+}
+
+impl Element {
+    pub fn is(&self, namespace: &str, name: &str) -> bool {
+        self.name == name && self.namespace.as_deref() == Some(namespace)
+    }
+
+    pub fn elements(&self) -> impl Iterator<Item = &Element> {
+        self.children.iter().filter_map(|v| match v {
+            Node::Element(element) => Some(element),
+            Node::Text(_) => None,
+        })
+    }
+
+    pub fn children_named<'a>(
+        &'a self,
+        namespace: &'a str,
+        name: &'a str,
+    ) -> impl Iterator<Item = &'a Element> {
+        self.elements().filter(move |v| v.is(namespace, name))
+    }
+
+    pub fn child(&self, namespace: &str, name: &str) -> Option<&Element> {
+        self.elements().find(|v| v.is(namespace, name))
+    }
+
+    /// Value of an attribute without namespace
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|v| v.namespace.is_none() && v.name == name)
+            .map(|v| v.value.as_str())
+    }
+
+    /// Concatenated text content, surrounding whitespace removed
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        self.collect_text(&mut text);
+        text.trim().to_string()
+    }
+
+    fn collect_text(&self, text: &mut String) {
+        for child in &self.children {
+            match child {
+                Node::Text(value) => text.push_str(value),
+                Node::Element(element) => element.collect_text(text),
+            }
+        }
+    }
+
+    /// Every `ID` attribute of the subtree, used to reject duplicated ids
+    pub fn ids(&self) -> Vec<&str> {
+        let mut ids: Vec<&str> = self.attr("ID").into_iter().collect();
+        for element in self.elements() {
+            ids.extend(element.ids());
+        }
+        ids
+    }
+
+    /// Exclusive XML canonicalization (without comments) of the subtree
+    pub fn canonicalize(&self, exclude: Option<&Element>, inclusive_prefixes: &[String]) -> String {
+        let mut output = String::new();
+        self.write_canonical(&BTreeMap::new(), exclude, inclusive_prefixes, &mut output);
+        output
+    }
+
+    fn qualified_name(&self) -> String {
+        match &self.prefix {
+            Some(prefix) => format!("{}:{}", prefix, self.name),
+            None => self.name.clone(),
+        }
+    }
+
+    fn write_canonical(
+        &self,
+        rendered: &BTreeMap<String, String>,
+        exclude: Option<&Element>,
+        inclusive_prefixes: &[String],
+        output: &mut String,
+    ) {
+        // Only namespaces visibly utilized by the element or its attributes are rendered
+        let mut utilized: Vec<String> = vec![self.prefix.clone().unwrap_or_default()];
+        for attribute in &self.attributes {
+            if let Some(prefix) = &attribute.prefix {
+                if prefix != "xml" {
+                    utilized.push(prefix.clone());
+                }
+            }
+        }
+        for prefix in inclusive_prefixes {
+            let prefix = if prefix == "#default" { "" } else { prefix.as_str() };
+            if self.in_scope.contains_key(prefix) {
+                utilized.push(prefix.to_string());
+            }
+        }
+        utilized.sort();
+        utilized.dedup();
+
+        let mut rendered = rendered.clone();
+        let mut namespaces = Vec::new();
+        for prefix in utilized {
+            let uri = self.in_scope.get(&prefix).cloned().unwrap_or_default();
+            let previous = rendered.get(&prefix).cloned().unwrap_or_default();
+            if uri == previous && (rendered.contains_key(&prefix) || uri.is_empty()) {
+                continue;
+            }
+
+            namespaces.push((prefix.clone(), uri.clone()));
+            rendered.insert(prefix, uri);
+        }
+
+        let mut attributes: Vec<&Attribute> = self.attributes.iter().collect();
+        attributes.sort_by(|a, b| {
+            (a.namespace.as_deref().unwrap_or(""), a.name.as_str())
+                .cmp(&(b.namespace.as_deref().unwrap_or(""), b.name.as_str()))
+        });
+
+        let name = self.qualified_name();
+        output.push('<');
+        output.push_str(&name);
+        for (prefix, uri) in namespaces {
+            if prefix.is_empty() {
+                output.push_str(" xmlns=\"");
+            } else {
+                output.push_str(" xmlns:");
+                output.push_str(&prefix);
+                output.push_str("=\"");
+            }
+            output.push_str(&escape_attribute(&uri));
+            output.push('"');
+        }
+        for attribute in attributes {
+            output.push(' ');
+            if let Some(prefix) = &attribute.prefix {
+                output.push_str(prefix);
+                output.push(':');
+            }
+            output.push_str(&attribute.name);
+            output.push_str("=\"");
+            output.push_str(&escape_attribute(&attribute.value));
+            output.push('"');
+        }
+        output.push('>');
+
+        for child in &self.children {
+            match child {
+                Node::Text(text) => output.push_str(&escape_text(text)),
+                Node::Element(element) => {
+                    if exclude.is_some_and(|v| std::ptr::eq(v, element)) {
+                        continue;
+                    }
+                    element.write_canonical(&rendered, exclude, inclusive_prefixes, output);
+                }
+            }
+        }
+
+        output.push_str("</");
+        output.push_str(&name);
+        output.push('>');
+    }
+}
+
+/// Parses a document and returns its root element, DTDs are rejected
+pub fn parse(input: &str) -> Result<Element, SamlError> {
+    let mut parser = Parser {
+        input: input.trim_start_matches('\u{feff}'),
+        position: 0,
+        depth: 0,
+    };
+
+    parser.skip_misc()?;
+    let root = parser.parse_element(&BTreeMap::new())?;
+    parser.skip_misc()?;
+
+    if parser.position != parser.input.len() {
+        return Err(parser.error("content after the root element"));
+    }
+
+    Ok(root)
+}
+
+pub fn escape_text(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\r', "&#xD;")
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    position: usize,
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.input[self.position..]
+    }
+
+    fn error(&self, message: &str) -> SamlError {
+        SamlError::InvalidXml(format!("{} at {}", message, self.position))
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn skip_past(&mut self, end: &str) -> Result<&'a str, SamlError> {
+        let rest = self.rest();
+        let Some(index) = rest.find(end) else {
+            return Err(self.error(&format!("missing {}", end)));
+        };
+
+        self.position += index + end.len();
+        Ok(&rest[..index])
+    }
+
+    /// Skips the declaration, comments, processing instructions and whitespace
+    fn skip_misc(&mut self) -> Result<(), SamlError> {
+        loop {
+            self.skip_whitespace();
+            let rest = self.rest();
+            if rest.starts_with("<?") {
+                self.skip_past("?>")?;
+            } else if rest.starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if rest.starts_with("<!") {
+                return Err(self.error("DTDs are not allowed"));
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn parse_name(&mut self) -> Result<&'a str, SamlError> {
+        let rest = self.rest();
+        let end = rest
+            .find(|c: char| c.is_whitespace() || matches!(c, '/' | '>' | '=' | '<'))
+            .unwrap_or(rest.len());
+
+        if end == 0 {
+            return Err(self.error("expected a name"));
+        }
+
+        self.position += end;
+        Ok(&rest[..end])
+    }
+
+    fn parse_element(&mut self, scope: &BTreeMap<String, String>) -> Result<Element, SamlError> {
+        if !self.rest().starts_with('<') {
+            return Err(self.error("expected an element"));
+        }
+        self.position += 1;
+
+        let qualified_name = self.parse_name()?;
+        let mut in_scope = scope.clone();
+        let mut raw_attributes = Vec::new();
+
+        let empty = loop {
+            self.skip_whitespace();
+            let rest = self.rest();
+            if rest.starts_with("/>") {
+                self.position += 2;
+                break true;
+            }
+            if rest.starts_with('>') {
+                self.position += 1;
+                break false;
+            }
+
+            let name = self.parse_name()?;
+            self.skip_whitespace();
+            if !self.rest().starts_with('=') {
+                return Err(self.error("expected ="));
+            }
+            self.position += 1;
+            self.skip_whitespace();
+
+            let Some(quote) = self.rest().chars().next().filter(|c| *c == '"' || *c == '\'') else {
+                return Err(self.error("expected a quoted value"));
+            };
+            self.position += 1;
+            let raw = self.skip_past(&quote.to_string())?;
+            // Attribute value normalization turns literal whitespace characters into spaces
+            let value = decode_entities(&raw.replace(['\t', '\n', '\r'], " "))
+                .map_err(|e| self.error(&e))?;
+
+            if name == "xmlns" {
+                in_scope.insert(String::new(), value);
+            } else if let Some(prefix) = name.strip_prefix("xmlns:") {
+                in_scope.insert(prefix.to_string(), value);
+            } else {
+                raw_attributes.push((name, value));
+            }
+        };
+
+        // An empty default namespace declaration undeclares it
+        if in_scope.get("").is_some_and(|v| v.is_empty()) {
+            in_scope.remove("");
+        }
+
+        let (prefix, name) = split_name(qualified_name);
+        let namespace = match &prefix {
+            Some(prefix) => Some(resolve(&in_scope, prefix).ok_or_else(|| {
+                self.error(&format!("undeclared prefix {}", prefix))
+            })?),
+            None => in_scope.get("").cloned(),
+        };
+
+        let mut attributes = Vec::new();
+        for (qualified_name, value) in raw_attributes {
+            let (prefix, name) = split_name(qualified_name);
+            let namespace = match &prefix {
+                Some(prefix) => Some(resolve(&in_scope, prefix).ok_or_else(|| {
+                    self.error(&format!("undeclared prefix {}", prefix))
+                })?),
+                None => None,
+            };
+            attributes.push(Attribute {
+                prefix,
+                name,
+                namespace,
+                value,
+            });
+        }
+
+        let mut element = Element {
+            prefix,
+            name,
+            namespace,
+            attributes,
+            in_scope,
+            children: Vec::new(),
+        };
+
+        if empty {
+            return Ok(element);
+        }
+
+        loop {
+            let rest = self.rest();
+            if rest.is_empty() {
+                return Err(self.error("unexpected end of document"));
+            }
+
+            if rest.starts_with("</") {
+                self.position += 2;
+                let end_name = self.parse_name()?;
+                if end_name != qualified_name {
+                    return Err(self.error(&format!("mismatched end tag {}", end_name)));
+                }
+                self.skip_whitespace();
+                if !self.rest().starts_with('>') {
+                    return Err(self.error("expected >"));
+                }
+                self.position += 1;
+                return Ok(element);
+            } else if rest.starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if rest.starts_with("<![CDATA[") {
+                self.position += 9;
+                let text = self.skip_past("]]>")?;
+                push_text(&mut element, &normalize_newlines(text));
+            } else if rest.starts_with("<?") {
+                self.skip_past("?>")?;
+            } else if rest.starts_with("<!") {
+                return Err(self.error("DTDs are not allowed"));
+            } else if rest.starts_with('<') {
+                // Bounds the recursion, SAML documents are only a few levels deep
+                if self.depth >= MAX_DEPTH {
+                    return Err(self.error("document nested too deeply"));
+                }
+                self.depth += 1;
+                let child = self.parse_element(&element.in_scope)?;
+                self.depth -= 1;
+                element.children.push(Node::Element(child));
+            } else {
+                let end = rest.find('<').unwrap_or(rest.len());
+                self.position += end;
+                let text = decode_entities(&normalize_newlines(&rest[..end]))
+                    .map_err(|e| self.error(&e))?;
+                push_text(&mut element, &text);
+            }
+        }
+    }
+}
+
+fn split_name(qualified_name: &str) -> (Option<String>, String) {
+    match qualified_name.split_once(':') {
+        Some((prefix, name)) => (Some(prefix.to_string()), name.to_string()),
+        None => (None, qualified_name.to_string()),
+    }
+}
+
+fn resolve(scope: &BTreeMap<String, String>, prefix: &str) -> Option<String> {
+    if prefix == "xml" {
+        return Some(NS_XML.to_string());
+    }
+    scope.get(prefix).cloned()
+}
+
+fn push_text(element: &mut Element, text: &str) {
+    if let Some(Node::Text(previous)) = element.children.last_mut() {
+        previous.push_str(text);
+    } else {
+        element.children.push(Node::Text(text.to_string()));
+    }
+}
+
+fn normalize_newlines(text: &str) -> String {
+    text.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+fn decode_entities(value: &str) -> Result<String, String> {
+    let mut output = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(index) = rest.find('&') {
+        output.push_str(&rest[..index]);
+        rest = &rest[index + 1..];
+
+        let Some(end) = rest.find(';') else {
+            return Err("unterminated entity".to_string());
+        };
+
+        let entity = &rest[..end];
+        let decoded = match entity {
+            "lt" => '<',
+            "gt" => '>',
+            "amp" => '&',
+            "quot" => '"',
+            "apos" => '\'',
+            _ => {
+                let code = if let Some(hex) = entity.strip_prefix("#x") {
+                    u32::from_str_radix(hex, 16).ok()
+                } else if let Some(decimal) = entity.strip_prefix('#') {
+                    decimal.parse().ok()
+                } else {
+                    None
+                };
+
+                code.and_then(char::from_u32)
+                    .ok_or_else(|| format!("unknown entity {}", entity))?
+            }
+        };
+
+        output.push(decoded);
+        rest = &rest[end + 1..];
+    }
+
+    output.push_str(rest);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_depth() {
+        let nested = |depth: usize| format!("{}{}", "<a>".repeat(depth), "</a>".repeat(depth));
+
+        assert!(parse(&nested(MAX_DEPTH)).is_ok());
+        assert!(matches!(parse(&nested(MAX_DEPTH + 2)), Err(SamlError::InvalidXml(_))));
+    }
+}
@@ -2,8 +2,11 @@ use std::sync::Arc;
 
 use thiserror::Error;
 
+use crate::config::AppConfig;
+use crate::ldap::{Directory, DirectoryMode, LdapError};
 use crate::model::user::{User, UserError};
 use crate::model::user_repository::{UserRepository, UserRepositoryError};
+use crate::service::user_serivce::{UserService, UserServiceError};
 
 #[derive(Error, Debug)]
 pub enum AuthServiceError {
@@ -16,27 +19,96 @@ pub enum AuthServiceError {
     #[error("Invalid Credentials")]
     InvalidCredentials,
 
+    #[error("Domain requires SSO sign in")]
+    SsoRequired(String),
+
     #[error("Internal account")]
     InternalAccountError(#[from] UserError),
 
     #[error("Internal data store error")]
     InternalDbError(#[from] UserRepositoryError),
+
+    #[error("Directory error")]
+    DirectoryError(#[from] LdapError),
+
+    #[error("Directory identity error")]
+    DirectoryIdentityError(#[from] UserServiceError),
+}
+
+#[derive(Clone)]
+struct DirectoryBackend {
+    directory: Arc<dyn Directory + Send + Sync + 'static>,
+    mode: DirectoryMode,
+    user_service: UserService,
 }
 
 #[derive(Clone)]
 pub struct AuthService {
     account_repository: Arc<dyn UserRepository + Send + Sync + 'static>,
+    directory: Option<DirectoryBackend>,
 }
 
 impl AuthService {
     pub fn new(account_repository: Arc<dyn UserRepository + Send + Sync + 'static>) -> Self {
-        AuthService { account_repository }
+        AuthService {
+            account_repository,
+            directory: None,
+        }
+    }
+
+    /// Verifies passwords against a directory too, its users get a local account on first sign in
+    pub fn set_directory(
+        mut self,
+        directory: Arc<dyn Directory + Send + Sync + 'static>,
+        mode: DirectoryMode,
+        user_service: UserService,
+    ) -> Self {
+        self.directory = Some(DirectoryBackend {
+            directory,
+            mode,
+            user_service,
+        });
+        self
     }
 
     pub async fn authenticate(
         &self,
         email: String,
         password: String,
+    ) -> Result<User, AuthServiceError> {
+        // Checked before the lookup so the answer doesn't depend on the account existing
+        let sso_domains = AppConfig::sso_domains();
+        if let Some(connection) = sso_domains.and_then(|v| v.required_connection(&email)) {
+            return Err(AuthServiceError::SsoRequired(connection.name.clone()));
+        }
+
+        let Some(backend) = &self.directory else {
+            return self.authenticate_local(email, password).await;
+        };
+
+        if backend.mode == DirectoryMode::Fallback {
+            match self.authenticate_local(email.clone(), password.clone()).await {
+                Ok(user) => return Ok(user),
+                Err(AuthServiceError::AccountNotFound)
+                | Err(AuthServiceError::InvalidCredentials) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        let Some(data) = backend.directory.authenticate(&email, &password).await? else {
+            return Err(AuthServiceError::InvalidCredentials);
+        };
+
+        Ok(backend
+            .user_service
+            .create_user_from_directory_identity(&data)
+            .await?)
+    }
+
+    async fn authenticate_local(
+        &self,
+        email: String,
+        password: String,
     ) -> Result<User, AuthServiceError> {
         let Ok(user) = self.account_repository.find_by_email(email.as_str()).await else {
             return Err(AuthServiceError::AccountNotFound);
@@ -61,3 +133,123 @@ impl AuthService {
         Ok(user)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LdapConfiguration;
+    use crate::ldap::map_entry;
+    use crate::model::identity_repository::DbIdentityRepository;
+    use crate::model::provider_notification_repository::DbProviderNotificationRepository;
+    use crate::model::provider_token_revocation_repository::DbProviderTokenRevocationRepository;
+    use crate::model::user_repository::DbUserRepository;
+    use crate::oidc::provider::UserProvidedData;
+    use crate::oidc::revocation::{ProviderTokenRevoker, ReqwestHttpClient};
+    use async_trait::async_trait;
+    use ldap3::SearchEntry;
+    use sqlx::PgPool;
+    use std::collections::HashMap;
+
+    /// Directory with a single `jane` entry
+    struct StubDirectory;
+
+    #[async_trait]
+    impl Directory for StubDirectory {
+        async fn authenticate(
+            &self,
+            username: &str,
+            password: &str,
+        ) -> Result<Option<UserProvidedData>, LdapError> {
+            if username != "jane@corp.example.com" || password != "directory-password" {
+                return Ok(None);
+            }
+
+            let entry = SearchEntry {
+                dn: "uid=jane,ou=people,dc=corp,dc=example,dc=com".to_string(),
+                attrs: HashMap::from([
+                    ("entryUUID".to_string(), vec!["9f1c-jane".to_string()]),
+                    ("mail".to_string(), vec!["jane@corp.example.com".to_string()]),
+                    ("cn".to_string(), vec!["Jane Doe".to_string()]),
+                ]),
+                bin_attrs: HashMap::new(),
+            };
+            Ok(Some(map_entry(&test_configuration(), entry)?))
+        }
+    }
+
+    fn test_configuration() -> LdapConfiguration {
+        LdapConfiguration {
+            url: "ldap://localhost".to_string(),
+            starttls: false,
+            bind_dn: None,
+            bind_password: None,
+            base_dn: "dc=corp,dc=example,dc=com".to_string(),
+            user_filter: "(mail={username})".to_string(),
+            user_dn_template: None,
+            id_attribute: "entryUUID".to_string(),
+            attribute_mapping: crate::saml::response::AttributeMapping {
+                email: "mail".to_string(),
+                name: "cn".to_string(),
+                given_name: "givenName".to_string(),
+                family_name: "sn".to_string(),
+            },
+            mode: DirectoryMode::Fallback,
+            linking_policy: Default::default(),
+            sync_profile: false,
+        }
+    }
+
+    fn auth_service(pool: &PgPool, mode: DirectoryMode) -> (AuthService, UserService) {
+        let user_repository = Arc::new(DbUserRepository::new(pool.clone()));
+        let user_service = UserService::new(
+            user_repository.clone(),
+            Arc::new(DbIdentityRepository::new(pool.clone())),
+            Arc::new(ProviderTokenRevoker::new(Arc::new(ReqwestHttpClient::new()))),
+            Arc::new(DbProviderTokenRevocationRepository::new(pool.clone())),
+            Arc::new(DbProviderNotificationRepository::new(pool.clone())),
+        );
+        let auth_service = AuthService::new(user_repository).set_directory(
+            Arc::new(StubDirectory),
+            mode,
+            user_service.clone(),
+        );
+
+        (auth_service, user_service)
+    }
+
+    #[sqlx::test]
+    async fn directory_authenticate_test(pool: PgPool) -> Result<(), Box<dyn std::error::Error>> {
+        let (service, user_service) = auth_service(&pool, DirectoryMode::Fallback);
+        user_service
+            .create_user("local@corp.example.com".to_string(), "local-password".to_string())
+            .await?;
+
+        let local = service
+            .authenticate("local@corp.example.com".to_string(), "local-password".to_string())
+            .await?;
+        assert_eq!(local.email.as_deref(), Some("local@corp.example.com"));
+
+        let user = service
+            .authenticate("jane@corp.example.com".to_string(), "directory-password".to_string())
+            .await?;
+        let identities = user_service.get_identities(&user.id).await?;
+        assert!(identities.iter().any(|v| v.provider == "ldap"));
+
+        let again = service
+            .authenticate("jane@corp.example.com".to_string(), "directory-password".to_string())
+            .await?;
+        assert_eq!(again.id, user.id);
+
+        let result = service
+            .authenticate("jane@corp.example.com".to_string(), "wrong".to_string())
+            .await;
+        assert!(matches!(result, Err(AuthServiceError::InvalidCredentials)));
+
+        let (service, _) = auth_service(&pool, DirectoryMode::Exclusive);
+        let result = service
+            .authenticate("local@corp.example.com".to_string(), "local-password".to_string())
+            .await;
+        assert!(matches!(result, Err(AuthServiceError::InvalidCredentials)));
+        Ok(())
+    }
+}
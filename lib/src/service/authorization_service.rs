@@ -0,0 +1,638 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{Duration, TimeZone, Utc};
+use openidconnect::url::Url;
+use ring::digest::{digest, SHA256};
+use std::sync::Arc;
+use thiserror::Error;
+
+use crate::config::AppConfig;
+use crate::constants::{
+    JWT_ISS_CLAIM, OAUTH_AUTHORIZATION_EXPIRED_IN, OAUTH_REQUEST_URI_EXPIRED_IN,
+    OAUTH_SESSION_AUD_CLAIM, OAUTH_SESSION_EXPIRED_IN,
+};
+use crate::model::authorization::Authorization;
+use crate::model::authorization_repository::{
+    AuthorizationRepository, AuthorizationRepositoryError,
+};
+use crate::model::client::OAuthClient;
+use crate::model::identity_repository::{IdentityRepository, IdentityRepositoryError};
+use crate::model::jwt::JWTClaims;
+use crate::model::session::Session;
+use crate::model::session_repository::{SessionRepository, SessionRepositoryError};
+use crate::model::user::User;
+use crate::oauth::id_token::{at_hash, IdTokenClaims, UserInfo};
+use crate::oauth::request_object::{decode_request_object, REQUEST_URI_PREFIX};
+use crate::oauth::{
+    grant_scope, AuthorizationRequest, OAuthError, CODE_CHALLENGE_METHOD_S256,
+    GRANT_TYPE_AUTHORIZATION_CODE, OIDC_SCOPES,
+};
+use crate::service::client_service::ClientService;
+use crate::utils::crypto::{constant_time_eq, random_secret_token};
+
+#[derive(Error, Debug)]
+pub enum AuthorizationServiceError {
+    /// Unknown client or redirect uri, the user must not be redirected
+    #[error("Invalid client or redirect uri")]
+    InvalidClient,
+
+    /// Error response sent back to the redirect uri of the client
+    #[error("Authorization request rejected")]
+    ErrorRedirect(Url),
+
+    #[error("Authorization not found")]
+    AuthorizationNotFound,
+
+    #[error("Session not found")]
+    SessionNotFound,
+
+    #[error("{0}")]
+    OAuthError(#[from] OAuthError),
+
+    #[error("Internal data store error")]
+    InternalDbError(#[from] AuthorizationRepositoryError),
+
+    #[error("Internal session store error")]
+    InternalSessionRepositoryError(#[from] SessionRepositoryError),
+
+    #[error("Internal identity store error")]
+    InternalIdentityRepositoryError(#[from] IdentityRepositoryError),
+
+    #[error("Internal JWT error")]
+    InternalJWTError(#[from] jsonwebtoken::errors::Error),
+}
+
+/// Authcare as an OpenID provider, relying parties send their users here to sign in
+#[derive(Clone)]
+pub struct AuthorizationService {
+    authorization_repository: Arc<dyn AuthorizationRepository + Send + Sync + 'static>,
+    session_repository: Arc<dyn SessionRepository + Send + Sync + 'static>,
+    identity_repository: Arc<dyn IdentityRepository + Send + Sync + 'static>,
+    client_service: ClientService,
+}
+
+impl AuthorizationService {
+    pub fn new(
+        authorization_repository: Arc<dyn AuthorizationRepository + Send + Sync + 'static>,
+        session_repository: Arc<dyn SessionRepository + Send + Sync + 'static>,
+        identity_repository: Arc<dyn IdentityRepository + Send + Sync + 'static>,
+        client_service: ClientService,
+    ) -> Self {
+        Self {
+            authorization_repository,
+            session_repository,
+            identity_repository,
+            client_service,
+        }
+    }
+
+    /// Validates and stores the request, the user signs in before it is granted
+    pub async fn start_authorization(
+        &self,
+        request: &AuthorizationRequest,
+    ) -> Result<Authorization, AuthorizationServiceError> {
+        let Some(client_id) = &request.client_id else {
+            return Err(AuthorizationServiceError::InvalidClient);
+        };
+
+        let Ok(client) = self.client_service.get_client(client_id).await else {
+            return Err(AuthorizationServiceError::InvalidClient);
+        };
+
+        // Pushed requests were validated when the client pushed them
+        if let Some(request_uri) = &request.request_uri {
+            let expires_at = Utc::now() + Duration::minutes(OAUTH_AUTHORIZATION_EXPIRED_IN);
+            let Ok(authorization) = self
+                .authorization_repository
+                .take_by_request_uri(&client.id, request_uri, expires_at)
+                .await
+            else {
+                return Err(OAuthError::InvalidRequestUri.into());
+            };
+
+            return Ok(authorization);
+        }
+
+        if client.require_pushed_authorization_requests {
+            return Err(OAuthError::InvalidRequest(
+                "authorization requests must be pushed".to_string(),
+            )
+            .into());
+        }
+
+        let request = match &request.request {
+            Some(request_object) => decode_request_object(&client, request_object)?,
+            None => request.clone(),
+        };
+
+        let Some(redirect_uri) = resolve_redirect_uri(&client, &request) else {
+            return Err(AuthorizationServiceError::InvalidClient);
+        };
+
+        let authorization = match validate_request(&client, &redirect_uri, &request) {
+            Ok(authorization) => authorization,
+            Err(error) => {
+                let url = error_redirect_url(&redirect_uri, request.state.as_deref(), &error)?;
+                return Err(AuthorizationServiceError::ErrorRedirect(url));
+            }
+        };
+
+        Ok(self.authorization_repository.add(authorization).await?)
+    }
+
+    /// Stores the request of an authenticated client, the user agent is sent to the
+    /// authorization endpoint with the request uri only, see RFC 9126
+    pub async fn push_authorization(
+        &self,
+        client: &OAuthClient,
+        request: &AuthorizationRequest,
+    ) -> Result<Authorization, OAuthError> {
+        if request.request_uri.is_some() {
+            return Err(OAuthError::InvalidRequest("request_uri can't be pushed".to_string()));
+        }
+
+        if request.client_id.as_ref().is_some_and(|v| v != &client.id) {
+            return Err(OAuthError::InvalidRequest("client_id does not match".to_string()));
+        }
+
+        let request = match &request.request {
+            Some(request_object) => decode_request_object(client, request_object)?,
+            None => request.clone(),
+        };
+
+        let Some(redirect_uri) = resolve_redirect_uri(client, &request) else {
+            return Err(OAuthError::InvalidRequest("invalid redirect_uri".to_string()));
+        };
+
+        let mut authorization = validate_request(client, &redirect_uri, &request)?;
+        authorization.request_uri = Some(format!("{}{}", REQUEST_URI_PREFIX, random_secret_token(32)));
+        authorization.expires_at = Utc::now() + Duration::seconds(OAUTH_REQUEST_URI_EXPIRED_IN);
+
+        self.authorization_repository
+            .add(authorization)
+            .await
+            .map_err(|_| OAuthError::ServerError)
+    }
+
+    /// Pending authorization waiting for the user to sign in
+    pub async fn get_authorization(
+        &self,
+        id: &uuid::Uuid,
+    ) -> Result<Authorization, AuthorizationServiceError> {
+        let Ok(authorization) = self.authorization_repository.get(id).await else {
+            return Err(AuthorizationServiceError::AuthorizationNotFound);
+        };
+
+        if authorization.code.is_some()
+            || authorization.request_uri.is_some()
+            || authorization.is_expired()
+        {
+            return Err(AuthorizationServiceError::AuthorizationNotFound);
+        }
+
+        Ok(authorization)
+    }
+
+    /// Starts the browser session of the hosted login, returns the cookie value
+    pub async fn start_session(&self, user: &User) -> Result<String, AuthorizationServiceError> {
+        // Signed with the provider key, so the cookie is never accepted as an access token
+        let Some(signing_key) = AppConfig::oidc_signing_key() else {
+            return Err(OAuthError::ServerError.into());
+        };
+
+        let session = self
+            .session_repository
+            .add(Session::new(user.id, None, None))
+            .await?;
+
+        let now = Utc::now();
+        let claims = JWTClaims {
+            aud: OAUTH_SESSION_AUD_CLAIM.to_string(),
+            exp: (now + Duration::minutes(OAUTH_SESSION_EXPIRED_IN)).timestamp(),
+            iat: now.timestamp(),
+            iss: JWT_ISS_CLAIM.to_string(),
+            sub: user.id.to_string(),
+            sid: session.id.to_string(),
+            scope: String::new(),
+            act: None,
+            cnf: None,
+        };
+
+        Ok(signing_key.sign(&claims)?)
+    }
+
+    /// Browser session of the cookie, it ends when the session is revoked
+    pub async fn authenticate_session(
+        &self,
+        cookie: &str,
+    ) -> Result<JWTClaims, AuthorizationServiceError> {
+        let Some(signing_key) = AppConfig::oidc_signing_key() else {
+            return Err(AuthorizationServiceError::SessionNotFound);
+        };
+
+        let mut validation = signing_key.validation();
+        validation.set_audience(&[OAUTH_SESSION_AUD_CLAIM]);
+        validation.set_issuer(&[JWT_ISS_CLAIM]);
+
+        let Ok(claims) = signing_key.verify::<JWTClaims>(cookie, &validation) else {
+            return Err(AuthorizationServiceError::SessionNotFound);
+        };
+
+        let Ok(session_id) = uuid::Uuid::parse_str(&claims.sid) else {
+            return Err(AuthorizationServiceError::SessionNotFound);
+        };
+
+        if self.session_repository.get(session_id).await.is_err() {
+            return Err(AuthorizationServiceError::SessionNotFound);
+        }
+
+        Ok(claims)
+    }
+
+    /// Grants the authorization to the signed in user, returns the redirect carrying the code
+    pub async fn complete_authorization(
+        &self,
+        mut authorization: Authorization,
+        session: &JWTClaims,
+    ) -> Result<Url, AuthorizationServiceError> {
+        let (Ok(user_id), Ok(session_id)) = (
+            uuid::Uuid::parse_str(&session.sub),
+            uuid::Uuid::parse_str(&session.sid),
+        ) else {
+            return Err(AuthorizationServiceError::SessionNotFound);
+        };
+        let auth_time = Utc
+            .timestamp_opt(session.iat, 0)
+            .single()
+            .unwrap_or_else(Utc::now);
+
+        let code = random_secret_token(64);
+        authorization.grant(code.clone(), user_id, session_id, auth_time);
+        let authorization = self.authorization_repository.update(authorization).await?;
+
+        let Ok(mut url) = Url::parse(&authorization.redirect_uri) else {
+            return Err(AuthorizationServiceError::InvalidClient);
+        };
+        url.query_pairs_mut().append_pair("code", &code);
+        if let Some(state) = &authorization.state {
+            url.query_pairs_mut().append_pair("state", state);
+        }
+        // Lets the client detect mix-up attacks, see RFC 9207
+        url.query_pairs_mut()
+            .append_pair("iss", &AppConfig::oidc_issuer());
+
+        Ok(url)
+    }
+
+    /// Error response of a stored authorization, e.g. `login_required` for `prompt=none`
+    pub fn deny_authorization(
+        &self,
+        authorization: &Authorization,
+        error: &OAuthError,
+    ) -> Result<Url, AuthorizationServiceError> {
+        error_redirect_url(
+            &authorization.redirect_uri,
+            authorization.state.as_deref(),
+            error,
+        )
+    }
+
+    /// Exchanges the code, it can be used only once
+    pub async fn redeem_code(
+        &self,
+        client: &OAuthClient,
+        code: &str,
+        redirect_uri: Option<&str>,
+        code_verifier: Option<&str>,
+    ) -> Result<Authorization, OAuthError> {
+        let Ok(authorization) = self.authorization_repository.delete_by_code(code).await else {
+            return Err(OAuthError::InvalidGrant("unknown code".to_string()));
+        };
+
+        if authorization.client_id != client.id {
+            return Err(OAuthError::InvalidGrant("code was issued to another client".to_string()));
+        }
+
+        // The authorization always stores the redirect uri, so the client has to repeat it
+        if redirect_uri != Some(authorization.redirect_uri.as_str()) {
+            return Err(OAuthError::InvalidGrant("redirect_uri does not match".to_string()));
+        }
+
+        if authorization.is_expired() {
+            return Err(OAuthError::InvalidGrant("code expired".to_string()));
+        }
+
+        if let Some(code_challenge) = &authorization.code_challenge {
+            let Some(code_verifier) = code_verifier else {
+                return Err(OAuthError::InvalidGrant("code_verifier is required".to_string()));
+            };
+
+            let hash = URL_SAFE_NO_PAD.encode(digest(&SHA256, code_verifier.as_bytes()));
+            if !constant_time_eq(&hash, code_challenge) {
+                return Err(OAuthError::InvalidGrant("code_verifier does not match".to_string()));
+            }
+        }
+
+        Ok(authorization)
+    }
+
+    /// Claims of the user released for the scopes
+    pub async fn user_info(
+        &self,
+        user: &User,
+        scopes: &[&str],
+    ) -> Result<UserInfo, AuthorizationServiceError> {
+        Ok(UserInfo::new(user, self.is_email_verified(user).await?, scopes))
+    }
+
+    /// ID token of a redeemed authorization, bound to the access token issued with it
+    pub async fn issue_id_token(
+        &self,
+        authorization: &Authorization,
+        user: &User,
+        access_token: &str,
+    ) -> Result<String, AuthorizationServiceError> {
+        let Some(signing_key) = AppConfig::oidc_signing_key() else {
+            return Err(OAuthError::ServerError.into());
+        };
+
+        let user_info = self.user_info(user, &authorization.scopes()).await?;
+        let mut claims =
+            IdTokenClaims::new(&authorization.client_id, user_info, authorization.auth_time);
+        claims.nonce = authorization.nonce.clone();
+        claims.sid = authorization.session_id.map(|v| v.to_string());
+        claims.at_hash = Some(at_hash(access_token));
+
+        Ok(signing_key.sign(&claims)?)
+    }
+
+    /// Emails are verified by the external providers, password accounts once confirmed
+    async fn is_email_verified(&self, user: &User) -> Result<bool, AuthorizationServiceError> {
+        if user.confirmed_at.is_some() {
+            return Ok(true);
+        }
+
+        let identities = self.identity_repository.find_all_by_user(&user.id).await?;
+        Ok(identities.iter().any(|v| v.provider != "email"))
+    }
+}
+
+/// The redirect uri must match exactly, it may be omitted when the client has only one
+fn resolve_redirect_uri(client: &OAuthClient, request: &AuthorizationRequest) -> Option<String> {
+    match (&request.redirect_uri, client.redirect_uris.as_slice()) {
+        (Some(redirect_uri), uris) if uris.contains(redirect_uri) => Some(redirect_uri.clone()),
+        (None, [redirect_uri]) => Some(redirect_uri.clone()),
+        _ => None,
+    }
+}
+
+/// Authorization of a valid request, errors are sent back to the redirect uri
+fn validate_request(
+    client: &OAuthClient,
+    redirect_uri: &str,
+    request: &AuthorizationRequest,
+) -> Result<Authorization, OAuthError> {
+    if request.response_type.as_deref() != Some("code") {
+        return Err(OAuthError::UnsupportedResponseType);
+    }
+
+    if !client.allows_grant(GRANT_TYPE_AUTHORIZATION_CODE) {
+        return Err(OAuthError::UnauthorizedClient);
+    }
+
+    let mut allowed = client.scopes.clone();
+    allowed.extend(OIDC_SCOPES.iter().map(|v| v.to_string()));
+    let scope = match grant_scope(request.scope.as_deref(), &allowed) {
+        Ok(scope) if !scope.is_empty() => scope,
+        _ => return Err(OAuthError::InvalidScope),
+    };
+
+    match (&request.code_challenge, request.code_challenge_method.as_deref()) {
+        (Some(_), Some(CODE_CHALLENGE_METHOD_S256)) => {}
+        (Some(_), _) => {
+            return Err(OAuthError::InvalidRequest(
+                "code_challenge_method must be S256".to_string(),
+            ))
+        }
+        (None, _) if client.is_public() => {
+            return Err(OAuthError::InvalidRequest("code_challenge is required".to_string()))
+        }
+        (None, _) => {}
+    }
+
+    let mut authorization = Authorization::new(&client.id, redirect_uri, &scope);
+    authorization.state = request.state.clone();
+    authorization.nonce = request.nonce.clone();
+    authorization.code_challenge = request.code_challenge.clone();
+    authorization.code_challenge_method = request.code_challenge_method.clone();
+    authorization.prompt = request.prompt.clone();
+
+    Ok(authorization)
+}
+
+fn error_redirect_url(
+    redirect_uri: &str,
+    state: Option<&str>,
+    error: &OAuthError,
+) -> Result<Url, AuthorizationServiceError> {
+    let Ok(mut url) = Url::parse(redirect_uri) else {
+        return Err(AuthorizationServiceError::InvalidClient);
+    };
+    url.query_pairs_mut()
+        .append_pair("error", error.code())
+        .append_pair("error_description", &error.to_string());
+    if let Some(state) = state {
+        url.query_pairs_mut().append_pair("state", state);
+    }
+
+    Ok(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::authorization_repository::DbAuthorizationRepository;
+    use crate::model::client_repository::DbClientRepository;
+    use crate::model::identity_repository::DbIdentityRepository;
+    use crate::model::session_repository::DbSessionRepository;
+    use crate::model::user_repository::{DbUserRepository, UserRepository};
+    use crate::oauth::keys::tests::RSA_PRIVATE_KEY;
+    use crate::oauth::keys::SigningKey;
+    use crate::oauth::registration::AUTH_METHOD_NONE;
+    use sqlx::PgPool;
+
+    const CODE_VERIFIER: &str = "dBjftJeZ4CVP-mB92K9uhvKcv0Yl-2Pc5tY6JGt2fmM";
+    const CODE_CHALLENGE: &str = "-fYLSN1qteJaqoiyFelsL7MC4pknzsNpwP4y3SltKNM";
+    const REDIRECT_URI: &str = "com.example.app:/callback";
+
+    fn services(pool: &PgPool) -> (AuthorizationService, ClientService) {
+        std::env::set_var("OIDC_ISSUER", "https://auth.example.com/api/v1");
+        std::env::set_var("OIDC_SIGNING_KEY", RSA_PRIVATE_KEY);
+
+        let client_service = ClientService::new(Arc::new(DbClientRepository::new(pool.clone())));
+        let service = AuthorizationService::new(
+            Arc::new(DbAuthorizationRepository::new(pool.clone())),
+            Arc::new(DbSessionRepository::new(pool.clone())),
+            Arc::new(DbIdentityRepository::new(pool.clone())),
+            client_service.clone(),
+        );
+
+        (service, client_service)
+    }
+
+    #[sqlx::test]
+    async fn authorization_code_test(pool: PgPool) -> Result<(), Box<dyn std::error::Error>> {
+        let (service, client_service) = services(&pool);
+        let (client, _) = client_service
+            .create_client(
+                OAuthClient::new(
+                    vec![GRANT_TYPE_AUTHORIZATION_CODE.to_string()],
+                    vec![],
+                    vec![REDIRECT_URI.to_string()],
+                ),
+                false,
+            )
+            .await?;
+        let user = DbUserRepository::new(pool.clone())
+            .add(User::new("jane@example.com".to_string(), "hash".to_string()))
+            .await?;
+
+        let mut request = AuthorizationRequest {
+            response_type: Some("code".to_string()),
+            client_id: Some(client.id.clone()),
+            scope: Some("openid email".to_string()),
+            state: Some("af0ifjsldkj".to_string()),
+            nonce: Some("n-0S6_WzA2Mj".to_string()),
+            ..Default::default()
+        };
+
+        // Public clients must use PKCE
+        let result = service.start_authorization(&request).await;
+        let Err(AuthorizationServiceError::ErrorRedirect(url)) = result else {
+            panic!("Expect error redirect");
+        };
+        assert!(url.as_str().contains("error=invalid_request"));
+
+        request.code_challenge = Some(CODE_CHALLENGE.to_string());
+        request.code_challenge_method = Some("S256".to_string());
+        let authorization = service.start_authorization(&request).await?;
+        assert_eq!(authorization.redirect_uri, "com.example.app:/callback");
+
+        let cookie = service.start_session(&user).await?;
+        let session = service.authenticate_session(&cookie).await?;
+        let url = service.complete_authorization(authorization, &session).await?;
+        let code = url
+            .query_pairs()
+            .find(|(key, _)| key == "code")
+            .map(|(_, value)| value.to_string())
+            .expect("Expect code");
+
+        let client = client_service
+            .authenticate_client(&client.id, None, AUTH_METHOD_NONE)
+            .await?;
+        let authorization = service
+            .redeem_code(&client, &code, Some(REDIRECT_URI), Some(CODE_VERIFIER))
+            .await?;
+        assert_eq!(authorization.user_id, Some(user.id));
+
+        let id_token = service
+            .issue_id_token(&authorization, &user, "access-token")
+            .await?;
+        let signing_key = AppConfig::oidc_signing_key().expect("Expect signing key");
+        let mut validation = signing_key.validation();
+        validation.set_audience(&[&client.id]);
+        let claims: IdTokenClaims = signing_key.verify(&id_token, &validation)?;
+        assert_eq!(claims.user_info.sub, user.id.to_string());
+        assert_eq!(claims.nonce.as_deref(), Some("n-0S6_WzA2Mj"));
+        assert_eq!(claims.sid, Some(session.sid.clone()));
+        assert_eq!(claims.user_info.email_verified, Some(false));
+
+        // Codes can be redeemed only once
+        let result = service
+            .redeem_code(&client, &code, Some(REDIRECT_URI), Some(CODE_VERIFIER))
+            .await;
+        assert!(matches!(result, Err(OAuthError::InvalidGrant(_))));
+
+        // The redirect uri must be repeated even when the request left it out
+        let authorization = service.start_authorization(&request).await?;
+        let url = service.complete_authorization(authorization, &session).await?;
+        let (_, code) = url.query_pairs().find(|(key, _)| key == "code").expect("Expect code");
+        let result = service
+            .redeem_code(&client, &code, None, Some(CODE_VERIFIER))
+            .await;
+        assert!(matches!(result, Err(OAuthError::InvalidGrant(_))));
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn pushed_authorization_test(pool: PgPool) -> Result<(), Box<dyn std::error::Error>> {
+        let (service, client_service) = services(&pool);
+        let client_key = SigningKey::from_pem(RSA_PRIVATE_KEY)?;
+        let mut client = OAuthClient::new(
+            vec![GRANT_TYPE_AUTHORIZATION_CODE.to_string()],
+            vec![],
+            vec!["https://partner.example.com/callback".to_string()],
+        );
+        client.jwks = Some(client_key.jwks());
+        client.require_pushed_authorization_requests = true;
+        let (client, _) = client_service.create_client(client, true).await?;
+
+        let mut request = AuthorizationRequest {
+            response_type: Some("code".to_string()),
+            client_id: Some(client.id.clone()),
+            scope: Some("openid".to_string()),
+            ..Default::default()
+        };
+        let result = service.start_authorization(&request).await;
+        assert!(matches!(
+            result,
+            Err(AuthorizationServiceError::OAuthError(OAuthError::InvalidRequest(_)))
+        ));
+
+        let request_object = client_key.sign(&serde_json::json!({
+            "iss": client.id,
+            "aud": AppConfig::oidc_issuer(),
+            "iat": Utc::now().timestamp(),
+            "nbf": Utc::now().timestamp(),
+            "exp": (Utc::now() + Duration::minutes(1)).timestamp(),
+            "response_type": "code",
+            "scope": "openid",
+            "state": "af0ifjsldkj",
+            "prompt": "login",
+        }))?;
+
+        // Parameters outside of the request object are ignored
+        request.request = Some(request_object.clone());
+        request.scope = Some("openid email".to_string());
+        let pushed = service.push_authorization(&client, &request).await?;
+        let request_uri = pushed.request_uri.clone().expect("Expect request uri");
+        assert!(request_uri.starts_with(REQUEST_URI_PREFIX));
+        assert_eq!(pushed.scope, "openid");
+
+        let (header, _) = request_object.rsplit_once('.').expect("Expect JWS");
+        request.request = Some(format!("{}.{}", header, "tampered"));
+        let result = service.push_authorization(&client, &request).await;
+        assert!(matches!(result, Err(OAuthError::InvalidRequestObject(_))));
+
+        let request = AuthorizationRequest {
+            client_id: Some(client.id.clone()),
+            request_uri: Some(request_uri),
+            ..Default::default()
+        };
+        let authorization = service.start_authorization(&request).await?;
+        assert_eq!(authorization.id, pushed.id);
+        assert_eq!(authorization.state.as_deref(), Some("af0ifjsldkj"));
+        assert_eq!(authorization.prompt.as_deref(), Some("login"));
+        assert!(service.get_authorization(&authorization.id).await.is_ok());
+
+        // Request uris can be used once
+        let result = service.start_authorization(&request).await;
+        assert!(matches!(
+            result,
+            Err(AuthorizationServiceError::OAuthError(OAuthError::InvalidRequestUri))
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,412 @@
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::task;
+
+use crate::config::{AppConfig, OAuthClientConfiguration};
+use crate::model::client::OAuthClient;
+use crate::model::client_repository::{ClientRepository, ClientRepositoryError};
+use crate::model::jwt::JWTClaims;
+use crate::oauth::registration::{ClientMetadata, ClientRegistration};
+use crate::oauth::{grant_scope, OAuthError, GRANT_TYPE_CLIENT_CREDENTIALS};
+use crate::utils::crypto::{compare_hash_and_password, hash_password, random_secret_token};
+
+#[derive(Error, Debug)]
+pub enum ClientServiceError {
+    #[error("Client not found")]
+    ClientNotFound,
+
+    #[error("Internal error while hashing the secret")]
+    InternalHashError,
+
+    #[error("Internal data store error")]
+    InternalDbError(#[from] ClientRepositoryError),
+}
+
+#[derive(Clone)]
+pub struct ClientService {
+    client_repository: Arc<dyn ClientRepository + Send + Sync + 'static>,
+}
+
+impl ClientService {
+    pub fn new(client_repository: Arc<dyn ClientRepository + Send + Sync + 'static>) -> Self {
+        Self { client_repository }
+    }
+
+    pub async fn get_client(&self, id: &str) -> Result<OAuthClient, ClientServiceError> {
+        self.client_repository
+            .get(id)
+            .await
+            .map_err(|_| ClientServiceError::ClientNotFound)
+    }
+
+    /// Stores the client, confidential clients get a secret that is only returned here
+    pub async fn create_client(
+        &self,
+        mut client: OAuthClient,
+        confidential: bool,
+    ) -> Result<(OAuthClient, Option<String>), ClientServiceError> {
+        let secret = confidential.then(|| random_secret_token(64));
+
+        if let Some(secret) = secret.clone() {
+            client.client_secret_hash = Some(hash_secret(secret).await?);
+        }
+
+        let client = self.client_repository.add(client).await?;
+        Ok((client, secret))
+    }
+
+    /// Stores the clients of `OIDC_CLIENTS`, so they don't have to be registered by hand
+    pub async fn save_configured_clients(
+        &self,
+        configurations: impl IntoIterator<Item = &OAuthClientConfiguration>,
+    ) -> Result<(), ClientServiceError> {
+        for configuration in configurations {
+            let mut client = OAuthClient::new(
+                configuration.grant_types.clone(),
+                configuration.scopes.clone(),
+                configuration.redirect_uris.clone(),
+            );
+            client.id = configuration.client_id.clone();
+            client.post_logout_redirect_uris = configuration.post_logout_redirect_uris.clone();
+            if let Some(secret) = configuration.secret.clone() {
+                client.client_secret_hash = Some(hash_secret(secret).await?);
+            }
+
+            self.client_repository.upsert(client).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Authenticates the client at the token endpoint, public clients send no secret
+    ///
+    /// Registered clients have to use the `token_endpoint_auth_method` they registered with.
+    pub async fn authenticate_client(
+        &self,
+        client_id: &str,
+        client_secret: Option<&str>,
+        auth_method: &str,
+    ) -> Result<OAuthClient, OAuthError> {
+        let Ok(client) = self.client_repository.get(client_id).await else {
+            return Err(OAuthError::InvalidClient);
+        };
+
+        if client
+            .token_endpoint_auth_method
+            .as_deref()
+            .is_some_and(|v| v != auth_method)
+        {
+            return Err(OAuthError::InvalidClient);
+        }
+
+        let authenticated = match (client.client_secret_hash.clone(), client_secret) {
+            (Some(hash), Some(secret)) => {
+                let secret = secret.to_string();
+                task::spawn_blocking(move || compare_hash_and_password(&hash, &secret))
+                    .await
+                    .map_err(|_| OAuthError::ServerError)?
+            }
+            (None, None) => true,
+            _ => false,
+        };
+
+        if !authenticated {
+            return Err(OAuthError::InvalidClient);
+        }
+
+        Ok(client)
+    }
+
+    /// Registers a client of a partner, see RFC 7591
+    pub async fn register_client(
+        &self,
+        metadata: &ClientMetadata,
+    ) -> Result<ClientRegistration, OAuthError> {
+        let metadata = metadata.validate(&AppConfig::client_registration_scopes())?;
+
+        let mut client = OAuthClient::new(
+            metadata.grant_types.clone(),
+            metadata.scopes.clone(),
+            metadata.redirect_uris.clone(),
+        );
+        client.client_name = metadata.client_name.clone();
+        client.token_endpoint_auth_method = Some(metadata.token_endpoint_auth_method.clone());
+        client.jwks = metadata.jwks.clone();
+        client.require_pushed_authorization_requests = metadata.require_pushed_authorization_requests;
+        client.post_logout_redirect_uris = metadata.post_logout_redirect_uris.clone();
+        client.frontchannel_logout_uri = metadata.frontchannel_logout_uri.clone();
+        client.backchannel_logout_uri = metadata.backchannel_logout_uri.clone();
+
+        let registration_access_token = random_secret_token(64);
+        let token = registration_access_token.clone();
+        let hash = task::spawn_blocking(move || hash_password(&token))
+            .await
+            .map_err(|_| OAuthError::ServerError)?;
+        client.registration_access_token_hash = Some(hash);
+
+        let (client, client_secret) = self
+            .create_client(client, !metadata.is_public())
+            .await
+            .map_err(|_| OAuthError::ServerError)?;
+
+        Ok(ClientRegistration {
+            client,
+            client_secret,
+            registration_access_token,
+        })
+    }
+
+    /// Client managed by the registration access token, see RFC 7592 section 2
+    pub async fn authenticate_registration(
+        &self,
+        client_id: &str,
+        registration_access_token: &str,
+    ) -> Result<OAuthClient, OAuthError> {
+        let Ok(client) = self.client_repository.get(client_id).await else {
+            return Err(OAuthError::InvalidToken);
+        };
+
+        let Some(hash) = client.registration_access_token_hash.clone() else {
+            return Err(OAuthError::InvalidToken);
+        };
+
+        let token = registration_access_token.to_string();
+        let authenticated = task::spawn_blocking(move || compare_hash_and_password(&hash, &token))
+            .await
+            .map_err(|_| OAuthError::ServerError)?;
+
+        if !authenticated {
+            return Err(OAuthError::InvalidToken);
+        }
+
+        Ok(client)
+    }
+
+    /// Replaces the metadata of a registered client, a client can't switch between public and
+    /// confidential since it has no secret to switch to
+    pub async fn update_registration(
+        &self,
+        mut client: OAuthClient,
+        metadata: &ClientMetadata,
+    ) -> Result<OAuthClient, OAuthError> {
+        if metadata.client_id.as_deref() != Some(client.id.as_str()) {
+            return Err(OAuthError::InvalidRequest("client_id does not match".to_string()));
+        }
+
+        let metadata = metadata.validate(&AppConfig::client_registration_scopes())?;
+        if metadata.is_public() != client.is_public() {
+            return Err(OAuthError::InvalidClientMetadata(
+                "token_endpoint_auth_method can't change between public and confidential"
+                    .to_string(),
+            ));
+        }
+
+        client.grant_types = metadata.grant_types;
+        client.scopes = metadata.scopes;
+        client.redirect_uris = metadata.redirect_uris;
+        client.client_name = metadata.client_name;
+        client.token_endpoint_auth_method = Some(metadata.token_endpoint_auth_method);
+        client.jwks = metadata.jwks;
+        client.require_pushed_authorization_requests = metadata.require_pushed_authorization_requests;
+        client.post_logout_redirect_uris = metadata.post_logout_redirect_uris;
+        client.frontchannel_logout_uri = metadata.frontchannel_logout_uri;
+        client.backchannel_logout_uri = metadata.backchannel_logout_uri;
+
+        self.client_repository
+            .update(client)
+            .await
+            .map_err(|_| OAuthError::ServerError)
+    }
+
+    /// Removes the client, its sessions and refresh tokens are revoked with it
+    pub async fn delete_client(&self, id: &str) -> Result<(), ClientServiceError> {
+        self.client_repository.delete(id).await?;
+        Ok(())
+    }
+
+    /// Claims of a client credentials token, the client acts on its own behalf
+    pub fn client_token_claims(
+        &self,
+        client: &OAuthClient,
+        scope: Option<&str>,
+    ) -> Result<JWTClaims, OAuthError> {
+        if client.is_public() || !client.allows_grant(GRANT_TYPE_CLIENT_CREDENTIALS) {
+            return Err(OAuthError::UnauthorizedClient);
+        }
+
+        let scope = grant_scope(scope, &client.scopes)?;
+        let expires_in = client
+            .token_ttl
+            .map(i64::from)
+            .unwrap_or(AppConfig::jwt_expires_in() * 60);
+
+        Ok(JWTClaims::new_client(client.id.clone(), scope, expires_in))
+    }
+}
+
+async fn hash_secret(secret: String) -> Result<String, ClientServiceError> {
+    task::spawn_blocking(move || hash_password(&secret))
+        .await
+        .map_err(|_| ClientServiceError::InternalHashError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::client_repository::DbClientRepository;
+    use crate::model::session::Session;
+    use crate::model::session_repository::{DbSessionRepository, SessionRepository};
+    use crate::model::user::User;
+    use crate::model::user_repository::{DbUserRepository, UserRepository};
+    use crate::oauth::registration::{
+        AUTH_METHOD_CLIENT_SECRET_BASIC, AUTH_METHOD_CLIENT_SECRET_POST, AUTH_METHOD_NONE,
+    };
+    use sqlx::PgPool;
+
+    #[sqlx::test]
+    async fn client_credentials_test(pool: PgPool) -> Result<(), Box<dyn std::error::Error>> {
+        let service = ClientService::new(Arc::new(DbClientRepository::new(pool.clone())));
+
+        let mut client = OAuthClient::new(
+            vec![GRANT_TYPE_CLIENT_CREDENTIALS.to_string()],
+            vec!["users:read".to_string(), "users:write".to_string()],
+            vec![],
+        );
+        client.token_ttl = Some(300);
+        let (client, secret) = service.create_client(client, true).await?;
+        let secret = secret.expect("Expect secret");
+        assert_ne!(client.client_secret_hash.as_deref(), Some(secret.as_str()));
+
+        let result = service
+            .authenticate_client(&client.id, Some("wrong"), AUTH_METHOD_CLIENT_SECRET_BASIC)
+            .await;
+        assert_eq!(result.err(), Some(OAuthError::InvalidClient));
+        let result = service.authenticate_client(&client.id, None, AUTH_METHOD_NONE).await;
+        assert_eq!(result.err(), Some(OAuthError::InvalidClient));
+
+        let client = service
+            .authenticate_client(&client.id, Some(&secret), AUTH_METHOD_CLIENT_SECRET_BASIC)
+            .await?;
+        let claims = service.client_token_claims(&client, Some("users:read"))?;
+        assert_eq!(claims.sub, client.id);
+        assert!(claims.has_scope("users:read"));
+        assert!(!claims.has_scope("users:write"));
+        assert!(claims.sid.is_empty());
+        assert_eq!(claims.exp - claims.iat, 300);
+
+        let result = service.client_token_claims(&client, Some("users:delete"));
+        assert_eq!(result.err(), Some(OAuthError::InvalidScope));
+
+        // Public clients cannot act on their own behalf
+        let (public_client, _) = service
+            .create_client(
+                OAuthClient::new(vec![GRANT_TYPE_CLIENT_CREDENTIALS.to_string()], vec![], vec![]),
+                false,
+            )
+            .await?;
+        let result = service.client_token_claims(&public_client, None);
+        assert_eq!(result.err(), Some(OAuthError::UnauthorizedClient));
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn client_registration_test(pool: PgPool) -> Result<(), Box<dyn std::error::Error>> {
+        let service = ClientService::new(Arc::new(DbClientRepository::new(pool.clone())));
+
+        let mut metadata = ClientMetadata {
+            redirect_uris: vec!["https://partner.example.com/callback".to_string()],
+            client_name: Some("Partner".to_string()),
+            ..Default::default()
+        };
+        let registration = service.register_client(&metadata).await?;
+        let client = registration.client;
+        let client_secret = registration.client_secret.expect("Expect secret");
+        assert_eq!(client.client_name.as_deref(), Some("Partner"));
+
+        // Registered for the basic scheme, the secret can't be posted
+        let result = service
+            .authenticate_client(&client.id, Some(&client_secret), AUTH_METHOD_CLIENT_SECRET_POST)
+            .await;
+        assert_eq!(result.err(), Some(OAuthError::InvalidClient));
+        service
+            .authenticate_client(&client.id, Some(&client_secret), AUTH_METHOD_CLIENT_SECRET_BASIC)
+            .await?;
+
+        let result = service.authenticate_registration(&client.id, "wrong").await;
+        assert_eq!(result.err(), Some(OAuthError::InvalidToken));
+        let client = service
+            .authenticate_registration(&client.id, &registration.registration_access_token)
+            .await?;
+
+        metadata.client_id = Some(client.id.clone());
+        metadata.redirect_uris = vec!["https://partner.example.com/v2/callback".to_string()];
+        let client = service.update_registration(client, &metadata).await?;
+        assert_eq!(client.redirect_uris, metadata.redirect_uris);
+
+        metadata.token_endpoint_auth_method = Some("none".to_string());
+        let result = service.update_registration(client.clone(), &metadata).await;
+        assert!(matches!(result, Err(OAuthError::InvalidClientMetadata(_))));
+
+        let user = DbUserRepository::new(pool.clone())
+            .add(User::new("jane@example.com".to_string(), "hash".to_string()))
+            .await?;
+        let session_repository = DbSessionRepository::new(pool.clone());
+        let session = session_repository
+            .add(Session::new(user.id, Some(client.id.clone()), None))
+            .await?;
+
+        service.delete_client(&client.id).await?;
+        let result = service
+            .authenticate_registration(&client.id, &registration.registration_access_token)
+            .await;
+        assert_eq!(result.err(), Some(OAuthError::InvalidToken));
+        assert!(session_repository.get(session.id).await.is_err());
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn save_configured_clients_test(pool: PgPool) -> Result<(), Box<dyn std::error::Error>> {
+        let service = ClientService::new(Arc::new(DbClientRepository::new(pool.clone())));
+
+        let mut configuration = OAuthClientConfiguration {
+            client_id: "dashboard".to_string(),
+            secret: Some("dashboard-secret".to_string()),
+            redirect_uris: vec!["https://dashboard.example.com/callback".to_string()],
+            post_logout_redirect_uris: vec![],
+            grant_types: vec![GRANT_TYPE_CLIENT_CREDENTIALS.to_string()],
+            scopes: vec!["users:read".to_string()],
+        };
+        service.save_configured_clients([&configuration]).await?;
+        let client = service
+            .authenticate_client(
+                "dashboard",
+                Some("dashboard-secret"),
+                AUTH_METHOD_CLIENT_SECRET_BASIC,
+            )
+            .await?;
+        assert_eq!(client.redirect_uris, configuration.redirect_uris);
+
+        // Restarting with a rotated secret replaces the stored one
+        configuration.secret = Some("rotated-secret".to_string());
+        service.save_configured_clients([&configuration]).await?;
+        let result = service
+            .authenticate_client(
+                "dashboard",
+                Some("dashboard-secret"),
+                AUTH_METHOD_CLIENT_SECRET_BASIC,
+            )
+            .await;
+        assert_eq!(result.err(), Some(OAuthError::InvalidClient));
+        service
+            .authenticate_client(
+                "dashboard",
+                Some("rotated-secret"),
+                AUTH_METHOD_CLIENT_SECRET_POST,
+            )
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,310 @@
+use chrono::{Duration, Utc};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+
+use crate::constants::{DEVICE_USER_CODE_ATTEMPTS_RESET_IN, DEVICE_USER_CODE_MAX_ATTEMPTS};
+use crate::model::client::OAuthClient;
+use crate::model::device_authorization::{normalize_user_code, DeviceAuthorization};
+use crate::model::device_authorization_repository::{
+    DeviceAuthorizationRepository, DeviceAuthorizationRepositoryError,
+};
+use crate::oauth::{grant_scope, OAuthError, GRANT_TYPE_DEVICE_CODE};
+
+/// Seconds a device has to add to its interval after polling too fast
+const SLOW_DOWN_INTERVAL: i32 = 5;
+
+#[derive(Error, Debug)]
+pub enum DeviceAuthorizationServiceError {
+    /// Unknown, expired or already decided user code
+    #[error("Device authorization not found")]
+    DeviceAuthorizationNotFound,
+
+    /// The user entered too many unknown user codes, they have to wait for the reset
+    #[error("Too many attempts")]
+    TooManyAttempts,
+
+    #[error("Internal data store error")]
+    InternalDbError(#[from] DeviceAuthorizationRepositoryError),
+}
+
+/// Device authorization grant of RFC 8628, for clients that cannot host a browser
+///
+/// User codes are short, so the unknown codes a user enters are counted to stop them from
+/// guessing the codes of other devices, see RFC 8628 section 5.1.
+#[derive(Clone)]
+pub struct DeviceAuthorizationService {
+    device_authorization_repository:
+        Arc<dyn DeviceAuthorizationRepository + Send + Sync + 'static>,
+    failed_attempts: Arc<Mutex<HashMap<uuid::Uuid, (u32, i64)>>>,
+}
+
+impl DeviceAuthorizationService {
+    pub fn new(
+        device_authorization_repository: Arc<
+            dyn DeviceAuthorizationRepository + Send + Sync + 'static,
+        >,
+    ) -> Self {
+        Self {
+            device_authorization_repository,
+            failed_attempts: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Issues the device and user codes of an authenticated client
+    pub async fn start_device_authorization(
+        &self,
+        client: &OAuthClient,
+        scope: Option<&str>,
+    ) -> Result<DeviceAuthorization, OAuthError> {
+        if !client.allows_grant(GRANT_TYPE_DEVICE_CODE) {
+            return Err(OAuthError::UnauthorizedClient);
+        }
+
+        let scope = grant_scope(scope, &client.scopes)?;
+
+        if let Err(error) = self.device_authorization_repository.delete_expired().await {
+            log::warn!("Failed to delete expired device authorizations: {}", error);
+        }
+
+        self.device_authorization_repository
+            .add(DeviceAuthorization::new(&client.id, &scope))
+            .await
+            .map_err(|_| OAuthError::ServerError)
+    }
+
+    /// Pending request of a user code, shown to the user before approving it
+    pub async fn get_pending(
+        &self,
+        user_code: &str,
+        user_id: &uuid::Uuid,
+    ) -> Result<DeviceAuthorization, DeviceAuthorizationServiceError> {
+        if self.attempts_exceeded(user_id) {
+            return Err(DeviceAuthorizationServiceError::TooManyAttempts);
+        }
+
+        let result = self.find_pending(user_code).await;
+        if result.is_err() {
+            self.add_failed_attempt(user_id);
+        }
+
+        result
+    }
+
+    async fn find_pending(
+        &self,
+        user_code: &str,
+    ) -> Result<DeviceAuthorization, DeviceAuthorizationServiceError> {
+        let Ok(device_authorization) = self
+            .device_authorization_repository
+            .get_by_user_code(&normalize_user_code(user_code))
+            .await
+        else {
+            return Err(DeviceAuthorizationServiceError::DeviceAuthorizationNotFound);
+        };
+
+        if device_authorization.is_expired() || device_authorization.approved.is_some() {
+            return Err(DeviceAuthorizationServiceError::DeviceAuthorizationNotFound);
+        }
+
+        Ok(device_authorization)
+    }
+
+    fn attempts_exceeded(&self, user_id: &uuid::Uuid) -> bool {
+        let Ok(mut failed_attempts) = self.failed_attempts.lock() else {
+            return true;
+        };
+
+        let now = Utc::now().timestamp();
+        failed_attempts.retain(|_, (_, reset_at)| *reset_at >= now);
+        failed_attempts
+            .get(user_id)
+            .is_some_and(|(attempts, _)| *attempts >= DEVICE_USER_CODE_MAX_ATTEMPTS)
+    }
+
+    fn add_failed_attempt(&self, user_id: &uuid::Uuid) {
+        if let Ok(mut failed_attempts) = self.failed_attempts.lock() {
+            let reset_at = Utc::now().timestamp() + DEVICE_USER_CODE_ATTEMPTS_RESET_IN * 60;
+            failed_attempts.entry(*user_id).or_insert((0, reset_at)).0 += 1;
+        }
+    }
+
+    /// Approves or denies the device on behalf of the signed in user
+    pub async fn decide(
+        &self,
+        user_code: &str,
+        user_id: &uuid::Uuid,
+        approved: bool,
+    ) -> Result<DeviceAuthorization, DeviceAuthorizationServiceError> {
+        let device_authorization = self.get_pending(user_code, user_id).await?;
+
+        self.device_authorization_repository
+            .update_approval(&device_authorization.id, user_id, approved)
+            .await
+            .map_err(|_| DeviceAuthorizationServiceError::DeviceAuthorizationNotFound)
+    }
+
+    /// Answers a poll of the device, the approved request is returned only once
+    pub async fn poll(
+        &self,
+        client: &OAuthClient,
+        device_code: &str,
+    ) -> Result<DeviceAuthorization, OAuthError> {
+        let Ok(device_authorization) = self
+            .device_authorization_repository
+            .get_by_device_code(device_code)
+            .await
+        else {
+            return Err(OAuthError::InvalidGrant("unknown device code".to_string()));
+        };
+
+        if device_authorization.client_id != client.id {
+            return Err(OAuthError::InvalidGrant(
+                "device code was issued to another client".to_string(),
+            ));
+        }
+
+        if device_authorization.is_expired() {
+            return Err(OAuthError::ExpiredToken);
+        }
+
+        let now = Utc::now();
+        let too_fast = device_authorization.last_polled_at.is_some_and(|v| {
+            now < v + Duration::seconds(i64::from(device_authorization.interval))
+        });
+        let interval = match too_fast {
+            true => device_authorization.interval + SLOW_DOWN_INTERVAL,
+            false => device_authorization.interval,
+        };
+
+        self.device_authorization_repository
+            .update_poll(&device_authorization.id, now, interval)
+            .await
+            .map_err(|_| OAuthError::ServerError)?;
+
+        if too_fast {
+            return Err(OAuthError::SlowDown);
+        }
+
+        match device_authorization.approved {
+            None => Err(OAuthError::AuthorizationPending),
+            Some(approved) => {
+                let Ok(device_authorization) = self
+                    .device_authorization_repository
+                    .delete(&device_authorization.id)
+                    .await
+                else {
+                    return Err(OAuthError::InvalidGrant("unknown device code".to_string()));
+                };
+
+                match approved {
+                    true => Ok(device_authorization),
+                    false => Err(OAuthError::AccessDenied),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::client_repository::DbClientRepository;
+    use crate::model::device_authorization_repository::DbDeviceAuthorizationRepository;
+    use crate::model::user::User;
+    use crate::model::user_repository::{DbUserRepository, UserRepository};
+    use crate::service::client_service::ClientService;
+    use sqlx::PgPool;
+
+    #[sqlx::test]
+    async fn device_authorization_test(pool: PgPool) -> Result<(), Box<dyn std::error::Error>> {
+        let repository = Arc::new(DbDeviceAuthorizationRepository::new(pool.clone()));
+        let service = DeviceAuthorizationService::new(repository.clone());
+        let client_service = ClientService::new(Arc::new(DbClientRepository::new(pool.clone())));
+        let (client, _) = client_service
+            .create_client(
+                OAuthClient::new(
+                    vec![GRANT_TYPE_DEVICE_CODE.to_string()],
+                    vec!["users:read".to_string()],
+                    vec![],
+                ),
+                false,
+            )
+            .await?;
+        let user = DbUserRepository::new(pool.clone())
+            .add(User::new("jane@example.com".to_string(), "hash".to_string()))
+            .await?;
+
+        let result = service.start_device_authorization(&client, Some("users:write")).await;
+        assert_eq!(result.err(), Some(OAuthError::InvalidScope));
+
+        let device_authorization = service.start_device_authorization(&client, None).await?;
+        assert_eq!(device_authorization.scope, "users:read");
+        let device_code = device_authorization.device_code.clone();
+
+        let result = service.poll(&client, &device_code).await;
+        assert_eq!(result.err(), Some(OAuthError::AuthorizationPending));
+        let result = service.poll(&client, &device_code).await;
+        assert_eq!(result.err(), Some(OAuthError::SlowDown));
+
+        // Users may type the code as displayed and in lower case
+        let user_code = device_authorization.display_user_code().to_lowercase();
+        let pending = service.get_pending(&user_code, &user.id).await?;
+        assert_eq!(pending.interval, 10);
+        service.decide(&user_code, &user.id, true).await?;
+        assert!(service.decide(&user_code, &user.id, false).await.is_err());
+
+        // The device waited for the increased interval
+        repository
+            .update_poll(&pending.id, Utc::now() - Duration::seconds(11), pending.interval)
+            .await?;
+        let approved = service.poll(&client, &device_code).await?;
+        assert_eq!(approved.user_id, Some(user.id));
+
+        let result = service.poll(&client, &device_code).await;
+        assert!(matches!(result, Err(OAuthError::InvalidGrant(_))));
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn device_user_code_attempts_test(
+        pool: PgPool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let repository = Arc::new(DbDeviceAuthorizationRepository::new(pool.clone()));
+        let service = DeviceAuthorizationService::new(repository.clone());
+        let client_service = ClientService::new(Arc::new(DbClientRepository::new(pool.clone())));
+        let (client, _) = client_service
+            .create_client(
+                OAuthClient::new(vec![GRANT_TYPE_DEVICE_CODE.to_string()], vec![], vec![]),
+                false,
+            )
+            .await?;
+        let user = DbUserRepository::new(pool.clone())
+            .add(User::new("jane@example.com".to_string(), "hash".to_string()))
+            .await?;
+
+        let mut expired = DeviceAuthorization::new(&client.id, "");
+        expired.expires_at = Utc::now() - Duration::minutes(1);
+        let expired = repository.add(expired).await?;
+        let device_authorization = service.start_device_authorization(&client, None).await?;
+        assert!(repository.get_by_device_code(&expired.device_code).await.is_err());
+
+        for _ in 0..DEVICE_USER_CODE_MAX_ATTEMPTS {
+            let result = service.get_pending("BCDF-GHJK", &user.id).await;
+            assert!(matches!(
+                result,
+                Err(DeviceAuthorizationServiceError::DeviceAuthorizationNotFound)
+            ));
+        }
+
+        // Even the right code is refused until the attempts are reset
+        let user_code = device_authorization.display_user_code();
+        let result = service.get_pending(&user_code, &user.id).await;
+        assert!(matches!(result, Err(DeviceAuthorizationServiceError::TooManyAttempts)));
+        let result = service.decide(&user_code, &user.id, true).await;
+        assert!(matches!(result, Err(DeviceAuthorizationServiceError::TooManyAttempts)));
+
+        Ok(())
+    }
+}
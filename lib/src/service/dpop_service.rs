@@ -0,0 +1,139 @@
+use chrono::Utc;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::constants::DPOP_PROOF_EXPIRED_IN;
+use crate::oauth::dpop::{decode_proof, DPoPProof};
+use crate::oauth::OAuthError;
+use openidconnect::url::Url;
+
+/// Verifies DPoP proofs of RFC 9449, each proof is accepted once
+///
+/// The `jti` of accepted proofs are remembered while their `iat` is within the acceptable window,
+/// older proofs are rejected anyway. The cache is kept in memory and is per process, a proof may
+/// be replayed once against each instance when the api runs on several of them.
+#[derive(Clone, Default)]
+pub struct DPoPService {
+    seen_proofs: Arc<Mutex<HashMap<String, i64>>>,
+    public_url: Option<String>,
+}
+
+impl DPoPService {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Public url of the api, e.g. `https://auth.example.com/api/v1`
+    pub fn set_public_url(mut self, public_url: String) -> Self {
+        self.public_url = Some(public_url);
+        self
+    }
+
+    /// `htu` of a request to the path, the `Host` header of the request is never trusted
+    pub fn target_uri(&self, path: &str) -> Option<String> {
+        let public_url = Url::parse(self.public_url.as_deref()?).ok()?;
+        public_url.join(path).ok().map(|v| v.to_string())
+    }
+
+    /// Proof of a request to `htu`, the access token is sent along to protected resources
+    pub fn verify_proof(
+        &self,
+        proof: &str,
+        htm: &str,
+        htu: &str,
+        access_token: Option<&str>,
+    ) -> Result<DPoPProof, OAuthError> {
+        let proof = decode_proof(proof, htm, htu, access_token)?;
+
+        let Ok(mut seen_proofs) = self.seen_proofs.lock() else {
+            return Err(OAuthError::ServerError);
+        };
+
+        let now = Utc::now().timestamp();
+        seen_proofs.retain(|_, expires_at| *expires_at >= now);
+
+        let key = format!("{}:{}", proof.jkt, proof.jti);
+        if seen_proofs
+            .insert(key, proof.iat + DPOP_PROOF_EXPIRED_IN)
+            .is_some()
+        {
+            return Err(OAuthError::InvalidDPoPProof("proof was already used".to_string()));
+        }
+
+        Ok(proof)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oauth::dpop::{access_token_hash, DPoPClaims};
+    use crate::oauth::keys::tests::RSA_PRIVATE_KEY;
+    use crate::oauth::keys::SigningKey;
+    use jsonwebtoken::{Algorithm, EncodingKey, Header};
+
+    const TOKEN_ENDPOINT: &str = "https://auth.example.com/api/v1/oauth/token";
+
+    #[test]
+    fn test_verify_proof() -> Result<(), Box<dyn std::error::Error>> {
+        let service = DPoPService::new();
+        let signing_key = SigningKey::from_pem(RSA_PRIVATE_KEY)?;
+        let encoding_key = EncodingKey::from_rsa_pem(RSA_PRIVATE_KEY.as_bytes())?;
+        let mut header = Header::new(Algorithm::RS256);
+        header.typ = Some("dpop+jwt".to_string());
+        header.jwk = Some(serde_json::from_value(signing_key.jwk())?);
+
+        let proof = |jti: &str, htm: &str, ath: Option<String>| {
+            let claims = DPoPClaims {
+                jti: jti.to_string(),
+                htm: htm.to_string(),
+                htu: TOKEN_ENDPOINT.to_string(),
+                iat: Utc::now().timestamp(),
+                ath,
+            };
+            jsonwebtoken::encode(&header, &claims, &encoding_key)
+        };
+
+        let first = proof("e1j3V_bKic8-LAEB", "POST", None)?;
+        let verified = service.verify_proof(&first, "POST", TOKEN_ENDPOINT, None)?;
+        assert_eq!(verified.jkt, signing_key.kid);
+
+        let result = service.verify_proof(&first, "POST", TOKEN_ENDPOINT, None);
+        assert!(matches!(result, Err(OAuthError::InvalidDPoPProof(_))));
+
+        let result = service.verify_proof(
+            &proof("4Tq4ZsmIJmHQXG6y", "GET", None)?,
+            "POST",
+            TOKEN_ENDPOINT,
+            None,
+        );
+        assert!(matches!(result, Err(OAuthError::InvalidDPoPProof(_))));
+
+        // Protected resources check the proof was made for the access token
+        let ath = Some(access_token_hash("access-token"));
+        let result = service.verify_proof(
+            &proof("sKb0Rh3iyxYUw6Pq", "POST", ath.clone())?,
+            "POST",
+            TOKEN_ENDPOINT,
+            Some("another-access-token"),
+        );
+        assert!(matches!(result, Err(OAuthError::InvalidDPoPProof(_))));
+        service.verify_proof(
+            &proof("Hlk3DUSmYL5Gxdka", "POST", ath)?,
+            "POST",
+            &format!("{}?grant_type=refresh_token", TOKEN_ENDPOINT),
+            Some("access-token"),
+        )?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_target_uri() {
+        assert_eq!(DPoPService::new().target_uri("/api/v1/oauth/token"), None);
+
+        let service =
+            DPoPService::new().set_public_url("https://auth.example.com/api/v1".to_string());
+        assert_eq!(service.target_uri("/api/v1/oauth/token").as_deref(), Some(TOKEN_ENDPOINT));
+    }
+}
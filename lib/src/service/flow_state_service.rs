@@ -0,0 +1,441 @@
+use crate::config::{AppConfig, SamlConnection};
+use crate::model::flow_state::FlowState;
+use crate::model::flow_state_repository::{FlowStateRepository, FlowStateRepositoryError};
+use crate::model::user::User;
+use crate::oidc::oidc::{AuthorizationCodeClient, OidcError, OidcProvider};
+use crate::oidc::provider::UserProvidedData;
+use crate::saml::request::AuthnRequest;
+use crate::saml::response::{parse_response, ResponseValidation};
+use crate::saml::SamlError;
+use crate::utils::crypto::random_secret_token;
+use chrono::Utc;
+use openidconnect::url::Url;
+use std::sync::Arc;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum FlowStateServiceError {
+    #[error("Redirect uri is not allowed")]
+    RedirectUriNotAllowed,
+
+    #[error("Flow state not found")]
+    FlowStateNotFound,
+
+    #[error("Flow state expired")]
+    FlowStateExpired,
+
+    #[error("SAML service provider is not configured")]
+    SsoNotConfigured,
+
+    #[error("Email domain is not owned by the SSO connection")]
+    EmailDomainNotAllowed,
+
+    #[error("Internal OIDC error")]
+    InternalOidcError(#[from] OidcError),
+
+    #[error("SAML error")]
+    InternalSamlError(#[from] SamlError),
+
+    #[error("Internal data store error")]
+    InternalDbError(FlowStateRepositoryError),
+}
+
+impl From<FlowStateRepositoryError> for FlowStateServiceError {
+    fn from(value: FlowStateRepositoryError) -> Self {
+        match value {
+            FlowStateRepositoryError::InternalDbError(sqlx::Error::RowNotFound) => {
+                FlowStateServiceError::FlowStateNotFound
+            }
+            _ => FlowStateServiceError::InternalDbError(value),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct FlowStateService {
+    flow_state_repository: Arc<dyn FlowStateRepository + Send + Sync + 'static>,
+}
+
+impl FlowStateService {
+    pub fn new(flow_state_repository: Arc<dyn FlowStateRepository + Send + Sync + 'static>) -> Self {
+        FlowStateService {
+            flow_state_repository,
+        }
+    }
+
+    /// Starts an authorization code flow and returns the provider url to redirect to
+    pub async fn start_flow(
+        &self,
+        provider: &OidcProvider,
+        client: &(dyn AuthorizationCodeClient + Send + Sync),
+        redirect_uri: &str,
+    ) -> Result<Url, FlowStateServiceError> {
+        if !AppConfig::redirect_allowlist().iter().any(|v| v == redirect_uri) {
+            return Err(FlowStateServiceError::RedirectUriNotAllowed);
+        }
+
+        let authorization = client.authorize_url();
+        let flow_state = FlowState::new(provider, &authorization, redirect_uri);
+        self.flow_state_repository.add(flow_state).await?;
+
+        Ok(authorization.url)
+    }
+
+    /// Starts a SAML login and returns the IdP url carrying the AuthnRequest
+    pub async fn start_sso_flow(
+        &self,
+        connection: &SamlConnection,
+        redirect_uri: &str,
+    ) -> Result<Url, FlowStateServiceError> {
+        if !AppConfig::redirect_allowlist().iter().any(|v| v == redirect_uri) {
+            return Err(FlowStateServiceError::RedirectUriNotAllowed);
+        }
+
+        let Some(sp) = AppConfig::saml_service_provider() else {
+            return Err(FlowStateServiceError::SsoNotConfigured);
+        };
+
+        let request = AuthnRequest::new(&connection.idp, &sp.entity_id, &sp.acs_url);
+        let relay_state = random_secret_token(32);
+        let url = request.redirect_url(&relay_state)?;
+
+        let flow_state = FlowState::new_sso(
+            connection.identity_provider(),
+            &request.id,
+            &relay_state,
+            redirect_uri,
+        );
+        self.flow_state_repository.add(flow_state).await?;
+
+        Ok(url)
+    }
+
+    /// Returns the pending flow of the `state` the provider redirected back with
+    pub async fn get_flow(&self, state: &str) -> Result<FlowState, FlowStateServiceError> {
+        let flow_state = self.flow_state_repository.find_by_state(state).await?;
+
+        if flow_state.auth_code.is_some() {
+            return Err(FlowStateServiceError::FlowStateNotFound);
+        }
+
+        if flow_state.is_expired() {
+            return Err(FlowStateServiceError::FlowStateExpired);
+        }
+
+        Ok(flow_state)
+    }
+
+    /// Returns the pending SSO flow of the relay state, a replayed response finds nothing
+    pub async fn take_sso_flow(
+        &self,
+        relay_state: &str,
+    ) -> Result<FlowState, FlowStateServiceError> {
+        let flow_state = self.flow_state_repository.consume_by_state(relay_state).await?;
+
+        if flow_state.is_expired() {
+            return Err(FlowStateServiceError::FlowStateExpired);
+        }
+
+        Ok(flow_state)
+    }
+
+    pub async fn exchange_code(
+        &self,
+        flow_state: &FlowState,
+        client: &(dyn AuthorizationCodeClient + Send + Sync),
+        code: &str,
+    ) -> Result<UserProvidedData, FlowStateServiceError> {
+        let provider_data = client
+            .exchange_code(code, &flow_state.code_verifier, &flow_state.nonce)
+            .await?;
+
+        Ok(provider_data)
+    }
+
+    /// Validates the SAML response posted back for the flow
+    pub fn validate_sso_response(
+        &self,
+        flow_state: &FlowState,
+        connection: &SamlConnection,
+        saml_response: &str,
+    ) -> Result<UserProvidedData, FlowStateServiceError> {
+        // The relay state must belong to a flow started with the same connection
+        if flow_state.provider != connection.identity_provider() {
+            return Err(FlowStateServiceError::FlowStateNotFound);
+        }
+
+        let Some(sp) = AppConfig::saml_service_provider() else {
+            return Err(FlowStateServiceError::SsoNotConfigured);
+        };
+
+        let validation = ResponseValidation {
+            idp: &connection.idp,
+            sp_entity_id: &sp.entity_id,
+            acs_url: &sp.acs_url,
+            request_id: &flow_state.nonce,
+            now: Utc::now(),
+        };
+
+        sso_user_data(connection, saml_response, &validation)
+    }
+
+    /// Completes the flow with a one-time code the client redeems at the token endpoint
+    pub async fn issue_auth_code(
+        &self,
+        mut flow_state: FlowState,
+        user: &User,
+        client_id: Option<String>,
+    ) -> Result<String, FlowStateServiceError> {
+        let auth_code = random_secret_token(64);
+
+        flow_state.auth_code = Some(auth_code.clone());
+        flow_state.user_id = Some(user.id);
+        flow_state.client_id = client_id;
+        self.flow_state_repository.update(flow_state).await?;
+
+        Ok(auth_code)
+    }
+
+    pub async fn redeem_auth_code(
+        &self,
+        auth_code: &str,
+    ) -> Result<FlowState, FlowStateServiceError> {
+        let flow_state = self
+            .flow_state_repository
+            .delete_by_auth_code(auth_code)
+            .await?;
+
+        if flow_state.is_expired() {
+            return Err(FlowStateServiceError::FlowStateExpired);
+        }
+
+        Ok(flow_state)
+    }
+}
+
+/// Maps a valid response to the user, a connection with domains only signs in their users
+fn sso_user_data(
+    connection: &SamlConnection,
+    saml_response: &str,
+    validation: &ResponseValidation,
+) -> Result<UserProvidedData, FlowStateServiceError> {
+    let assertion = parse_response(saml_response, validation)?;
+    let mapping = &connection.attribute_mapping;
+    let data = assertion.into_user_provided_data(mapping, &connection.domains)?;
+
+    // Emails are verified only within the domains of the connection
+    if !connection.domains.is_empty() && !data.emails.iter().all(|v| v.verified) {
+        return Err(FlowStateServiceError::EmailDomainNotAllowed);
+    }
+
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::flow_state_repository::DbFlowStateRepository;
+    use crate::model::user_repository::{DbUserRepository, UserRepository};
+    use crate::oidc::oidc::OidcClient;
+    use crate::oidc::provider::google::tests::{
+        mock_token_endpoint, provider_metadata, sign_id_token,
+    };
+    use crate::oidc::provider::google::ISSUER_GOOGLE;
+    use crate::config::AccountLinkingPolicy;
+    use crate::saml::metadata::IdpMetadata;
+    use crate::saml::response::AttributeMapping;
+    use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+    use base64::Engine;
+    use ring::digest::{digest, SHA256};
+    use serde_json::json;
+    use sqlx::PgPool;
+    use std::collections::HashMap;
+    use tokio::net::TcpListener;
+
+    const CLIENT_ID: &str = "web.client.id";
+    const CODE: &str = "mock-code";
+
+    /// Token endpoint of a mock IdP, only the code with the verifier of `code_challenge` is accepted
+    async fn mock_pkce_token_endpoint(listener: TcpListener, code_challenge: String, nonce: String) {
+        mock_token_endpoint(listener, |form| {
+            let verifier = form.get("code_verifier").cloned().unwrap_or_default();
+            let challenge = URL_SAFE_NO_PAD.encode(digest(&SHA256, verifier.as_bytes()));
+            if form.get("code").map(|v| v.as_str()) != Some(CODE) || challenge != code_challenge {
+                return (400, json!({ "error": "invalid_grant" }));
+            }
+
+            let now = chrono::Utc::now().timestamp();
+            let id_token = sign_id_token(json!({
+                "iss": ISSUER_GOOGLE,
+                "sub": "google-sub",
+                "aud": CLIENT_ID,
+                "iat": now,
+                "exp": now + 600,
+                "nonce": nonce,
+                "email": "test@gmail.com",
+                "email_verified": true,
+            }));
+            let body = json!({
+                "access_token": "google-access-token",
+                "token_type": "Bearer",
+                "expires_in": 3600,
+                "refresh_token": "google-refresh-token",
+                "id_token": id_token,
+            });
+            (200, body)
+        })
+        .await
+    }
+
+    /// Starts a flow against the mock IdP, `code_verifier` overrides the verifier of the flow
+    async fn start_mock_flow(
+        service: &FlowStateService,
+        code_verifier: Option<&str>,
+    ) -> (FlowState, OidcClient) {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Expect listener");
+        let token_url = format!(
+            "http://{}/token",
+            listener.local_addr().expect("Expect address")
+        );
+
+        let client = OidcClient::new(
+            OidcProvider::Google,
+            provider_metadata(ISSUER_GOOGLE, &token_url),
+            CLIENT_ID.to_string(),
+            Some("secret".to_string()),
+            vec![CLIENT_ID.to_string()],
+        )
+        .set_redirect_uri("https://app.example.com/callback")
+        .expect("Expect redirect uri");
+
+        let authorization = client.authorize_url();
+        let query: HashMap<String, String> = authorization.url.query_pairs().into_owned().collect();
+        assert_eq!(query.get("code_challenge_method").map(|v| v.as_str()), Some("S256"));
+        assert_eq!(query.get("state"), Some(&authorization.state));
+        tokio::spawn(mock_pkce_token_endpoint(
+            listener,
+            query.get("code_challenge").cloned().expect("Expect code challenge"),
+            authorization.nonce.clone(),
+        ));
+
+        let mut flow_state = FlowState::new(
+            &OidcProvider::Google,
+            &authorization,
+            "https://app.example.com/done",
+        );
+        if let Some(code_verifier) = code_verifier {
+            flow_state.code_verifier = code_verifier.to_string();
+        }
+        service
+            .flow_state_repository
+            .add(flow_state)
+            .await
+            .expect("Expect flow state");
+
+        let flow_state = service
+            .get_flow(&authorization.state)
+            .await
+            .expect("Expect pending flow");
+        (flow_state, client)
+    }
+
+    #[sqlx::test]
+    async fn auth_code_flow_test(pool: PgPool) -> Result<(), Box<dyn std::error::Error>> {
+        let service = FlowStateService::new(Arc::new(DbFlowStateRepository::new(pool.clone())));
+        let (flow_state, client) = start_mock_flow(&service, None).await;
+
+        let data = service.exchange_code(&flow_state, &client, CODE).await?;
+        assert_eq!(data.emails[0].email, "test@gmail.com");
+        let provider_tokens = data.provider_tokens.expect("Expect provider tokens");
+        assert_eq!(provider_tokens.access_token, "google-access-token");
+
+        let user = DbUserRepository::new(pool.clone())
+            .add(User::new("test@gmail.com".to_string(), "hash".to_string()))
+            .await?;
+        let state = flow_state.state.clone();
+        let auth_code = service
+            .issue_auth_code(flow_state, &user, Some(CLIENT_ID.to_string()))
+            .await?;
+
+        // The state can't be replayed once the code is issued, the code is redeemed once
+        assert!(matches!(
+            service.get_flow(&state).await,
+            Err(FlowStateServiceError::FlowStateNotFound)
+        ));
+        let redeemed = service.redeem_auth_code(&auth_code).await?;
+        assert_eq!(redeemed.user_id, Some(user.id));
+        assert!(matches!(
+            service.redeem_auth_code(&auth_code).await,
+            Err(FlowStateServiceError::FlowStateNotFound)
+        ));
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn auth_code_flow_wrong_verifier_test(pool: PgPool) -> Result<(), Box<dyn std::error::Error>> {
+        let service = FlowStateService::new(Arc::new(DbFlowStateRepository::new(pool.clone())));
+        let (flow_state, client) = start_mock_flow(&service, Some("other-verifier")).await;
+
+        let result = service.exchange_code(&flow_state, &client, CODE).await;
+        assert!(matches!(
+            result,
+            Err(FlowStateServiceError::InternalOidcError(OidcError::CodeExchangeError(_)))
+        ));
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn take_sso_flow_once_test(pool: PgPool) -> Result<(), Box<dyn std::error::Error>> {
+        let repository = Arc::new(DbFlowStateRepository::new(pool.clone()));
+        let service = FlowStateService::new(repository.clone());
+        let flow_state =
+            FlowState::new_sso("sso:acme".to_string(), "_request-id", "relay-state", "/done");
+        repository.add(flow_state).await?;
+
+        let flow_state = service.take_sso_flow("relay-state").await?;
+        assert_eq!(flow_state.nonce, "_request-id");
+
+        // A replayed response can't complete the flow again
+        let result = service.take_sso_flow("relay-state").await;
+        assert!(matches!(result, Err(FlowStateServiceError::FlowStateNotFound)));
+
+        let user = DbUserRepository::new(pool.clone())
+            .add(User::new("test@acme.com".to_string(), "hash".to_string()))
+            .await?;
+        let auth_code = service.issue_auth_code(flow_state, &user, None).await?;
+        assert_eq!(service.redeem_auth_code(&auth_code).await?.user_id, Some(user.id));
+        Ok(())
+    }
+
+    #[test]
+    fn sso_user_data_test() -> Result<(), Box<dyn std::error::Error>> {
+        let mut connection = SamlConnection {
+            name: "acme".to_string(),
+            idp: IdpMetadata::parse(include_str!("../saml/fixtures/idp_metadata.xml"))?,
+            attribute_mapping: AttributeMapping::default(),
+            linking_policy: AccountLinkingPolicy::RequireSignedIn,
+            sync_profile: false,
+            domains: vec!["acme.com".to_string()],
+            require_sso: false,
+        };
+        let validation = ResponseValidation {
+            idp: &connection.idp.clone(),
+            sp_entity_id: "https://auth.example.com/saml",
+            acs_url: "https://auth.example.com/auth/sso/acs",
+            request_id: "_request-id",
+            now: "2026-10-19T10:01:00Z".parse()?,
+        };
+        let response = STANDARD.encode(include_str!("../saml/fixtures/response.xml"));
+
+        let data = sso_user_data(&connection, &response, &validation)?;
+        assert_eq!(data.emails[0].email, "jane.doe@acme.com");
+
+        // The IdP of other.com can't sign in acme.com users
+        connection.domains = vec!["other.com".to_string()];
+        let result = sso_user_data(&connection, &response, &validation);
+        assert!(matches!(result, Err(FlowStateServiceError::EmailDomainNotAllowed)));
+        Ok(())
+    }
+}
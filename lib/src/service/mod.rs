@@ -1,4 +1,11 @@
 pub mod auth_service;
+pub mod authorization_service;
+pub mod client_service;
+pub mod device_authorization_service;
+pub mod dpop_service;
+pub mod flow_state_service;
+pub mod provider_token_service;
 pub mod session_service;
+pub mod token_exchange_service;
 pub mod token_service;
 pub mod user_serivce;
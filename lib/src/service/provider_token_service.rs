@@ -0,0 +1,181 @@
+use crate::model::identity::IdentityError;
+use crate::model::identity_repository::{IdentityRepository, IdentityRepositoryError};
+use crate::oidc::oauth2::refresh_provider_tokens;
+use crate::oidc::oidc::{OidcError, OidcProvider};
+use crate::oidc::revocation::HttpClient;
+use std::sync::Arc;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ProviderTokenServiceError {
+    #[error("Identity not found")]
+    IdentityNotFound,
+
+    #[error("No provider token stored for the identity")]
+    ProviderTokenNotFound,
+
+    #[error("Provider token expired and can't be refreshed")]
+    ProviderTokenExpired,
+
+    #[error("Internal OIDC error")]
+    InternalOidcError(#[from] OidcError),
+
+    #[error("Internal identity error")]
+    InternalIdentityError(#[from] IdentityError),
+
+    #[error("Internal data store error")]
+    InternalDbError(IdentityRepositoryError),
+}
+
+impl From<IdentityRepositoryError> for ProviderTokenServiceError {
+    fn from(value: IdentityRepositoryError) -> Self {
+        match value {
+            IdentityRepositoryError::InternalDbError(sqlx::Error::RowNotFound) => {
+                ProviderTokenServiceError::IdentityNotFound
+            }
+            _ => ProviderTokenServiceError::InternalDbError(value),
+        }
+    }
+}
+
+/// Gives access to the provider APIs on behalf of the user
+#[derive(Clone)]
+pub struct ProviderTokenService {
+    identity_repository: Arc<dyn IdentityRepository + Send + Sync>,
+    http_client: Arc<dyn HttpClient + Send + Sync>,
+}
+
+impl ProviderTokenService {
+    pub fn new(
+        identity_repository: Arc<dyn IdentityRepository + Send + Sync>,
+        http_client: Arc<dyn HttpClient + Send + Sync>,
+    ) -> Self {
+        Self {
+            identity_repository,
+            http_client,
+        }
+    }
+
+    /// Returns a valid provider access token, refreshing it when expired
+    pub async fn access_token(
+        &self,
+        user_id: &uuid::Uuid,
+        provider: OidcProvider,
+    ) -> Result<String, ProviderTokenServiceError> {
+        let mut identity = self.identity_repository.get(user_id, provider.name()).await?;
+
+        let Some(tokens) = identity.provider_tokens() else {
+            return Err(ProviderTokenServiceError::ProviderTokenNotFound);
+        };
+
+        if !tokens.is_expired() {
+            return Ok(tokens.access_token);
+        }
+
+        let Some(refresh_token) = &tokens.refresh_token else {
+            return Err(ProviderTokenServiceError::ProviderTokenExpired);
+        };
+
+        let tokens =
+            refresh_provider_tokens(self.http_client.as_ref(), &provider, refresh_token).await?;
+        identity.set_provider_tokens(&tokens)?;
+        self.identity_repository.update(identity).await?;
+
+        Ok(tokens.access_token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::identity::Identity;
+    use crate::model::identity_repository::DbIdentityRepository;
+    use crate::model::user::User;
+    use crate::model::user_repository::{DbUserRepository, UserRepository};
+    use crate::oidc::provider::{Claims, Email, ProviderTokens, UserProvidedData};
+    use crate::oidc::revocation::ReqwestHttpClient;
+    use crate::utils::crypto::decrypt_secret;
+    use chrono::Duration;
+    use sqlx::PgPool;
+
+    #[sqlx::test]
+    async fn access_token_test(pool: PgPool) -> Result<(), Box<dyn std::error::Error>> {
+        std::env::set_var("PROVIDER_TOKEN_SECRET", "test-provider-token-secret");
+        let user_repository = DbUserRepository::new(pool.clone());
+        let identity_repository = Arc::new(DbIdentityRepository::new(pool.clone()));
+        let service =
+            ProviderTokenService::new(identity_repository.clone(), Arc::new(ReqwestHttpClient::new()));
+
+        let user = user_repository
+            .add(User::new_from_provider("test@gmail.com", Default::default()))
+            .await?;
+        let data = UserProvidedData {
+            emails: vec![Email {
+                email: "test@gmail.com".to_string(),
+                verified: true,
+                primary: true,
+            }],
+            metadata: Some(Claims {
+                subject: Some("google-sub".to_string()),
+                email: Some("test@gmail.com".to_string()),
+                ..Default::default()
+            }),
+            provider_tokens: Some(ProviderTokens::new(
+                "google-access-token".to_string(),
+                None,
+                Some(Duration::hours(1)),
+            )),
+        };
+        let mut identity = Identity::new_from_provider(&user, "google", &data)?;
+        assert_ne!(identity.provider_access_token.as_deref(), Some("google-access-token"));
+        identity = identity_repository.add(&identity).await?;
+
+        let access_token = service.access_token(&user.id, OidcProvider::Google).await?;
+        assert_eq!(access_token, "google-access-token");
+
+        // Expired without a refresh token
+        identity.set_provider_tokens(&ProviderTokens::new(
+            "google-access-token".to_string(),
+            None,
+            Some(Duration::hours(-1)),
+        ))?;
+        identity_repository.update(identity).await?;
+        let result = service.access_token(&user.id, OidcProvider::Google).await;
+        assert!(matches!(result, Err(ProviderTokenServiceError::ProviderTokenExpired)));
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn encrypt_pending_refresh_tokens_test(
+        pool: PgPool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let user_repository = DbUserRepository::new(pool.clone());
+        let identity_repository = DbIdentityRepository::new(pool.clone());
+        let user = user_repository
+            .add(User::new_from_provider("test@gmail.com", Default::default()))
+            .await?;
+        let data = UserProvidedData {
+            emails: vec![],
+            metadata: Some(Claims {
+                subject: Some("google-sub".to_string()),
+                ..Default::default()
+            }),
+            provider_tokens: None,
+        };
+        identity_repository
+            .add(&Identity::new_from_provider(&user, "google", &data)?)
+            .await?;
+        sqlx::query!(
+            "INSERT INTO pending_provider_token_encryption VALUES ('google-sub', 'google', 'plaintext')"
+        )
+        .execute(&pool)
+        .await?;
+
+        assert_eq!(identity_repository.encrypt_pending_refresh_tokens("secret").await?, 1);
+        let identity = identity_repository.get(&user.id, "google").await?;
+        let refresh_token = identity.provider_refresh_token.expect("Expect refresh token");
+        assert_eq!(decrypt_secret(&refresh_token, "secret").as_deref(), Some("plaintext"));
+        assert_eq!(identity_repository.encrypt_pending_refresh_tokens("secret").await?, 0);
+        Ok(())
+    }
+}
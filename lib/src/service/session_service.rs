@@ -1,4 +1,6 @@
+use crate::model::session::Session;
 use crate::model::session_repository::{SessionRepository, SessionRepositoryError};
+use crate::oauth::logout::LogoutNotifier;
 use std::sync::Arc;
 use thiserror::Error;
 
@@ -14,15 +16,203 @@ pub enum SessionServiceError {
 #[derive(Clone)]
 pub struct SessionService {
     session_repository: Arc<dyn SessionRepository + Send + Sync + 'static>,
+    logout_notifier: Option<Arc<LogoutNotifier>>,
 }
 
 impl SessionService {
     pub fn new(session_repository: Arc<dyn SessionRepository + Send + Sync + 'static>) -> Self {
-        SessionService { session_repository }
+        SessionService {
+            session_repository,
+            logout_notifier: None,
+        }
+    }
+
+    /// Relying parties are told about ended sessions on their back-channel logout uri
+    pub fn set_logout_notifier(mut self, logout_notifier: Arc<LogoutNotifier>) -> Self {
+        self.logout_notifier = Some(logout_notifier);
+        self
     }
 
     pub async fn revoke_session(&self, session_id: &uuid::Uuid) -> Result<(), SessionServiceError> {
+        self.end_session(session_id).await?;
+        Ok(())
+    }
+
+    /// Ends the session with the relying party sessions started from it, returns the ended sessions
+    pub async fn end_session(
+        &self,
+        session_id: &uuid::Uuid,
+    ) -> Result<Vec<Session>, SessionServiceError> {
+        let mut sessions = match self.session_repository.get(*session_id).await {
+            Ok(session) => vec![session],
+            Err(SessionRepositoryError::InternalDbError(sqlx::Error::RowNotFound)) => vec![],
+            Err(e) => return Err(e.into()),
+        };
+        sessions.extend(
+            self.session_repository
+                .find_all_by_sso_session(session_id)
+                .await?,
+        );
+
+        // Relying party sessions are removed with the browser session
         self.session_repository.delete(session_id).await?;
+        self.notify_logout(&sessions);
+
+        Ok(sessions)
+    }
+
+    /// Signs the user out everywhere, refresh tokens are removed with their sessions
+    pub async fn revoke_user_sessions(
+        &self,
+        user_id: &uuid::Uuid,
+    ) -> Result<(), SessionServiceError> {
+        let sessions = self.session_repository.find_all_by_user(user_id).await?;
+        self.session_repository.delete_all_by_user(user_id).await?;
+        self.notify_logout(&sessions);
+        Ok(())
+    }
+
+    fn notify_logout(&self, sessions: &[Session]) {
+        let Some(logout_notifier) = &self.logout_notifier else {
+            return;
+        };
+
+        let sessions: Vec<Session> = sessions
+            .iter()
+            .filter(|v| v.client_id.is_some())
+            .cloned()
+            .collect();
+        if !sessions.is_empty() {
+            logout_notifier.notify(sessions);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::client::OAuthClient;
+    use crate::model::client_repository::{ClientRepository, DbClientRepository};
+    use crate::model::session_repository::DbSessionRepository;
+    use crate::model::user::User;
+    use crate::model::user_repository::{DbUserRepository, UserRepository};
+    use crate::oauth::keys::tests::RSA_PRIVATE_KEY;
+    use crate::oauth::logout::{LogoutTokenClaims, BACKCHANNEL_LOGOUT_EVENT};
+    use crate::oidc::revocation::ReqwestHttpClient;
+    use sqlx::PgPool;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    /// Relying party answering with the given statuses, the request bodies are sent to the test
+    async fn logout_receiver(statuses: Vec<u16>) -> (String, mpsc::UnboundedReceiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Expect listener");
+        let uri = format!(
+            "http://{}/logout",
+            listener.local_addr().expect("Expect address")
+        );
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            for status in statuses {
+                let Ok((mut stream, _)) = listener.accept().await else {
+                    return;
+                };
+
+                let mut request = vec![];
+                let mut buf = [0u8; 4096];
+                let body = loop {
+                    let Ok(n) = stream.read(&mut buf).await else {
+                        return;
+                    };
+                    request.extend_from_slice(&buf[..n]);
+
+                    let text = String::from_utf8_lossy(&request).to_string();
+                    let Some((head, body)) = text.split_once("\r\n\r\n") else {
+                        continue;
+                    };
+                    let length = head
+                        .lines()
+                        .find_map(|v| {
+                            v.to_lowercase()
+                                .strip_prefix("content-length:")
+                                .map(|v| v.trim().to_string())
+                        })
+                        .and_then(|v| v.parse::<usize>().ok())
+                        .unwrap_or_default();
+                    if body.len() >= length || n == 0 {
+                        break body.to_string();
+                    }
+                };
+
+                let response = format!(
+                    "HTTP/1.1 {} Status\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+                let _ = sender.send(body);
+            }
+        });
+
+        (uri, receiver)
+    }
+
+    #[sqlx::test]
+    async fn backchannel_logout_test(pool: PgPool) -> Result<(), Box<dyn std::error::Error>> {
+        std::env::set_var("OIDC_ISSUER", "https://auth.example.com/api/v1");
+        std::env::set_var("OIDC_SIGNING_KEY", RSA_PRIVATE_KEY);
+
+        let client_repository = Arc::new(DbClientRepository::new(pool.clone()));
+        let session_repository = Arc::new(DbSessionRepository::new(pool.clone()));
+        let service = SessionService::new(session_repository.clone()).set_logout_notifier(
+            Arc::new(LogoutNotifier::new(
+                client_repository.clone(),
+                Arc::new(ReqwestHttpClient::new()),
+            )),
+        );
+
+        // The first delivery fails temporarily and is retried
+        let (uri, mut receiver) = logout_receiver(vec![503, 200]).await;
+        let mut client = OAuthClient::new(vec![], vec![], vec![]);
+        client.backchannel_logout_uri = Some(uri);
+        let client = client_repository.add(client).await?;
+
+        let user = DbUserRepository::new(pool.clone())
+            .add(User::new("test@email.com".to_string(), "hash".to_string()))
+            .await?;
+        let browser_session = session_repository
+            .add(Session::new(user.id, None, None))
+            .await?;
+        let mut client_session = Session::new(user.id, Some(client.id.clone()), None);
+        client_session.sso_session_id = Some(browser_session.id);
+        let client_session = session_repository.add(client_session).await?;
+
+        let ended = service.end_session(&browser_session.id).await?;
+        assert_eq!(ended.len(), 2);
+        assert!(session_repository.get(client_session.id).await.is_err());
+
+        let timeout = std::time::Duration::from_secs(10);
+        let first = tokio::time::timeout(timeout, receiver.recv())
+            .await?
+            .expect("Expect request");
+        let second = tokio::time::timeout(timeout, receiver.recv())
+            .await?
+            .expect("Expect retry");
+        assert_eq!(first, second);
+
+        let logout_token = second
+            .strip_prefix("logout_token=")
+            .expect("Expect logout token");
+        let signing_key = crate::config::AppConfig::oidc_signing_key().expect("Expect signing key");
+        let mut validation = signing_key.validation();
+        validation.set_audience(&[&client.id]);
+        let claims: LogoutTokenClaims = signing_key.verify(logout_token, &validation)?;
+        assert_eq!(claims.sub, user.id.to_string());
+        assert_eq!(claims.sid, browser_session.id.to_string());
+        assert!(claims.events.get(BACKCHANNEL_LOGOUT_EVENT).is_some());
+
         Ok(())
     }
 }
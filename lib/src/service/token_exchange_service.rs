@@ -0,0 +1,373 @@
+use chrono::{Duration, Utc};
+use serde_json::json;
+use std::sync::Arc;
+
+use crate::config::{AppConfig, UserScopes};
+use crate::constants::{JWT_AUD_CLAIM, JWT_CLIENT_AUD_CLAIM, TOKEN_EXCHANGE_EXPIRED_IN};
+use crate::model::audit_log::{
+    AuditLogEntry, AUDIT_ACTION_IMPERSONATION_DENIED, AUDIT_ACTION_TOKEN_EXCHANGE,
+};
+use crate::model::audit_log_repository::AuditLogRepository;
+use crate::model::client::OAuthClient;
+use crate::model::jwt::{decode_jwt, Actor, JWTClaims};
+use crate::model::session_repository::SessionRepository;
+use crate::model::user_repository::UserRepository;
+use crate::oauth::{grant_scope, parse_scope, OAuthError, TokenExchangeRequest, TOKEN_TYPE_ACCESS_TOKEN};
+
+/// Token exchange of RFC 8693, super users impersonate users and services narrow their tokens
+#[derive(Clone)]
+pub struct TokenExchangeService {
+    user_repository: Arc<dyn UserRepository + Send + Sync + 'static>,
+    session_repository: Arc<dyn SessionRepository + Send + Sync + 'static>,
+    audit_log_repository: Arc<dyn AuditLogRepository + Send + Sync + 'static>,
+    user_scopes: UserScopes,
+    jwt_secret: Option<String>,
+}
+
+impl TokenExchangeService {
+    pub fn new(
+        user_repository: Arc<dyn UserRepository + Send + Sync + 'static>,
+        session_repository: Arc<dyn SessionRepository + Send + Sync + 'static>,
+        audit_log_repository: Arc<dyn AuditLogRepository + Send + Sync + 'static>,
+    ) -> Self {
+        Self {
+            user_repository,
+            session_repository,
+            audit_log_repository,
+            user_scopes: AppConfig::user_scope_configuration().clone(),
+            jwt_secret: None,
+        }
+    }
+
+    /// Secret of the access tokens, `JWT_SECRET` when not set
+    pub fn set_jwt_secret(mut self, jwt_secret: String) -> Self {
+        self.jwt_secret = Some(jwt_secret);
+        self
+    }
+
+    pub fn set_user_scopes(mut self, user_scopes: UserScopes) -> Self {
+        self.user_scopes = user_scopes;
+        self
+    }
+
+    /// Claims of the issued token, the exchange is recorded in the audit log before it is issued
+    ///
+    /// The token is bound to the DPoP key of the thumbprint, a bound subject token keeps its key.
+    pub async fn exchange(
+        &self,
+        client: &OAuthClient,
+        request: &TokenExchangeRequest,
+        dpop_jkt: Option<&str>,
+    ) -> Result<JWTClaims, OAuthError> {
+        if request.subject_token_type != TOKEN_TYPE_ACCESS_TOKEN
+            || request
+                .requested_token_type
+                .as_deref()
+                .is_some_and(|v| v != TOKEN_TYPE_ACCESS_TOKEN)
+        {
+            return Err(OAuthError::InvalidRequest("unsupported token type".to_string()));
+        }
+
+        let jwt_secret = self.jwt_secret.clone().unwrap_or_else(AppConfig::jwt_secret);
+        let Ok(subject) = decode_jwt(&request.subject_token, jwt_secret) else {
+            return Err(OAuthError::InvalidGrant("invalid subject token".to_string()));
+        };
+
+        // Only the holder of the key may exchange a bound token, see RFC 9449 section 7.1
+        if subject
+            .cnf
+            .as_ref()
+            .is_some_and(|v| Some(v.jkt.as_str()) != dpop_jkt)
+        {
+            return Err(OAuthError::InvalidDPoPProof(
+                "proof of the subject token key is required".to_string(),
+            ));
+        }
+
+        // Tokens of ended sessions can't be exchanged
+        if !subject.sid.is_empty() {
+            let Ok(session_id) = uuid::Uuid::parse_str(&subject.sid) else {
+                return Err(OAuthError::InvalidGrant("invalid subject token".to_string()));
+            };
+
+            if self.session_repository.get(session_id).await.is_err() {
+                return Err(OAuthError::InvalidGrant("session ended".to_string()));
+            }
+        }
+
+        let mut claims = match &request.requested_subject {
+            Some(requested_subject) => {
+                match self
+                    .impersonate(&subject, requested_subject, request.scope.as_deref())
+                    .await
+                {
+                    Ok(claims) => claims,
+                    Err(error) => {
+                        self.record_denied_impersonation(
+                            client,
+                            &subject,
+                            requested_subject,
+                            &error,
+                        )
+                        .await?;
+                        return Err(error);
+                    }
+                }
+            }
+            None => narrow(&subject, request.audience.as_deref(), request.scope.as_deref())?,
+        };
+
+        claims.bind(dpop_jkt);
+
+        let actor = claims.act.as_ref().map_or(&claims.sub, |v| &v.sub);
+        let mut entry = AuditLogEntry::new(
+            AUDIT_ACTION_TOKEN_EXCHANGE,
+            actor,
+            Some(claims.sub.clone()),
+            Some(client.id.clone()),
+        );
+        entry.details = json!({
+            "impersonation": request.requested_subject.is_some(),
+            "aud": claims.aud,
+            "scope": claims.scope,
+            "sid": claims.sid,
+            "exp": claims.exp,
+        });
+
+        self.audit_log_repository
+            .add(entry)
+            .await
+            .map_err(|_| OAuthError::ServerError)?;
+
+        Ok(claims)
+    }
+
+    /// Failed attempts are recorded as well, they may be someone probing for super user rights
+    async fn record_denied_impersonation(
+        &self,
+        client: &OAuthClient,
+        subject: &JWTClaims,
+        requested_subject: &str,
+        error: &OAuthError,
+    ) -> Result<(), OAuthError> {
+        let mut entry = AuditLogEntry::new(
+            AUDIT_ACTION_IMPERSONATION_DENIED,
+            &subject.sub,
+            Some(requested_subject.to_string()),
+            Some(client.id.clone()),
+        );
+        entry.details = json!({
+            "error": error.to_string(),
+            "scope": subject.scope,
+            "sid": subject.sid,
+        });
+
+        self.audit_log_repository
+            .add(entry)
+            .await
+            .map(|_| ())
+            .map_err(|_| OAuthError::ServerError)
+    }
+
+    /// Short lived token of the requested user, the super user is recorded as the actor
+    async fn impersonate(
+        &self,
+        subject: &JWTClaims,
+        requested_subject: &str,
+        scope: Option<&str>,
+    ) -> Result<JWTClaims, OAuthError> {
+        // An impersonation token can't be used to impersonate someone else
+        if subject.act.is_some() {
+            return Err(OAuthError::InvalidGrant("subject token is delegated".to_string()));
+        }
+
+        let Ok(actor_id) = uuid::Uuid::parse_str(&subject.sub) else {
+            return Err(OAuthError::InvalidGrant("subject token is not of a user".to_string()));
+        };
+
+        let Ok(actor) = self.user_repository.get(&actor_id).await else {
+            return Err(OAuthError::InvalidGrant("subject token is not of a user".to_string()));
+        };
+
+        if actor.is_super_user != Some(true) {
+            return Err(OAuthError::InvalidGrant("impersonation requires a super user".to_string()));
+        }
+
+        let Ok(user_id) = uuid::Uuid::parse_str(requested_subject) else {
+            return Err(OAuthError::InvalidRequest("unknown requested_subject".to_string()));
+        };
+
+        let Ok(user) = self.user_repository.get(&user_id).await else {
+            return Err(OAuthError::InvalidRequest("unknown requested_subject".to_string()));
+        };
+
+        // Super users can't gain the rights of each other
+        if user.is_super_user == Some(true) {
+            return Err(OAuthError::InvalidGrant("super users can't be impersonated".to_string()));
+        }
+
+        let scope = grant_scope(scope, &self.user_scopes.for_user(&user))?;
+        let expires_at = Utc::now() + Duration::minutes(TOKEN_EXCHANGE_EXPIRED_IN);
+
+        // The token ends with the session of the super user
+        let mut claims = JWTClaims::new(user.id.to_string(), subject.sid.clone(), scope);
+        claims.exp = claims.exp.min(expires_at.timestamp()).min(subject.exp);
+        claims.act = Some(Actor {
+            sub: actor.id.to_string(),
+            act: None,
+        });
+
+        Ok(claims)
+    }
+}
+
+/// Same subject with a narrower audience or scope, the token never outlives the subject token
+fn narrow(
+    subject: &JWTClaims,
+    audience: Option<&str>,
+    scope: Option<&str>,
+) -> Result<JWTClaims, OAuthError> {
+    let mut claims = subject.clone();
+    claims.iat = Utc::now().timestamp();
+
+    if let Some(audience) = audience {
+        let general = subject.aud == JWT_AUD_CLAIM || subject.aud == JWT_CLIENT_AUD_CLAIM;
+        if !general && subject.aud != audience {
+            return Err(OAuthError::InvalidTarget);
+        }
+        claims.aud = audience.to_string();
+    }
+
+    let allowed = parse_scope(&subject.scope)
+        .into_iter()
+        .map(|v| v.to_string())
+        .collect::<Vec<_>>();
+    claims.scope = grant_scope(scope, &allowed)?;
+
+    Ok(claims)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::audit_log_repository::DbAuditLogRepository;
+    use crate::model::jwt::encode_jwt;
+    use crate::model::session::Session;
+    use crate::model::session_repository::DbSessionRepository;
+    use crate::model::user::User;
+    use crate::model::user_repository::DbUserRepository;
+    use crate::oauth::GRANT_TYPE_TOKEN_EXCHANGE;
+    use sqlx::PgPool;
+
+    const JWT_SECRET: &str = "test-jwt-secret";
+
+    #[sqlx::test]
+    async fn token_exchange_test(pool: PgPool) -> Result<(), Box<dyn std::error::Error>> {
+
+        let user_repository = Arc::new(DbUserRepository::new(pool.clone()));
+        let session_repository = Arc::new(DbSessionRepository::new(pool.clone()));
+        let audit_log_repository = Arc::new(DbAuditLogRepository::new(pool.clone()));
+        let service = TokenExchangeService::new(
+            user_repository.clone(),
+            session_repository.clone(),
+            audit_log_repository.clone(),
+        )
+        .set_user_scopes(UserScopes {
+            user: vec!["users:read".to_string(), "users:write".to_string()],
+            super_user: vec![],
+        })
+        .set_jwt_secret(JWT_SECRET.to_string());
+        let client = OAuthClient::new(vec![GRANT_TYPE_TOKEN_EXCHANGE.to_string()], vec![], vec![]);
+
+        let staff = user_repository
+            .add(User::new("staff@example.com".to_string(), "hash".to_string()))
+            .await?;
+        sqlx::query!("UPDATE auth_user SET is_super_user = true WHERE id = $1", staff.id)
+            .execute(&pool)
+            .await?;
+        let customer = user_repository
+            .add(User::new("jane@example.com".to_string(), "hash".to_string()))
+            .await?;
+
+        let access_token = |user: &User, sid: uuid::Uuid| {
+            let claims = JWTClaims::new(
+                user.id.to_string(),
+                sid.to_string(),
+                "users:read users:write".to_string(),
+            );
+            encode_jwt(&claims, JWT_SECRET.to_string())
+        };
+        let staff_session = session_repository.add(Session::new(staff.id, None, None)).await?;
+        let customer_session = session_repository
+            .add(Session::new(customer.id, None, None))
+            .await?;
+
+        let mut request = TokenExchangeRequest {
+            subject_token: access_token(&staff, staff_session.id)?,
+            subject_token_type: TOKEN_TYPE_ACCESS_TOKEN.to_string(),
+            requested_subject: Some(customer.id.to_string()),
+            scope: Some("users:read".to_string()),
+            ..Default::default()
+        };
+        let claims = service.exchange(&client, &request, None).await?;
+        assert_eq!(claims.sub, customer.id.to_string());
+        assert_eq!(claims.act.as_ref().map(|v| v.sub.clone()), Some(staff.id.to_string()));
+        assert_eq!(claims.scope, "users:read");
+        assert!(claims.exp - claims.iat <= TOKEN_EXCHANGE_EXPIRED_IN * 60);
+
+        // Only super users may impersonate
+        request.subject_token = access_token(&customer, customer_session.id)?;
+        request.requested_subject = Some(staff.id.to_string());
+        let result = service.exchange(&client, &request, None).await;
+        assert!(matches!(result, Err(OAuthError::InvalidGrant(_))));
+
+        // Down scoping keeps the subject
+        request.requested_subject = None;
+        request.audience = Some("orders".to_string());
+        let claims = service.exchange(&client, &request, None).await?;
+        assert_eq!(claims.sub, customer.id.to_string());
+        assert_eq!(claims.aud, "orders");
+        assert!(claims.act.is_none());
+
+        request.subject_token = encode_jwt(&claims, JWT_SECRET.to_string())?;
+        request.audience = Some("billing".to_string());
+        let result = service.exchange(&client, &request, None).await;
+        assert_eq!(result.err(), Some(OAuthError::InvalidTarget));
+
+        request.audience = None;
+        request.scope = Some("users:write".to_string());
+        let result = service.exchange(&client, &request, None).await;
+        assert_eq!(result.err(), Some(OAuthError::InvalidScope));
+
+        // Bound tokens stay bound to the key of the subject token
+        let mut bound = JWTClaims::new(
+            customer.id.to_string(),
+            customer_session.id.to_string(),
+            "users:read".to_string(),
+        );
+        bound.bind(Some("customer-key"));
+        request.subject_token = encode_jwt(&bound, JWT_SECRET.to_string())?;
+        request.scope = None;
+        for dpop_jkt in [None, Some("another-key")] {
+            let result = service.exchange(&client, &request, dpop_jkt).await;
+            assert!(matches!(result, Err(OAuthError::InvalidDPoPProof(_))));
+        }
+        let claims = service.exchange(&client, &request, Some("customer-key")).await?;
+        assert_eq!(claims.cnf.map(|v| v.jkt).as_deref(), Some("customer-key"));
+
+        let entries = audit_log_repository
+            .list_by_subject(&customer.id.to_string())
+            .await?;
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].actor, staff.id.to_string());
+        assert_eq!(entries[0].details["impersonation"], true);
+        assert_eq!(entries[1].actor, customer.id.to_string());
+
+        let entries = audit_log_repository.list_by_subject(&staff.id.to_string()).await?;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].action, AUDIT_ACTION_IMPERSONATION_DENIED);
+        assert_eq!(entries[0].actor, customer.id.to_string());
+
+        Ok(())
+    }
+}
@@ -1,5 +1,5 @@
 use crate::config::AppConfig;
-use crate::model::jwt::decode_jwt;
+use crate::model::jwt::{decode_jwt, decode_user_jwt, JWTClaims};
 use std::sync::Arc;
 use thiserror::Error;
 use uuid::Uuid;
@@ -11,6 +11,7 @@ use crate::model::session_repository::{SessionRepository, SessionRepositoryError
 use crate::model::token_info::TokenInfo;
 use crate::model::user::User;
 use crate::model::user_repository::{UserRepository, UserRepositoryError};
+use crate::oauth::introspection::TokenIntrospection;
 use crate::utils::crypto::random_secret_token;
 
 #[derive(Error, Debug)]
@@ -18,6 +19,10 @@ pub enum TokenServiceError {
     #[error("Refresh Token not found")]
     RefreshTokenNotFound,
 
+    /// The session is bound to another DPoP key, or the proof is missing
+    #[error("Invalid DPoP key")]
+    InvalidDPoPKey,
+
     #[error("Internal Error")]
     InternalError,
 
@@ -39,6 +44,7 @@ pub struct TokenService {
     refresh_token_repository: Arc<dyn RefreshTokenRepository + Send + Sync + 'static>,
     user_repository: Arc<dyn UserRepository + Send + Sync + 'static>,
     session_repository: Arc<dyn SessionRepository + Send + Sync + 'static>,
+    jwt_secret: Option<String>,
 }
 
 impl TokenService {
@@ -51,35 +57,75 @@ impl TokenService {
             refresh_token_repository,
             user_repository,
             session_repository,
+            jwt_secret: None,
         }
     }
 
+    /// Secret of the access tokens, `JWT_SECRET` when not set
+    pub fn set_jwt_secret(mut self, jwt_secret: String) -> Self {
+        self.jwt_secret = Some(jwt_secret);
+        self
+    }
+
+    /// Starts a session, its refresh tokens are bound to the DPoP key of the thumbprint
     pub async fn issue_refresh_token(
         &self,
         user: &User,
+        client_id: Option<String>,
+        scope: Option<String>,
+        dpop_jkt: Option<String>,
+    ) -> Result<RefreshToken, TokenServiceError> {
+        let mut session = Session::new(user.id, client_id, scope);
+        session.dpop_jkt = dpop_jkt;
+        self.start_session(session).await
+    }
+
+    /// Starts a relying party session which ends with the browser session it was started from
+    pub async fn issue_sso_refresh_token(
+        &self,
+        user: &User,
+        client_id: String,
+        scope: Option<String>,
+        dpop_jkt: Option<String>,
+        sso_session_id: Uuid,
     ) -> Result<RefreshToken, TokenServiceError> {
-        let session = Session::new(user.id);
+        let mut session = Session::new(user.id, Some(client_id), scope);
+        session.dpop_jkt = dpop_jkt;
+        session.sso_session_id = Some(sso_session_id);
+        self.start_session(session).await
+    }
+
+    async fn start_session(&self, session: Session) -> Result<RefreshToken, TokenServiceError> {
+        let user_id = session.user_id;
         let session = self.session_repository.add(session).await?;
 
-        let refresh_token = self.generate_refresh_token(user.id, session.id);
+        let refresh_token = self.generate_refresh_token(user_id, session.id);
         self.refresh_token_repository
             .add(refresh_token)
             .await
             .map_err(TokenServiceError::InternalDbError)
     }
 
+    /// Issues the next refresh token, the session tells which scopes were granted
+    ///
+    /// Refresh tokens of a bound session need a proof of the same DPoP key.
     pub async fn swap_refresh_token(
         &self,
         refresh_token: &str,
-    ) -> Result<RefreshToken, TokenServiceError> {
+        dpop_jkt: Option<&str>,
+    ) -> Result<(RefreshToken, Session), TokenServiceError> {
         let refresh_token = self.refresh_token_repository.find(refresh_token).await?;
 
-        let _session = self
+        let session = self
             .session_repository
             .get(refresh_token.session_id)
             .await?;
         //TODO: check whether session is still valid?
 
+        if session.dpop_jkt.is_some() && session.dpop_jkt.as_deref() != dpop_jkt {
+            return Err(TokenServiceError::InvalidDPoPKey);
+        }
+
         let user_uuid = refresh_token.user_id;
         let user = self.user_repository.get(&user_uuid).await?;
 
@@ -87,14 +133,58 @@ impl TokenService {
         //TODO(feat):   check whether the user is banned or not
 
         let new_refresh_token = self.generate_refresh_token(user.id, refresh_token.session_id);
-        self.refresh_token_repository
-            .add(new_refresh_token)
-            .await
-            .map_err(TokenServiceError::InternalDbError)
+        let new_refresh_token = self.refresh_token_repository.add(new_refresh_token).await?;
+
+        Ok((new_refresh_token, session))
+    }
+
+    /// Refresh grant of a relying party, the token must belong to a session of the client
+    pub async fn swap_client_refresh_token(
+        &self,
+        refresh_token: &str,
+        client_id: &str,
+        dpop_jkt: Option<&str>,
+    ) -> Result<(RefreshToken, Session), TokenServiceError> {
+        let session = self.refresh_token_session(refresh_token).await?;
+        if session.client_id.as_deref() != Some(client_id) {
+            return Err(TokenServiceError::RefreshTokenNotFound);
+        }
+
+        self.swap_refresh_token(refresh_token, dpop_jkt).await
+    }
+
+    /// Refresh grant of a first party app, tokens of relying parties need their client
+    pub async fn swap_user_refresh_token(
+        &self,
+        refresh_token: &str,
+        dpop_jkt: Option<&str>,
+    ) -> Result<(RefreshToken, Session), TokenServiceError> {
+        let session = self.refresh_token_session(refresh_token).await?;
+        if session.client_id.is_some() {
+            return Err(TokenServiceError::RefreshTokenNotFound);
+        }
+
+        self.swap_refresh_token(refresh_token, dpop_jkt).await
+    }
+
+    async fn refresh_token_session(
+        &self,
+        refresh_token: &str,
+    ) -> Result<Session, TokenServiceError> {
+        let Ok(found) = self.refresh_token_repository.find(refresh_token).await else {
+            return Err(TokenServiceError::RefreshTokenNotFound);
+        };
+
+        Ok(self.session_repository.get(found.session_id).await?)
+    }
+
+    /// Claims of an access token presented to a user route
+    pub fn decode_user_token(&self, token: &str) -> Result<JWTClaims, TokenServiceError> {
+        Ok(decode_user_jwt(token, self.jwt_secret())?)
     }
 
     pub async fn token_info(&self, access_token: &str) -> Result<TokenInfo, TokenServiceError> {
-        let jwt_claims = decode_jwt(access_token, AppConfig::jwt_secret())?;
+        let jwt_claims = decode_jwt(access_token, self.jwt_secret())?;
         let user_uuid =
             Uuid::parse_str(&jwt_claims.sub).map_err(|_| TokenServiceError::InternalError)?;
         let user = self.user_repository.get(&user_uuid).await?;
@@ -105,7 +195,152 @@ impl TokenService {
         })
     }
 
+    /// Introspects an access or refresh token, tokens of ended sessions are inactive
+    pub async fn introspect(&self, token: &str) -> TokenIntrospection {
+        if let Ok(claims) = decode_jwt(token, self.jwt_secret()) {
+            // Client credentials tokens have no session
+            if claims.sid.is_empty() {
+                return TokenIntrospection::access_token(claims, None);
+            }
+
+            let Ok(session_id) = Uuid::parse_str(&claims.sid) else {
+                return TokenIntrospection::inactive();
+            };
+
+            return match self.session_repository.get(session_id).await {
+                Ok(session) => TokenIntrospection::access_token(claims, Some(&session)),
+                Err(_) => TokenIntrospection::inactive(),
+            };
+        }
+
+        let Ok(refresh_token) = self.refresh_token_repository.find(token).await else {
+            return TokenIntrospection::inactive();
+        };
+
+        if refresh_token.is_revoke() {
+            return TokenIntrospection::inactive();
+        }
+
+        match self.session_repository.get(refresh_token.session_id).await {
+            Ok(session) => TokenIntrospection::refresh_token(&refresh_token, &session),
+            Err(_) => TokenIntrospection::inactive(),
+        }
+    }
+
+    /// Introspection for a client, tokens issued to other clients are reported as inactive
+    pub async fn introspect_for_client(&self, token: &str, client_id: &str) -> TokenIntrospection {
+        let introspection = self.introspect(token).await;
+        if introspection.client_id.as_deref() != Some(client_id) {
+            return TokenIntrospection::inactive();
+        }
+
+        introspection
+    }
+
+    fn jwt_secret(&self) -> String {
+        self.jwt_secret.clone().unwrap_or_else(AppConfig::jwt_secret)
+    }
+
     fn generate_refresh_token(&self, user_id: uuid::Uuid, session_id: uuid::Uuid) -> RefreshToken {
         RefreshToken::new(user_id, session_id, random_secret_token(64))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::jwt::encode_jwt;
+    use crate::model::refresh_token_repository::DbRefreshTokenRepository;
+    use crate::model::session_repository::DbSessionRepository;
+    use crate::model::user_repository::DbUserRepository;
+    use crate::service::session_service::SessionService;
+    use sqlx::PgPool;
+
+    const JWT_SECRET: &str = "test-jwt-secret";
+
+    #[sqlx::test]
+    async fn introspect_test(pool: PgPool) -> Result<(), Box<dyn std::error::Error>> {
+        let session_repository = Arc::new(DbSessionRepository::new(pool.clone()));
+        let user_repository = Arc::new(DbUserRepository::new(pool.clone()));
+        let service = TokenService::new(
+            Arc::new(DbRefreshTokenRepository::new(pool.clone())),
+            user_repository.clone(),
+            session_repository.clone(),
+        )
+        .set_jwt_secret(JWT_SECRET.to_string());
+        let user = user_repository
+            .add(User::new("jane@example.com".to_string(), "hash".to_string()))
+            .await?;
+
+        let refresh_token = service
+            .issue_refresh_token(
+                &user,
+                Some("tv-app".to_string()),
+                Some("users:read".to_string()),
+                None,
+            )
+            .await?;
+        let claims = JWTClaims::new(
+            user.id.to_string(),
+            refresh_token.session_id.to_string(),
+            "users:read".to_string(),
+        );
+        let access_token = encode_jwt(&claims, JWT_SECRET.to_string())?;
+
+        let introspection = service.introspect(&access_token).await;
+        assert!(introspection.active);
+        assert_eq!(introspection.client_id.as_deref(), Some("tv-app"));
+        assert_eq!(introspection.exp, Some(claims.exp));
+
+        let introspection = service.introspect(&refresh_token.token).await;
+        assert!(introspection.active);
+        assert_eq!(introspection.sub, Some(user.id.to_string()));
+        assert_eq!(introspection.scope.as_deref(), Some("users:read"));
+
+        assert!(!service.introspect("unknown").await.active);
+
+        let introspection = service.introspect_for_client(&access_token, "tv-app").await;
+        assert!(introspection.active);
+        let introspection = service.introspect_for_client(&access_token, "web-app").await;
+        assert!(!introspection.active);
+        assert!(introspection.sub.is_none());
+
+        SessionService::new(session_repository)
+            .revoke_session(&refresh_token.session_id)
+            .await?;
+        assert!(!service.introspect(&access_token).await.active);
+        assert!(!service.introspect(&refresh_token.token).await.active);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn dpop_bound_refresh_token_test(pool: PgPool) -> Result<(), Box<dyn std::error::Error>> {
+        let user_repository = Arc::new(DbUserRepository::new(pool.clone()));
+        let service = TokenService::new(
+            Arc::new(DbRefreshTokenRepository::new(pool.clone())),
+            user_repository.clone(),
+            Arc::new(DbSessionRepository::new(pool.clone())),
+        );
+        let user = user_repository
+            .add(User::new("jane@example.com".to_string(), "hash".to_string()))
+            .await?;
+
+        let jkt = "0ZcOCORZNYy-DWpqq30jZyJGHTN0d2HglBV3uiguA4I";
+        let refresh_token = service
+            .issue_refresh_token(&user, None, None, Some(jkt.to_string()))
+            .await?;
+
+        for dpop_jkt in [None, Some("another-key")] {
+            let result = service.swap_refresh_token(&refresh_token.token, dpop_jkt).await;
+            assert!(matches!(result, Err(TokenServiceError::InvalidDPoPKey)));
+        }
+
+        let (_, session) = service
+            .swap_refresh_token(&refresh_token.token, Some(jkt))
+            .await?;
+        assert_eq!(session.dpop_jkt.as_deref(), Some(jkt));
+
+        Ok(())
+    }
+}
@@ -1,14 +1,26 @@
-use crate::model::identity::Identity;
+use crate::config::{AccountLinkingPolicy, AppConfig, SamlConnection, SsoDomains};
+use crate::ldap::LDAP_PROVIDER;
+use crate::model::identity::{Identity, IdentityError};
 use crate::model::identity_repository::{IdentityRepository, IdentityRepositoryError};
+use crate::model::provider_notification_repository::{
+    ProviderNotificationRepository, ProviderNotificationRepositoryError,
+};
+use crate::model::provider_token_revocation::ProviderTokenRevocation;
+use crate::model::provider_token_revocation_repository::{
+    ProviderTokenRevocationRepository, ProviderTokenRevocationRepositoryError,
+};
 use crate::model::user::User;
 use crate::model::user_repository::{UserRepository, UserRepositoryError};
-use crate::oidc::provider::UserProvidedData;
+use crate::oidc::provider::{Claims, UserProvidedData};
 use crate::utils::crypto::hash_password;
+use serde_json::{Map, Value};
 use std::collections::HashMap;
 use std::sync::Arc;
 use thiserror::Error;
 use tokio::task;
 use crate::oidc::oidc::OidcProvider;
+use crate::oidc::provider::apple::{AppleEventType, AppleNotification, AppleNotificationEvent};
+use crate::oidc::revocation::{spawn_revocation, TokenRevoker};
 
 #[derive(Error, Debug)]
 pub enum UserServiceError {
@@ -21,35 +33,92 @@ pub enum UserServiceError {
     #[error("Invalid External Identity")]
     InvalidExternalIdentity,
 
+    #[error("Multiple accounts match the external identity")]
+    MultipleAccounts(Vec<uuid::Uuid>),
+
+    #[error("Account linking is not allowed")]
+    AccountLinkingNotAllowed,
+
+    #[error("Sign in is required to link the external identity")]
+    AccountLinkingRequiresSignIn,
+
+    #[error("External identity email is not verified")]
+    UnverifiedEmail,
+
+    #[error("Identity not found")]
+    IdentityNotFound,
+
+    #[error("Identity is already linked")]
+    IdentityAlreadyLinked,
+
+    #[error("Last identity of the user can't be removed")]
+    LastIdentity,
+
+    #[error("Notification was already handled")]
+    NotificationAlreadyHandled,
+
+    #[error("Domain requires SSO sign in")]
+    SsoRequired(String),
+
     #[error("Internal user data store error")]
     InternalDbError(#[from] UserRepositoryError),
 
     #[error("Internal identity data store error")]
     InternalIdentityDbError(#[from] IdentityRepositoryError),
+
+    #[error("Internal identity error")]
+    InternalIdentityError(#[from] IdentityError),
+
+    #[error("Internal revocation data store error")]
+    InternalRevocationDbError(#[from] ProviderTokenRevocationRepositoryError),
+
+    #[error("Internal notification data store error")]
+    InternalNotificationDbError(#[from] ProviderNotificationRepositoryError),
 }
 
 #[derive(Clone)]
 pub struct UserService {
     user_repository: Arc<dyn UserRepository + Send + Sync>,
     identity_repository: Arc<dyn IdentityRepository + Send + Sync>,
+    token_revoker: Arc<dyn TokenRevoker + Send + Sync>,
+    revocation_repository: Arc<dyn ProviderTokenRevocationRepository + Send + Sync>,
+    notification_repository: Arc<dyn ProviderNotificationRepository + Send + Sync>,
+    sso_domains: SsoDomains,
 }
 
 impl UserService {
     pub fn new(
         user_repository: Arc<dyn UserRepository + Send + Sync>,
         identity_repository: Arc<dyn IdentityRepository + Send + Sync>,
+        token_revoker: Arc<dyn TokenRevoker + Send + Sync>,
+        revocation_repository: Arc<dyn ProviderTokenRevocationRepository + Send + Sync>,
+        notification_repository: Arc<dyn ProviderNotificationRepository + Send + Sync>,
     ) -> Self {
         UserService {
             user_repository: user_repository.clone(),
             identity_repository: identity_repository.clone(),
+            token_revoker,
+            revocation_repository,
+            notification_repository,
+            sso_domains: AppConfig::sso_domains().cloned().unwrap_or_default(),
         }
     }
 
+    /// Replaces the domains routed to SSO connections, signup is blocked where SSO is required
+    pub fn set_sso_domains(mut self, sso_domains: SsoDomains) -> Self {
+        self.sso_domains = sso_domains;
+        self
+    }
+
     pub async fn create_user(
         &self,
         email: String,
         password: String,
     ) -> Result<User, UserServiceError> {
+        if let Some(connection) = self.sso_domains.required_connection(&email) {
+            return Err(UserServiceError::SsoRequired(connection.name.clone()));
+        }
+
         let exists = self.user_repository.contains_with_email(&email).await?;
         if exists == true {
             return Err(UserServiceError::UserExists);
@@ -79,6 +148,52 @@ impl UserService {
         &self,
         provider_data: &UserProvidedData,
         provider: OidcProvider,
+    ) -> Result<User, UserServiceError> {
+        self.create_user_from_identity(
+            provider_data,
+            provider.name(),
+            AppConfig::account_linking_policy(&provider),
+            AppConfig::sync_user_profile(&provider),
+        )
+        .await
+    }
+
+    /// Signs in with an assertion of an enterprise SAML connection
+    pub async fn create_user_from_sso_identity(
+        &self,
+        provider_data: &UserProvidedData,
+        connection: &SamlConnection,
+    ) -> Result<User, UserServiceError> {
+        self.create_user_from_identity(
+            provider_data,
+            &connection.identity_provider(),
+            connection.linking_policy,
+            connection.sync_profile,
+        )
+        .await
+    }
+
+    /// Signs in with an entry of the directory that verified the password
+    pub async fn create_user_from_directory_identity(
+        &self,
+        provider_data: &UserProvidedData,
+    ) -> Result<User, UserServiceError> {
+        let configuration = AppConfig::ldap_configuration();
+        self.create_user_from_identity(
+            provider_data,
+            LDAP_PROVIDER,
+            configuration.map(|v| v.linking_policy).unwrap_or_default(),
+            configuration.is_some_and(|v| v.sync_profile),
+        )
+        .await
+    }
+
+    async fn create_user_from_identity(
+        &self,
+        provider_data: &UserProvidedData,
+        provider: &str,
+        linking_policy: AccountLinkingPolicy,
+        sync_profile: bool,
     ) -> Result<User, UserServiceError> {
         let Some(meta) = &provider_data.metadata else {
             return Err(UserServiceError::InvalidExternalIdentity);
@@ -88,12 +203,10 @@ impl UserService {
             return Err(UserServiceError::InvalidExternalIdentity);
         };
 
-        let provider = provider.name();
-
         let emails: Vec<String> = provider_data
             .emails
             .iter()
-            .map(|e| e.email.clone())
+            .map(|e| e.email.to_lowercase())
             .collect();
         let account_linking = self
             .determine_account_linking(provider, &sub, &emails)
@@ -105,22 +218,248 @@ impl UserService {
                     return Err(UserServiceError::InvalidExternalIdentity);
                 };
 
-                return Ok(user);
+                let identity = account_linking.identities.and_then(|v| v.into_iter().next());
+                if let Some(mut identity) = identity {
+                    identity.update_from_provider(provider_data)?;
+                    self.identity_repository.update(identity).await?;
+                }
+
+                if sync_profile {
+                    return self.sync_user_profile(user, meta).await;
+                }
+
+                Ok(user)
             }
             AccountLinkingDecision::CreateAccount => {
-                let user = User::new_from_provider(&emails[0]);
-                let user = self.user_repository.add(user).await?;
+                let Some(email) = provider_data.account_email() else {
+                    return Err(UserServiceError::InvalidExternalIdentity);
+                };
 
-                let idenity = Identity::new_from_provider(&user, provider, provider_data);
+                let user = User::new_from_provider(&email.email.to_lowercase(), meta.profile());
+                let idenity = Identity::new_from_provider(&user, provider, provider_data)?;
+                let user = self.user_repository.add(user).await?;
                 self.identity_repository.add(&idenity).await?;
 
-                return Ok(user);
+                Ok(user)
             }
             AccountLinkingDecision::LinkAccount => {
-                todo!()
+                let Some(user) = account_linking.user else {
+                    return Err(UserServiceError::InvalidExternalIdentity);
+                };
+
+                match linking_policy {
+                    AccountLinkingPolicy::AutoLinkVerified => {}
+                    AccountLinkingPolicy::Never => {
+                        return Err(UserServiceError::AccountLinkingNotAllowed)
+                    }
+                    AccountLinkingPolicy::RequireSignedIn => {
+                        return Err(UserServiceError::AccountLinkingRequiresSignIn)
+                    }
+                }
+
+                let identities = account_linking.identities.unwrap_or_default();
+                if !has_verified_matching_email(provider_data, &identities) {
+                    return Err(UserServiceError::UnverifiedEmail);
+                }
+
+                // Anyone can register an unverified email, the owner has to link it signed in
+                if !is_account_email_verified(&user, &identities) {
+                    return Err(UserServiceError::AccountLinkingRequiresSignIn);
+                }
+
+                let idenity = Identity::new_from_provider(&user, provider, provider_data)?;
+                self.identity_repository.add(&idenity).await?;
+
+                if sync_profile {
+                    return self.sync_user_profile(user, meta).await;
+                }
+
+                Ok(user)
             }
             AccountLinkingDecision::MultipleAccounts => {
-                todo!()
+                let mut user_ids: Vec<uuid::Uuid> = account_linking
+                    .identities
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|v| v.user_id)
+                    .collect();
+                user_ids.sort();
+                user_ids.dedup();
+
+                Err(UserServiceError::MultipleAccounts(user_ids))
+            }
+        }
+    }
+
+    async fn sync_user_profile(&self, mut user: User, meta: &Claims) -> Result<User, UserServiceError> {
+        if !user.merge_metadata(meta.profile()) {
+            return Ok(user);
+        }
+
+        self.user_repository
+            .update_metadata(&user.id, &user.user_metadata)
+            .await
+            .map_err(UserServiceError::InternalDbError)
+    }
+
+    pub async fn get_identities(
+        &self,
+        user_id: &uuid::Uuid,
+    ) -> Result<Vec<Identity>, UserServiceError> {
+        self.identity_repository
+            .find_all_by_user(user_id)
+            .await
+            .map_err(UserServiceError::InternalIdentityDbError)
+    }
+
+    pub async fn link_identity(
+        &self,
+        user_id: &uuid::Uuid,
+        provider_data: &UserProvidedData,
+        provider: OidcProvider,
+    ) -> Result<Identity, UserServiceError> {
+        let Some(sub) = provider_data.metadata.as_ref().and_then(|v| v.subject.as_ref()) else {
+            return Err(UserServiceError::InvalidExternalIdentity);
+        };
+
+        if provider_data.emails.is_empty() {
+            return Err(UserServiceError::InvalidExternalIdentity);
+        }
+
+        let provider = provider.name();
+
+        match self.identity_repository.find(sub, provider).await {
+            Ok(identity) if identity.user_id == *user_id => return Ok(identity),
+            Ok(_) => return Err(UserServiceError::IdentityAlreadyLinked),
+            Err(IdentityRepositoryError::InternalDbError(sqlx::Error::RowNotFound)) => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        let identities = self.identity_repository.find_all_by_user(user_id).await?;
+        if identities.iter().any(|v| v.provider == provider) {
+            return Err(UserServiceError::IdentityAlreadyLinked);
+        }
+
+        let user = self.user_repository.get(user_id).await?;
+        let idenity = Identity::new_from_provider(&user, provider, provider_data)?;
+
+        self.identity_repository
+            .add(&idenity)
+            .await
+            .map_err(UserServiceError::InternalIdentityDbError)
+    }
+
+    pub async fn unlink_identity(
+        &self,
+        user_id: &uuid::Uuid,
+        provider: OidcProvider,
+    ) -> Result<(), UserServiceError> {
+        let identities = self.identity_repository.find_all_by_user(user_id).await?;
+
+        if !identities.iter().any(|v| v.provider == provider.name()) {
+            return Err(UserServiceError::IdentityNotFound);
+        }
+
+        // Every identity is a way to sign in, keep at least one
+        let deleted = self
+            .identity_repository
+            .delete_unless_last(user_id, provider.name())
+            .await
+            .map_err(UserServiceError::InternalIdentityDbError)?;
+        if !deleted {
+            return Err(UserServiceError::LastIdentity);
+        }
+
+        Ok(())
+    }
+
+    /// Applies a Sign in with Apple notification once, returns the user to sign out everywhere
+    pub async fn handle_apple_notification(
+        &self,
+        notification: &AppleNotification,
+    ) -> Result<Option<uuid::Uuid>, UserServiceError> {
+        let provider = OidcProvider::Apple.name();
+        let jti = notification.jti.as_str();
+        let added = self
+            .notification_repository
+            .add(provider, jti, notification.expires_at())
+            .await?;
+        if !added {
+            return Err(UserServiceError::NotificationAlreadyHandled);
+        }
+
+        // Failed notifications are delivered again, those of unknown users are done
+        let result = self.handle_apple_event(&notification.event).await;
+        if matches!(&result, Err(e) if !matches!(e, UserServiceError::IdentityNotFound)) {
+            let _ = self.notification_repository.delete(provider, jti).await;
+        }
+
+        result
+    }
+
+    async fn handle_apple_event(
+        &self,
+        event: &AppleNotificationEvent,
+    ) -> Result<Option<uuid::Uuid>, UserServiceError> {
+        let mut identity = match self
+            .identity_repository
+            .find(&event.sub, OidcProvider::Apple.name())
+            .await
+        {
+            Ok(identity) => identity,
+            Err(IdentityRepositoryError::InternalDbError(sqlx::Error::RowNotFound)) => {
+                return Err(UserServiceError::IdentityNotFound)
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        match event.event_type {
+            AppleEventType::EmailDisabled | AppleEventType::EmailEnabled => {
+                if let Value::Object(data) = &mut identity.identity_data {
+                    if let Some(email) = &event.email {
+                        data.insert("email".to_string(), Value::String(email.clone()));
+                    }
+
+                    let custom_claims = data
+                        .entry("custom_claims")
+                        .or_insert_with(|| Value::Object(Map::new()));
+                    if let Value::Object(custom_claims) = custom_claims {
+                        custom_claims.insert(
+                            "is_private_email".to_string(),
+                            Value::Bool(event.is_private_email),
+                        );
+                        custom_claims.insert(
+                            "email_disabled".to_string(),
+                            Value::Bool(event.event_type == AppleEventType::EmailDisabled),
+                        );
+                    }
+                }
+
+                self.identity_repository.update(identity).await?;
+                Ok(None)
+            }
+            AppleEventType::ConsentRevoked => {
+                // Apple already invalidated the tokens issued for the app
+                identity.clear_provider_tokens();
+                let identity = self.identity_repository.update(identity).await?;
+                Ok(Some(identity.user_id))
+            }
+            AppleEventType::AccountDelete => {
+                let identities = self
+                    .identity_repository
+                    .find_all_by_user(&identity.user_id)
+                    .await?;
+
+                // Keep accounts that can still sign in with another identity
+                if identities.len() > 1 {
+                    self.identity_repository
+                        .delete(&identity.id, &identity.provider)
+                        .await?;
+                } else {
+                    self.user_repository.delete(&identity.user_id).await?;
+                }
+
+                Ok(Some(identity.user_id))
             }
         }
     }
@@ -139,14 +478,58 @@ impl UserService {
             .map_err(UserServiceError::InternalDbError)
     }
 
+    /// Deletes the user and revokes the provider tokens of its identities in the background
     pub async fn delete_user(&self, id: &uuid::Uuid) -> Result<(), UserServiceError> {
-        self.user_repository
-            .delete(id)
-            .await
-            .map_err(UserServiceError::InternalDbError)
+        let identities = self.identity_repository.find_all_by_user(id).await?;
+
+        // Tokens are stored as pending first, so a restart can't lose them
+        let revocations: Vec<ProviderTokenRevocation> = identities
+            .iter()
+            .filter_map(ProviderTokenRevocation::new)
+            .collect();
+        self.revocation_repository.add_all(&revocations).await?;
+
+        if let Err(e) = self.user_repository.delete(id).await {
+            for revocation in &revocations {
+                let _ = self.revocation_repository.delete(&revocation.id).await;
+            }
+            return Err(UserServiceError::InternalDbError(e));
+        }
+
+        spawn_revocation(
+            self.token_revoker.clone(),
+            self.revocation_repository.clone(),
+            revocations,
+        );
+        Ok(())
     }
 }
 
+/// Whether the provider verified one of the emails the existing identities were matched on
+fn has_verified_matching_email(provider_data: &UserProvidedData, identities: &[Identity]) -> bool {
+    provider_data
+        .emails
+        .iter()
+        .filter(|e| e.verified)
+        .any(|e| {
+            identities.iter().any(|identity| {
+                identity
+                    .email
+                    .as_ref()
+                    .is_some_and(|v| v.eq_ignore_ascii_case(&e.email))
+            })
+        })
+}
+
+/// Whether the existing account verified the email it was matched on, password accounts once
+/// confirmed and external identities when their provider verified it
+fn is_account_email_verified(user: &User, identities: &[Identity]) -> bool {
+    let is_confirmed = user.confirmed_at.is_some();
+    identities.iter().any(|identity| {
+        identity.is_email_verified() || (identity.provider == "email" && is_confirmed)
+    })
+}
+
 #[derive(Debug, PartialEq)]
 enum AccountLinkingDecision {
     AccountExists,
@@ -245,3 +628,439 @@ impl UserService {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::identity_repository::DbIdentityRepository;
+    use crate::model::provider_notification_repository::DbProviderNotificationRepository;
+    use crate::model::provider_token_revocation_repository::DbProviderTokenRevocationRepository;
+    use crate::model::user_repository::DbUserRepository;
+    use crate::oidc::oidc::OidcError;
+    use crate::oidc::provider::google::REVOKE_URL_GOOGLE;
+    use crate::oidc::provider::Email;
+    use crate::oidc::provider::ProviderTokens;
+    use crate::oidc::revocation::{
+        sweep_revocations, HttpClient, HttpClientResponse, ProviderTokenRevoker,
+    };
+    use crate::saml::metadata::IdpMetadata;
+    use crate::saml::response::{parse_response, AttributeMapping, ResponseValidation};
+    use async_trait::async_trait;
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use sqlx::PgPool;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    /// Accepts every request and reports the url and form to the test
+    struct StubHttpClient {
+        requests: mpsc::UnboundedSender<(String, Vec<(String, String)>)>,
+    }
+
+    #[async_trait]
+    impl HttpClient for StubHttpClient {
+        async fn post_form(
+            &self,
+            url: &str,
+            form: &[(&str, &str)],
+        ) -> Result<HttpClientResponse, OidcError> {
+            let form = form
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            let _ = self.requests.send((url.to_string(), form));
+            Ok(HttpClientResponse {
+                status: 200,
+                body: String::new(),
+            })
+        }
+    }
+
+    fn user_service_with_stub(
+        pool: &PgPool,
+    ) -> (UserService, mpsc::UnboundedReceiver<(String, Vec<(String, String)>)>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let http_client = Arc::new(StubHttpClient { requests: sender });
+
+        let service = UserService::new(
+            Arc::new(DbUserRepository::new(pool.clone())),
+            Arc::new(DbIdentityRepository::new(pool.clone())),
+            Arc::new(ProviderTokenRevoker::new(http_client)),
+            Arc::new(DbProviderTokenRevocationRepository::new(pool.clone())),
+            Arc::new(DbProviderNotificationRepository::new(pool.clone())),
+        );
+        (service, receiver)
+    }
+
+    fn user_service(pool: &PgPool) -> UserService {
+        user_service_with_stub(pool).0
+    }
+
+    fn provider_data(sub: &str, email: &str, verified: bool) -> UserProvidedData {
+        UserProvidedData {
+            emails: vec![Email {
+                email: email.to_string(),
+                verified,
+                primary: true,
+            }],
+            metadata: Some(Claims {
+                subject: Some(sub.to_string()),
+                email: Some(email.to_string()),
+                email_verified: Some(verified),
+                ..Default::default()
+            }),
+            provider_tokens: None,
+        }
+    }
+
+    /// Marks the email of a password account as verified, signup doesn't confirm it yet
+    async fn confirm_user(pool: &PgPool, user: &User) -> Result<(), sqlx::Error> {
+        sqlx::query!("UPDATE auth_user SET confirmed_at = now() WHERE id = $1", user.id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    fn saml_connection(domains: &[&str], linking_policy: AccountLinkingPolicy) -> SamlConnection {
+        SamlConnection {
+            name: "acme".to_string(),
+            idp: IdpMetadata::parse(include_str!("../saml/fixtures/idp_metadata.xml"))
+                .expect("Expect metadata"),
+            attribute_mapping: AttributeMapping::default(),
+            linking_policy,
+            sync_profile: false,
+            domains: domains.iter().map(|v| v.to_string()).collect(),
+            require_sso: false,
+        }
+    }
+
+    /// Provider data of the signed fixture response, asserting jane.doe@acme.com
+    fn saml_provider_data(connection: &SamlConnection) -> UserProvidedData {
+        let validation = ResponseValidation {
+            idp: &connection.idp,
+            sp_entity_id: "https://auth.example.com/saml",
+            acs_url: "https://auth.example.com/auth/sso/acs",
+            request_id: "_request-id",
+            now: "2026-10-19T10:01:00Z".parse().expect("Expect instant"),
+        };
+        let response = STANDARD.encode(include_str!("../saml/fixtures/response.xml"));
+        let mapping = &connection.attribute_mapping;
+        parse_response(&response, &validation)
+            .and_then(|v| v.into_user_provided_data(mapping, &connection.domains))
+            .expect("Expect valid response")
+    }
+
+    #[sqlx::test]
+    async fn refuse_foreign_domain_sso_identity_test(
+        pool: PgPool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let service = user_service(&pool);
+        let user = service
+            .create_user("jane.doe@acme.com".to_string(), "password".to_string())
+            .await?;
+        confirm_user(&pool, &user).await?;
+
+        // Another organization's IdP asserts an acme.com address
+        let connection = saml_connection(&["other.com"], AccountLinkingPolicy::AutoLinkVerified);
+        let result = service
+            .create_user_from_sso_identity(&saml_provider_data(&connection), &connection)
+            .await;
+        assert!(matches!(result, Err(UserServiceError::UnverifiedEmail)));
+
+        let connection = saml_connection(&["acme.com"], AccountLinkingPolicy::AutoLinkVerified);
+        let linked = service
+            .create_user_from_sso_identity(&saml_provider_data(&connection), &connection)
+            .await?;
+        assert_eq!(linked.id, user.id);
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn block_signup_for_sso_domain_test(
+        pool: PgPool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut connection = saml_connection(&["acme.com"], AccountLinkingPolicy::RequireSignedIn);
+        connection.require_sso = true;
+        let service = user_service(&pool).set_sso_domains(SsoDomains::new([&connection])?);
+
+        let result = service
+            .create_user("Jane.Doe@acme.com".to_string(), "password".to_string())
+            .await;
+        assert!(matches!(result, Err(UserServiceError::SsoRequired(name)) if name == "acme"));
+
+        service
+            .create_user("jane.doe@other.com".to_string(), "password".to_string())
+            .await?;
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn link_verified_identity_test(pool: PgPool) -> Result<(), Box<dyn std::error::Error>> {
+        let service = user_service(&pool);
+        let user = service
+            .create_user("test@email.com".to_string(), "password".to_string())
+            .await?;
+        confirm_user(&pool, &user).await?;
+
+        let data = provider_data("google-sub", "Test@email.com", true);
+        let linked = service
+            .create_user_from_external_identity(&data, OidcProvider::Google)
+            .await?;
+        assert_eq!(linked.id, user.id);
+
+        let again = service
+            .create_user_from_external_identity(&data, OidcProvider::Google)
+            .await?;
+        assert_eq!(again.id, user.id);
+
+        let identities = service.get_identities(&user.id).await?;
+        let identity = identities.iter().find(|v| v.provider == "google").expect("Linked");
+        assert!(identity.last_sign_in_at.is_some());
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn refuse_link_to_unverified_account_test(
+        pool: PgPool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let service = user_service(&pool);
+
+        // Registered with the victim's email before the victim signs in with Google
+        let user = service
+            .create_user("victim@gmail.com".to_string(), "password".to_string())
+            .await?;
+
+        let data = provider_data("google-sub", "victim@gmail.com", true);
+        let result = service
+            .create_user_from_external_identity(&data, OidcProvider::Google)
+            .await;
+        assert!(matches!(result, Err(UserServiceError::AccountLinkingRequiresSignIn)));
+        assert_eq!(service.get_identities(&user.id).await?.len(), 1);
+
+        // An identity whose provider verified the email can be linked to
+        let data = provider_data("github-sub", "verified@example.com", true);
+        let user = service
+            .create_user_from_external_identity(&data, OidcProvider::GitHub)
+            .await?;
+        let data = provider_data("google-sub-2", "verified@example.com", true);
+        let linked = service
+            .create_user_from_external_identity(&data, OidcProvider::Google)
+            .await?;
+        assert_eq!(linked.id, user.id);
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn refresh_identity_on_sign_in_test(pool: PgPool) -> Result<(), Box<dyn std::error::Error>> {
+        let service = user_service(&pool);
+        let mut data = provider_data("google-sub", "test@gmail.com", true);
+        data.metadata.as_mut().expect("Expect metadata").name = Some("Old Name".to_string());
+        let user = service
+            .create_user_from_external_identity(&data, OidcProvider::Google)
+            .await?;
+        let first = service.get_identities(&user.id).await?.remove(0);
+        assert_eq!(first.identity_data["name"], "Old Name");
+
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        data.metadata.as_mut().expect("Expect metadata").name = Some("New Name".to_string());
+        service
+            .create_user_from_external_identity(&data, OidcProvider::Google)
+            .await?;
+
+        let second = service.get_identities(&user.id).await?.remove(0);
+        assert_eq!(second.identity_data["name"], "New Name");
+        assert!(second.last_sign_in_at > first.last_sign_in_at);
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn create_account_with_verified_email_test(pool: PgPool) -> Result<(), Box<dyn std::error::Error>> {
+        let service = user_service(&pool);
+        let mut data = provider_data("github-sub", "Unverified@example.com", false);
+        data.emails.push(Email {
+            email: "Verified@example.com".to_string(),
+            verified: true,
+            primary: false,
+        });
+
+        let user = service
+            .create_user_from_external_identity(&data, OidcProvider::GitHub)
+            .await?;
+        assert_eq!(user.email.as_deref(), Some("verified@example.com"));
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn reject_unverified_link_test(pool: PgPool) -> Result<(), Box<dyn std::error::Error>> {
+        let service = user_service(&pool);
+        service
+            .create_user("test@email.com".to_string(), "password".to_string())
+            .await?;
+
+        let data = provider_data("google-sub", "test@email.com", false);
+        let result = service
+            .create_user_from_external_identity(&data, OidcProvider::Google)
+            .await;
+        assert!(matches!(result, Err(UserServiceError::UnverifiedEmail)));
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn unlink_identity_test(pool: PgPool) -> Result<(), Box<dyn std::error::Error>> {
+        let service = user_service(&pool);
+        let user = service
+            .create_user("test@email.com".to_string(), "password".to_string())
+            .await?;
+
+        let data = provider_data("apple-sub", "relay@privaterelay.appleid.com", true);
+        service
+            .link_identity(&user.id, &data, OidcProvider::Apple)
+            .await?;
+        assert_eq!(service.get_identities(&user.id).await?.len(), 2);
+
+        service.unlink_identity(&user.id, OidcProvider::Apple).await?;
+        assert_eq!(service.get_identities(&user.id).await?.len(), 1);
+
+        let result = service.unlink_identity(&user.id, OidcProvider::Apple).await;
+        assert!(matches!(result, Err(UserServiceError::IdentityNotFound)));
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn unlink_last_identity_test(pool: PgPool) -> Result<(), Box<dyn std::error::Error>> {
+        let service = user_service(&pool);
+        let data = provider_data("google-sub", "test@gmail.com", true);
+        let user = service
+            .create_user_from_external_identity(&data, OidcProvider::Google)
+            .await?;
+
+        let result = service.unlink_identity(&user.id, OidcProvider::Google).await;
+        assert!(matches!(result, Err(UserServiceError::LastIdentity)));
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn concurrent_unlink_identity_test(pool: PgPool) -> Result<(), Box<dyn std::error::Error>> {
+        let service = user_service(&pool);
+        let data = provider_data("google-sub", "test@gmail.com", true);
+        let user = service
+            .create_user_from_external_identity(&data, OidcProvider::Google)
+            .await?;
+        let data = provider_data("apple-sub", "relay@privaterelay.appleid.com", true);
+        service
+            .link_identity(&user.id, &data, OidcProvider::Apple)
+            .await?;
+
+        let (google, apple) = tokio::join!(
+            service.unlink_identity(&user.id, OidcProvider::Google),
+            service.unlink_identity(&user.id, OidcProvider::Apple),
+        );
+        assert!(google.is_ok() != apple.is_ok());
+        assert!(matches!(
+            google.and(apple),
+            Err(UserServiceError::LastIdentity)
+        ));
+        assert_eq!(service.get_identities(&user.id).await?.len(), 1);
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn delete_user_revokes_tokens_test(pool: PgPool) -> Result<(), Box<dyn std::error::Error>> {
+        let (service, mut requests) = user_service_with_stub(&pool);
+        let mut data = provider_data("google-sub", "test@gmail.com", true);
+        std::env::set_var("PROVIDER_TOKEN_SECRET", "test-provider-token-secret");
+        data.provider_tokens = Some(ProviderTokens::new(
+            "google-access-token".to_string(),
+            Some("google-refresh-token".to_string()),
+            None,
+        ));
+        let user = service
+            .create_user_from_external_identity(&data, OidcProvider::Google)
+            .await?;
+
+        service.delete_user(&user.id).await?;
+
+        let (url, form) = tokio::time::timeout(Duration::from_secs(5), requests.recv())
+            .await?
+            .expect("Expect revocation request");
+        assert_eq!(url, REVOKE_URL_GOOGLE);
+        assert_eq!(form, vec![("token".to_string(), "google-refresh-token".to_string())]);
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn sweep_pending_revocations_test(pool: PgPool) -> Result<(), Box<dyn std::error::Error>> {
+        let (sender, mut requests) = mpsc::unbounded_channel();
+        let revoker = Arc::new(ProviderTokenRevoker::new(Arc::new(StubHttpClient {
+            requests: sender,
+        })));
+        let repository = Arc::new(DbProviderTokenRevocationRepository::new(pool.clone()));
+
+        // Left pending by a restart before the provider was reached
+        std::env::set_var("PROVIDER_TOKEN_SECRET", "test-provider-token-secret");
+        let user = User::new("test@gmail.com".to_string(), "hash".to_string());
+        let data = provider_data("google-sub", "test@gmail.com", true);
+        let mut identity = Identity::new_from_provider(&user, "google", &data)?;
+        identity.set_provider_tokens(&ProviderTokens::new(
+            "google-access-token".to_string(),
+            Some("google-refresh-token".to_string()),
+            None,
+        ))?;
+        let revocation = ProviderTokenRevocation::new(&identity).expect("Expect revocation");
+        repository.add_all(&[revocation]).await?;
+
+        sweep_revocations(revoker, repository.clone()).await?.await?;
+
+        let (url, form) = requests.recv().await.expect("Expect revocation request");
+        assert_eq!(url, REVOKE_URL_GOOGLE);
+        assert_eq!(form, vec![("token".to_string(), "google-refresh-token".to_string())]);
+        assert!(repository.find_all().await?.is_empty());
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn apple_events_test(pool: PgPool) -> Result<(), Box<dyn std::error::Error>> {
+        let service = user_service(&pool);
+        let data = provider_data("apple-sub", "relay@privaterelay.appleid.com", true);
+        let user = service
+            .create_user_from_external_identity(&data, OidcProvider::Apple)
+            .await?;
+
+        let event = |event_type| AppleNotificationEvent {
+            event_type,
+            sub: "apple-sub".to_string(),
+            email: Some("relay@privaterelay.appleid.com".to_string()),
+            is_private_email: true,
+            event_time: 1508184845,
+        };
+
+        let notification = |jti: &str, event_type| AppleNotification {
+            jti: jti.to_string(),
+            iat: chrono::Utc::now().timestamp(),
+            event: event(event_type),
+        };
+
+        let email_disabled = notification("email-disabled-id", AppleEventType::EmailDisabled);
+        let result = service.handle_apple_notification(&email_disabled).await?;
+        assert_eq!(result, None);
+        let identities = service.get_identities(&user.id).await?;
+        assert_eq!(identities[0].identity_data["custom_claims"]["email_disabled"], true);
+
+        let result = service.handle_apple_notification(&email_disabled).await;
+        assert!(matches!(result, Err(UserServiceError::NotificationAlreadyHandled)));
+
+        let account_delete = notification("account-delete-id", AppleEventType::AccountDelete);
+        let result = service.handle_apple_notification(&account_delete).await?;
+        assert_eq!(result, Some(user.id));
+        assert!(service.get_user(&user.id).await.is_err());
+
+        // A replay can't delete the account created by the next sign in
+        let user = service
+            .create_user_from_external_identity(&data, OidcProvider::Apple)
+            .await?;
+        let result = service.handle_apple_notification(&account_delete).await;
+        assert!(matches!(result, Err(UserServiceError::NotificationAlreadyHandled)));
+        assert!(service.get_user(&user.id).await.is_ok());
+        Ok(())
+    }
+}
@@ -1,8 +1,17 @@
 use argon2::password_hash::rand_core::OsRng;
 use argon2::password_hash::SaltString;
 use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
 use rand::distributions::Alphanumeric;
 use rand::Rng;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::hkdf::{Salt, HKDF_SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use subtle::ConstantTimeEq;
+
+const ENCRYPTION_KEY_SALT: &[u8] = b"authcare";
+const ENCRYPTION_KEY_INFO: &[u8] = b"authcare secret encryption";
 
 pub fn random_secret_token(lenght: usize) -> String {
     rand::thread_rng()
@@ -27,3 +36,50 @@ pub fn compare_hash_and_password(hash: &str, password: &str) -> bool {
         .verify_password(password.as_bytes(), &parsed_hash)
         .map_or(false, |_| true)
 }
+
+/// Compares secrets without leaking the position of the first difference
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.as_bytes().ct_eq(b.as_bytes()).into()
+}
+
+/// Encrypts with AES-256-GCM, returns base64 of the nonce followed by the ciphertext
+pub fn encrypt_secret(plaintext: &str, secret: &str) -> String {
+    let mut nonce = [0u8; NONCE_LEN];
+    SystemRandom::new()
+        .fill(&mut nonce)
+        .expect("Error while generating nonce");
+
+    let mut in_out = plaintext.as_bytes().to_vec();
+    encryption_key(secret)
+        .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut in_out)
+        .expect("Error while encrypting secret");
+
+    let mut output = nonce.to_vec();
+    output.extend_from_slice(&in_out);
+    STANDARD.encode(output)
+}
+
+pub fn decrypt_secret(ciphertext: &str, secret: &str) -> Option<String> {
+    let data = STANDARD.decode(ciphertext).ok()?;
+    if data.len() < NONCE_LEN {
+        return None;
+    }
+
+    let (nonce, encrypted) = data.split_at(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce).ok()?;
+    let mut in_out = encrypted.to_vec();
+    let plaintext = encryption_key(secret)
+        .open_in_place(nonce, Aad::empty(), &mut in_out)
+        .ok()?;
+
+    String::from_utf8(plaintext.to_vec()).ok()
+}
+
+/// Derives the AES key with HKDF, the secret is not required to be uniformly random
+fn encryption_key(secret: &str) -> LessSafeKey {
+    let prk = Salt::new(HKDF_SHA256, ENCRYPTION_KEY_SALT).extract(secret.as_bytes());
+    let key = prk
+        .expand(&[ENCRYPTION_KEY_INFO], &AES_256_GCM)
+        .expect("Expect 256 bit key");
+    LessSafeKey::new(UnboundKey::from(key))
+}
@@ -0,0 +1,12 @@
+/// Escapes a double quoted attribute value of XML or HTML
+///
+/// Whitespace other than spaces is kept as character references, so canonical XML is unchanged.
+pub fn escape_attribute(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('"', "&quot;")
+        .replace('\t', "&#x9;")
+        .replace('\n', "&#xA;")
+        .replace('\r', "&#xD;")
+}